@@ -0,0 +1,207 @@
+use chrono::NaiveDate;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use p32::AccountId;
+use p32::currency::Currency;
+use p32::merge::{AccountReconciliation, ConflictResolution, CreditLines};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+
+const EXIT_CODES: &str = "\
+Exit codes:
+  0   success
+  1   any other failure, such as an unreadable state file
+  2   invalid arguments
+  10  sender not found          16  overflow
+  11  receiver not found        17  same account
+  12  insufficient funds        18  account frozen
+  13  currency mismatch         19  account over limit
+  14  exchange rate unavailable 20  account closed
+  15  non-positive amount";
+
+/// Runs a bank kept in a state file
+#[derive(Parser)]
+#[command(name = "p12", version, after_help = EXIT_CODES)]
+pub struct Cli {
+    /// Snapshot file holding the bank, rewritten after every change
+    #[arg(long, global = true, env = "P12_STATE", default_value = "bank.json")]
+    pub state: PathBuf,
+    /// Print JSON instead of tables
+    #[arg(long, global = true)]
+    pub json: bool,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Create a bank without accounts in a new state file
+    Init {
+        name: String,
+        /// Annual rate of negative balances, in basis points
+        #[arg(long, default_value_t = 0)]
+        credit_interest: u32,
+        /// Annual rate of positive balances, in basis points
+        #[arg(long, default_value_t = 0)]
+        debit_interest: u32,
+    },
+    /// Open an account
+    Open(OpenArgs),
+    /// Move funds between two accounts
+    Transfer {
+        #[arg(value_parser = parse_account)]
+        sender: AccountId,
+        #[arg(value_parser = parse_account)]
+        receiver: AccountId,
+        /// Decimal amount, such as 12.30
+        amount: String,
+        /// Currency of the amount, the home currency of the sender by default
+        #[arg(long)]
+        currency: Option<Currency>,
+    },
+    /// Accrue interest, daily or over a range of days
+    Accrue(AccrueArgs),
+    /// Show the balance sheet of every currency
+    BalanceSheet,
+    /// Merge the bank of another state file into this one
+    Merge {
+        other_state: PathBuf,
+        #[arg(long, value_enum, default_value_t = Reconciliation::ByName)]
+        reconciliation: Reconciliation,
+        #[arg(long, value_enum, default_value_t = Conflicts::Combine)]
+        conflicts: Conflicts,
+        /// Credit line of combined accounts
+        #[arg(long, value_enum, default_value_t = CreditLineChoice::Keep)]
+        credit_lines: CreditLineChoice,
+    },
+    /// Write every account to a file, or to standard output
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Run statements such as `transfer alice bob 100` at a prompt, keeping the history of
+    /// the prompt next to the state file
+    Repl,
+    /// Serve the bank over HTTP, saving the state file after every change
+    Serve {
+        /// Address to listen on, port 0 picking a free port
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: SocketAddr,
+        /// Number of idempotency keys of transfers remembered, the oldest being forgotten
+        /// first. They are kept next to the state file.
+        #[arg(long, default_value = "10000")]
+        idempotency_keys: NonZeroUsize,
+    },
+}
+
+/// Also the body of `POST /accounts`, with the same defaults
+#[derive(Args, Deserialize)]
+pub struct OpenArgs {
+    pub name: String,
+    /// Home currency of the account
+    #[arg(long, default_value = "EUR")]
+    #[serde(default = "euro")]
+    pub currency: Currency,
+    /// Decimal amount in the home currency
+    #[arg(long, default_value = "0", allow_hyphen_values = true)]
+    #[serde(default = "zero")]
+    pub credit_line: String,
+    /// Opening balance, a decimal amount in the home currency
+    #[arg(long, default_value = "0", allow_hyphen_values = true)]
+    #[serde(default = "zero")]
+    pub balance: String,
+}
+
+fn euro() -> Currency {
+    Currency::EUR
+}
+
+fn zero() -> String {
+    "0".to_string()
+}
+
+/// Also the body of `POST /interest/accruals`
+#[derive(Args, Deserialize)]
+pub struct AccrueArgs {
+    /// First day to accrue, together with --to
+    #[arg(long, requires = "to")]
+    pub from: Option<NaiveDate>,
+    /// Day after the last day to accrue
+    #[arg(long, requires = "from")]
+    pub to: Option<NaiveDate>,
+    /// Post the accrued interest to the accounts afterwards
+    #[arg(long)]
+    #[serde(default)]
+    pub post: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Reconciliation {
+    /// Match accounts held by the same name
+    ByName,
+    /// Open every merged account as a new one
+    Separate,
+}
+
+impl From<Reconciliation> for AccountReconciliation {
+    fn from(reconciliation: Reconciliation) -> Self {
+        match reconciliation {
+            Reconciliation::ByName => AccountReconciliation::ByName,
+            Reconciliation::Separate => AccountReconciliation::Separate,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Conflicts {
+    Combine,
+    Rename,
+    Reject,
+}
+
+impl From<Conflicts> for ConflictResolution {
+    fn from(conflicts: Conflicts) -> Self {
+        match conflicts {
+            Conflicts::Combine => ConflictResolution::Combine,
+            Conflicts::Rename => ConflictResolution::Rename,
+            Conflicts::Reject => ConflictResolution::Reject,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum CreditLineChoice {
+    Keep,
+    Max,
+    Min,
+    Sum,
+}
+
+impl From<CreditLineChoice> for CreditLines {
+    fn from(choice: CreditLineChoice) -> Self {
+        match choice {
+            CreditLineChoice::Keep => CreditLines::Keep,
+            CreditLineChoice::Max => CreditLines::Max,
+            CreditLineChoice::Min => CreditLines::Min,
+            CreditLineChoice::Sum => CreditLines::Sum,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+}
+
+/// Parses an account id such as `3` or `#3`
+fn parse_account(text: &str) -> Result<AccountId, String> {
+    text.strip_prefix('#')
+        .unwrap_or(text)
+        .parse()
+        .map(AccountId::new)
+        .map_err(|_| format!("'{text}' is not an account id"))
+}
@@ -1,3 +1,449 @@
-fn main() {
-    println!("Hello, world!");
+mod cli;
+mod output;
+mod repl;
+mod server;
+
+use clap::Parser;
+use cli::{AccrueArgs, Cli, Command, ExportFormat, OpenArgs};
+use output::{Align, Amount, Output, Table};
+use p32::bulk::FileFormat;
+use p32::currency::Currency;
+use p32::error::ErrorCode;
+use p32::ledger::{EntryKind, LedgerEntry};
+use p32::merge::{MergeAction, MergePolicy, MergeReport};
+use p32::money::{BasisPoints, Money};
+use p32::snapshot::SnapshotFormat;
+use p32::{AccountId, Bank, TransferFundsError, User};
+use serde::Serialize;
+use serde_json::json;
+use std::error::Error;
+use std::fs::File;
+use std::process::ExitCode;
+
+/// Why a command failed
+enum CliError {
+    /// The bank refused a transfer, which has an exit code of its own
+    Transfer(TransferFundsError),
+    Other(Box<dyn Error>),
+}
+
+impl CliError {
+    fn message(message: String) -> Self {
+        CliError::Other(message.into())
+    }
+
+    fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Transfer(error) => transfer_exit_code(error.code()),
+            CliError::Other(_) => 1,
+        }
+    }
+
+    fn print(&self, json: bool) {
+        let (code, message) = match self {
+            CliError::Transfer(error) => (Some(error.code().as_str()), error.to_string()),
+            CliError::Other(error) => (None, error.to_string()),
+        };
+        match json {
+            true => eprintln!(
+                "{}",
+                json!({ "error": { "code": code, "message": message } })
+            ),
+            false => eprintln!("error: {message}"),
+        }
+    }
+}
+
+impl<E: Error + 'static> From<E> for CliError {
+    fn from(error: E) -> Self {
+        CliError::Other(Box::new(error))
+    }
+}
+
+/// Exit code of a refused transfer, one per error code so that scripts can tell them apart
+fn transfer_exit_code(code: ErrorCode) -> u8 {
+    match code {
+        ErrorCode::SenderNotFound => 10,
+        ErrorCode::ReceiverNotFound => 11,
+        ErrorCode::InsufficientFunds => 12,
+        ErrorCode::CurrencyMismatch => 13,
+        ErrorCode::ExchangeRateUnavailable => 14,
+        ErrorCode::NonPositiveAmount => 15,
+        ErrorCode::Overflow => 16,
+        ErrorCode::SameAccount => 17,
+        ErrorCode::AccountFrozen => 18,
+        ErrorCode::AccountOverLimit => 19,
+        ErrorCode::AccountClosed => 20,
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli) {
+        Ok(output) => {
+            output.print(cli.json);
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            error.print(cli.json);
+            ExitCode::from(error.exit_code())
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<Output, CliError> {
+    if let Command::Init {
+        name,
+        credit_interest,
+        debit_interest,
+    } = &cli.command
+    {
+        if cli.state.exists() {
+            return Err(CliError::message(format!(
+                "{} already exists",
+                cli.state.display()
+            )));
+        }
+        let bank = Bank::new(
+            vec![],
+            name.clone(),
+            BasisPoints::new(*credit_interest),
+            BasisPoints::new(*debit_interest),
+        );
+        bank.save_snapshot(&cli.state, SnapshotFormat::Json)?;
+        let text = format!("Created bank '{name}' in {}\n", cli.state.display());
+        return Ok(Output::new(&json!({ "name": name }), text));
+    }
+
+    match &cli.command {
+        Command::Repl => return repl::run(&cli.state, cli.json),
+        Command::Serve {
+            listen,
+            idempotency_keys,
+        } => return server::run(&cli.state, *listen, *idempotency_keys),
+        _ => {}
+    }
+
+    let mut bank = Bank::load_snapshot(&cli.state)?;
+    let output = match &cli.command {
+        Command::Init { .. } | Command::Repl | Command::Serve { .. } => {
+            unreachable!("handled above")
+        }
+        Command::Open(args) => open(&mut bank, args)?,
+        Command::Transfer {
+            sender,
+            receiver,
+            amount,
+            currency,
+        } => transfer(&mut bank, *sender, *receiver, amount, *currency)?,
+        Command::Accrue(args) => accrue(&mut bank, args)?,
+        Command::BalanceSheet => return balance_sheet(&bank),
+        Command::Merge {
+            other_state,
+            reconciliation,
+            conflicts,
+            credit_lines,
+        } => {
+            let other = Bank::load_snapshot(other_state)?;
+            let policy = MergePolicy {
+                reconciliation: (*reconciliation).into(),
+                credit_lines: (*credit_lines).into(),
+                conflicts: (*conflicts).into(),
+            };
+            merged(&bank.merge_bank(other, &policy)?)
+        }
+        Command::Export { format, output } => {
+            let format = match format {
+                ExportFormat::Csv => FileFormat::Csv,
+                ExportFormat::Jsonl => FileFormat::JsonLines,
+            };
+            return match output {
+                Some(path) => {
+                    let count = bank.export_users(File::create(path)?, format)?;
+                    let text = format!("Exported {count} accounts to {}\n", path.display());
+                    Ok(Output::new(&json!({ "accounts": count }), text))
+                }
+                None => {
+                    bank.export_users(std::io::stdout().lock(), format)?;
+                    Ok(Output::none())
+                }
+            };
+        }
+    };
+    bank.save_snapshot(&cli.state, SnapshotFormat::Json)?;
+    Ok(output)
+}
+
+fn open(bank: &mut Bank, args: &OpenArgs) -> Result<Output, CliError> {
+    let credit_line = parse_amount(&args.credit_line, args.currency)?;
+    let balance = parse_amount(&args.balance, args.currency)?;
+    let account = bank.open_account(User::new(args.name.clone(), credit_line, balance))?;
+    let text = format!("Opened account {account} for '{}'\n", args.name);
+    let json = json!({
+        "account": account.value(),
+        "name": args.name,
+        "credit_line": Amount::from(credit_line),
+        "balance": Amount::from(balance),
+    });
+    Ok(Output::new(&json, text))
+}
+
+/// Transfers `amount`, in the home currency of the sender unless `currency` is given
+fn transfer(
+    bank: &mut Bank,
+    sender: AccountId,
+    receiver: AccountId,
+    amount: &str,
+    currency: Option<Currency>,
+) -> Result<Output, CliError> {
+    let home_currency = bank.user(sender).map(User::currency);
+    let Some(currency) = currency.or(home_currency) else {
+        let error = TransferFundsError::SenderNotExistsError { account: sender };
+        return Err(CliError::Transfer(error));
+    };
+    let amount = parse_amount(amount, currency)?;
+    bank.transfer_funds(sender, receiver, amount)
+        .map_err(CliError::Transfer)?;
+    let entry = bank.ledger().entries().last();
+    Ok(transferred(entry.expect("the transfer is recorded")))
+}
+
+/// Without a range, interest is posted as it accrues, so the output lists the interest entries
+/// the call recorded
+fn accrue(bank: &mut Bank, args: &AccrueArgs) -> Result<Output, CliError> {
+    let Some((from, to)) = args.from.zip(args.to) else {
+        let recorded = bank.ledger().entries().len();
+        bank.accrue_interest();
+        if args.post {
+            bank.post_accrued_interest();
+        }
+        let posted = bank.ledger().entries()[recorded..]
+            .iter()
+            .filter(|entry| entry.kind() == EntryKind::InterestAccrual)
+            .filter_map(|entry| Some((entry.receiver()?, entry.amount())));
+        return Ok(interest(bank, posted.collect(), true));
+    };
+    bank.accrue_interest_between(from, to)?;
+    let output = accrued(bank, args.post);
+    if args.post {
+        bank.post_accrued_interest();
+    }
+    Ok(output)
+}
+
+fn parse_amount(text: &str, currency: Currency) -> Result<Money, CliError> {
+    Money::from_decimal(text, currency)
+        .map_err(|error| CliError::message(format!("invalid amount '{text}': {error}")))
+}
+
+/// The transfer recorded by `entry`, with the balances it left
+fn transferred(entry: &LedgerEntry) -> Output {
+    let sender = entry.sender().expect("transfers have a sender");
+    let receiver = entry.receiver().expect("transfers have a receiver");
+    let (sender_balance, receiver_balance) = (entry.sender_balance(), entry.receiver_balance());
+    let mut table = Table::new(&[("account", Align::Left), ("balance", Align::Right)]);
+    for (account, balance) in [(sender, sender_balance), (receiver, receiver_balance)] {
+        let balance = balance.map_or_else(String::new, |balance| balance.to_string());
+        table.row(vec![account.to_string(), balance]);
+    }
+    let text = format!(
+        "Transferred {} from {sender} to {receiver}\n\n{table}",
+        entry.amount()
+    );
+    let json = json!({
+        "sender": sender.value(),
+        "receiver": receiver.value(),
+        "amount": Amount::from(entry.amount()),
+        "received_amount": Amount::from(entry.received_amount()),
+        "sender_balance": sender_balance.map(Amount::from),
+        "receiver_balance": receiver_balance.map(Amount::from),
+    });
+    Output::new(&json, text)
+}
+
+/// An account as JSON, balance and credit line in its home currency
+#[derive(Serialize)]
+struct AccountSummary {
+    account: u64,
+    name: String,
+    balance: Amount,
+    credit_line: Amount,
+    state: String,
+}
+
+impl AccountSummary {
+    fn new(account: AccountId, user: &User) -> Self {
+        AccountSummary {
+            account: account.value(),
+            name: user.name().to_string(),
+            balance: user.balance(user.currency()).into(),
+            credit_line: user.credit_line().into(),
+            state: format!("{:?}", user.state()),
+        }
+    }
+}
+
+fn accounts(bank: &Bank) -> Output {
+    let mut table = Table::new(&[
+        ("account", Align::Left),
+        ("name", Align::Left),
+        ("balance", Align::Right),
+        ("credit line", Align::Right),
+        ("state", Align::Left),
+    ]);
+    let mut accounts = vec![];
+    for account in bank.account_ids() {
+        let user = bank.user(account).expect("the account exists");
+        table.row(vec![
+            account.to_string(),
+            user.name().to_string(),
+            user.balance(user.currency()).to_string(),
+            user.credit_line().to_string(),
+            format!("{:?}", user.state()),
+        ]);
+        accounts.push(AccountSummary::new(account, user));
+    }
+    Output::new(&accounts, table)
+}
+
+#[derive(Serialize)]
+struct AccruedInterest {
+    account: u64,
+    name: String,
+    interest: Amount,
+}
+
+/// Interest accrued by every account in its home currency, which is about to be posted if
+/// `posting`
+fn accrued(bank: &Bank, posting: bool) -> Output {
+    let accrued = bank
+        .account_ids()
+        .into_iter()
+        .map(|account| {
+            let user = bank.user(account).expect("the account exists");
+            (account, bank.accrued_interest(account, user.currency()))
+        })
+        .collect();
+    interest(bank, accrued, posting)
+}
+
+/// The non-zero interest of accounts, which was posted if `posted`
+fn interest(bank: &Bank, interest: Vec<(AccountId, Money)>, posted: bool) -> Output {
+    let mut table = Table::new(&[
+        ("account", Align::Left),
+        ("name", Align::Left),
+        ("interest", Align::Right),
+    ]);
+    let mut accounts = vec![];
+    for (account, interest) in interest {
+        if interest.is_zero() {
+            continue;
+        }
+        let user = bank.user(account).expect("the account exists");
+        table.row(vec![
+            account.to_string(),
+            user.name().to_string(),
+            interest.to_string(),
+        ]);
+        accounts.push(AccruedInterest {
+            account: account.value(),
+            name: user.name().to_string(),
+            interest: interest.into(),
+        });
+    }
+    let text = match posted {
+        true => format!("Posted interest\n\n{table}"),
+        false => format!("Accrued interest not posted yet\n\n{table}"),
+    };
+    Output::new(&json!({ "posted": posted, "accounts": accounts }), text)
+}
+
+#[derive(Serialize)]
+struct BalanceSheetRow {
+    currency: Currency,
+    assets: Amount,
+    liabilities: Amount,
+    equity: Amount,
+    balanced: bool,
+}
+
+fn balance_sheet(bank: &Bank) -> Result<Output, CliError> {
+    let mut table = Table::new(&[
+        ("currency", Align::Left),
+        ("assets", Align::Right),
+        ("liabilities", Align::Right),
+        ("equity", Align::Right),
+        ("balanced", Align::Left),
+    ]);
+    let mut rows = vec![];
+    for (currency, sheet) in bank.calc_balance_per_currency()? {
+        table.row(vec![
+            currency.to_string(),
+            sheet.assets.to_decimal(),
+            sheet.liabilities.to_decimal(),
+            sheet.equity.to_decimal(),
+            if sheet.is_balanced() { "yes" } else { "no" }.to_string(),
+        ]);
+        rows.push(BalanceSheetRow {
+            currency,
+            assets: sheet.assets.into(),
+            liabilities: sheet.liabilities.into(),
+            equity: sheet.equity.into(),
+            balanced: sheet.is_balanced(),
+        });
+    }
+    Ok(Output::new(&rows, table))
+}
+
+#[derive(Serialize)]
+struct MergedAccount {
+    source: u64,
+    target: u64,
+    action: &'static str,
+    username: Option<String>,
+}
+
+fn merged(report: &MergeReport) -> Output {
+    let mut table = Table::new(&[
+        ("merged account", Align::Left),
+        ("account", Align::Left),
+        ("action", Align::Left),
+    ]);
+    let mut accounts = vec![];
+    for account in &report.accounts {
+        let (action, username) = match &account.action {
+            MergeAction::Combined { .. } => ("combined", None),
+            MergeAction::Renamed { username } => ("renamed", Some(username.clone())),
+            MergeAction::Opened => ("opened", None),
+            MergeAction::Conflict => ("conflict", None),
+        };
+        let described = match &username {
+            Some(username) => format!("{action} to '{username}'"),
+            None => action.to_string(),
+        };
+        table.row(vec![
+            account.source.to_string(),
+            account.target.to_string(),
+            described,
+        ]);
+        accounts.push(MergedAccount {
+            source: account.source.value(),
+            target: account.target.value(),
+            action,
+            username,
+        });
+    }
+    let discarded_interest = report.discarded_interest.map(|(credit, debit)| {
+        json!({ "credit_interest": credit.value(), "debit_interest": debit.value() })
+    });
+    let mut text = table.to_string();
+    if let Some((credit, debit)) = report.discarded_interest {
+        text.push_str(&format!(
+            "\nDiscarded the interest rates of the merged bank: {} credit, {} debit basis points\n",
+            credit.value(),
+            debit.value()
+        ));
+    }
+    let json = json!({ "accounts": accounts, "discarded_interest": discarded_interest });
+    Output::new(&json, text)
 }
@@ -0,0 +1,134 @@
+use p32::currency::Currency;
+use p32::money::Money;
+use serde::Serialize;
+use std::fmt;
+
+/// What a command prints: a table, or the same data as JSON
+pub struct Output {
+    json: serde_json::Value,
+    text: String,
+}
+
+impl Output {
+    pub fn new(json: &impl Serialize, text: impl fmt::Display) -> Self {
+        Output {
+            json: serde_json::to_value(json).expect("output serializes to JSON"),
+            text: text.to_string(),
+        }
+    }
+
+    /// Nothing to print, e.g. when the command wrote its output itself
+    pub fn none() -> Self {
+        Output {
+            json: serde_json::Value::Null,
+            text: String::new(),
+        }
+    }
+
+    pub fn into_json(self) -> serde_json::Value {
+        self.json
+    }
+
+    pub fn print(&self, json: bool) {
+        match json {
+            true if !self.json.is_null() => println!("{}", self.json),
+            false if !self.text.is_empty() => print!("{}", self.text),
+            _ => {}
+        }
+    }
+}
+
+/// An amount as JSON: a decimal string, which keeps every digit, and its currency
+#[derive(Serialize)]
+pub struct Amount {
+    amount: String,
+    currency: Currency,
+}
+
+impl From<Money> for Amount {
+    fn from(money: Money) -> Self {
+        Amount {
+            amount: money.to_decimal(),
+            currency: money.currency(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+/// Plain text table whose columns are as wide as their widest cell
+pub struct Table {
+    columns: Vec<(&'static str, Align)>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(columns: &[(&'static str, Align)]) -> Self {
+        Table {
+            columns: columns.to_vec(),
+            rows: vec![],
+        }
+    }
+
+    pub fn row(&mut self, cells: Vec<String>) {
+        debug_assert_eq!(cells.len(), self.columns.len());
+        self.rows.push(cells);
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(index, (header, _))| {
+                self.rows
+                    .iter()
+                    .map(|row| row[index].chars().count())
+                    .chain([header.len()])
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+        let headers = self.columns.iter().map(|(header, _)| header.to_string());
+        let rule = widths.iter().map(|width| "-".repeat(*width));
+        for cells in [headers.collect(), rule.collect()].iter().chain(&self.rows) {
+            let line: Vec<String> = cells
+                .iter()
+                .zip(&self.columns)
+                .zip(&widths)
+                .map(|((cell, (_, align)), width)| match align {
+                    Align::Left => format!("{cell:<width$}"),
+                    Align::Right => format!("{cell:>width$}"),
+                })
+                .collect();
+            writeln!(f, "{}", line.join("  ").trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columns_fit_their_widest_cell() {
+        let mut table = Table::new(&[("account", Align::Left), ("balance", Align::Right)]);
+        table.row(vec!["#0".to_string(), "1.00 EUR".to_string()]);
+        table.row(vec!["#10".to_string(), "-100.00 EUR".to_string()]);
+
+        assert_eq!(
+            table.to_string(),
+            "account      balance\n\
+             -------  -----------\n\
+             #0          1.00 EUR\n\
+             #10      -100.00 EUR\n"
+        );
+    }
+}
@@ -0,0 +1,504 @@
+use crate::cli::{AccrueArgs, OpenArgs};
+use crate::output::Output;
+use crate::{CliError, accounts, accrue, balance_sheet, open, transfer};
+use clap::{CommandFactory, Parser, Subcommand};
+use p32::currency::Currency;
+use p32::money::BasisPoints;
+use p32::snapshot::{BankSnapshot, SnapshotFormat};
+use p32::{AccountId, Bank};
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use serde_json::json;
+use std::collections::{BTreeSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+const META_COMMANDS: &str = "\
+Meta commands:
+  :save [path]  Write the bank to the state file, or to path
+  :load [path]  Replace the bank with the one in the state file, or in path
+  :undo         Revert the last change, up to 100 changes back
+  :quit         Leave, without saving";
+
+/// Meta commands, which act on the session instead of the bank
+const META: [&str; 4] = [":save", ":load", ":undo", ":quit"];
+
+/// How many changes `:undo` can revert, since each keeps a snapshot of the whole bank
+const UNDO_LIMIT: usize = 100;
+
+/// A statement typed at the prompt
+#[derive(Parser)]
+#[command(multicall = true, after_help = META_COMMANDS)]
+struct Line {
+    #[command(subcommand)]
+    statement: Statement,
+}
+
+#[derive(Subcommand)]
+enum Statement {
+    /// Open an account
+    Open(OpenArgs),
+    /// Move funds between two accounts, each given by username or as #id
+    Transfer {
+        sender: String,
+        receiver: String,
+        /// Decimal amount, such as 12.30
+        amount: String,
+        /// Currency of the amount, the home currency of the sender by default
+        #[arg(long)]
+        currency: Option<Currency>,
+    },
+    /// Accrue interest, daily or over a range of days
+    Accrue(AccrueArgs),
+    /// List every account
+    Accounts,
+    /// Show the balance sheet of every currency
+    BalanceSheet,
+}
+
+/// Runs statements read from the prompt against the bank in `state`, or against a bank without
+/// accounts if there is no such file, until `:quit` or the end of the input
+pub fn run(state: &Path, json: bool) -> Result<Output, CliError> {
+    let bank = match state.exists() {
+        true => Bank::load_snapshot(state)?,
+        false => Bank::new(
+            vec![],
+            "p12".to_string(),
+            BasisPoints::new(0),
+            BasisPoints::new(0),
+        ),
+    };
+    let mut session = Session::new(bank, state.to_path_buf());
+    let mut editor: Editor<Completion, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(Completion::default()));
+    let history = state.with_extension("history");
+    if history.exists() {
+        editor.load_history(&history)?;
+    }
+
+    loop {
+        if let Some(completion) = editor.helper_mut() {
+            completion.usernames = session.usernames();
+        }
+        let line = match editor.readline("p12> ") {
+            Ok(line) => line,
+            // Ctrl-C abandons the line being typed
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(error) => return Err(error.into()),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str())?;
+        if line.trim() == ":quit" {
+            break;
+        }
+        match session.execute(&line) {
+            Ok(output) => output.print(json),
+            Err(error) => error.print(json),
+        }
+    }
+    editor.save_history(&history)?;
+    Ok(Output::none())
+}
+
+/// The bank being worked on, and what is needed to undo changes to it
+struct Session {
+    bank: Bank,
+    /// Snapshots taken before the last [`UNDO_LIMIT`] changes, the latest last
+    undo: VecDeque<BankSnapshot>,
+    /// State file that `:save` and `:load` use by default
+    state: PathBuf,
+}
+
+impl Session {
+    fn new(bank: Bank, state: PathBuf) -> Self {
+        Session {
+            bank,
+            undo: VecDeque::new(),
+            state,
+        }
+    }
+
+    fn execute(&mut self, line: &str) -> Result<Output, CliError> {
+        let words = split_words(line)?;
+        let path = || match &words[1..] {
+            [] => Ok(self.state.clone()),
+            [path] => Ok(PathBuf::from(path)),
+            _ => Err(CliError::message(format!("{} takes one path", words[0]))),
+        };
+        match words.first().map(String::as_str) {
+            None => Ok(Output::none()),
+            Some(":save") => {
+                let path = path()?;
+                self.bank.save_snapshot(&path, SnapshotFormat::Json)?;
+                let json = json!({ "saved": path });
+                Ok(Output::new(&json, format!("Saved to {}\n", path.display())))
+            }
+            Some(":load") => {
+                let path = path()?;
+                let bank = Bank::load_snapshot(&path)?;
+                self.remember(self.bank.snapshot());
+                self.bank = bank;
+                let json = json!({ "loaded": path });
+                Ok(Output::new(&json, format!("Loaded {}\n", path.display())))
+            }
+            Some(":undo") => {
+                let snapshot = self
+                    .undo
+                    .pop_back()
+                    .ok_or_else(|| CliError::message("nothing to undo".into()))?;
+                self.bank.revert_to(snapshot)?;
+                let json = json!({ "undone": true });
+                Ok(Output::new(&json, "Reverted the last change\n"))
+            }
+            Some(meta) if meta.starts_with(':') => Err(CliError::message(format!(
+                "unknown command '{meta}', expected one of {}",
+                META.join(", ")
+            ))),
+            Some(_) => self.statement(&words),
+        }
+    }
+
+    fn statement(&mut self, words: &[String]) -> Result<Output, CliError> {
+        let statement = match Line::try_parse_from(words) {
+            Ok(line) => line.statement,
+            // `help` and `--help` are not mistakes
+            Err(error) if !error.use_stderr() => {
+                let help = error.render().to_string();
+                return Ok(Output::new(&json!({ "help": help }), help));
+            }
+            Err(error) => {
+                let message = error.render().to_string();
+                let message = message.strip_prefix("error: ").unwrap_or(&message);
+                return Err(CliError::message(message.trim_end().to_string()));
+            }
+        };
+        match statement {
+            Statement::Open(args) => self.change(|bank| open(bank, &args)),
+            Statement::Transfer {
+                sender,
+                receiver,
+                amount,
+                currency,
+            } => {
+                let sender = self.account(&sender)?;
+                let receiver = self.account(&receiver)?;
+                self.change(|bank| transfer(bank, sender, receiver, &amount, currency))
+            }
+            Statement::Accrue(args) => self.change(|bank| accrue(bank, &args)),
+            Statement::Accounts => Ok(accounts(&self.bank)),
+            Statement::BalanceSheet => balance_sheet(&self.bank),
+        }
+    }
+
+    /// Applies a change to the bank, which `:undo` reverts unless it failed
+    fn change(
+        &mut self,
+        apply: impl FnOnce(&mut Bank) -> Result<Output, CliError>,
+    ) -> Result<Output, CliError> {
+        let before = self.bank.snapshot();
+        let output = apply(&mut self.bank)?;
+        self.remember(before);
+        Ok(output)
+    }
+
+    /// Keeps `before` for `:undo`, forgetting the oldest snapshot past [`UNDO_LIMIT`]
+    fn remember(&mut self, before: BankSnapshot) {
+        if self.undo.len() == UNDO_LIMIT {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(before);
+    }
+
+    /// The account given as `#id`, or the only account held by a username
+    fn account(&self, reference: &str) -> Result<AccountId, CliError> {
+        if let Some(id) = reference.strip_prefix('#') {
+            return id
+                .parse()
+                .map(AccountId::new)
+                .map_err(|_| CliError::message(format!("'{reference}' is not an account id")));
+        }
+        match self.bank.accounts_named(reference)[..] {
+            [account] => Ok(account),
+            [] => Err(CliError::message(format!(
+                "no account is held by '{reference}'"
+            ))),
+            ref accounts => {
+                let ids: Vec<String> = accounts.iter().map(AccountId::to_string).collect();
+                Err(CliError::message(format!(
+                    "'{reference}' holds accounts {}, pick one by id",
+                    ids.join(", ")
+                )))
+            }
+        }
+    }
+
+    fn usernames(&self) -> BTreeSet<String> {
+        self.bank
+            .account_ids()
+            .into_iter()
+            .filter_map(|account| self.bank.user(account))
+            .map(|user| user.name().to_string())
+            .collect()
+    }
+}
+
+/// Splits a line into words at whitespace outside of single or double quotes
+///
+/// Unlike a shell, `#` does not start a comment, so that accounts can be given as `#3`.
+fn split_words(line: &str) -> Result<Vec<String>, CliError> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in line.chars() {
+        match (quote, c) {
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_default();
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (Some(open), c) if c == open => quote = None,
+            (_, c) => word.get_or_insert_default().push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(CliError::message("unbalanced quotes".into()));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Completes statements and meta commands, then usernames, or paths after `:save` and `:load`
+#[derive(Default)]
+struct Completion {
+    usernames: BTreeSet<String>,
+    files: FilenameCompleter,
+}
+
+impl Completion {
+    /// Start of the word before `pos` and the words it may be completed to, unless it is a path
+    fn candidates(&self, line: &str, pos: usize) -> Option<(usize, Vec<String>)> {
+        let typed = &line[..pos];
+        let start = typed
+            .char_indices()
+            .rfind(|(_, c)| c.is_whitespace())
+            .map_or(0, |(space, c)| space + c.len_utf8());
+        let word = &typed[start..];
+        let words: Vec<String> = match typed[..start].split_whitespace().next() {
+            None => {
+                let mut line = Line::command();
+                line.build();
+                let statements = line.get_subcommands().map(|statement| statement.get_name());
+                statements.chain(META).map(str::to_string).collect()
+            }
+            Some(":save" | ":load") => return None,
+            Some(_) => self
+                .usernames
+                .iter()
+                .map(|name| match name.contains(char::is_whitespace) {
+                    true => format!("'{name}'"),
+                    false => name.clone(),
+                })
+                .collect(),
+        };
+        let words = words
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(word));
+        Some((start, words.collect()))
+    }
+}
+
+impl Completer for Completion {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        context: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let Some((start, words)) = self.candidates(line, pos) else {
+            return self.files.complete(line, pos, context);
+        };
+        let pairs = words.into_iter().map(|word| Pair {
+            display: word.clone(),
+            replacement: word,
+        });
+        Ok((start, pairs.collect()))
+    }
+}
+
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p32::User;
+    use p32::money::Money;
+
+    fn session() -> Session {
+        let eur = |amount| Money::from_major(amount, Currency::EUR).unwrap();
+        let users = vec![
+            User::new("alice".to_string(), eur(0), eur(100)),
+            User::new("bob".to_string(), eur(50), eur(0)),
+            User::new("Carol Smith".to_string(), eur(0), eur(0)),
+        ];
+        let bank = Bank::new(
+            users,
+            "Bank".to_string(),
+            BasisPoints::new(0),
+            BasisPoints::new(0),
+        );
+        Session::new(bank, PathBuf::from("bank.json"))
+    }
+
+    fn balance(session: &Session, account: u64) -> String {
+        let user = session.bank.user(AccountId::new(account)).unwrap();
+        user.balance(Currency::EUR).to_decimal()
+    }
+
+    #[test]
+    fn transfers_between_usernames_and_ids() {
+        let mut session = session();
+
+        session.execute("transfer alice bob 30").ok().unwrap();
+        session
+            .execute("transfer #1 'Carol Smith' 12.5")
+            .ok()
+            .unwrap();
+
+        assert_eq!(balance(&session, 0), "70.00");
+        assert_eq!(balance(&session, 1), "17.50");
+        assert_eq!(balance(&session, 2), "12.50");
+    }
+
+    #[test]
+    fn accounts_must_be_unambiguous() {
+        let mut session = session();
+        session.execute("open alice").ok().unwrap();
+
+        let error = session.execute("transfer alice bob 1").err().unwrap();
+        assert!(matches!(error, CliError::Other(error)
+            if error.to_string() == "'alice' holds accounts #0, #3, pick one by id"));
+        assert!(session.execute("transfer dave bob 1").is_err());
+        session.execute("transfer #3 bob 1").err().unwrap();
+        session.execute("transfer #0 bob 1").ok().unwrap();
+    }
+
+    #[test]
+    fn undo_reverts_changes_in_reverse_order() {
+        let mut session = session();
+        session.execute("transfer alice bob 30").ok().unwrap();
+        session.execute("open dave --balance 5").ok().unwrap();
+        // Failures change nothing, so there is nothing to undo
+        session.execute("transfer bob alice 1000").err().unwrap();
+
+        session.execute(":undo").ok().unwrap();
+        assert_eq!(session.bank.account_ids().len(), 3);
+        assert_eq!(balance(&session, 1), "30.00");
+        session.execute(":undo").ok().unwrap();
+        assert_eq!(balance(&session, 1), "0.00");
+        assert!(session.execute(":undo").is_err());
+    }
+
+    #[test]
+    fn undo_forgets_the_oldest_changes() {
+        let mut session = session();
+        for change in 0..=UNDO_LIMIT {
+            let transfer = match change % 2 {
+                0 => "transfer alice bob 1",
+                _ => "transfer bob alice 1",
+            };
+            session.execute(transfer).ok().unwrap();
+        }
+
+        for _ in 0..UNDO_LIMIT {
+            session.execute(":undo").ok().unwrap();
+        }
+        assert!(session.execute(":undo").is_err());
+        assert_eq!(balance(&session, 1), "1.00");
+    }
+
+    #[test]
+    fn save_and_load() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut session = session();
+        session.state = directory.path().join("bank.json");
+        let copy = directory.path().join("copy.json");
+
+        session.execute("transfer alice bob 30").ok().unwrap();
+        session.execute(":save").ok().unwrap();
+        session
+            .execute(&format!(":save {}", copy.display()))
+            .ok()
+            .unwrap();
+        session.execute("transfer alice bob 30").ok().unwrap();
+        session.execute(":load").ok().unwrap();
+        assert_eq!(balance(&session, 1), "30.00");
+
+        // Loading is a change too
+        session.execute(":undo").ok().unwrap();
+        assert_eq!(balance(&session, 1), "60.00");
+        assert!(session.execute(":load missing.json").is_err());
+        assert!(session.execute(":frobnicate").is_err());
+    }
+
+    #[test]
+    fn quotes_group_words() {
+        let words = |line| split_words(line).ok().unwrap();
+
+        assert_eq!(
+            words("  transfer #1  bob 2 "),
+            ["transfer", "#1", "bob", "2"]
+        );
+        assert_eq!(words("open 'Carol Smith'"), ["open", "Carol Smith"]);
+        assert_eq!(words(r#"open "O'Brien" ''"#), ["open", "O'Brien", ""]);
+        assert!(split_words("open 'Carol").is_err());
+    }
+
+    #[test]
+    fn completes_statements_then_usernames() {
+        let completion = Completion {
+            usernames: session().usernames(),
+            ..Completion::default()
+        };
+        let candidates = |line: &str| completion.candidates(line, line.len());
+
+        assert_eq!(
+            candidates("a"),
+            Some((0, vec!["accrue".to_string(), "accounts".to_string()]))
+        );
+        assert_eq!(candidates(":u"), Some((0, vec![":undo".to_string()])));
+        assert_eq!(
+            candidates("transfer alice "),
+            Some((
+                15,
+                vec![
+                    "'Carol Smith'".to_string(),
+                    "alice".to_string(),
+                    "bob".to_string()
+                ]
+            ))
+        );
+        assert_eq!(candidates("transfer b"), Some((9, vec!["bob".to_string()])));
+        assert_eq!(candidates(":load ba"), None);
+        assert_eq!(
+            candidates("transfer\u{a0}b"),
+            Some((10, vec!["bob".to_string()]))
+        );
+    }
+}
@@ -0,0 +1,529 @@
+use crate::cli::{AccrueArgs, OpenArgs};
+use crate::output::Output;
+use crate::{
+    AccountSummary, CliError, accounts, accrue, balance_sheet, merged, open, transfer, transferred,
+};
+use axum::extract::rejection::{JsonRejection, PathRejection};
+use axum::extract::{Path as UrlPath, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use p32::currency::Currency;
+use p32::error::{ErrorCode, InterestError, MergeError};
+use p32::ledger::EntryKind;
+use p32::merge::MergePolicy;
+use p32::money::{Money, MoneyError};
+use p32::snapshot::{BankSnapshot, SnapshotFormat, write_durably};
+use p32::{AccountId, Bank};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Header naming the transfer a `POST /transfers` is a retry of
+const IDEMPOTENCY_KEY: &str = "idempotency-key";
+/// Header set on responses replayed for a known idempotency key
+const IDEMPOTENT_REPLAYED: &str = "idempotent-replayed";
+
+/// Serves the bank in `state` on `listen` until interrupted, remembering the outcomes of the
+/// last `idempotency_keys` transfers made with an idempotency key
+pub fn run(
+    state: &Path,
+    listen: SocketAddr,
+    idempotency_keys: NonZeroUsize,
+) -> Result<Output, CliError> {
+    let bank = Bank::load_snapshot(state)?;
+    let path = state.with_extension("idempotency.json");
+    let replays = Replays::load(path, idempotency_keys, &bank)?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind(listen).await?;
+        println!("Listening on http://{}", listener.local_addr()?);
+        axum::serve(listener, router(bank, state.to_path_buf(), replays))
+            .with_graceful_shutdown(async {
+                tokio::signal::ctrl_c().await.ok();
+            })
+            .await
+    })?;
+    Ok(Output::none())
+}
+
+/// Routes every endpoint. Bodies are JSON shaped like the output of `--json`, amounts are
+/// decimal strings and errors are typed by the codes of `ErrorCode` where transfers fail, and by
+/// codes of their own where merges, accruals and balance sheets fail.
+fn router(bank: Bank, state: PathBuf, replays: Replays) -> Router {
+    let server = Server {
+        bank,
+        state,
+        replays,
+    };
+    Router::new()
+        .route("/accounts", get(list_accounts).post(open_account))
+        .route("/accounts/{id}", get(get_account))
+        .route("/transfers", post(transfer_funds))
+        .route("/interest/accruals", post(accrue_interest))
+        .route("/balance-sheet", get(get_balance_sheet))
+        .route("/merges", post(merge_bank))
+        .fallback(|| async {
+            ApiError::new(StatusCode::NOT_FOUND, "NOT_FOUND", "no such endpoint")
+        })
+        .with_state(Arc::new(Mutex::new(server)))
+}
+
+/// The bank served, shared by every request
+struct Server {
+    bank: Bank,
+    state: PathBuf,
+    replays: Replays,
+}
+
+/// Outcomes of the latest transfers made with an idempotency key, saved next to the state file
+/// so that retries are answered across restarts.
+///
+/// A key is saved before its transfer is made, and its outcome once the transfer is saved. A key
+/// left without an outcome by a crash in between is resolved from the bank on load.
+struct Replays {
+    path: PathBuf,
+    capacity: NonZeroUsize,
+    /// From the oldest to the newest
+    replays: VecDeque<Replay>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Replay {
+    key: String,
+    request: TransferRequest,
+    /// How many ledger entries the bank had before the transfer
+    #[serde(default)]
+    ledger_entries: usize,
+    /// Missing until the transfer is made or refused
+    #[serde(flatten)]
+    outcome: Option<Outcome>,
+}
+
+/// The response to a transfer, which retries get again
+#[derive(Serialize, Deserialize)]
+struct Outcome {
+    #[serde(with = "status_code")]
+    status: StatusCode,
+    body: Value,
+}
+
+impl Replays {
+    fn load(path: PathBuf, capacity: NonZeroUsize, bank: &Bank) -> Result<Self, CliError> {
+        let mut replays: VecDeque<Replay> = match std::fs::read(&path) {
+            Ok(replays) => serde_json::from_slice(&replays)?,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => VecDeque::new(),
+            Err(error) => return Err(error.into()),
+        };
+        replays.retain_mut(|replay| replay.outcome.is_some() || replay.resolve(bank));
+        if let Some(forgotten) = replays.len().checked_sub(capacity.get()) {
+            replays.drain(..forgotten);
+        }
+        Ok(Replays {
+            path,
+            capacity,
+            replays,
+        })
+    }
+
+    /// The transfer and outcome of `key`
+    fn get(&self, key: &str) -> Option<(&TransferRequest, &Outcome)> {
+        let replay = self.replays.iter().find(|replay| replay.key == key)?;
+        Some((&replay.request, replay.outcome.as_ref()?))
+    }
+
+    /// Remembers `replay`, forgetting the oldest one beyond the capacity, then saves them all.
+    /// Should saving fail, they are saved with the next replay.
+    fn insert(&mut self, replay: Replay) -> std::io::Result<()> {
+        if self.replays.len() == self.capacity.get() {
+            self.replays.pop_front();
+        }
+        self.replays.push_back(replay);
+        self.save()
+    }
+
+    /// Records the outcome of the transfer of `key`, then saves every replay
+    fn complete(&mut self, key: &str, outcome: Outcome) -> std::io::Result<()> {
+        if let Some(replay) = self.replays.iter_mut().rfind(|replay| replay.key == key) {
+            replay.outcome = Some(outcome);
+        }
+        self.save()
+    }
+
+    /// Forgets `key`, whose transfer was not made, then saves every replay
+    fn remove(&mut self, key: &str) -> std::io::Result<()> {
+        self.replays.retain(|replay| replay.key != key);
+        self.save()
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        write_durably(&self.path, &serde_json::to_vec(&self.replays)?)
+    }
+}
+
+impl Replay {
+    /// Gives a replay saved without an outcome the one of its transfer, if the bank recorded it
+    /// right after the ledger entries it had before. Returns whether it did; otherwise the
+    /// transfer was not made, and a retry makes it.
+    fn resolve(&mut self, bank: &Bank) -> bool {
+        let Some(entry) = bank.ledger().entries().get(self.ledger_entries) else {
+            return false;
+        };
+        let currency = self.request.currency.unwrap_or(entry.amount().currency());
+        let amount = Money::from_decimal(&self.request.amount, currency);
+        let made = entry.kind() == EntryKind::Transfer
+            && entry.sender() == Some(self.request.sender)
+            && entry.receiver() == Some(self.request.receiver)
+            && amount == Ok(entry.amount());
+        if made {
+            self.outcome = Some(Outcome {
+                status: StatusCode::CREATED,
+                body: transferred(entry).into_json(),
+            });
+        }
+        made
+    }
+}
+
+/// Status codes as numbers
+mod status_code {
+    use axum::http::StatusCode;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(status: &StatusCode, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u16(status.as_u16())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<StatusCode, D::Error> {
+        StatusCode::from_u16(u16::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+type Shared = Arc<Mutex<Server>>;
+
+fn lock(server: &Shared) -> MutexGuard<'_, Server> {
+    server
+        .lock()
+        .expect("a request panicked while changing the bank")
+}
+
+/// Runs `handle` with the server on a blocking thread, since waiting for the lock and saving the
+/// state file would hold up the requests sharing the thread of the handler
+async fn with_server<T: Send + 'static>(
+    server: Shared,
+    handle: impl FnOnce(&mut Server) -> T + Send + 'static,
+) -> T {
+    tokio::task::spawn_blocking(move || handle(&mut lock(&server)))
+        .await
+        .unwrap_or_else(|error| std::panic::resume_unwind(error.into_panic()))
+}
+
+impl Server {
+    /// Applies a change to the bank and saves it to the state file, reverting the change if it
+    /// cannot be saved
+    fn change(
+        &mut self,
+        apply: impl FnOnce(&mut Bank) -> Result<Output, CliError>,
+    ) -> Result<Value, ApiError> {
+        let before = self.bank.snapshot();
+        let output = apply(&mut self.bank)?;
+        if let Err(error) = self.bank.save_snapshot(&self.state, SnapshotFormat::Json) {
+            let reverted = self.bank.revert_to(before);
+            reverted.expect("a snapshot of the bank restores");
+            let status = StatusCode::INTERNAL_SERVER_ERROR;
+            return Err(ApiError::new(status, "STORAGE_FAILED", error.to_string()));
+        }
+        Ok(output.into_json())
+    }
+}
+
+/// A failed request, answered with `{"error": {"code", "message"}}` like the errors printed with
+/// `--json`
+struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn invalid(message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "INVALID_REQUEST", message)
+    }
+
+    fn body(&self) -> Value {
+        json!({ "error": { "code": self.code, "message": self.message } })
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body())).into_response()
+    }
+}
+
+impl From<CliError> for ApiError {
+    fn from(error: CliError) -> Self {
+        let error = match error {
+            CliError::Transfer(error) => {
+                let code = error.code();
+                return ApiError::new(transfer_status(code), code.as_str(), error.to_string());
+            }
+            CliError::Other(error) => error,
+        };
+        let (status, code) = if let Some(error) = error.downcast_ref::<MergeError>() {
+            merge_status(error)
+        } else if let Some(error) = error.downcast_ref::<InterestError>() {
+            interest_status(error)
+        } else if error.is::<MoneyError>() {
+            // Totals of the bank itself that do not fit in an amount
+            (StatusCode::INTERNAL_SERVER_ERROR, "OVERFLOW")
+        } else {
+            (StatusCode::UNPROCESSABLE_ENTITY, "INVALID_REQUEST")
+        };
+        ApiError::new(status, code, error.to_string())
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::new(
+            rejection.status(),
+            "MALFORMED_REQUEST",
+            rejection.body_text(),
+        )
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        ApiError::new(
+            rejection.status(),
+            "MALFORMED_REQUEST",
+            rejection.body_text(),
+        )
+    }
+}
+
+/// Status of a refused transfer: missing accounts are not found, accounts that cannot send the
+/// amount right now conflict with it, and the rest is a request that can never succeed
+fn transfer_status(code: ErrorCode) -> StatusCode {
+    match code {
+        ErrorCode::SenderNotFound | ErrorCode::ReceiverNotFound => StatusCode::NOT_FOUND,
+        ErrorCode::InsufficientFunds
+        | ErrorCode::AccountFrozen
+        | ErrorCode::AccountOverLimit
+        | ErrorCode::AccountClosed => StatusCode::CONFLICT,
+        ErrorCode::CurrencyMismatch
+        | ErrorCode::ExchangeRateUnavailable
+        | ErrorCode::NonPositiveAmount
+        | ErrorCode::Overflow
+        | ErrorCode::SameAccount => StatusCode::UNPROCESSABLE_ENTITY,
+    }
+}
+
+/// Status and code of a refused merge: an overflow is a request that can never succeed, and the
+/// rest conflicts with the accounts of one of the banks
+fn merge_status(error: &MergeError) -> (StatusCode, &'static str) {
+    let code = match error {
+        MergeError::Overflow { .. } => return (StatusCode::UNPROCESSABLE_ENTITY, "OVERFLOW"),
+        MergeError::AmbiguousName { .. } => "AMBIGUOUS_NAME",
+        MergeError::UnknownSourceAccount { .. } => "UNKNOWN_SOURCE_ACCOUNT",
+        MergeError::UnknownTargetAccount { .. } => "UNKNOWN_TARGET_ACCOUNT",
+        MergeError::ClosedTargetAccount { .. } => "CLOSED_TARGET_ACCOUNT",
+        MergeError::Conflicts { .. } => "MERGE_CONFLICTS",
+    };
+    (StatusCode::CONFLICT, code)
+}
+
+/// Status and code of a refused accrual: an empty range can never be accrued, and a range
+/// accrued already conflicts with the accruals made
+fn interest_status(error: &InterestError) -> (StatusCode, &'static str) {
+    match error {
+        InterestError::InvalidRange { .. } => (StatusCode::UNPROCESSABLE_ENTITY, "INVALID_RANGE"),
+        InterestError::AlreadyAccrued { .. } => (StatusCode::CONFLICT, "ALREADY_ACCRUED"),
+    }
+}
+
+async fn list_accounts(State(server): State<Shared>) -> Json<Value> {
+    Json(with_server(server, |server| accounts(&server.bank).into_json()).await)
+}
+
+async fn get_account(
+    State(server): State<Shared>,
+    id: Result<UrlPath<u64>, PathRejection>,
+) -> Result<Json<AccountSummary>, ApiError> {
+    let account = AccountId::new(id?.0);
+    with_server(server, move |server| {
+        let user = server.bank.user(account).ok_or_else(|| {
+            let message = format!("account {account} does not exist");
+            ApiError::new(StatusCode::NOT_FOUND, "ACCOUNT_NOT_FOUND", message)
+        })?;
+        Ok(Json(AccountSummary::new(account, user)))
+    })
+    .await
+}
+
+async fn open_account(
+    State(server): State<Shared>,
+    args: Result<Json<OpenArgs>, JsonRejection>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let Json(args) = args?;
+    let opened = with_server(server, move |server| {
+        server.change(|bank| open(bank, &args))
+    })
+    .await?;
+    Ok((StatusCode::CREATED, Json(opened)))
+}
+
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+struct TransferRequest {
+    sender: AccountId,
+    receiver: AccountId,
+    /// Decimal amount, such as `"12.30"`
+    amount: String,
+    /// Currency of the amount, the home currency of the sender by default
+    currency: Option<Currency>,
+}
+
+/// Transfers funds once per idempotency key: a retry with the key of an earlier transfer gets
+/// the response to that transfer, whether it succeeded or was refused
+async fn transfer_funds(
+    State(server): State<Shared>,
+    headers: HeaderMap,
+    request: Result<Json<TransferRequest>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let Json(request) = request?;
+    let key = match headers.get(IDEMPOTENCY_KEY) {
+        Some(key) => Some(key.to_str().map_err(|_| {
+            let message = "the idempotency key is not visible ASCII";
+            ApiError::new(StatusCode::BAD_REQUEST, "MALFORMED_REQUEST", message)
+        })?),
+        None => None,
+    };
+    let key = key.map(str::to_string);
+    with_server(server, move |server| transfer_once(server, key, request)).await
+}
+
+fn transfer_once(
+    server: &mut Server,
+    key: Option<String>,
+    request: TransferRequest,
+) -> Result<Response, ApiError> {
+    if let Some((made, outcome)) = key.as_deref().and_then(|key| server.replays.get(key)) {
+        if *made != request {
+            let message = "the idempotency key was used for another transfer";
+            let status = StatusCode::UNPROCESSABLE_ENTITY;
+            return Err(ApiError::new(status, "IDEMPOTENCY_KEY_REUSED", message));
+        }
+        let replayed = [(IDEMPOTENT_REPLAYED, "true")];
+        return Ok((outcome.status, replayed, Json(outcome.body.clone())).into_response());
+    }
+    // The key is saved before the transfer, so that a retry after a crash finds it
+    if let Some(key) = &key {
+        let pending = Replay {
+            key: key.clone(),
+            request: request.clone(),
+            ledger_entries: server.bank.ledger().entries().len(),
+            outcome: None,
+        };
+        if let Err(error) = server.replays.insert(pending) {
+            let status = StatusCode::INTERNAL_SERVER_ERROR;
+            return Err(ApiError::new(status, "STORAGE_FAILED", error.to_string()));
+        }
+    }
+    let transferred = server.change(|bank| {
+        let TransferRequest {
+            sender,
+            receiver,
+            amount,
+            currency,
+        } = &request;
+        transfer(bank, *sender, *receiver, amount, *currency)
+    });
+    let (status, body) = match transferred {
+        Ok(body) => (StatusCode::CREATED, body),
+        // Failures of the server are worth retrying
+        Err(error) if error.status.is_server_error() => {
+            // A key saved without an outcome is resolved on load, so it may be left behind
+            if let Some(key) = &key
+                && let Err(error) = server.replays.remove(key)
+            {
+                eprintln!("error: saving idempotency keys failed: {error}");
+            }
+            return Err(error);
+        }
+        Err(error) => (error.status, error.body()),
+    };
+    if let Some(key) = key {
+        let outcome = Outcome {
+            status,
+            body: body.clone(),
+        };
+        // The outcome is remembered even if it is not saved, and resolved on load then
+        if let Err(error) = server.replays.complete(&key, outcome) {
+            eprintln!("error: saving idempotency keys failed: {error}");
+        }
+    }
+    Ok((status, Json(body)).into_response())
+}
+
+async fn accrue_interest(
+    State(server): State<Shared>,
+    args: Result<Json<AccrueArgs>, JsonRejection>,
+) -> Result<Json<Value>, ApiError> {
+    let Json(args) = args?;
+    if args.from.is_some() != args.to.is_some() {
+        return Err(ApiError::invalid("from and to must be given together"));
+    }
+    let accrued = with_server(server, move |server| {
+        server.change(|bank| accrue(bank, &args))
+    });
+    Ok(Json(accrued.await?))
+}
+
+async fn get_balance_sheet(State(server): State<Shared>) -> Result<Json<Value>, ApiError> {
+    let sheet = with_server(server, |server| {
+        Ok::<_, ApiError>(balance_sheet(&server.bank)?.into_json())
+    });
+    Ok(Json(sheet.await?))
+}
+
+#[derive(Deserialize)]
+struct MergeRequest {
+    /// Snapshot of the bank to merge, as saved in a state file
+    bank: BankSnapshot,
+    #[serde(default)]
+    policy: MergePolicy,
+}
+
+async fn merge_bank(
+    State(server): State<Shared>,
+    request: Result<Json<MergeRequest>, JsonRejection>,
+) -> Result<Json<Value>, ApiError> {
+    let Json(MergeRequest { bank, policy }) = request?;
+    let other = Bank::restore(bank).map_err(|error| {
+        ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "INVALID_SNAPSHOT",
+            error.to_string(),
+        )
+    })?;
+    let merged = with_server(server, move |server| {
+        server.change(|bank| Ok(merged(&bank.merge_bank(other, &policy)?)))
+    });
+    Ok(Json(merged.await?))
+}
@@ -0,0 +1,283 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/// A bank state file in its own temporary directory
+struct State {
+    directory: tempfile::TempDir,
+    path: PathBuf,
+}
+
+impl State {
+    fn new() -> Self {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("bank.json");
+        State { directory, path }
+    }
+
+    /// Runs the binary on this state file
+    fn run(&self, args: &[&str]) -> Output {
+        run_on(&self.path, args)
+    }
+
+    /// Runs the binary and returns its standard output, failing the test if it fails
+    fn ok(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "{args:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    fn json(&self, args: &[&str]) -> serde_json::Value {
+        let args: Vec<&str> = args.iter().copied().chain(["--json"]).collect();
+        serde_json::from_str(&self.ok(&args)).unwrap()
+    }
+}
+
+fn run_on(state: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_p12"))
+        .arg("--state")
+        .arg(state)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// A bank with two accounts, #0 holding 100 EUR and #1 holding nothing
+fn bank() -> State {
+    let state = State::new();
+    state.ok(&["init", "Bank", "--debit-interest", "365"]);
+    state.ok(&["open", "alice", "--balance", "100"]);
+    state.ok(&["open", "bob", "--credit-line", "50"]);
+    state
+}
+
+#[test]
+fn open_and_transfer() {
+    let state = bank();
+
+    let text = state.ok(&["transfer", "0", "#1", "12.30"]);
+    assert!(text.starts_with("Transferred 12.30 EUR from #0 to #1\n"));
+    assert!(text.contains("#0       87.70 EUR"), "{text}");
+
+    let json = state.json(&["transfer", "1", "0", "60"]);
+    assert_eq!(json["sender_balance"]["amount"], "-47.70");
+    assert_eq!(json["receiver_balance"]["amount"], "147.70");
+    assert_eq!(
+        state.ok(&["export"]),
+        "id,name,currency,credit_line,balance,state\n\
+         0,alice,EUR,0.00,147.70,Active\n\
+         1,bob,EUR,50.00,-47.70,Active\n"
+    );
+}
+
+#[test]
+fn refused_transfers_have_exit_codes() {
+    let state = bank();
+    let cases = [
+        (
+            &["transfer", "0", "1", "1000"][..],
+            12,
+            "INSUFFICIENT_FUNDS",
+        ),
+        (&["transfer", "7", "1", "1"][..], 10, "SENDER_NOT_FOUND"),
+        (&["transfer", "0", "7", "1"][..], 11, "RECEIVER_NOT_FOUND"),
+        (&["transfer", "0", "0", "1"][..], 17, "SAME_ACCOUNT"),
+        (&["transfer", "0", "1", "0"][..], 15, "NON_POSITIVE_AMOUNT"),
+    ];
+
+    for (args, exit_code, error_code) in cases {
+        let args: Vec<&str> = args.iter().copied().chain(["--json"]).collect();
+        let output = state.run(&args);
+        assert_eq!(output.status.code(), Some(exit_code), "{args:?}");
+        let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+        assert_eq!(error["error"]["code"], error_code);
+    }
+    // Nothing was transferred
+    assert!(
+        state
+            .ok(&["export"])
+            .contains("0,alice,EUR,0.00,100.00,Active")
+    );
+}
+
+#[test]
+fn other_failures_exit_with_one() {
+    let state = State::new();
+
+    let output = state.run(&["balance-sheet"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("error: "));
+
+    state.ok(&["init", "Bank"]);
+    assert_eq!(state.run(&["init", "Bank"]).status.code(), Some(1));
+    assert_eq!(
+        state
+            .run(&["open", "carol", "--balance", "1.234"])
+            .status
+            .code(),
+        Some(1)
+    );
+    assert_eq!(
+        state.run(&["transfer", "x", "1", "1"]).status.code(),
+        Some(2)
+    );
+}
+
+#[test]
+fn accrue_and_post_interest() {
+    let state = bank();
+
+    let json = state.json(&["accrue", "--from", "2024-01-01", "--to", "2024-01-11"]);
+    assert_eq!(json["posted"], false);
+    assert_eq!(json["accounts"][0]["account"], 0);
+    assert_eq!(json["accounts"][0]["interest"]["amount"], "0.10");
+
+    let text = state.ok(&[
+        "accrue",
+        "--from",
+        "2024-01-11",
+        "--to",
+        "2024-01-21",
+        "--post",
+    ]);
+    assert!(text.starts_with("Posted interest\n"), "{text}");
+    assert!(
+        state
+            .ok(&["export"])
+            .contains("0,alice,EUR,0.00,100.20,Active")
+    );
+    let output = state.run(&["accrue", "--from", "2024-01-01", "--to", "2024-01-05"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn accrue_without_a_range_reports_the_interest_posted() {
+    let state = bank();
+
+    let text = state.ok(&["accrue"]);
+    assert!(text.starts_with("Posted interest\n"), "{text}");
+    assert!(text.contains("alice  3.65 EUR"), "{text}");
+    let json = state.json(&["accrue"]);
+    assert_eq!(json["posted"], true);
+    assert_eq!(json["accounts"][0]["account"], 0);
+    assert_eq!(json["accounts"][0]["interest"]["amount"], "3.78");
+    assert!(
+        state
+            .ok(&["export"])
+            .contains("0,alice,EUR,0.00,107.43,Active")
+    );
+}
+
+#[test]
+fn balance_sheet_per_currency() {
+    let state = bank();
+    state.ok(&["open", "carol", "--currency", "USD", "--balance", "20"]);
+
+    let text = state.ok(&["balance-sheet"]);
+    assert_eq!(
+        text,
+        "currency  assets  liabilities  equity  balanced\n\
+         --------  ------  -----------  ------  --------\n\
+         EUR       100.00         0.00  100.00  yes\n\
+         USD        20.00         0.00   20.00  yes\n"
+    );
+    let json = state.json(&["balance-sheet"]);
+    assert_eq!(json[1]["currency"], "USD");
+    assert_eq!(json[1]["balanced"], true);
+}
+
+#[test]
+fn merge_another_state_file() {
+    let state = bank();
+    let other = State::new();
+    other.ok(&["init", "Other", "--debit-interest", "365"]);
+    other.ok(&["open", "bob", "--balance", "5"]);
+    other.ok(&["open", "dave", "--balance", "7"]);
+
+    let json = state.json(&[
+        "merge",
+        other.path.to_str().unwrap(),
+        "--credit-lines",
+        "sum",
+    ]);
+
+    assert_eq!(json["accounts"][0]["action"], "combined");
+    assert_eq!(json["accounts"][0]["target"], 1);
+    assert_eq!(json["accounts"][1]["action"], "opened");
+    assert_eq!(json["accounts"][1]["target"], 2);
+    assert_eq!(json["discarded_interest"], serde_json::Value::Null);
+    assert!(state.ok(&["export"]).ends_with(
+        "1,bob,EUR,50.00,5.00,Active\n\
+         2,dave,EUR,0.00,7.00,Active\n"
+    ));
+}
+
+#[test]
+fn export_to_a_file() {
+    let state = bank();
+    let path = state.directory.path().join("users.jsonl");
+
+    let json = state.json(&[
+        "export",
+        "--format",
+        "jsonl",
+        "--output",
+        path.to_str().unwrap(),
+    ]);
+
+    assert_eq!(json["accounts"], 2);
+    let exported = std::fs::read_to_string(path).unwrap();
+    let first: serde_json::Value = serde_json::from_str(exported.lines().next().unwrap()).unwrap();
+    assert_eq!(first["name"], "alice");
+    assert_eq!(first["balance"], "100.00");
+}
+
+#[test]
+fn repl_runs_piped_statements() {
+    let state = bank();
+    let mut repl = Command::new(env!("CARGO_BIN_EXE_p12"))
+        .arg("--state")
+        .arg(&state.path)
+        .arg("repl")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let script = "\
+        transfer alice bob 10\n\
+        transfer bob carol 1\n\
+        transfer alice #1 5\n\
+        :undo\n\
+        :save\n\
+        transfer alice bob 20\n\
+        :quit\n\
+        transfer alice bob 40\n";
+    repl.stdin
+        .take()
+        .unwrap()
+        .write_all(script.as_bytes())
+        .unwrap();
+    let output = repl.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Transferred 10.00 EUR from #0 to #1"));
+    assert!(stdout.contains("Reverted the last change"));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "error: no account is held by 'carol'\n"
+    );
+    // Only the saved changes reached the state file
+    assert!(
+        state
+            .ok(&["export"])
+            .contains("0,alice,EUR,0.00,90.00,Active")
+    );
+    let history = std::fs::read_to_string(state.path.with_extension("history")).unwrap();
+    assert!(history.contains("transfer alice bob 20"));
+}
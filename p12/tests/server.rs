@@ -0,0 +1,398 @@
+use serde_json::{Value, json};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+/// `p12 serve` on an ephemeral loopback port, killed when dropped
+struct Server {
+    process: Child,
+    url: String,
+    agent: ureq::Agent,
+    state: PathBuf,
+    args: Vec<String>,
+    _directory: tempfile::TempDir,
+}
+
+impl Server {
+    /// Serves a bank with two accounts, #0 holding 100 EUR and #1 holding nothing
+    fn start() -> Self {
+        Server::start_with(&[])
+    }
+
+    /// Like [`Server::start`], with more arguments to `p12 serve`
+    fn start_with(args: &[&str]) -> Self {
+        let directory = tempfile::tempdir().unwrap();
+        let state = directory.path().join("bank.json");
+        for args in [
+            &["init", "Bank"][..],
+            &["open", "alice", "--balance", "100"],
+            &["open", "bob", "--credit-line", "50"],
+        ] {
+            let status = Command::new(env!("CARGO_BIN_EXE_p12"))
+                .arg("--state")
+                .arg(&state)
+                .args(args)
+                .stdout(Stdio::null())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let (process, url) = serve(&state, &args);
+        let agent = ureq::Agent::config_builder()
+            .http_status_as_error(false)
+            .build()
+            .into();
+        Server {
+            process,
+            url,
+            agent,
+            state,
+            args,
+            _directory: directory,
+        }
+    }
+
+    /// Stops the server and serves the same state file again
+    fn restart(&mut self) {
+        self.process.kill().unwrap();
+        self.process.wait().unwrap();
+        (self.process, self.url) = serve(&self.state, &self.args);
+    }
+
+    fn get(&self, path: &str) -> (u16, Value) {
+        let mut response = self
+            .agent
+            .get(format!("{}{path}", self.url))
+            .call()
+            .unwrap();
+        let body = response.body_mut().read_json().unwrap();
+        (response.status().as_u16(), body)
+    }
+
+    fn post(&self, path: &str, body: Value) -> (u16, Value) {
+        self.post_with_key(path, None, body)
+    }
+
+    fn post_with_key(&self, path: &str, key: Option<&str>, body: Value) -> (u16, Value) {
+        let mut request = self.agent.post(format!("{}{path}", self.url));
+        if let Some(key) = key {
+            request = request.header("Idempotency-Key", key);
+        }
+        let mut response = request.send_json(body).unwrap();
+        let body = response.body_mut().read_json().unwrap();
+        (response.status().as_u16(), body)
+    }
+}
+
+/// Runs `p12 serve` on `state` and returns the process with the URL it listens on
+fn serve(state: &Path, args: &[String]) -> (Child, String) {
+    let mut process = Command::new(env!("CARGO_BIN_EXE_p12"))
+        .arg("--state")
+        .arg(state)
+        .args(["serve", "--listen", "127.0.0.1:0"])
+        .args(args)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(process.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let url = line
+        .trim()
+        .strip_prefix("Listening on ")
+        .unwrap()
+        .to_string();
+    (process, url)
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.process.kill().ok();
+        self.process.wait().ok();
+    }
+}
+
+#[test]
+fn accounts() {
+    let server = Server::start();
+
+    let (status, opened) = server.post(
+        "/accounts",
+        json!({ "name": "carol", "currency": "USD", "balance": "20" }),
+    );
+    assert_eq!(status, 201);
+    assert_eq!(opened["account"], 2);
+
+    let (status, accounts) = server.get("/accounts");
+    assert_eq!(status, 200);
+    assert_eq!(accounts.as_array().unwrap().len(), 3);
+    let (status, carol) = server.get("/accounts/2");
+    assert_eq!(status, 200);
+    assert_eq!(
+        carol["balance"],
+        json!({ "amount": "20.00", "currency": "USD" })
+    );
+    assert_eq!(carol["state"], "Active");
+    let exported = Command::new(env!("CARGO_BIN_EXE_p12"))
+        .arg("--state")
+        .arg(&server.state)
+        .arg("export")
+        .output()
+        .unwrap();
+    assert!(
+        String::from_utf8(exported.stdout)
+            .unwrap()
+            .ends_with("2,carol,USD,0.00,20.00,Active\n")
+    );
+
+    let (status, error) = server.get("/accounts/7");
+    assert_eq!(status, 404);
+    assert_eq!(error["error"]["code"], "ACCOUNT_NOT_FOUND");
+    let (status, error) = server.post("/accounts", json!({ "name": "dave", "balance": "x" }));
+    assert_eq!(status, 422);
+    assert_eq!(error["error"]["code"], "INVALID_REQUEST");
+    let (status, error) = server.post("/accounts", json!({ "balance": "1" }));
+    assert_eq!(status, 422);
+    assert_eq!(error["error"]["code"], "MALFORMED_REQUEST");
+}
+
+#[test]
+fn refused_transfers_have_typed_errors() {
+    let server = Server::start();
+    let cases = [
+        (
+            json!({ "sender": 0, "receiver": 1, "amount": "1000" }),
+            409,
+            "INSUFFICIENT_FUNDS",
+        ),
+        (
+            json!({ "sender": 7, "receiver": 1, "amount": "1" }),
+            404,
+            "SENDER_NOT_FOUND",
+        ),
+        (
+            json!({ "sender": 0, "receiver": 7, "amount": "1" }),
+            404,
+            "RECEIVER_NOT_FOUND",
+        ),
+        (
+            json!({ "sender": 0, "receiver": 0, "amount": "1" }),
+            422,
+            "SAME_ACCOUNT",
+        ),
+        (
+            json!({ "sender": 0, "receiver": 1, "amount": "-1" }),
+            422,
+            "NON_POSITIVE_AMOUNT",
+        ),
+        (
+            json!({ "sender": 0, "receiver": 1, "amount": "1.001" }),
+            422,
+            "INVALID_REQUEST",
+        ),
+        (
+            json!({ "sender": "alice", "receiver": 1, "amount": "1" }),
+            422,
+            "MALFORMED_REQUEST",
+        ),
+    ];
+
+    for (request, status, code) in cases {
+        let (actual, error) = server.post("/transfers", request.clone());
+        assert_eq!(
+            (actual, &error["error"]["code"]),
+            (status, &json!(code)),
+            "{request}"
+        );
+        assert!(error["error"]["message"].is_string());
+    }
+    let (_, alice) = server.get("/accounts/0");
+    assert_eq!(alice["balance"]["amount"], "100.00");
+}
+
+#[test]
+fn transfers_happen_once_per_idempotency_key() {
+    let server = Server::start();
+    let transfer = json!({ "sender": 0, "receiver": 1, "amount": "30" });
+
+    let first = server.post_with_key("/transfers", Some("a"), transfer.clone());
+    let retry = server.post_with_key("/transfers", Some("a"), transfer.clone());
+    assert_eq!(first.0, 201);
+    assert_eq!(first, retry);
+    assert_eq!(first.1["sender_balance"]["amount"], "70.00");
+
+    // Without a key every request is a new transfer
+    server.post("/transfers", transfer.clone());
+    server.post("/transfers", transfer.clone());
+    let (_, bob) = server.get("/accounts/1");
+    assert_eq!(bob["balance"]["amount"], "90.00");
+
+    // Refusals are replayed too, even once the transfer would succeed
+    let overdraft = json!({ "sender": 1, "receiver": 0, "amount": "145" });
+    let refused = server.post_with_key("/transfers", Some("b"), overdraft.clone());
+    assert_eq!(refused.0, 409);
+    server.post(
+        "/transfers",
+        json!({ "sender": 0, "receiver": 1, "amount": "10" }),
+    );
+    assert_eq!(
+        server.post_with_key("/transfers", Some("b"), overdraft),
+        refused
+    );
+
+    let (status, error) = server.post_with_key(
+        "/transfers",
+        Some("a"),
+        json!({
+            "sender": 0, "receiver": 1, "amount": "31"
+        }),
+    );
+    assert_eq!(status, 422);
+    assert_eq!(error["error"]["code"], "IDEMPOTENCY_KEY_REUSED");
+}
+
+#[test]
+fn idempotency_keys_outlive_the_server_up_to_a_limit() {
+    let mut server = Server::start_with(&["--idempotency-keys", "2"]);
+    let transfer = json!({ "sender": 0, "receiver": 1, "amount": "10" });
+    let first = server.post_with_key("/transfers", Some("a"), transfer.clone());
+    let second = server.post_with_key("/transfers", Some("b"), transfer.clone());
+
+    server.restart();
+
+    assert_eq!(
+        server.post_with_key("/transfers", Some("a"), transfer.clone()),
+        first
+    );
+    assert_eq!(
+        server.post_with_key("/transfers", Some("b"), transfer.clone()),
+        second
+    );
+    let (_, bob) = server.get("/accounts/1");
+    assert_eq!(bob["balance"]["amount"], "20.00");
+
+    // A third key makes the server forget the first one
+    server.post_with_key("/transfers", Some("c"), transfer.clone());
+    server.restart();
+    let (status, _) = server.post_with_key("/transfers", Some("a"), transfer);
+    assert_eq!(status, 201);
+    let (_, bob) = server.get("/accounts/1");
+    assert_eq!(bob["balance"]["amount"], "40.00");
+}
+
+#[test]
+fn idempotency_keys_saved_before_a_crash_are_resolved_from_the_bank() {
+    let mut server = Server::start();
+    let transfer = json!({ "sender": 0, "receiver": 1, "amount": "10" });
+    let made = server.post_with_key("/transfers", Some("a"), transfer.clone());
+
+    // As if the server crashed after saving the transfer of "a" but not its outcome, and
+    // before making the transfer of "b"
+    let path = server.state.with_extension("idempotency.json");
+    let mut replays: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    let pending = |key: &str, ledger_entries: u64| json!({ "key": key, "request": transfer, "ledger_entries": ledger_entries });
+    let ledger_entries = replays[0]["ledger_entries"].as_u64().unwrap();
+    replays[0] = pending("a", ledger_entries);
+    replays
+        .as_array_mut()
+        .unwrap()
+        .push(pending("b", ledger_entries + 1));
+    std::fs::write(&path, serde_json::to_vec(&replays).unwrap()).unwrap();
+    server.restart();
+
+    assert_eq!(
+        server.post_with_key("/transfers", Some("a"), transfer.clone()),
+        made
+    );
+    let (status, _) = server.post_with_key("/transfers", Some("b"), transfer);
+    assert_eq!(status, 201);
+    let (_, bob) = server.get("/accounts/1");
+    assert_eq!(bob["balance"]["amount"], "20.00");
+}
+
+#[test]
+fn interest_and_balance_sheet() {
+    let server = Server::start();
+    server.post(
+        "/transfers",
+        json!({ "sender": 1, "receiver": 0, "amount": "50" }),
+    );
+
+    let (status, accrued) = server.post(
+        "/interest/accruals",
+        json!({ "from": "2024-01-01", "to": "2024-02-01", "post": true }),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(accrued["posted"], true);
+    let (status, error) = server.post("/interest/accruals", json!({ "from": "2024-03-01" }));
+    assert_eq!(status, 422);
+    assert_eq!(error["error"]["code"], "INVALID_REQUEST");
+    let (status, error) = server.post(
+        "/interest/accruals",
+        json!({ "from": "2024-03-01", "to": "2024-02-01" }),
+    );
+    assert_eq!(status, 422);
+    assert_eq!(error["error"]["code"], "INVALID_RANGE");
+    let (status, error) = server.post(
+        "/interest/accruals",
+        json!({ "from": "2024-01-01", "to": "2024-02-01" }),
+    );
+    assert_eq!(status, 409);
+    assert_eq!(error["error"]["code"], "ALREADY_ACCRUED");
+
+    let (status, sheet) = server.get("/balance-sheet");
+    assert_eq!(status, 200);
+    assert_eq!(sheet[0]["currency"], "EUR");
+    assert_eq!(sheet[0]["assets"]["amount"], "150.00");
+    assert_eq!(sheet[0]["liabilities"]["amount"], "50.00");
+    assert_eq!(sheet[0]["balanced"], true);
+
+    let largest = json!({ "name": "dave", "balance": "92233720368547758.07" });
+    server.post("/accounts", largest.clone());
+    server.post("/accounts", largest);
+    let (status, error) = server.get("/balance-sheet");
+    assert_eq!(status, 500);
+    assert_eq!(error["error"]["code"], "OVERFLOW");
+}
+
+#[test]
+fn merge_a_bank_snapshot() {
+    let server = Server::start();
+    let directory = tempfile::tempdir().unwrap();
+    let other = directory.path().join("other.json");
+    for args in [&["init", "Other"][..], &["open", "bob", "--balance", "5"]] {
+        let status = Command::new(env!("CARGO_BIN_EXE_p12"))
+            .arg("--state")
+            .arg(&other)
+            .args(args)
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+    let snapshot: Value = serde_json::from_slice(&std::fs::read(other).unwrap()).unwrap();
+
+    let (status, report) = server.post(
+        "/merges",
+        json!({ "bank": snapshot, "policy": {
+            "reconciliation": "Separate", "credit_lines": "Keep", "conflicts": "Combine"
+        } }),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(report["accounts"][0]["action"], "opened");
+    assert_eq!(report["accounts"][0]["target"], 2);
+
+    let (status, report) = server.post("/merges", json!({ "bank": snapshot }));
+    assert_eq!(status, 409, "{report}");
+    assert_eq!(report["error"]["code"], "AMBIGUOUS_NAME");
+    let mut invalid = snapshot.clone();
+    invalid["next_account_id"] = json!(0);
+    let (status, report) = server.post("/merges", json!({ "bank": invalid }));
+    assert_eq!(status, 422, "{report}");
+    assert_eq!(report["error"]["code"], "INVALID_SNAPSHOT");
+    let (status, _) = server.get("/banks");
+    assert_eq!(status, 404);
+}
@@ -0,0 +1,211 @@
+//! Client of the gRPC interface of a bank, served by `p32_grpc::BankService`.
+//!
+//! Requests and replies use the types of `p32`, except for ledger entries, which only a bank can
+//! create and are returned as the messages of the schema.
+
+use chrono::NaiveDate;
+use p32::error::{ErrorCode, SnapshotError};
+use p32::merge::{MergePolicy, MergeReport};
+use p32::money::Money;
+use p32::snapshot::{BankSnapshot, SnapshotFormat};
+use p32::{AccountId, BalanceSheet};
+use p32_grpc::pb::bank_client;
+use p32_grpc::{ERROR_DOMAIN, InvalidMessage, pb};
+use std::error::Error;
+use std::fmt;
+use tonic::Status;
+use tonic::codegen::StdError;
+use tonic::transport::{Channel, Endpoint};
+use tonic_types::StatusExt;
+
+pub use p32_grpc::pb::LedgerEntry;
+
+/// Why a request to the bank failed
+#[derive(Debug)]
+pub enum ClientError {
+    /// The bank refused a transfer
+    Transfer { code: ErrorCode, message: String },
+    /// The request failed otherwise, such as an invalid request or an unreachable server
+    Status(Status),
+    /// The reply does not describe valid values
+    InvalidReply(InvalidMessage),
+    /// The snapshot of the bank to merge cannot be encoded
+    Snapshot(SnapshotError),
+}
+
+impl ClientError {
+    /// The error code of a refused transfer
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            ClientError::Transfer { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Transfer { message, .. } => f.write_str(message),
+            ClientError::Status(status) => {
+                write!(f, "{:?}: {}", status.code(), status.message())
+            }
+            ClientError::InvalidReply(error) => write!(f, "invalid reply: {error}"),
+            ClientError::Snapshot(error) => write!(f, "cannot encode the snapshot: {error}"),
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ClientError::Transfer { .. } => None,
+            ClientError::Status(status) => Some(status),
+            ClientError::InvalidReply(error) => Some(error),
+            ClientError::Snapshot(error) => Some(error),
+        }
+    }
+}
+
+/// Refused transfers are told apart by the `ErrorInfo` the server attaches to them
+impl From<Status> for ClientError {
+    fn from(status: Status) -> Self {
+        let code = status
+            .get_details_error_info()
+            .filter(|info| info.domain == ERROR_DOMAIN)
+            .and_then(|info| ErrorCode::parse(&info.reason));
+        match code {
+            Some(code) => ClientError::Transfer {
+                code,
+                message: status.message().to_string(),
+            },
+            None => ClientError::Status(status),
+        }
+    }
+}
+
+impl From<InvalidMessage> for ClientError {
+    fn from(error: InvalidMessage) -> Self {
+        ClientError::InvalidReply(error)
+    }
+}
+
+impl From<SnapshotError> for ClientError {
+    fn from(error: SnapshotError) -> Self {
+        ClientError::Snapshot(error)
+    }
+}
+
+/// Connection to a bank served over gRPC. Clones share the connection.
+#[derive(Clone)]
+pub struct BankClient {
+    inner: bank_client::BankClient<Channel>,
+}
+
+impl BankClient {
+    /// Connects to the server at `endpoint`, such as `"http://127.0.0.1:50051"`
+    pub async fn connect<D>(endpoint: D) -> Result<Self, tonic::transport::Error>
+    where
+        D: TryInto<Endpoint>,
+        D::Error: Into<StdError>,
+    {
+        Ok(BankClient::new(Endpoint::new(endpoint)?.connect().await?))
+    }
+
+    pub fn new(channel: Channel) -> Self {
+        BankClient {
+            inner: bank_client::BankClient::new(channel),
+        }
+    }
+
+    /// Transfers `amount` and returns the ledger entry of the transfer
+    pub async fn transfer_funds(
+        &self,
+        sender: AccountId,
+        receiver: AccountId,
+        amount: Money,
+    ) -> Result<LedgerEntry, ClientError> {
+        let request = pb::TransferRequest {
+            sender: sender.value(),
+            receiver: receiver.value(),
+            amount: Some(amount.into()),
+        };
+        let reply = self.inner.clone().transfer(request).await?.into_inner();
+        reply
+            .entry
+            .ok_or_else(|| InvalidMessage::new("entry is missing").into())
+    }
+
+    /// Accrues one period of interest at today's rates, then posts all accrued interest if
+    /// `post` is set and returns the entries posting recorded
+    pub async fn accrue_interest(&self, post: bool) -> Result<Vec<LedgerEntry>, ClientError> {
+        self.accrue(pb::AccrueInterestRequest {
+            from: None,
+            to: None,
+            post,
+        })
+        .await
+    }
+
+    /// Like [`BankClient::accrue_interest`], but accrues the days from `from` until `to`
+    pub async fn accrue_interest_between(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+        post: bool,
+    ) -> Result<Vec<LedgerEntry>, ClientError> {
+        self.accrue(pb::AccrueInterestRequest {
+            from: Some(from.to_string()),
+            to: Some(to.to_string()),
+            post,
+        })
+        .await
+    }
+
+    async fn accrue(
+        &self,
+        request: pb::AccrueInterestRequest,
+    ) -> Result<Vec<LedgerEntry>, ClientError> {
+        let reply = self.inner.clone().accrue_interest(request).await?;
+        Ok(reply.into_inner().posted)
+    }
+
+    /// Balance sheet of every currency the bank holds, ordered by currency
+    pub async fn calc_balance_per_currency(&self) -> Result<Vec<BalanceSheet>, ClientError> {
+        let request = pb::GetBalanceSheetRequest {};
+        let reply = self.inner.clone().get_balance_sheet(request).await?;
+        let sheets = reply.into_inner().sheets.into_iter().map(TryInto::try_into);
+        Ok(sheets.collect::<Result<_, InvalidMessage>>()?)
+    }
+
+    /// Merges the bank `other` is a snapshot of into the served bank
+    pub async fn merge_bank(
+        &self,
+        other: &BankSnapshot,
+        policy: MergePolicy,
+    ) -> Result<MergeReport, ClientError> {
+        let request = pb::MergeBankRequest {
+            snapshot: other.encode(SnapshotFormat::Binary)?,
+            policy: Some(policy.into()),
+        };
+        let reply = self.inner.clone().merge_bank(request).await?;
+        Ok(reply.into_inner().try_into()?)
+    }
+
+    /// Entries of the ledger from position `from` on. The stream ends after the last entry
+    /// recorded so far, unless `follow` is set, in which case it goes on with the entries
+    /// recorded afterwards.
+    pub async fn stream_ledger(
+        &self,
+        from: u64,
+        follow: bool,
+    ) -> Result<tonic::Streaming<LedgerEntry>, ClientError> {
+        let request = pb::StreamLedgerRequest { from, follow };
+        Ok(self
+            .inner
+            .clone()
+            .stream_ledger(request)
+            .await?
+            .into_inner())
+    }
+}
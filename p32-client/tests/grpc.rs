@@ -0,0 +1,167 @@
+use chrono::NaiveDate;
+use p32::currency::Currency;
+use p32::error::ErrorCode;
+use p32::merge::{AccountReconciliation, MergeAction, MergePolicy};
+use p32::money::{BasisPoints, Money};
+use p32::service::BankHandle;
+use p32::{AccountId, Bank, User};
+use p32_client::{BankClient, ClientError};
+use p32_grpc::BankService;
+use std::num::NonZeroUsize;
+use tokio::net::TcpListener;
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::Code;
+use tonic::transport::Server;
+
+fn eur(major: i64) -> Money {
+    Money::from_major(major, Currency::EUR).unwrap()
+}
+
+fn bank(name: &str, users: &[(&str, i64)]) -> Bank {
+    let users = users
+        .iter()
+        .map(|(username, balance)| User::new(username.to_string(), eur(50), eur(*balance)))
+        .collect();
+    Bank::new(
+        users,
+        name.to_string(),
+        BasisPoints::new(400),
+        BasisPoints::new(100),
+    )
+}
+
+/// Serves a bank with two accounts, #0 holding 100 EUR and #1 holding nothing, on an ephemeral
+/// loopback port of this process
+async fn serve() -> BankClient {
+    let (handle, _task) = BankHandle::spawn(
+        bank("Bank", &[("alice", 100), ("bob", 0)]),
+        NonZeroUsize::new(16).unwrap(),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(BankService::new(handle).into_server())
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    BankClient::connect(format!("http://{address}"))
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn transfers() {
+    let client = serve().await;
+    let (alice, bob) = (AccountId::new(0), AccountId::new(1));
+
+    let entry = client.transfer_funds(alice, bob, eur(30)).await.unwrap();
+    assert_eq!(entry.id, 2);
+    assert_eq!(entry.sender, Some(0));
+    assert_eq!(entry.receiver, Some(1));
+    assert_eq!(entry.sender_balance.unwrap().minor, 7000);
+
+    let refusals = [
+        (alice, bob, eur(1000), ErrorCode::InsufficientFunds),
+        (AccountId::new(7), bob, eur(1), ErrorCode::SenderNotFound),
+        (alice, alice, eur(1), ErrorCode::SameAccount),
+        (alice, bob, eur(-1), ErrorCode::NonPositiveAmount),
+    ];
+    for (sender, receiver, amount, code) in refusals {
+        let error = client.transfer_funds(sender, receiver, amount).await;
+        assert_eq!(error.unwrap_err().code(), Some(code));
+    }
+    let sheets = client.calc_balance_per_currency().await.unwrap();
+    assert_eq!(sheets.len(), 1);
+    assert_eq!(sheets[0].assets, eur(100));
+    assert!(sheets[0].is_balanced());
+}
+
+#[tokio::test]
+async fn accrues_interest() {
+    let client = serve().await;
+    let (alice, bob) = (AccountId::new(0), AccountId::new(1));
+    client.transfer_funds(bob, alice, eur(50)).await.unwrap();
+
+    let from = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+    let to = NaiveDate::from_ymd_opt(2024, 1, 11).unwrap();
+    let posted = client
+        .accrue_interest_between(from, to, true)
+        .await
+        .unwrap();
+    assert_eq!(posted.len(), 2);
+
+    let error = client
+        .accrue_interest_between(from, to, false)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        ClientError::Status(status) if status.code() == Code::FailedPrecondition
+    ));
+    let error = client
+        .accrue_interest_between(to, from, false)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        ClientError::Status(status) if status.code() == Code::InvalidArgument
+    ));
+}
+
+#[tokio::test]
+async fn merges_snapshots() {
+    let client = serve().await;
+    let other = bank("Other", &[("bob", 5), ("carol", 1)]).snapshot();
+
+    let report = client
+        .merge_bank(&other, MergePolicy::default())
+        .await
+        .unwrap();
+    assert_eq!(report.ids()[&AccountId::new(0)], AccountId::new(1));
+    assert_eq!(
+        report.accounts[0].action,
+        MergeAction::Combined {
+            credit_line: eur(50)
+        }
+    );
+    assert_eq!(report.accounts[1].action, MergeAction::Opened);
+    assert_eq!(report.ids()[&AccountId::new(1)], AccountId::new(2));
+
+    let policy = MergePolicy {
+        reconciliation: AccountReconciliation::Explicit(
+            [(AccountId::new(0), AccountId::new(9))].into(),
+        ),
+        ..MergePolicy::default()
+    };
+    let error = client.merge_bank(&other, policy).await.unwrap_err();
+    assert!(matches!(
+        error,
+        ClientError::Status(status) if status.code() == Code::FailedPrecondition
+    ));
+}
+
+#[tokio::test]
+async fn streams_the_ledger() {
+    let client = serve().await;
+    let (alice, bob) = (AccountId::new(0), AccountId::new(1));
+
+    // Without following, the stream ends after the entries recorded so far
+    let entries: Vec<_> = client
+        .stream_ledger(0, false)
+        .await
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .await
+        .unwrap();
+    assert_eq!(entries.len(), 2);
+
+    let mut followed = client.stream_ledger(1, true).await.unwrap();
+    assert_eq!(followed.next().await.unwrap().unwrap().id, 1);
+    client.transfer_funds(alice, bob, eur(10)).await.unwrap();
+    client.transfer_funds(bob, alice, eur(5)).await.unwrap();
+    let transfer = followed.next().await.unwrap().unwrap();
+    assert_eq!((transfer.id, transfer.sender), (2, Some(0)));
+    let transfer = followed.next().await.unwrap().unwrap();
+    assert_eq!((transfer.id, transfer.sender), (3, Some(1)));
+}
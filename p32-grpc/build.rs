@@ -0,0 +1,25 @@
+use prost::Message as _;
+use protobuf::Message as _;
+
+const PROTO: &str = "proto/p32/bank/v1/bank.proto";
+
+/// Generates the messages and the service from the schema. The schema is parsed in Rust, so that
+/// building does not need `protoc`.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed={PROTO}");
+    let parsed = protobuf_parse::Parser::new()
+        .pure()
+        .include("proto")
+        .input(PROTO)
+        .parse_and_typecheck()?;
+    // Imported files too, which prost needs to resolve their types
+    let files = parsed.file_descriptors.iter().map(|file| {
+        let bytes = file.write_to_bytes()?;
+        Ok(prost_types::FileDescriptorProto::decode(&*bytes)?)
+    });
+    let descriptors = prost_types::FileDescriptorSet {
+        file: files.collect::<Result<_, Box<dyn std::error::Error>>>()?,
+    };
+    tonic_prost_build::configure().compile_fds(descriptors)?;
+    Ok(())
+}
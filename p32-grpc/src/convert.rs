@@ -0,0 +1,335 @@
+use crate::pb;
+use p32::clock::Timestamp;
+use p32::currency::Currency;
+use p32::ledger::{EntryKind, LedgerEntry};
+use p32::merge::{
+    AccountReconciliation, ConflictResolution, CreditLines, MergeAction, MergePolicy, MergeReport,
+    MergedAccount,
+};
+use p32::money::{BasisPoints, Money};
+use p32::{AccountId, BalanceSheet};
+use std::error::Error;
+use std::fmt;
+
+/// A message that does not describe a valid value, such as an amount in an unknown currency
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidMessage(String);
+
+impl InvalidMessage {
+    pub fn new(message: impl Into<String>) -> Self {
+        InvalidMessage(message.into())
+    }
+}
+
+impl fmt::Display for InvalidMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for InvalidMessage {}
+
+/// The value of a message field that proto3 always makes optional
+pub(crate) fn required<T>(field: Option<T>, name: &str) -> Result<T, InvalidMessage> {
+    field.ok_or_else(|| InvalidMessage::new(format!("{name} is missing")))
+}
+
+impl From<Money> for pb::Money {
+    fn from(money: Money) -> Self {
+        pb::Money {
+            minor: money.minor(),
+            currency: money.currency().to_string(),
+        }
+    }
+}
+
+impl TryFrom<pb::Money> for Money {
+    type Error = InvalidMessage;
+
+    fn try_from(money: pb::Money) -> Result<Self, InvalidMessage> {
+        let currency: Currency = money
+            .currency
+            .parse()
+            .map_err(|error| InvalidMessage::new(format!("{error}")))?;
+        Ok(Money::from_minor(money.minor, currency))
+    }
+}
+
+fn money(field: Option<pb::Money>, name: &str) -> Result<Money, InvalidMessage> {
+    required(field, name)?.try_into()
+}
+
+fn timestamp(timestamp: Timestamp) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: timestamp.timestamp(),
+        nanos: timestamp.timestamp_subsec_nanos() as i32,
+    }
+}
+
+impl From<EntryKind> for pb::EntryKind {
+    fn from(kind: EntryKind) -> Self {
+        match kind {
+            EntryKind::OpeningBalance => pb::EntryKind::OpeningBalance,
+            EntryKind::Transfer => pb::EntryKind::Transfer,
+            EntryKind::InterestAccrual => pb::EntryKind::InterestAccrual,
+            EntryKind::Merge => pb::EntryKind::Merge,
+            EntryKind::OverdraftFee => pb::EntryKind::OverdraftFee,
+            EntryKind::Settlement => pb::EntryKind::Settlement,
+        }
+    }
+}
+
+impl From<&LedgerEntry> for pb::LedgerEntry {
+    fn from(entry: &LedgerEntry) -> Self {
+        pb::LedgerEntry {
+            id: entry.id().value(),
+            timestamp: Some(timestamp(entry.timestamp())),
+            kind: pb::EntryKind::from(entry.kind()).into(),
+            sender: entry.sender().map(|account| account.value()),
+            receiver: entry.receiver().map(|account| account.value()),
+            amount: Some(entry.amount().into()),
+            converted_amount: entry.converted_amount().map(Into::into),
+            sender_balance: entry.sender_balance().map(Into::into),
+            receiver_balance: entry.receiver_balance().map(Into::into),
+        }
+    }
+}
+
+impl From<BalanceSheet> for pb::BalanceSheet {
+    fn from(sheet: BalanceSheet) -> Self {
+        pb::BalanceSheet {
+            currency: sheet.currency.to_string(),
+            assets: Some(sheet.assets.into()),
+            liabilities: Some(sheet.liabilities.into()),
+            equity: Some(sheet.equity.into()),
+            balanced: sheet.is_balanced(),
+        }
+    }
+}
+
+impl TryFrom<pb::BalanceSheet> for BalanceSheet {
+    type Error = InvalidMessage;
+
+    /// Ignores `balanced`, which follows from the amounts
+    fn try_from(sheet: pb::BalanceSheet) -> Result<Self, InvalidMessage> {
+        Ok(BalanceSheet {
+            currency: sheet
+                .currency
+                .parse()
+                .map_err(|error| InvalidMessage::new(format!("{error}")))?,
+            liabilities: money(sheet.liabilities, "liabilities")?,
+            assets: money(sheet.assets, "assets")?,
+            equity: money(sheet.equity, "equity")?,
+        })
+    }
+}
+
+impl From<MergePolicy> for pb::MergePolicy {
+    fn from(policy: MergePolicy) -> Self {
+        let (reconciliation, mapping) = match policy.reconciliation {
+            AccountReconciliation::ByName => (pb::Reconciliation::ByName, Default::default()),
+            AccountReconciliation::Explicit(mapping) => {
+                let mapping = mapping
+                    .into_iter()
+                    .map(|(source, target)| (source.value(), target.value()))
+                    .collect();
+                (pb::Reconciliation::Explicit, mapping)
+            }
+            AccountReconciliation::Separate => (pb::Reconciliation::Separate, Default::default()),
+        };
+        let credit_lines = match policy.credit_lines {
+            CreditLines::Keep => pb::CreditLines::Keep,
+            CreditLines::Max => pb::CreditLines::Max,
+            CreditLines::Min => pb::CreditLines::Min,
+            CreditLines::Sum => pb::CreditLines::Sum,
+        };
+        let conflicts = match policy.conflicts {
+            ConflictResolution::Combine => pb::ConflictResolution::Combine,
+            ConflictResolution::Rename => pb::ConflictResolution::Rename,
+            ConflictResolution::Reject => pb::ConflictResolution::Reject,
+        };
+        pb::MergePolicy {
+            reconciliation: reconciliation.into(),
+            mapping,
+            credit_lines: credit_lines.into(),
+            conflicts: conflicts.into(),
+        }
+    }
+}
+
+impl TryFrom<pb::MergePolicy> for MergePolicy {
+    type Error = InvalidMessage;
+
+    fn try_from(policy: pb::MergePolicy) -> Result<Self, InvalidMessage> {
+        let unknown =
+            |field: &'static str| move |_| InvalidMessage::new(format!("unknown {field}"));
+        let reconciliation = match pb::Reconciliation::try_from(policy.reconciliation)
+            .map_err(unknown("reconciliation"))?
+        {
+            pb::Reconciliation::ByName => AccountReconciliation::ByName,
+            pb::Reconciliation::Explicit => AccountReconciliation::Explicit(
+                policy
+                    .mapping
+                    .into_iter()
+                    .map(|(source, target)| (AccountId::new(source), AccountId::new(target)))
+                    .collect(),
+            ),
+            pb::Reconciliation::Separate => AccountReconciliation::Separate,
+        };
+        let credit_lines = match pb::CreditLines::try_from(policy.credit_lines)
+            .map_err(unknown("credit lines"))?
+        {
+            pb::CreditLines::Keep => CreditLines::Keep,
+            pb::CreditLines::Max => CreditLines::Max,
+            pb::CreditLines::Min => CreditLines::Min,
+            pb::CreditLines::Sum => CreditLines::Sum,
+        };
+        let conflicts = match pb::ConflictResolution::try_from(policy.conflicts)
+            .map_err(unknown("conflict resolution"))?
+        {
+            pb::ConflictResolution::Combine => ConflictResolution::Combine,
+            pb::ConflictResolution::Rename => ConflictResolution::Rename,
+            pb::ConflictResolution::Reject => ConflictResolution::Reject,
+        };
+        Ok(MergePolicy {
+            reconciliation,
+            credit_lines,
+            conflicts,
+        })
+    }
+}
+
+impl From<MergeReport> for pb::MergeBankReply {
+    fn from(report: MergeReport) -> Self {
+        let accounts = report.accounts.into_iter().map(|account| {
+            let mut merged = pb::MergedAccount {
+                source: account.source.value(),
+                target: account.target.value(),
+                ..Default::default()
+            };
+            let action = match account.action {
+                MergeAction::Combined { credit_line } => {
+                    merged.credit_line = Some(credit_line.into());
+                    pb::MergeAction::Combined
+                }
+                MergeAction::Renamed { username } => {
+                    merged.username = username;
+                    pb::MergeAction::Renamed
+                }
+                MergeAction::Opened => pb::MergeAction::Opened,
+                MergeAction::Conflict => pb::MergeAction::Conflict,
+            };
+            merged.set_action(action);
+            merged
+        });
+        pb::MergeBankReply {
+            accounts: accounts.collect(),
+            discarded_interest: report.discarded_interest.map(|(credit, debit)| {
+                pb::DiscardedInterest {
+                    credit_interest: credit.value(),
+                    debit_interest: debit.value(),
+                }
+            }),
+        }
+    }
+}
+
+impl TryFrom<pb::MergeBankReply> for MergeReport {
+    type Error = InvalidMessage;
+
+    fn try_from(reply: pb::MergeBankReply) -> Result<Self, InvalidMessage> {
+        let accounts = reply.accounts.into_iter().map(|account| {
+            let action = match pb::MergeAction::try_from(account.action) {
+                Ok(pb::MergeAction::Combined) => MergeAction::Combined {
+                    credit_line: money(account.credit_line, "credit line")?,
+                },
+                Ok(pb::MergeAction::Renamed) => MergeAction::Renamed {
+                    username: account.username,
+                },
+                Ok(pb::MergeAction::Opened) => MergeAction::Opened,
+                Ok(pb::MergeAction::Conflict) => MergeAction::Conflict,
+                Ok(pb::MergeAction::Unspecified) | Err(_) => {
+                    return Err(InvalidMessage::new("unknown merge action"));
+                }
+            };
+            Ok(MergedAccount {
+                source: AccountId::new(account.source),
+                target: AccountId::new(account.target),
+                action,
+            })
+        });
+        Ok(MergeReport {
+            accounts: accounts.collect::<Result<_, _>>()?,
+            discarded_interest: reply.discarded_interest.map(|interest| {
+                (
+                    BasisPoints::new(interest.credit_interest),
+                    BasisPoints::new(interest.debit_interest),
+                )
+            }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn money_needs_a_known_currency() {
+        let money = Money::from_minor(-1250, Currency::USD);
+        assert_eq!(Money::try_from(pb::Money::from(money)), Ok(money));
+
+        let unknown = pb::Money {
+            minor: 1,
+            currency: "XYZ".to_string(),
+        };
+        assert!(Money::try_from(unknown).is_err());
+    }
+
+    #[test]
+    fn merges_round_trip() {
+        let policy = MergePolicy {
+            reconciliation: AccountReconciliation::Explicit(BTreeMap::from([(
+                AccountId::new(0),
+                AccountId::new(3),
+            )])),
+            credit_lines: CreditLines::Sum,
+            conflicts: ConflictResolution::Rename,
+        };
+        assert_eq!(
+            MergePolicy::try_from(pb::MergePolicy::from(policy.clone())),
+            Ok(policy)
+        );
+
+        let report = MergeReport {
+            accounts: vec![
+                MergedAccount {
+                    source: AccountId::new(0),
+                    target: AccountId::new(3),
+                    action: MergeAction::Combined {
+                        credit_line: Money::from_minor(5000, Currency::EUR),
+                    },
+                },
+                MergedAccount {
+                    source: AccountId::new(1),
+                    target: AccountId::new(4),
+                    action: MergeAction::Renamed {
+                        username: "bob (Other)".to_string(),
+                    },
+                },
+            ],
+            discarded_interest: Some((BasisPoints::new(100), BasisPoints::new(900))),
+        };
+        assert_eq!(
+            MergeReport::try_from(pb::MergeBankReply::from(report.clone())),
+            Ok(report)
+        );
+
+        let unknown = pb::MergePolicy {
+            conflicts: 7,
+            ..Default::default()
+        };
+        assert!(MergePolicy::try_from(unknown).is_err());
+    }
+}
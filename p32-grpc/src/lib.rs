@@ -0,0 +1,16 @@
+//! gRPC interface of a bank, defined by `proto/p32/bank/v1/bank.proto`.
+//!
+//! [`BankService`] serves a bank running on its own task through a [`p32::service::BankHandle`].
+//! The `p32-client` crate talks to it with the types of `p32`.
+
+mod convert;
+mod service;
+
+pub use convert::InvalidMessage;
+pub use service::{BankService, ERROR_DOMAIN};
+
+/// Messages and stubs generated from the schema
+pub mod pb {
+    #![allow(clippy::all)]
+    tonic::include_proto!("p32.bank.v1");
+}
@@ -0,0 +1,244 @@
+use crate::convert::{InvalidMessage, required};
+use crate::pb;
+use crate::pb::bank_server::BankServer;
+use chrono::NaiveDate;
+use p32::error::{ErrorCode, InterestError, MergeError};
+use p32::service::{BankHandle, ServiceError};
+use p32::snapshot::BankSnapshot;
+use p32::{AccountId, Bank, TransferFundsError};
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Code, Request, Response, Status};
+use tonic_types::{ErrorDetails, StatusExt};
+
+/// Domain of the `google.rpc.ErrorInfo` of refused transfers
+pub const ERROR_DOMAIN: &str = "p32";
+
+/// Entries of the ledger buffered per stream before waiting for the client
+const STREAM_BUFFER: usize = 64;
+
+/// Serves the `p32.bank.v1.Bank` service for a bank running on its own task
+#[derive(Clone)]
+pub struct BankService {
+    bank: BankHandle,
+}
+
+impl BankService {
+    pub fn new(bank: BankHandle) -> Self {
+        BankService { bank }
+    }
+
+    /// The service, ready to be added to a `tonic::transport::Server`
+    pub fn into_server(self) -> BankServer<BankService> {
+        BankServer::new(self)
+    }
+}
+
+impl From<InvalidMessage> for Status {
+    fn from(error: InvalidMessage) -> Self {
+        Status::invalid_argument(error.to_string())
+    }
+}
+
+/// Status of a request the bank did not serve, or of its refusal `rejected`
+fn service_status<E>(error: ServiceError<E>, rejected: impl FnOnce(E) -> Status) -> Status {
+    match error {
+        ServiceError::Rejected(error) => rejected(error),
+        ServiceError::Busy => unrefused(ServiceError::Busy),
+        ServiceError::ShutDown => unrefused(ServiceError::ShutDown),
+    }
+}
+
+fn unrefused(error: ServiceError) -> Status {
+    match error {
+        ServiceError::Rejected(never) => match never {},
+        ServiceError::Busy => Status::resource_exhausted(error.to_string()),
+        ServiceError::ShutDown => Status::unavailable(error.to_string()),
+    }
+}
+
+/// Status of a refused transfer: missing accounts are not found, accounts that cannot send the
+/// amount right now fail a precondition, and the rest is a request that can never succeed
+fn transfer_status(error: TransferFundsError) -> Status {
+    let code = match error.code() {
+        ErrorCode::SenderNotFound | ErrorCode::ReceiverNotFound => Code::NotFound,
+        ErrorCode::InsufficientFunds
+        | ErrorCode::AccountFrozen
+        | ErrorCode::AccountOverLimit
+        | ErrorCode::AccountClosed
+        | ErrorCode::ExchangeRateUnavailable => Code::FailedPrecondition,
+        ErrorCode::CurrencyMismatch | ErrorCode::NonPositiveAmount | ErrorCode::SameAccount => {
+            Code::InvalidArgument
+        }
+        ErrorCode::Overflow => Code::OutOfRange,
+    };
+    let details = ErrorDetails::with_error_info(
+        error.code().as_str(),
+        ERROR_DOMAIN,
+        HashMap::<String, String>::new(),
+    );
+    Status::with_error_details(code, error.to_string(), details)
+}
+
+fn interest_status(error: InterestError) -> Status {
+    match error {
+        InterestError::InvalidRange { .. } => Status::invalid_argument(error.to_string()),
+        InterestError::AlreadyAccrued { .. } => Status::failed_precondition(error.to_string()),
+    }
+}
+
+fn merge_status(error: MergeError) -> Status {
+    match error {
+        MergeError::Overflow { .. } => Status::out_of_range(error.to_string()),
+        _ => Status::failed_precondition(error.to_string()),
+    }
+}
+
+fn parse_date(date: &str, name: &str) -> Result<NaiveDate, Status> {
+    date.parse()
+        .map_err(|error| Status::invalid_argument(format!("invalid {name} '{date}': {error}")))
+}
+
+#[tonic::async_trait]
+impl pb::bank_server::Bank for BankService {
+    async fn transfer(
+        &self,
+        request: Request<pb::TransferRequest>,
+    ) -> Result<Response<pb::TransferReply>, Status> {
+        let request = request.into_inner();
+        let amount = required(request.amount, "amount")?.try_into()?;
+        let entry = self
+            .bank
+            .transfer_funds_recorded(
+                AccountId::new(request.sender),
+                AccountId::new(request.receiver),
+                amount,
+            )
+            .await
+            .map_err(|error| service_status(error, transfer_status))?;
+        Ok(Response::new(pb::TransferReply {
+            entry: Some((&entry).into()),
+        }))
+    }
+
+    async fn accrue_interest(
+        &self,
+        request: Request<pb::AccrueInterestRequest>,
+    ) -> Result<Response<pb::AccrueInterestReply>, Status> {
+        let request = request.into_inner();
+        match (request.from, request.to) {
+            (Some(from), Some(to)) => {
+                let (from, to) = (parse_date(&from, "from")?, parse_date(&to, "to")?);
+                self.bank
+                    .accrue_interest_between(from, to)
+                    .await
+                    .map_err(|error| service_status(error, interest_status))?;
+            }
+            (None, None) => self.bank.accrue_interest().await.map_err(unrefused)?,
+            _ => {
+                return Err(Status::invalid_argument(
+                    "from and to must be given together",
+                ));
+            }
+        }
+        let posted = match request.post {
+            true => self.bank.post_accrued_interest().await.map_err(unrefused)?,
+            false => vec![],
+        };
+        Ok(Response::new(pb::AccrueInterestReply {
+            posted: posted.iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn get_balance_sheet(
+        &self,
+        _request: Request<pb::GetBalanceSheetRequest>,
+    ) -> Result<Response<pb::GetBalanceSheetReply>, Status> {
+        let sheets = self
+            .bank
+            .calc_balance_per_currency()
+            .await
+            .map_err(|error| {
+                service_status(error, |error| Status::out_of_range(error.to_string()))
+            })?;
+        Ok(Response::new(pb::GetBalanceSheetReply {
+            sheets: sheets.into_values().map(Into::into).collect(),
+        }))
+    }
+
+    async fn merge_bank(
+        &self,
+        request: Request<pb::MergeBankRequest>,
+    ) -> Result<Response<pb::MergeBankReply>, Status> {
+        let request = request.into_inner();
+        let policy = request.policy.unwrap_or_default().try_into()?;
+        let other = BankSnapshot::decode(&request.snapshot)
+            .map_err(|error| Status::invalid_argument(format!("invalid snapshot: {error}")))?;
+        let other = Bank::restore(other)
+            .map_err(|error| Status::invalid_argument(format!("invalid snapshot: {error}")))?;
+        let report = self
+            .bank
+            .merge_bank(other, policy)
+            .await
+            .map_err(|error| service_status(error, merge_status))?;
+        Ok(Response::new(report.into()))
+    }
+
+    type StreamLedgerStream = ReceiverStream<Result<pb::LedgerEntry, Status>>;
+
+    /// Streams from a task of its own, which stops once the client went away
+    async fn stream_ledger(
+        &self,
+        request: Request<pb::StreamLedgerRequest>,
+    ) -> Result<Response<Self::StreamLedgerStream>, Status> {
+        let request = request.into_inner();
+        let (entries, stream) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(stream_ledger(
+            self.bank.clone(),
+            request.from as usize,
+            request.follow,
+            entries,
+        ));
+        Ok(Response::new(ReceiverStream::new(stream)))
+    }
+}
+
+async fn stream_ledger(
+    bank: BankHandle,
+    mut next: usize,
+    follow: bool,
+    entries: mpsc::Sender<Result<pb::LedgerEntry, Status>>,
+) {
+    let mut ledger_len = bank.ledger_len();
+    loop {
+        let recorded = match bank.ledger_entries(next).await {
+            Ok(recorded) => recorded,
+            Err(error) => {
+                entries.send(Err(unrefused(error))).await.ok();
+                return;
+            }
+        };
+        for entry in &recorded {
+            if entries.send(Ok(entry.into())).await.is_err() {
+                return;
+            }
+        }
+        next += recorded.len();
+        if !follow {
+            return;
+        }
+        // The watch closes once the bank stopped
+        let stopped = tokio::select! {
+            changed = ledger_len.wait_for(|len| *len > next) => changed.is_err(),
+            () = entries.closed() => return,
+        };
+        if stopped {
+            entries
+                .send(Err(unrefused(ServiceError::ShutDown)))
+                .await
+                .ok();
+            return;
+        }
+    }
+}
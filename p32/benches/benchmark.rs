@@ -0,0 +1,92 @@
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use p32::currency::Currency;
+use p32::merge::MergePolicy;
+use p32::money::{BasisPoints, Money};
+use p32::{AccountId, Bank, User};
+use std::hint::black_box;
+
+const USERS: usize = 100_000;
+
+fn eur(amount: i64) -> Money {
+    Money::from_minor(amount, Currency::EUR)
+}
+
+fn users(usernames: impl Iterator<Item = usize>) -> Vec<User> {
+    usernames
+        .map(|i| User::new(format!("name{i}"), eur(0), eur(1_000)))
+        .collect()
+}
+
+fn bank(name: &str, usernames: impl Iterator<Item = usize>) -> Bank {
+    let users = users(usernames);
+    Bank::new(
+        users,
+        name.to_string(),
+        BasisPoints::new(4),
+        BasisPoints::new(1),
+    )
+}
+
+fn bench_transfer_funds(c: &mut Criterion) {
+    let mut bank = bank("Bank", 0..USERS);
+    let (first, last) = (AccountId::new(0), AccountId::new(USERS as u64 - 1));
+    let mut accounts = (first, last);
+    c.bench_function("transfer_funds", |b| {
+        b.iter(|| {
+            // Sending the money back and forth keeps every transfer within the balances
+            let (sender, receiver) = accounts;
+            accounts = (receiver, sender);
+            bank.transfer_funds(black_box(sender), black_box(receiver), eur(1))
+                .expect("both accounts hold enough to send")
+        })
+    });
+}
+
+/// Finding an account by username, with the username index against the linear scan over a
+/// `Vec<User>` that banks used before
+fn bench_find_by_username(c: &mut Criterion) {
+    let bank = bank("Bank", 0..USERS);
+    let users = users(0..USERS);
+    let username = format!("name{}", USERS - 1);
+    let mut group = c.benchmark_group("find_by_username");
+    group.bench_function("index", |b| {
+        b.iter(|| bank.accounts_named(black_box(&username)))
+    });
+    group.bench_function("linear_scan", |b| {
+        b.iter(|| {
+            users
+                .iter()
+                .position(|user| user.name() == black_box(&username))
+        })
+    });
+    group.finish();
+}
+
+/// Merges of banks of growing sizes, half of whose users are in both banks, to show how the
+/// time grows with the number of users
+fn bench_merge_bank(c: &mut Criterion) {
+    let mut group = c.benchmark_group("merge_bank");
+    for users in [USERS / 4, USERS / 2, USERS] {
+        group.throughput(Throughput::Elements(users as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(users), &users, |b, &users| {
+            b.iter_batched(
+                || {
+                    (
+                        bank("Bank1", 0..users),
+                        bank("Bank2", users / 2..users * 3 / 2),
+                    )
+                },
+                |(mut bank1, bank2)| bank1.merge_bank(bank2, &MergePolicy::default()),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = bench_transfer_funds, bench_find_by_username, bench_merge_bank
+}
+criterion_main!(benches);
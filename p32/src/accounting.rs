@@ -0,0 +1,273 @@
+use crate::AccountId;
+use crate::currency::Currency;
+use crate::ledger::{EntryId, LedgerEntry};
+use crate::money::{Money, MoneyError};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Chart of accounts of a bank.
+///
+/// Following the convention of `Bank::calc_balance`, customer deposit accounts are asset accounts:
+/// a positive user balance is a debit balance and an overdrawn one is a credit balance.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Account {
+    CustomerDeposit(AccountId),
+    InterestExpense,
+    InterestIncome,
+    /// Fees charged to customers, such as overdraft fees
+    FeeIncome,
+    Equity,
+    /// The bank's position in each currency, moved by cross-currency transfers
+    CurrencyExchange,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Side {
+    Debit,
+    Credit,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Posting {
+    account: Account,
+    side: Side,
+    amount: Money,
+}
+
+impl Posting {
+    pub fn account(&self) -> &Account {
+        &self.account
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    /// Never negative
+    pub fn amount(&self) -> Money {
+        self.amount
+    }
+}
+
+/// The postings caused by one ledger entry
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JournalEntry {
+    ledger_entry: EntryId,
+    postings: Vec<Posting>,
+}
+
+impl JournalEntry {
+    pub fn ledger_entry(&self) -> EntryId {
+        self.ledger_entry
+    }
+
+    pub fn postings(&self) -> &[Posting] {
+        &self.postings
+    }
+
+    /// Fails with [`MoneyError::Overflow`] if the total does not fit in a [`Money`]
+    pub fn total(&self, side: Side, currency: Currency) -> Result<Money, MoneyError> {
+        Money::from_total(self.sum(side, currency), currency)
+    }
+
+    /// Whether the entry was posted for `entry`: it has its id and moves the customer deposits
+    /// by the amounts `entry` moves the balances of its accounts
+    pub(crate) fn matches(&self, entry: &LedgerEntry) -> bool {
+        let mut movements: BTreeMap<(AccountId, Currency), i128> = BTreeMap::new();
+        for posting in &self.postings {
+            let Account::CustomerDeposit(account) = posting.account else {
+                continue;
+            };
+            let amount = i128::from(posting.amount.minor());
+            *movements
+                .entry((account, posting.amount.currency()))
+                .or_default() += match posting.side {
+                Side::Debit => amount,
+                Side::Credit => -amount,
+            };
+        }
+        // What the entry moves cancels out the postings, unless they differ
+        if let Some(sender) = entry.sender() {
+            let sent = entry.amount();
+            *movements.entry((sender, sent.currency())).or_default() += i128::from(sent.minor());
+        }
+        if let Some(receiver) = entry.receiver() {
+            let received = entry.received_amount();
+            *movements
+                .entry((receiver, received.currency()))
+                .or_default() -= i128::from(received.minor());
+        }
+        self.ledger_entry == entry.id() && movements.values().all(|movement| *movement == 0)
+    }
+
+    /// Debits equal credits in every currency
+    pub fn is_balanced(&self) -> bool {
+        self.postings.iter().all(|posting| {
+            let currency = posting.amount.currency();
+            self.sum(Side::Debit, currency) == self.sum(Side::Credit, currency)
+        })
+    }
+
+    fn sum(&self, side: Side, currency: Currency) -> i128 {
+        self.postings
+            .iter()
+            .filter(|posting| posting.side == side && posting.amount.currency() == currency)
+            .map(|posting| i128::from(posting.amount.minor()))
+            .sum()
+    }
+}
+
+/// General ledger of a bank: the journal and the resulting balance of every account,
+/// kept separately per currency.
+///
+/// Balances are added up in 128 bits, so posting never overflows. Reading a balance or total
+/// that does not fit in a [`Money`] fails with [`MoneyError::Overflow`] instead.
+#[derive(Default)]
+pub struct Books {
+    journal: Vec<JournalEntry>,
+    balances: BTreeMap<(Account, Currency), i128>,
+}
+
+impl Books {
+    /// Books holding `journal`, with the account balances it results in
+    pub(crate) fn from_journal(journal: Vec<JournalEntry>) -> Self {
+        let mut books = Books {
+            journal: vec![],
+            balances: BTreeMap::new(),
+        };
+        for posting in journal.iter().flat_map(|entry| &entry.postings) {
+            books.add(posting);
+        }
+        books.journal = journal;
+        books
+    }
+
+    pub fn journal(&self) -> &[JournalEntry] {
+        &self.journal
+    }
+
+    /// Balance of `account` in `currency`, positive for debit balances and negative for credit
+    /// balances
+    pub fn balance(&self, account: &Account, currency: Currency) -> Result<Money, MoneyError> {
+        Money::from_total(self.balance_total(account, currency), currency)
+    }
+
+    fn balance_total(&self, account: &Account, currency: Currency) -> i128 {
+        self.balances
+            .get(&(account.clone(), currency))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn currencies(&self) -> BTreeSet<Currency> {
+        self.balances
+            .keys()
+            .map(|(_, currency)| *currency)
+            .collect()
+    }
+
+    /// Sum of every account balance in `currency`, which is zero as long as all entries are
+    /// balanced
+    pub fn trial_balance(&self, currency: Currency) -> Result<Money, MoneyError> {
+        let total = self
+            .balances
+            .iter()
+            .filter(|((_, account_currency), _)| *account_currency == currency)
+            .map(|(_, balance)| balance)
+            .sum();
+        Money::from_total(total, currency)
+    }
+
+    /// Equity of the bank in `currency`: its capital and currency position plus the interest
+    /// and fees earned minus the interest paid
+    pub fn equity(&self, currency: Currency) -> Result<Money, MoneyError> {
+        Money::from_total(self.equity_total(currency), currency)
+    }
+
+    pub(crate) fn equity_total(&self, currency: Currency) -> i128 {
+        let equity: i128 = [
+            Account::Equity,
+            Account::CurrencyExchange,
+            Account::InterestIncome,
+            Account::InterestExpense,
+            Account::FeeIncome,
+        ]
+        .iter()
+        .map(|account| self.balance_total(account, currency))
+        .sum();
+        -equity
+    }
+
+    /// Posts `amount` moving from `from` to `to`, converted into `converted_amount` through the
+    /// currency exchange account if present
+    pub(crate) fn post_movement(
+        &mut self,
+        ledger_entry: EntryId,
+        from: Account,
+        to: Account,
+        amount: Money,
+        converted_amount: Option<Money>,
+    ) {
+        let legs = match converted_amount {
+            None => vec![(from, to, amount)],
+            Some(converted_amount) => vec![
+                (from, Account::CurrencyExchange, amount),
+                (Account::CurrencyExchange, to, converted_amount),
+            ],
+        };
+        self.post(ledger_entry, legs);
+    }
+
+    /// Posts one journal entry with every `(from, to, amount)` leg: `to` is debited and `from`
+    /// is credited. A negative amount moves in the opposite direction.
+    fn post(&mut self, ledger_entry: EntryId, legs: Vec<(Account, Account, Money)>) {
+        let mut postings = vec![];
+        for (from, to, amount) in legs {
+            let (debited, credited) = match amount.is_negative() {
+                false => (to, from),
+                true => (from, to),
+            };
+            // The size of `i64::MIN` is not a `Money`, so that amount is posted in two halves
+            let amounts = match amount.is_negative() {
+                false => vec![amount],
+                true => match amount.checked_neg() {
+                    Ok(amount) => vec![amount],
+                    Err(_) => {
+                        let half = Money::from_minor(-(amount.minor() / 2), amount.currency());
+                        vec![half, half]
+                    }
+                },
+            };
+            for amount in amounts {
+                for (account, side) in [(&debited, Side::Debit), (&credited, Side::Credit)] {
+                    let posting = Posting {
+                        account: account.clone(),
+                        side,
+                        amount,
+                    };
+                    self.add(&posting);
+                    postings.push(posting);
+                }
+            }
+        }
+        self.journal.push(JournalEntry {
+            ledger_entry,
+            postings,
+        });
+    }
+
+    fn add(&mut self, posting: &Posting) {
+        let amount = i128::from(posting.amount.minor());
+        let balance = self
+            .balances
+            .entry((posting.account.clone(), posting.amount.currency()))
+            .or_default();
+        match posting.side {
+            Side::Debit => *balance += amount,
+            Side::Credit => *balance -= amount,
+        }
+    }
+}
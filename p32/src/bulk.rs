@@ -0,0 +1,500 @@
+use crate::currency::Currency;
+use crate::error::{ImportError, RowError, RowProblem};
+use crate::money::Money;
+use crate::{AccountId, AccountState, Bank, User};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+/// Layout of a file of users.
+///
+/// Every row holds the `name` and home `currency` of a user, and its `credit_line` and
+/// `balance` as decimal amounts in that currency, such as `-12.30`. Exported rows also hold the
+/// `id` and `state` of the account, which importing ignores.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    /// Comma-separated values with a header row naming the columns
+    Csv,
+    /// One JSON object per line, whose amounts are strings
+    JsonLines,
+}
+
+/// Whether importing changes the bank
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportMode {
+    Apply,
+    /// Validates the file and reports the accounts importing it would open
+    DryRun,
+}
+
+/// An account opened for a row of an imported file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportedAccount {
+    pub line: u64,
+    pub account: AccountId,
+    pub name: String,
+    pub credit_line: Money,
+    pub balance: Money,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportReport {
+    pub mode: ImportMode,
+    /// In line order. The accounts of a dry run are the ones that would be opened.
+    pub accounts: Vec<ImportedAccount>,
+}
+
+/// A row as it is read, before its values are validated, or why it could not be read, with the
+/// line it starts on
+type ReadRow = (u64, Result<ImportRow, RowProblem>);
+
+/// A row as it is read, before its values are validated
+#[derive(serde::Deserialize)]
+struct ImportRow {
+    name: String,
+    currency: String,
+    credit_line: String,
+    balance: String,
+}
+
+#[derive(serde::Serialize)]
+struct ExportRow<'a> {
+    id: u64,
+    name: &'a str,
+    currency: Currency,
+    credit_line: String,
+    balance: String,
+    state: AccountState,
+}
+
+impl Bank {
+    /// Opens an account for every user of a file, or for none if any row is invalid. Rows are
+    /// validated as a whole, so the error lists the problems of every row.
+    pub fn import_users(
+        &mut self,
+        reader: impl Read,
+        format: FileFormat,
+        mode: ImportMode,
+    ) -> Result<ImportReport, ImportError> {
+        let rows = match format {
+            FileFormat::Csv => read_csv(reader)?,
+            FileFormat::JsonLines => read_json_lines(reader)?,
+        };
+
+        let mut errors = vec![];
+        let mut first_lines: HashMap<String, u64> = HashMap::new();
+        let mut accounts = vec![];
+        let mut next_id = self.users.next_id().value();
+        for (line, row) in rows {
+            let mut problems = vec![];
+            let user = row.map_err(|problem| problems.push(problem)).ok();
+            let user =
+                user.and_then(|row| self.validate(line, row, &mut first_lines, &mut problems));
+            errors.extend(
+                problems
+                    .into_iter()
+                    .map(|problem| RowError { line, problem }),
+            );
+            if let Some(user) = user {
+                accounts.push((line, AccountId::new(next_id), user));
+                next_id += 1;
+            }
+        }
+        if !errors.is_empty() {
+            return Err(ImportError::InvalidRows(errors));
+        }
+
+        let accounts = accounts
+            .into_iter()
+            .map(|(line, account, user)| {
+                let imported = ImportedAccount {
+                    line,
+                    account,
+                    name: user.name.clone(),
+                    credit_line: user.credit_line,
+                    balance: user.balance(user.currency),
+                };
+                if mode == ImportMode::Apply {
+                    self.open_account(user)
+                        .expect("rows are validated like opened accounts");
+                }
+                imported
+            })
+            .collect();
+        Ok(ImportReport { mode, accounts })
+    }
+
+    /// Writes every account, closed ones included, with its balance in its home currency.
+    /// Returns the number of accounts written.
+    ///
+    /// Importing the file again does not restore the bank: importing ignores the `state`
+    /// column, so it opens closed and frozen accounts as active ones, and it refuses accounts
+    /// over their credit line and names held by several accounts. Snapshots restore a bank.
+    pub fn export_users(&self, writer: impl Write, format: FileFormat) -> io::Result<usize> {
+        let rows = self.users.iter().map(|(id, user)| ExportRow {
+            id: id.value(),
+            name: &user.name,
+            currency: user.currency,
+            credit_line: user.credit_line.to_decimal(),
+            balance: user.balance(user.currency).to_decimal(),
+            state: user.state,
+        });
+        let mut count = 0;
+        match format {
+            FileFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(writer);
+                for row in rows {
+                    writer.serialize(row)?;
+                    count += 1;
+                }
+                writer.flush()?;
+            }
+            FileFormat::JsonLines => {
+                let mut writer = io::BufWriter::new(writer);
+                for row in rows {
+                    serde_json::to_writer(&mut writer, &row)?;
+                    writer.write_all(b"\n")?;
+                    count += 1;
+                }
+                writer.flush()?;
+            }
+        }
+        Ok(count)
+    }
+
+    /// The user of a row, or `None` after pushing the problems of the row to `problems`
+    fn validate(
+        &self,
+        line: u64,
+        row: ImportRow,
+        first_lines: &mut HashMap<String, u64>,
+        problems: &mut Vec<RowProblem>,
+    ) -> Option<User> {
+        let name = row.name.trim().to_string();
+        if name.is_empty() {
+            problems.push(RowProblem::EmptyName);
+        } else if let Some(first_line) = first_lines.get(&name) {
+            problems.push(RowProblem::DuplicateName {
+                name: name.clone(),
+                first_line: *first_line,
+            });
+        } else {
+            first_lines.insert(name.clone(), line);
+            let open = self
+                .users
+                .ids_named(&name)
+                .find(|id| self.users[*id].state != AccountState::Closed);
+            if let Some(account) = open {
+                problems.push(RowProblem::NameTaken {
+                    name: name.clone(),
+                    account,
+                });
+            }
+        }
+
+        let currency = row.currency.trim();
+        let Ok(currency) = currency.parse::<Currency>() else {
+            problems.push(RowProblem::UnknownCurrency {
+                currency: currency.to_string(),
+            });
+            return None;
+        };
+        let amount = |column, value: &str| {
+            Money::from_decimal(value.trim(), currency).map_err(|source| {
+                RowProblem::MalformedAmount {
+                    column,
+                    value: value.trim().to_string(),
+                    source,
+                }
+            })
+        };
+        let credit_line = amount("credit_line", &row.credit_line)
+            .map_err(|problem| problems.push(problem))
+            .ok();
+        let balance = amount("balance", &row.balance)
+            .map_err(|problem| problems.push(problem))
+            .ok();
+        if let Some(credit_line) = credit_line.filter(Money::is_negative) {
+            problems.push(RowProblem::NegativeCreditLine { credit_line });
+        }
+        if let (Some(credit_line), Some(balance)) = (credit_line, balance)
+            && !credit_line.is_negative()
+            && balance.minor() < -credit_line.minor()
+        {
+            problems.push(RowProblem::OverLimit {
+                balance,
+                credit_line,
+            });
+        }
+
+        match (credit_line, balance) {
+            (Some(credit_line), Some(balance)) if problems.is_empty() => {
+                Some(User::new(name, credit_line, balance))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Rows of a CSV file with their line numbers
+fn read_csv(reader: impl Read) -> Result<Vec<ReadRow>, ImportError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::Headers)
+        .from_reader(reader);
+    let headers = match reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(error) => return Ok(vec![(1, Err(malformed(csv_error(error)?)))]),
+    };
+    let mut rows = vec![];
+    for record in reader.records() {
+        let row = match record {
+            Ok(record) => {
+                let line = record.position().map_or(0, |position| position.line());
+                let row = record.deserialize(Some(&headers)).map_err(malformed);
+                (line, row)
+            }
+            Err(error) => {
+                let line = error.position().map_or(0, |position| position.line());
+                (line, Err(malformed(csv_error(error)?)))
+            }
+        };
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Fails with the I/O errors of `error`, which are not a problem of a row
+fn csv_error(error: csv::Error) -> Result<csv::Error, ImportError> {
+    match error.kind() {
+        csv::ErrorKind::Io(_) => Err(ImportError::Io(error.into())),
+        _ => Ok(error),
+    }
+}
+
+fn malformed(error: impl std::fmt::Display) -> RowProblem {
+    RowProblem::Malformed {
+        reason: error.to_string(),
+    }
+}
+
+/// Rows of a JSON Lines file with their line numbers, skipping blank lines
+fn read_json_lines(reader: impl Read) -> Result<Vec<ReadRow>, ImportError> {
+    let mut rows = vec![];
+    for (line, text) in (1..).zip(BufReader::new(reader).lines()) {
+        let text = text?;
+        if text.trim().is_empty() {
+            continue;
+        }
+        rows.push((line, serde_json::from_str(&text).map_err(malformed)));
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::{BasisPoints, ParseMoneyError};
+    use crate::test_support::{self, eur};
+
+    fn bank() -> Bank {
+        test_support::bank([("name1", 0, 100)], 100)
+    }
+
+    fn problems(error: ImportError) -> Vec<(u64, RowProblem)> {
+        match error {
+            ImportError::InvalidRows(errors) => errors
+                .into_iter()
+                .map(|error| (error.line, error.problem))
+                .collect(),
+            ImportError::Io(error) => panic!("unexpected I/O error: {error}"),
+        }
+    }
+
+    #[test]
+    fn import_csv_opens_accounts() {
+        let mut bank = bank();
+        let csv = "name,currency,credit_line,balance\n\
+                   name2,EUR,50,12.5\n\
+                   name3, USD ,5,-3\n";
+
+        let report = bank
+            .import_users(csv.as_bytes(), FileFormat::Csv, ImportMode::Apply)
+            .unwrap();
+
+        let usd = Money::from_minor(-300, Currency::USD);
+        assert_eq!(
+            report.accounts,
+            vec![
+                ImportedAccount {
+                    line: 2,
+                    account: AccountId::new(1),
+                    name: "name2".to_string(),
+                    credit_line: eur(5000),
+                    balance: eur(1250),
+                },
+                ImportedAccount {
+                    line: 3,
+                    account: AccountId::new(2),
+                    name: "name3".to_string(),
+                    credit_line: Money::from_minor(500, Currency::USD),
+                    balance: usd,
+                },
+            ]
+        );
+        let user = bank.user(AccountId::new(2)).unwrap();
+        assert_eq!(user.balance(Currency::USD), usd);
+        assert_eq!(user.state(), AccountState::Active);
+        assert_eq!(
+            bank.books().trial_balance(Currency::USD).unwrap(),
+            Money::zero(Currency::USD)
+        );
+    }
+
+    #[test]
+    fn import_reports_every_problem_by_line() {
+        let mut bank = bank();
+        let before = bank.snapshot();
+        let csv = "name,currency,credit_line,balance\n\
+                   name2,EUR,10,1\n\
+                   name2,EUR,10,1\n\
+                   name1,EUR,10,1\n\
+                   name4,EUR,-5,1.234\n\
+                   name5,XYZ,0,0\n\
+                   ,EUR,0,abc\n\
+                   name7,EUR,0\n\
+                   name8,EUR,1,-2\n";
+
+        let error = bank
+            .import_users(csv.as_bytes(), FileFormat::Csv, ImportMode::Apply)
+            .unwrap_err();
+
+        let problems = problems(error);
+        assert_eq!(problems.len(), 9);
+        assert_eq!(
+            problems[..7],
+            [
+                (
+                    3,
+                    RowProblem::DuplicateName {
+                        name: "name2".to_string(),
+                        first_line: 2
+                    }
+                ),
+                (
+                    4,
+                    RowProblem::NameTaken {
+                        name: "name1".to_string(),
+                        account: AccountId::new(0)
+                    }
+                ),
+                (
+                    5,
+                    RowProblem::MalformedAmount {
+                        column: "balance",
+                        value: "1.234".to_string(),
+                        source: ParseMoneyError::TooPrecise
+                    }
+                ),
+                (
+                    5,
+                    RowProblem::NegativeCreditLine {
+                        credit_line: eur(-500)
+                    }
+                ),
+                (
+                    6,
+                    RowProblem::UnknownCurrency {
+                        currency: "XYZ".to_string()
+                    }
+                ),
+                (7, RowProblem::EmptyName),
+                (
+                    7,
+                    RowProblem::MalformedAmount {
+                        column: "balance",
+                        value: "abc".to_string(),
+                        source: ParseMoneyError::Malformed
+                    }
+                ),
+            ]
+        );
+        assert!(matches!(problems[7], (8, RowProblem::Malformed { .. })));
+        assert_eq!(
+            problems[8],
+            (
+                9,
+                RowProblem::OverLimit {
+                    balance: eur(-200),
+                    credit_line: eur(100)
+                }
+            )
+        );
+        assert_eq!(bank.snapshot(), before);
+    }
+
+    #[test]
+    fn dry_run_changes_nothing() {
+        let mut bank = bank();
+        let before = bank.snapshot();
+        let jsonl = r#"{"name": "name2", "currency": "EUR", "credit_line": "0", "balance": "7"}"#;
+
+        let report = bank
+            .import_users(jsonl.as_bytes(), FileFormat::JsonLines, ImportMode::DryRun)
+            .unwrap();
+
+        assert_eq!(report.mode, ImportMode::DryRun);
+        assert_eq!(report.accounts.len(), 1);
+        assert_eq!(report.accounts[0].account, AccountId::new(1));
+        assert_eq!(bank.snapshot(), before);
+    }
+
+    #[test]
+    fn exported_files_can_be_imported() {
+        let mut bank = bank();
+        bank.open_account(User::new("name2".to_string(), eur(2500), eur(-1999)))
+            .unwrap();
+
+        for format in [FileFormat::Csv, FileFormat::JsonLines] {
+            let mut file = vec![];
+            assert_eq!(bank.export_users(&mut file, format).unwrap(), 2);
+            let mut imported = Bank::new(
+                vec![],
+                "Imported".to_string(),
+                BasisPoints::new(400),
+                BasisPoints::new(100),
+            );
+            imported
+                .import_users(file.as_slice(), format, ImportMode::Apply)
+                .unwrap();
+
+            for id in [AccountId::new(0), AccountId::new(1)] {
+                let (user, imported) = (bank.user(id).unwrap(), imported.user(id).unwrap());
+                assert_eq!(imported.name(), user.name());
+                assert_eq!(imported.balance(Currency::EUR), user.balance(Currency::EUR));
+                assert_eq!(imported.credit_line(), user.credit_line());
+            }
+        }
+        let mut csv = vec![];
+        bank.export_users(&mut csv, FileFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "id,name,currency,credit_line,balance,state\n\
+             0,name1,EUR,0.00,1.00,Active\n\
+             1,name2,EUR,25.00,-19.99,Active\n"
+        );
+    }
+
+    #[test]
+    fn json_lines_report_lines_of_the_file() {
+        let mut bank = bank();
+        let jsonl = "\n{\"name\": \"name2\", \"currency\": \"EUR\", \"credit_line\": \"0\", \"balance\": 7}\n";
+
+        let error = bank
+            .import_users(jsonl.as_bytes(), FileFormat::JsonLines, ImportMode::Apply)
+            .unwrap_err();
+
+        assert!(matches!(
+            problems(error)[..],
+            [(2, RowProblem::Malformed { .. })]
+        ));
+    }
+}
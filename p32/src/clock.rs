@@ -0,0 +1,17 @@
+use chrono::{DateTime, Utc};
+
+pub type Timestamp = DateTime<Utc>;
+
+/// Source of the current time for everything the bank records
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Timestamp;
+}
+
+/// Wall-clock time, as reported by the operating system
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        Utc::now()
+    }
+}
@@ -0,0 +1,438 @@
+use crate::accounting::{Account, Books};
+use crate::currency::Currency;
+use crate::events::{BankEvent, EventLog};
+use crate::ledger::{EntryKind, Ledger};
+use crate::money::{Money, MoneyError};
+use crate::transfer::{self, CheckedTransfer, Transfer};
+use crate::{AccountId, BalanceSheet, BalanceTotals, Bank, TransferFundsError, User};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+
+/// A bank shared between threads, e.g. by the handlers of a service.
+///
+/// Every account has its own lock, so transfers between different accounts only wait for each
+/// other while they are recorded. Locks are always taken in account id order, which keeps
+/// concurrent transfers from deadlocking. Balance sheets are snapshots kept up to date by every
+/// transfer. Reading them takes a read lock only long enough to clone the latest snapshot, so it
+/// does not wait for transfers to be checked or recorded.
+///
+/// Interest is not accrued while the bank is shared: [`ConcurrentBank::try_unwrap`] gives the
+/// bank back, with its interest state as it was.
+#[derive(Clone)]
+pub struct ConcurrentBank {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    /// The bank the accounts and history were moved out of, which keeps the rest of its state
+    bank: Bank,
+    accounts: BTreeMap<AccountId, Mutex<User>>,
+    next_account: AccountId,
+    /// Taken after the locks of the accounts involved
+    history: Mutex<History>,
+    /// Customer balance totals and equity of every currency held
+    balance_sheets: RwLock<Arc<BTreeMap<Currency, (BalanceTotals, i128)>>>,
+}
+
+struct History {
+    ledger: Ledger,
+    books: Books,
+    events: EventLog,
+}
+
+impl From<Bank> for ConcurrentBank {
+    fn from(mut bank: Bank) -> Self {
+        let balance_sheets = bank
+            .currencies_held()
+            .into_iter()
+            .map(|currency| {
+                let totals = bank.balance_totals(currency);
+                (currency, (totals, bank.books.equity_total(currency)))
+            })
+            .collect();
+        let next_account = bank.users.next_id();
+        let accounts = std::mem::take(&mut bank.users)
+            .into_iter()
+            .map(|(id, user)| (id, Mutex::new(user)))
+            .collect();
+        let history = History {
+            ledger: std::mem::take(&mut bank.ledger),
+            books: std::mem::take(&mut bank.books),
+            events: std::mem::take(&mut bank.events),
+        };
+        ConcurrentBank {
+            shared: Arc::new(Shared {
+                bank,
+                accounts,
+                next_account,
+                history: Mutex::new(history),
+                balance_sheets: RwLock::new(Arc::new(balance_sheets)),
+            }),
+        }
+    }
+}
+
+impl ConcurrentBank {
+    /// The bank with every transfer made through it, once this is its last handle. Fails with
+    /// the handle while other handles remain.
+    pub fn try_unwrap(self) -> Result<Bank, ConcurrentBank> {
+        let Shared {
+            mut bank,
+            accounts,
+            next_account,
+            history,
+            ..
+        } = Arc::try_unwrap(self.shared).map_err(|shared| ConcurrentBank { shared })?;
+        for (id, user) in accounts {
+            let user = user
+                .into_inner()
+                .expect("a thread panicked while holding an account");
+            bank.users.restore(id, user);
+        }
+        bank.users.set_next_id(next_account);
+        let history = history
+            .into_inner()
+            .expect("a thread panicked while recording a transfer");
+        bank.ledger = history.ledger;
+        bank.books = history.books;
+        bank.events = history.events;
+        Ok(bank)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.shared.bank.name
+    }
+
+    pub fn account_ids(&self) -> Vec<AccountId> {
+        self.shared.accounts.keys().copied().collect()
+    }
+
+    /// Current balance of `account` in `currency`, which waits for transfers of that account only
+    pub fn balance(&self, account: AccountId, currency: Currency) -> Option<Money> {
+        self.lock(account).map(|user| user.balance(currency))
+    }
+
+    /// Moves `amount` from `sender` to `receiver`, checked like in [`Bank::transfer_funds`]
+    pub fn transfer_funds(
+        &self,
+        sender: AccountId,
+        receiver: AccountId,
+        amount: Money,
+    ) -> Result<(), TransferFundsError> {
+        let transfer = Transfer {
+            sender,
+            receiver,
+            amount,
+        };
+        transfer::check_accounts(&transfer, |account| {
+            self.shared.accounts.contains_key(&account)
+        })?;
+
+        let (mut sender_user, mut receiver_user) = match sender < receiver {
+            true => {
+                let sender_user = self.lock(sender).expect("checked above");
+                (sender_user, self.lock(receiver).expect("checked above"))
+            }
+            false => {
+                let receiver_user = self.lock(receiver).expect("checked above");
+                (self.lock(sender).expect("checked above"), receiver_user)
+            }
+        };
+        let checked = self.shared.bank.transfer_rules.check(
+            &transfer,
+            &sender_user,
+            sender_user.state,
+            &receiver_user,
+            |account, currency| match account == sender {
+                true => sender_user.balance(currency),
+                false => receiver_user.balance(currency),
+            },
+        )?;
+
+        let old_sender_balance = sender_user.balance(amount.currency());
+        let old_receiver_balance = receiver_user.balance(checked.credited.currency());
+        sender_user.set_balance(checked.new_sender_balance);
+        receiver_user.set_balance(checked.new_receiver_balance);
+        // Still holding the account locks, so that entries are recorded in the order the
+        // balances changed
+        self.record(&checked, old_sender_balance, old_receiver_balance);
+        Ok(())
+    }
+
+    /// Balance sheet of the accounts held in the currency of the bank, failing like
+    /// [`Bank::calc_balance`]
+    pub fn calc_balance(&self) -> Result<BalanceSheet, MoneyError> {
+        let currency = self.shared.bank.currency;
+        match self.balance_sheets().get(&currency) {
+            Some(&(totals, equity)) => totals.balance_sheet(Money::from_total(equity, currency)?),
+            None => BalanceTotals::new(currency).balance_sheet(Money::zero(currency)),
+        }
+    }
+
+    pub fn calc_balance_per_currency(
+        &self,
+    ) -> Result<BTreeMap<Currency, BalanceSheet>, MoneyError> {
+        self.balance_sheets()
+            .iter()
+            .map(|(&currency, &(totals, equity))| {
+                let equity = Money::from_total(equity, currency)?;
+                Ok((currency, totals.balance_sheet(equity)?))
+            })
+            .collect()
+    }
+
+    pub fn with_ledger<T>(&self, f: impl FnOnce(&Ledger) -> T) -> T {
+        f(&self.history().ledger)
+    }
+
+    pub fn with_books<T>(&self, f: impl FnOnce(&Books) -> T) -> T {
+        f(&self.history().books)
+    }
+
+    pub fn with_events<T>(&self, f: impl FnOnce(&EventLog) -> T) -> T {
+        f(&self.history().events)
+    }
+
+    fn record(
+        &self,
+        checked: &CheckedTransfer,
+        old_sender_balance: Money,
+        old_receiver_balance: Money,
+    ) {
+        let Transfer {
+            sender,
+            receiver,
+            amount,
+        } = checked.transfer;
+        let mut history = self.history();
+        let now = self.shared.bank.clock.now();
+        let ledger_entry = history.ledger.record(
+            now,
+            EntryKind::Transfer,
+            Some((sender, checked.new_sender_balance)),
+            Some((receiver, checked.new_receiver_balance)),
+            amount,
+            checked.converted_amount(),
+        );
+        history.books.post_movement(
+            ledger_entry,
+            Account::CustomerDeposit(sender),
+            Account::CustomerDeposit(receiver),
+            amount,
+            checked.converted_amount(),
+        );
+        history.events.record(
+            now,
+            BankEvent::FundsTransferred {
+                kind: EntryKind::Transfer,
+                sender: Some(sender),
+                receiver: Some(receiver),
+                amount,
+                converted_amount: checked.converted_amount(),
+            },
+        );
+
+        let mut balance_sheets = BTreeMap::clone(&self.balance_sheets());
+        for (old_balance, new_balance) in [
+            (old_sender_balance, checked.new_sender_balance),
+            (old_receiver_balance, checked.new_receiver_balance),
+        ] {
+            let currency = new_balance.currency();
+            let (totals, equity) = balance_sheets
+                .entry(currency)
+                .or_insert_with(|| (BalanceTotals::new(currency), 0));
+            totals.remove(old_balance);
+            totals.add(new_balance);
+            *equity = history.books.equity_total(currency);
+        }
+        *self
+            .shared
+            .balance_sheets
+            .write()
+            .expect("a thread panicked while publishing balance sheets") = Arc::new(balance_sheets);
+    }
+
+    fn lock(&self, account: AccountId) -> Option<MutexGuard<'_, User>> {
+        self.shared.accounts.get(&account).map(|user| {
+            user.lock()
+                .expect("a thread panicked while holding an account")
+        })
+    }
+
+    fn history(&self) -> MutexGuard<'_, History> {
+        self.shared
+            .history
+            .lock()
+            .expect("a thread panicked while recording a transfer")
+    }
+
+    fn balance_sheets(&self) -> Arc<BTreeMap<Currency, (BalanceTotals, i128)>> {
+        self.shared
+            .balance_sheets
+            .read()
+            .expect("a thread panicked while publishing balance sheets")
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Balances;
+    use crate::money::BasisPoints;
+    use crate::test_support::{self, eur};
+    use std::thread;
+
+    /// Linear congruential generator, enough to pick random transfers
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, bound: u64) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) % bound
+        }
+    }
+
+    fn bank(accounts: i64) -> ConcurrentBank {
+        let users = (0..accounts).map(|i| (format!("name{i}"), 100, 1_000 + i));
+        ConcurrentBank::from(test_support::bank(users, 100))
+    }
+
+    #[test]
+    fn concurrent_bank_is_a_shareable_handle() {
+        fn assert_shareable<T: Send + Sync + Clone>() {}
+        assert_shareable::<ConcurrentBank>();
+    }
+
+    #[test]
+    fn transfer_funds_checks_like_bank() {
+        let bank = bank(2);
+        let [id1, id2] = bank.account_ids()[..] else {
+            panic!("expected two accounts");
+        };
+
+        assert_eq!(bank.transfer_funds(id1, id2, eur(1_100)), Ok(()));
+        assert!(matches!(
+            bank.transfer_funds(id1, id2, eur(1)),
+            Err(TransferFundsError::SenderNotEnoughBalance { .. })
+        ));
+        assert!(matches!(
+            bank.transfer_funds(id1, id1, eur(1)),
+            Err(TransferFundsError::SameAccount { .. })
+        ));
+        assert_eq!(bank.balance(id1, Currency::EUR), Some(eur(-100)));
+        assert_eq!(bank.balance(id2, Currency::EUR), Some(eur(2_101)));
+        assert_eq!(bank.calc_balance().unwrap().liabilities, eur(100));
+        assert_eq!(bank.calc_balance().unwrap().assets, eur(2_101));
+        assert_eq!(bank.with_ledger(|ledger| ledger.entries().len()), 3);
+    }
+
+    #[test]
+    fn try_unwrap_gives_the_bank_back_once_unshared() {
+        let shared = bank(2);
+        let [id1, id2] = shared.account_ids()[..] else {
+            panic!("expected two accounts");
+        };
+        shared.transfer_funds(id1, id2, eur(1_100)).unwrap();
+        let other = shared.clone();
+        let Err(shared) = shared.try_unwrap() else {
+            panic!("another handle remains");
+        };
+        drop(other);
+
+        let Ok(mut bank) = shared.try_unwrap() else {
+            panic!("no other handle remains");
+        };
+        assert_eq!(bank.users[id1].balance(Currency::EUR), eur(-100));
+        assert_eq!(bank.ledger().entries().len(), 3);
+        assert_eq!(bank.events().events().len(), 3);
+        assert_eq!(bank.credit_interest, BasisPoints::new(400));
+        assert!(bank.calc_balance().unwrap().is_balanced());
+        let id3 = bank
+            .open_account(User::new("name2".to_string(), eur(0), eur(0)))
+            .unwrap();
+        assert_eq!(id3, AccountId::new(2));
+    }
+
+    #[test]
+    fn concurrent_transfers_conserve_money() {
+        const ACCOUNTS: i64 = 16;
+        const THREADS: u64 = 8;
+        const TRANSFERS: usize = 2_000;
+        let bank = bank(ACCOUNTS);
+        let ids = bank.account_ids();
+        let initial_total: i64 = ids
+            .iter()
+            .map(|id| bank.balance(*id, Currency::EUR).unwrap().minor())
+            .sum();
+
+        let transferring: Vec<_> = (0..THREADS)
+            .map(|seed| {
+                let (bank, ids) = (bank.clone(), ids.clone());
+                thread::spawn(move || {
+                    let mut random = Lcg(seed);
+                    for _ in 0..TRANSFERS {
+                        let sender = ids[random.next(ids.len() as u64) as usize];
+                        let receiver = ids[random.next(ids.len() as u64) as usize];
+                        let amount = eur(random.next(300) as i64 + 1);
+                        let _ = bank.transfer_funds(sender, receiver, amount);
+                    }
+                })
+            })
+            .collect();
+        let reading = {
+            let bank = bank.clone();
+            thread::spawn(move || {
+                for _ in 0..TRANSFERS {
+                    let balance_sheet = bank.calc_balance().unwrap();
+                    assert!(balance_sheet.is_balanced());
+                    assert_eq!(
+                        balance_sheet.assets.minor() - balance_sheet.liabilities.minor(),
+                        initial_total
+                    );
+                }
+            })
+        };
+        for thread in transferring {
+            thread.join().unwrap();
+        }
+        reading.join().unwrap();
+
+        let balances: Vec<Money> = ids
+            .iter()
+            .map(|id| bank.balance(*id, Currency::EUR).unwrap())
+            .collect();
+        let total: i64 = balances.iter().map(|balance| balance.minor()).sum();
+        assert_eq!(total, initial_total);
+        assert!(balances.iter().all(|balance| balance.minor() >= -100));
+        let balance_sheet = bank.calc_balance().unwrap();
+        let assets: i64 = balances.iter().map(|b| b.minor().max(0)).sum();
+        assert_eq!(balance_sheet.assets, eur(assets));
+        assert_eq!(
+            balance_sheet.assets.minor() - balance_sheet.liabilities.minor(),
+            total
+        );
+        bank.with_books(|books| {
+            assert_eq!(books.trial_balance(Currency::EUR).unwrap(), eur(0));
+            for (id, balance) in ids.iter().zip(&balances) {
+                let account = Account::CustomerDeposit(*id);
+                assert_eq!(books.balance(&account, Currency::EUR).unwrap(), *balance);
+            }
+        });
+        bank.with_events(|events| {
+            let mut replayed = Balances::default();
+            events.replay(&mut replayed);
+            for (id, balance) in ids.iter().zip(&balances) {
+                assert_eq!(replayed.balance(*id, Currency::EUR), *balance);
+            }
+        });
+        bank.with_ledger(|ledger| {
+            for (id, balance) in ids.iter().zip(&balances) {
+                assert_eq!(ledger.replay_balance(*id, Currency::EUR), Ok(*balance));
+            }
+        });
+    }
+}
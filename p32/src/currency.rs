@@ -0,0 +1,122 @@
+use crate::error::UnknownCurrency;
+use crate::money::{Money, MoneyError, div_round_half_even};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Currency {
+    EUR,
+    USD,
+    GBP,
+}
+
+impl Currency {
+    /// Number of decimal digits of the minor unit, e.g. 2 for cents
+    pub fn minor_units(&self) -> u32 {
+        match self {
+            Currency::EUR | Currency::USD | Currency::GBP => 2,
+        }
+    }
+
+    pub fn minor_units_per_major(&self) -> i64 {
+        10i64.pow(self.minor_units())
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl FromStr for Currency {
+    type Err = UnknownCurrency;
+
+    /// Parses an ISO 4217 code such as `EUR`
+    fn from_str(code: &str) -> Result<Self, UnknownCurrency> {
+        match code {
+            "EUR" => Ok(Currency::EUR),
+            "USD" => Ok(Currency::USD),
+            "GBP" => Ok(Currency::GBP),
+            _ => Err(UnknownCurrency(code.to_string())),
+        }
+    }
+}
+
+/// How many units of the target currency one unit of the source currency buys,
+/// as the fraction `numerator / denominator`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExchangeRate {
+    numerator: u64,
+    denominator: u64,
+}
+
+impl ExchangeRate {
+    /// Returns `None` for a zero denominator
+    pub fn new(numerator: u64, denominator: u64) -> Option<Self> {
+        if denominator == 0 {
+            return None;
+        }
+        Some(ExchangeRate {
+            numerator,
+            denominator,
+        })
+    }
+
+    pub fn identity() -> Self {
+        ExchangeRate {
+            numerator: 1,
+            denominator: 1,
+        }
+    }
+
+    /// Converts `amount` into `target`, rounded half to even to the minor unit
+    pub fn convert(&self, amount: Money, target: Currency) -> Result<Money, MoneyError> {
+        let converted = div_round_half_even(
+            amount.minor() as i128 * self.numerator as i128,
+            self.denominator as i128,
+        );
+        i64::try_from(converted)
+            .map(|minor| Money::from_minor(minor, target))
+            .map_err(|_| MoneyError::Overflow)
+    }
+}
+
+/// Source of the exchange rates used for cross-currency transfers
+pub trait ExchangeRateProvider: Send + Sync {
+    fn rate(&self, from: Currency, to: Currency) -> Option<ExchangeRate>;
+}
+
+/// Exchange rates set up front, e.g. once a day
+#[derive(Default)]
+pub struct FixedExchangeRates {
+    rates: HashMap<(Currency, Currency), ExchangeRate>,
+}
+
+impl FixedExchangeRates {
+    pub fn with_rate(mut self, from: Currency, to: Currency, rate: ExchangeRate) -> Self {
+        self.rates.insert((from, to), rate);
+        self
+    }
+}
+
+impl ExchangeRateProvider for FixedExchangeRates {
+    fn rate(&self, from: Currency, to: Currency) -> Option<ExchangeRate> {
+        if from == to {
+            return Some(ExchangeRate::identity());
+        }
+        self.rates.get(&(from, to)).copied()
+    }
+}
+
+/// What to do when the receiver holds no balance in the currency of a transfer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CrossCurrencyTransfers {
+    #[default]
+    Reject,
+    /// Convert the amount into the receiver's currency through the bank's exchange rates
+    Convert,
+}
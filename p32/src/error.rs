@@ -0,0 +1,1022 @@
+use crate::currency::Currency;
+use crate::ledger::EntryId;
+#[cfg(feature = "bulk")]
+use crate::money::ParseMoneyError;
+use crate::money::{Money, MoneyError};
+use crate::{AccountId, AccountState};
+use chrono::NaiveDate;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransferFundsError {
+    SenderNotExistsError {
+        account: AccountId,
+    },
+    ReceiverNotExistsError {
+        account: AccountId,
+    },
+    SenderNotEnoughBalance {
+        account: AccountId,
+        requested: Money,
+        /// Balance plus the credit line that can be drawn in the requested currency
+        available: Money,
+    },
+    /// The receiver holds no balance in the currency of the transfer
+    CurrencyMismatch {
+        account: AccountId,
+        currency: Currency,
+    },
+    ExchangeRateUnavailable {
+        from: Currency,
+        to: Currency,
+    },
+    /// The amount is zero or negative, which would pull money from the receiver
+    NonPositiveAmount {
+        amount: Money,
+    },
+    /// A resulting balance of `account` does not fit in a `Money`
+    Overflow {
+        account: AccountId,
+        source: MoneyError,
+    },
+    SameAccount {
+        account: AccountId,
+    },
+    /// The sender account is frozen and cannot send funds
+    AccountFrozen {
+        account: AccountId,
+    },
+    /// The sender account is over its credit line and can only receive funds
+    AccountOverLimit {
+        account: AccountId,
+    },
+    /// The sender or receiver account is closed
+    AccountClosed {
+        account: AccountId,
+    },
+}
+
+/// Identifies the kind of a [`TransferFundsError`].
+///
+/// The string form is stable and safe to expose to API clients.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    SenderNotFound,
+    ReceiverNotFound,
+    InsufficientFunds,
+    CurrencyMismatch,
+    ExchangeRateUnavailable,
+    NonPositiveAmount,
+    Overflow,
+    SameAccount,
+    AccountFrozen,
+    AccountOverLimit,
+    AccountClosed,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 11] = [
+        ErrorCode::SenderNotFound,
+        ErrorCode::ReceiverNotFound,
+        ErrorCode::InsufficientFunds,
+        ErrorCode::CurrencyMismatch,
+        ErrorCode::ExchangeRateUnavailable,
+        ErrorCode::NonPositiveAmount,
+        ErrorCode::Overflow,
+        ErrorCode::SameAccount,
+        ErrorCode::AccountFrozen,
+        ErrorCode::AccountOverLimit,
+        ErrorCode::AccountClosed,
+    ];
+
+    /// The code whose string form is `code`, as returned by [`ErrorCode::as_str`]
+    pub fn parse(code: &str) -> Option<ErrorCode> {
+        ErrorCode::ALL
+            .into_iter()
+            .find(|candidate| candidate.as_str() == code)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::SenderNotFound => "SENDER_NOT_FOUND",
+            ErrorCode::ReceiverNotFound => "RECEIVER_NOT_FOUND",
+            ErrorCode::InsufficientFunds => "INSUFFICIENT_FUNDS",
+            ErrorCode::CurrencyMismatch => "CURRENCY_MISMATCH",
+            ErrorCode::ExchangeRateUnavailable => "EXCHANGE_RATE_UNAVAILABLE",
+            ErrorCode::NonPositiveAmount => "NON_POSITIVE_AMOUNT",
+            ErrorCode::Overflow => "OVERFLOW",
+            ErrorCode::SameAccount => "SAME_ACCOUNT",
+            ErrorCode::AccountFrozen => "ACCOUNT_FROZEN",
+            ErrorCode::AccountOverLimit => "ACCOUNT_OVER_LIMIT",
+            ErrorCode::AccountClosed => "ACCOUNT_CLOSED",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TransferFundsError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            TransferFundsError::SenderNotExistsError { .. } => ErrorCode::SenderNotFound,
+            TransferFundsError::ReceiverNotExistsError { .. } => ErrorCode::ReceiverNotFound,
+            TransferFundsError::SenderNotEnoughBalance { .. } => ErrorCode::InsufficientFunds,
+            TransferFundsError::CurrencyMismatch { .. } => ErrorCode::CurrencyMismatch,
+            TransferFundsError::ExchangeRateUnavailable { .. } => {
+                ErrorCode::ExchangeRateUnavailable
+            }
+            TransferFundsError::NonPositiveAmount { .. } => ErrorCode::NonPositiveAmount,
+            TransferFundsError::Overflow { .. } => ErrorCode::Overflow,
+            TransferFundsError::SameAccount { .. } => ErrorCode::SameAccount,
+            TransferFundsError::AccountFrozen { .. } => ErrorCode::AccountFrozen,
+            TransferFundsError::AccountOverLimit { .. } => ErrorCode::AccountOverLimit,
+            TransferFundsError::AccountClosed { .. } => ErrorCode::AccountClosed,
+        }
+    }
+}
+
+impl fmt::Display for TransferFundsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferFundsError::SenderNotExistsError { account } => {
+                write!(f, "sender account {account} does not exist")
+            }
+            TransferFundsError::ReceiverNotExistsError { account } => {
+                write!(f, "receiver account {account} does not exist")
+            }
+            TransferFundsError::SenderNotEnoughBalance {
+                account,
+                requested,
+                available,
+            } => write!(
+                f,
+                "sender account {account} requested {requested} but only {available} is available"
+            ),
+            TransferFundsError::CurrencyMismatch { account, currency } => {
+                write!(
+                    f,
+                    "receiver account {account} holds no balance in {currency}"
+                )
+            }
+            TransferFundsError::ExchangeRateUnavailable { from, to } => {
+                write!(f, "no exchange rate from {from} to {to}")
+            }
+            TransferFundsError::NonPositiveAmount { amount } => {
+                write!(f, "transfer amount {amount} is not positive")
+            }
+            TransferFundsError::Overflow { account, .. } => {
+                write!(f, "the balance of account {account} would overflow")
+            }
+            TransferFundsError::SameAccount { account } => {
+                write!(f, "account {account} cannot transfer funds to itself")
+            }
+            TransferFundsError::AccountFrozen { account } => {
+                write!(f, "sender account {account} is frozen")
+            }
+            TransferFundsError::AccountOverLimit { account } => {
+                write!(f, "sender account {account} is over its credit line")
+            }
+            TransferFundsError::AccountClosed { account } => {
+                write!(f, "account {account} is closed")
+            }
+        }
+    }
+}
+
+impl Error for TransferFundsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TransferFundsError::Overflow { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Why a batch of transfers was rejected. None of its transfers is applied.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchTransferError {
+    /// Position of the first failing transfer in the batch
+    pub index: usize,
+    pub reason: TransferFundsError,
+}
+
+impl fmt::Display for BatchTransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "transfer {} of the batch failed: {}",
+            self.index, self.reason
+        )
+    }
+}
+
+impl Error for BatchTransferError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.reason)
+    }
+}
+
+/// The account does not exist in the bank
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AccountNotFound(pub AccountId);
+
+impl fmt::Display for AccountNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "account {} does not exist", self.0)
+    }
+}
+
+impl Error for AccountNotFound {}
+
+/// The code is not one of a currency the bank supports
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownCurrency(pub String);
+
+impl fmt::Display for UnknownCurrency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown currency '{}'", self.0)
+    }
+}
+
+impl Error for UnknownCurrency {}
+
+/// Why the credit line of an account cannot be changed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CreditLineError {
+    AccountNotFound(AccountId),
+    AccountClosed {
+        account: AccountId,
+    },
+    /// Credit lines can only be drawn in the home currency of the account
+    CurrencyMismatch {
+        account: AccountId,
+        currency: Currency,
+    },
+    NegativeAmount {
+        amount: Money,
+    },
+    NoPendingRequest {
+        account: AccountId,
+    },
+    /// A raise to less than the current credit line
+    NotARaise {
+        current: Money,
+        requested: Money,
+    },
+    /// A lowering to more than the current credit line
+    NotALowering {
+        current: Money,
+        requested: Money,
+    },
+}
+
+impl fmt::Display for CreditLineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CreditLineError::AccountNotFound(account) => {
+                write!(f, "account {account} does not exist")
+            }
+            CreditLineError::AccountClosed { account } => {
+                write!(f, "account {account} is closed")
+            }
+            CreditLineError::CurrencyMismatch { account, currency } => {
+                write!(
+                    f,
+                    "account {account} cannot draw a credit line in {currency}"
+                )
+            }
+            CreditLineError::NegativeAmount { amount } => {
+                write!(f, "credit line {amount} is negative")
+            }
+            CreditLineError::NoPendingRequest { account } => {
+                write!(f, "account {account} has no pending credit line request")
+            }
+            CreditLineError::NotARaise { current, requested } => {
+                write!(f, "{requested} does not raise the credit line of {current}")
+            }
+            CreditLineError::NotALowering { current, requested } => {
+                write!(f, "{requested} does not lower the credit line of {current}")
+            }
+        }
+    }
+}
+
+impl Error for CreditLineError {}
+
+impl From<AccountNotFound> for CreditLineError {
+    fn from(error: AccountNotFound) -> Self {
+        CreditLineError::AccountNotFound(error.0)
+    }
+}
+
+/// Why an account cannot be opened, frozen, unfrozen or closed. The bank is unchanged when
+/// any of these fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AccountError {
+    AccountNotFound(AccountId),
+    AccountClosed {
+        account: AccountId,
+    },
+    /// A frozen account has to be unfrozen before it is closed
+    AccountFrozen {
+        account: AccountId,
+    },
+    EmptyName,
+    NegativeCreditLine {
+        credit_line: Money,
+    },
+    /// Credit lines can only be drawn in the home currency of the account
+    CreditLineCurrencyMismatch {
+        credit_line: Money,
+        currency: Currency,
+    },
+    /// An account cannot be settled into itself
+    SameAccount {
+        account: AccountId,
+    },
+    /// The account to close owes `balance`, which must be repaid first
+    OutstandingDebt {
+        account: AccountId,
+        balance: Money,
+    },
+    /// A balance of `account` would overflow when receiving the settlement
+    Overflow {
+        account: AccountId,
+        source: MoneyError,
+    },
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountError::AccountNotFound(account) => {
+                write!(f, "account {account} does not exist")
+            }
+            AccountError::AccountClosed { account } => {
+                write!(f, "account {account} is closed")
+            }
+            AccountError::AccountFrozen { account } => {
+                write!(f, "account {account} is frozen")
+            }
+            AccountError::EmptyName => f.write_str("the account holder has no name"),
+            AccountError::NegativeCreditLine { credit_line } => {
+                write!(f, "credit line {credit_line} is negative")
+            }
+            AccountError::CreditLineCurrencyMismatch {
+                credit_line,
+                currency,
+            } => write!(
+                f,
+                "credit line {credit_line} is not in the home currency {currency}"
+            ),
+            AccountError::SameAccount { account } => {
+                write!(f, "account {account} cannot be settled into itself")
+            }
+            AccountError::OutstandingDebt { account, balance } => {
+                write!(f, "account {account} still owes {balance}")
+            }
+            AccountError::Overflow { account, .. } => {
+                write!(f, "the balance of account {account} would overflow")
+            }
+        }
+    }
+}
+
+impl Error for AccountError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AccountError::Overflow { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<AccountNotFound> for AccountError {
+    fn from(error: AccountNotFound) -> Self {
+        AccountError::AccountNotFound(error.0)
+    }
+}
+
+/// Why a snapshot does not describe a valid bank
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RestoreError {
+    UnsupportedVersion {
+        found: u32,
+        supported: u32,
+    },
+    DuplicateAccount {
+        account: AccountId,
+    },
+    EmptyName {
+        account: AccountId,
+    },
+    NegativeCreditLine {
+        account: AccountId,
+    },
+    /// The credit line or the credit line requested is not in the home currency of the account
+    CreditLineCurrencyMismatch {
+        account: AccountId,
+    },
+    /// The id of the next account opened is already taken
+    InvalidNextAccountId {
+        next_account_id: AccountId,
+    },
+    /// The state of `account` is not the one its balance and credit line put it in
+    StateMismatch {
+        account: AccountId,
+        state: AccountState,
+        expected: AccountState,
+    },
+    /// Interest is accrued for `account`, which the bank does not have
+    UnknownAccrualAccount {
+        account: AccountId,
+    },
+    /// The ledger entries are not numbered in order from zero
+    LedgerOutOfOrder,
+    /// Debits and credits of the journal entry of `ledger_entry` differ
+    UnbalancedJournalEntry {
+        ledger_entry: EntryId,
+    },
+    /// The journal entry of `ledger_entry` is missing or does not move the customer deposits
+    /// the ledger entry does
+    JournalMismatch {
+        ledger_entry: EntryId,
+    },
+    /// The ledger entries of `account` add up to more than an amount can hold
+    BalanceOverflow {
+        account: AccountId,
+    },
+    /// The balance the events of `account` add up to differs from the one its ledger entries
+    /// add up to
+    BalanceMismatch {
+        account: AccountId,
+        balance: Money,
+        replayed: Money,
+    },
+    /// The events are not numbered in order from zero
+    EventsOutOfOrder,
+    /// Events move money of `account`, which the bank does not have
+    UnknownEventAccount {
+        account: AccountId,
+    },
+}
+
+impl fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RestoreError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "snapshot schema version {found} is not supported, expected {supported}"
+            ),
+            RestoreError::DuplicateAccount { account } => {
+                write!(f, "account {account} appears more than once")
+            }
+            RestoreError::EmptyName { account } => {
+                write!(f, "the holder of account {account} has no name")
+            }
+            RestoreError::NegativeCreditLine { account } => {
+                write!(f, "the credit line of account {account} is negative")
+            }
+            RestoreError::CreditLineCurrencyMismatch { account } => write!(
+                f,
+                "the credit line of account {account} is not in its home currency"
+            ),
+            RestoreError::InvalidNextAccountId { next_account_id } => {
+                write!(f, "the next account id {next_account_id} is already taken")
+            }
+            RestoreError::StateMismatch {
+                account,
+                state,
+                expected,
+            } => write!(
+                f,
+                "account {account} is {state:?} but its balance and credit line make it {expected:?}"
+            ),
+            RestoreError::UnknownAccrualAccount { account } => {
+                write!(
+                    f,
+                    "interest is accrued for account {account}, which does not exist"
+                )
+            }
+            RestoreError::LedgerOutOfOrder => {
+                f.write_str("the ledger entries are not numbered in order")
+            }
+            RestoreError::UnbalancedJournalEntry { ledger_entry } => write!(
+                f,
+                "the journal entry of ledger entry {ledger_entry:?} is not balanced"
+            ),
+            RestoreError::JournalMismatch { ledger_entry } => write!(
+                f,
+                "the journal entry of ledger entry {ledger_entry:?} does not match it"
+            ),
+            RestoreError::BalanceOverflow { account } => write!(
+                f,
+                "the ledger entries of account {account} add up to more than an amount can hold"
+            ),
+            RestoreError::BalanceMismatch {
+                account,
+                balance,
+                replayed,
+            } => write!(
+                f,
+                "the events of account {account} add up to {balance} but its ledger entries add up to {replayed}"
+            ),
+            RestoreError::EventsOutOfOrder => f.write_str("the events are not numbered in order"),
+            RestoreError::UnknownEventAccount { account } => {
+                write!(
+                    f,
+                    "events move money of account {account}, which does not exist"
+                )
+            }
+        }
+    }
+}
+
+impl Error for RestoreError {}
+
+/// Why a snapshot cannot be written or read
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Binary(postcard::Error),
+    /// The data is not a snapshot of a known format
+    UnknownFormat,
+    Invalid(RestoreError),
+}
+
+#[cfg(feature = "serde")]
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(error) => write!(f, "cannot access the snapshot: {error}"),
+            SnapshotError::Json(error) => write!(f, "invalid JSON snapshot: {error}"),
+            SnapshotError::Binary(error) => write!(f, "invalid binary snapshot: {error}"),
+            SnapshotError::UnknownFormat => f.write_str("the data is not a bank snapshot"),
+            SnapshotError::Invalid(error) => write!(f, "invalid snapshot: {error}"),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SnapshotError::Io(error) => Some(error),
+            SnapshotError::Json(error) => Some(error),
+            SnapshotError::Binary(error) => Some(error),
+            SnapshotError::UnknownFormat => None,
+            SnapshotError::Invalid(error) => Some(error),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<std::io::Error> for SnapshotError {
+    fn from(error: std::io::Error) -> Self {
+        SnapshotError::Io(error)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<serde_json::Error> for SnapshotError {
+    fn from(error: serde_json::Error) -> Self {
+        SnapshotError::Json(error)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<postcard::Error> for SnapshotError {
+    fn from(error: postcard::Error) -> Self {
+        SnapshotError::Binary(error)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<RestoreError> for SnapshotError {
+    fn from(error: RestoreError) -> Self {
+        SnapshotError::Invalid(error)
+    }
+}
+
+/// Why the write-ahead log of a bank cannot be written or recovered
+#[cfg(feature = "wal")]
+#[derive(Debug)]
+pub enum WalError {
+    Io(std::io::Error),
+    Encoding(postcard::Error),
+    Snapshot(SnapshotError),
+    /// The directory holds no checkpoint to recover from
+    NoCheckpoint,
+}
+
+#[cfg(feature = "wal")]
+impl fmt::Display for WalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalError::Io(error) => write!(f, "cannot access the write-ahead log: {error}"),
+            WalError::Encoding(error) => write!(f, "cannot encode a log record: {error}"),
+            WalError::Snapshot(error) => error.fmt(f),
+            WalError::NoCheckpoint => f.write_str("no checkpoint to recover the bank from"),
+        }
+    }
+}
+
+#[cfg(feature = "wal")]
+impl Error for WalError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WalError::Io(error) => Some(error),
+            WalError::Encoding(error) => Some(error),
+            WalError::Snapshot(error) => Some(error),
+            WalError::NoCheckpoint => None,
+        }
+    }
+}
+
+#[cfg(feature = "wal")]
+impl From<std::io::Error> for WalError {
+    fn from(error: std::io::Error) -> Self {
+        WalError::Io(error)
+    }
+}
+
+#[cfg(feature = "wal")]
+impl From<postcard::Error> for WalError {
+    fn from(error: postcard::Error) -> Self {
+        WalError::Encoding(error)
+    }
+}
+
+#[cfg(feature = "wal")]
+impl From<SnapshotError> for WalError {
+    fn from(error: SnapshotError) -> Self {
+        WalError::Snapshot(error)
+    }
+}
+
+/// Why a mutation of a durable bank was not applied
+#[cfg(feature = "wal")]
+#[derive(Debug)]
+pub enum DurableError<E = std::convert::Infallible> {
+    /// The bank refused the mutation, which left it unchanged
+    Rejected(E),
+    /// What the mutation changed could not be logged, so the bank is recovered without it
+    Log(WalError),
+}
+
+#[cfg(feature = "wal")]
+impl<E: fmt::Display> fmt::Display for DurableError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DurableError::Rejected(error) => error.fmt(f),
+            DurableError::Log(error) => error.fmt(f),
+        }
+    }
+}
+
+#[cfg(feature = "wal")]
+impl<E: Error + 'static> Error for DurableError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DurableError::Rejected(error) => Some(error),
+            DurableError::Log(error) => Some(error),
+        }
+    }
+}
+
+#[cfg(feature = "wal")]
+impl<E> From<WalError> for DurableError<E> {
+    fn from(error: WalError) -> Self {
+        DurableError::Log(error)
+    }
+}
+
+/// Failure of a bank repository
+#[derive(Debug)]
+pub enum RepositoryError {
+    /// The repository holds no bank
+    NoBank,
+    /// The repository already holds a bank
+    AlreadyExists,
+    /// The stored bank is not a valid bank
+    Invalid(RestoreError),
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+    /// A stored value cannot be encoded or decoded
+    #[cfg(feature = "sqlite")]
+    Encoding(serde_json::Error),
+    /// The database was migrated by a newer version of the schema
+    #[cfg(feature = "sqlite")]
+    UnsupportedSchema { found: u32, supported: u32 },
+}
+
+impl fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepositoryError::NoBank => f.write_str("the repository holds no bank"),
+            RepositoryError::AlreadyExists => f.write_str("the repository already holds a bank"),
+            RepositoryError::Invalid(error) => write!(f, "invalid stored bank: {error}"),
+            #[cfg(feature = "sqlite")]
+            RepositoryError::Sqlite(error) => write!(f, "database error: {error}"),
+            #[cfg(feature = "sqlite")]
+            RepositoryError::Encoding(error) => write!(f, "invalid stored value: {error}"),
+            #[cfg(feature = "sqlite")]
+            RepositoryError::UnsupportedSchema { found, supported } => write!(
+                f,
+                "database schema version {found} is not supported, the latest is {supported}"
+            ),
+        }
+    }
+}
+
+impl Error for RepositoryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RepositoryError::Invalid(error) => Some(error),
+            #[cfg(feature = "sqlite")]
+            RepositoryError::Sqlite(error) => Some(error),
+            #[cfg(feature = "sqlite")]
+            RepositoryError::Encoding(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<RestoreError> for RepositoryError {
+    fn from(error: RestoreError) -> Self {
+        RepositoryError::Invalid(error)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for RepositoryError {
+    fn from(error: rusqlite::Error) -> Self {
+        RepositoryError::Sqlite(error)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<serde_json::Error> for RepositoryError {
+    fn from(error: serde_json::Error) -> Self {
+        RepositoryError::Encoding(error)
+    }
+}
+
+/// Why a mutation of a stored bank did not take effect
+#[derive(Debug)]
+pub enum StorageError<E = std::convert::Infallible> {
+    /// The bank refused the mutation, so there was nothing to save
+    Rejected(E),
+    /// The mutation could not be saved, so the bank was reloaded from the repository
+    Repository(RepositoryError),
+}
+
+impl<E: fmt::Display> fmt::Display for StorageError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Rejected(error) => error.fmt(f),
+            StorageError::Repository(error) => error.fmt(f),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for StorageError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StorageError::Rejected(error) => Some(error),
+            StorageError::Repository(error) => Some(error),
+        }
+    }
+}
+
+impl<E> From<RepositoryError> for StorageError<E> {
+    fn from(error: RepositoryError) -> Self {
+        StorageError::Repository(error)
+    }
+}
+
+/// What is wrong with a row of an imported file
+#[cfg(feature = "bulk")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RowProblem {
+    /// The row is not a user, e.g. a column is missing
+    Malformed {
+        reason: String,
+    },
+    EmptyName,
+    /// An earlier row holds the same name
+    DuplicateName {
+        name: String,
+        first_line: u64,
+    },
+    /// An open account of the bank is held by the name
+    NameTaken {
+        name: String,
+        account: AccountId,
+    },
+    UnknownCurrency {
+        currency: String,
+    },
+    MalformedAmount {
+        column: &'static str,
+        value: String,
+        source: ParseMoneyError,
+    },
+    NegativeCreditLine {
+        credit_line: Money,
+    },
+    /// The balance is below the credit line, which only an open account can come to
+    OverLimit {
+        balance: Money,
+        credit_line: Money,
+    },
+}
+
+#[cfg(feature = "bulk")]
+impl fmt::Display for RowProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RowProblem::Malformed { reason } => write!(f, "malformed row: {reason}"),
+            RowProblem::EmptyName => f.write_str("name must not be empty"),
+            RowProblem::DuplicateName { name, first_line } => {
+                write!(f, "'{name}' is already imported on line {first_line}")
+            }
+            RowProblem::NameTaken { name, account } => {
+                write!(f, "'{name}' already holds account {account}")
+            }
+            RowProblem::UnknownCurrency { currency } => {
+                write!(f, "unknown currency '{currency}'")
+            }
+            RowProblem::MalformedAmount {
+                column,
+                value,
+                source,
+            } => write!(f, "invalid {column} '{value}': {source}"),
+            RowProblem::NegativeCreditLine { credit_line } => {
+                write!(f, "credit line {credit_line} must not be negative")
+            }
+            RowProblem::OverLimit {
+                balance,
+                credit_line,
+            } => write!(
+                f,
+                "balance {balance} is below the credit line of {credit_line}"
+            ),
+        }
+    }
+}
+
+/// A problem with the row on `line` of an imported file, counted from 1
+#[cfg(feature = "bulk")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RowError {
+    pub line: u64,
+    pub problem: RowProblem,
+}
+
+#[cfg(feature = "bulk")]
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.problem)
+    }
+}
+
+#[cfg(feature = "bulk")]
+impl Error for RowError {}
+
+/// Why users could not be imported. Nothing is imported when importing fails.
+#[cfg(feature = "bulk")]
+#[derive(Debug)]
+pub enum ImportError {
+    Io(std::io::Error),
+    /// Every problem found in the file, in line order
+    InvalidRows(Vec<RowError>),
+}
+
+#[cfg(feature = "bulk")]
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Io(error) => write!(f, "cannot read the file: {error}"),
+            ImportError::InvalidRows(errors) => {
+                write!(f, "{} invalid rows", errors.len())?;
+                for error in errors {
+                    write!(f, "\n{error}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "bulk")]
+impl Error for ImportError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ImportError::Io(error) => Some(error),
+            ImportError::InvalidRows(_) => None,
+        }
+    }
+}
+
+#[cfg(feature = "bulk")]
+impl From<std::io::Error> for ImportError {
+    fn from(error: std::io::Error) -> Self {
+        ImportError::Io(error)
+    }
+}
+
+/// Why two banks cannot be merged. Neither bank is changed when merging fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MergeError {
+    /// Several accounts of one of the banks are held by `username`, so matching by name is
+    /// ambiguous
+    AmbiguousName { username: String },
+    /// The explicit mapping refers to an account missing from the merged bank
+    UnknownSourceAccount { account: AccountId },
+    /// The explicit mapping refers to an account missing from the bank merged into
+    UnknownTargetAccount { account: AccountId },
+    /// The explicit mapping refers to a closed account of the bank merged into
+    ClosedTargetAccount { account: AccountId },
+    /// Accounts of the merged bank match existing ones and the merge policy rejects conflicts
+    Conflicts { accounts: Vec<AccountId> },
+    /// A combined balance or credit line of `account` of the merged bank does not fit in a
+    /// `Money`
+    Overflow {
+        account: AccountId,
+        source: MoneyError,
+    },
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::AmbiguousName { username } => {
+                write!(f, "several accounts are held by '{username}'")
+            }
+            MergeError::UnknownSourceAccount { account } => {
+                write!(f, "account {account} does not exist in the merged bank")
+            }
+            MergeError::UnknownTargetAccount { account } => {
+                write!(
+                    f,
+                    "account {account} does not exist in the bank merged into"
+                )
+            }
+            MergeError::ClosedTargetAccount { account } => {
+                write!(f, "account {account} of the bank merged into is closed")
+            }
+            MergeError::Conflicts { accounts } => {
+                let accounts: Vec<String> = accounts.iter().map(|id| id.to_string()).collect();
+                write!(
+                    f,
+                    "accounts {} match existing accounts",
+                    accounts.join(", ")
+                )
+            }
+            MergeError::Overflow { account, .. } => {
+                write!(f, "merging account {account} would overflow")
+            }
+        }
+    }
+}
+
+impl Error for MergeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MergeError::Overflow { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Why interest cannot be accrued over a date range
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterestError {
+    /// The range ends before it starts
+    InvalidRange { from: NaiveDate, to: NaiveDate },
+    /// The range starts before `through`, the end of the range accrued last
+    AlreadyAccrued { through: NaiveDate },
+}
+
+impl fmt::Display for InterestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterestError::InvalidRange { from, to } => {
+                write!(f, "the range from {from} to {to} ends before it starts")
+            }
+            InterestError::AlreadyAccrued { through } => {
+                write!(f, "interest was already accrued until {through}")
+            }
+        }
+    }
+}
+
+impl Error for InterestError {}
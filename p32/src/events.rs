@@ -0,0 +1,585 @@
+use crate::clock::Timestamp;
+use crate::currency::Currency;
+use crate::ledger::EntryKind;
+#[cfg(feature = "serde")]
+use crate::ledger::LedgerEntry;
+use crate::money::{Money, MoneyError};
+use crate::{AccountId, BalanceSheet, BalanceTotals};
+use chrono::NaiveDate;
+use std::collections::BTreeMap;
+
+/// Something that changed the balances of a bank, recorded as it happens. Replaying the events
+/// of a bank rebuilds the balances of its accounts, which is how a restored bank gets them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BankEvent {
+    /// An account was opened with `balances`, which are empty for accounts opened by a merge
+    AccountOpened {
+        account: AccountId,
+        username: String,
+        currency: Currency,
+        credit_line: Money,
+        balances: Vec<Money>,
+    },
+    /// Money moved from `sender` to `receiver`, which received `converted_amount` instead of
+    /// `amount` if present. A missing side is the bank itself, e.g. the receiver of a fee.
+    FundsTransferred {
+        /// A transfer, an overdraft fee or the settlement of a closed account
+        kind: EntryKind,
+        sender: Option<AccountId>,
+        receiver: Option<AccountId>,
+        amount: Money,
+        converted_amount: Option<Money>,
+    },
+    /// Interest was added to the balance of `account`; debit interest is negative
+    InterestAccrued { account: AccountId, interest: Money },
+    /// Another bank was merged into this one, and its balances credited to the accounts they
+    /// were merged into. Zero balances are credited too, so that replaying the merge gives the
+    /// accounts every currency they held.
+    BanksMerged {
+        /// Empty for merges recorded before events were
+        bank: String,
+        credited: Vec<(AccountId, Money)>,
+    },
+}
+
+impl BankEvent {
+    /// The kind of the ledger entries the event recorded
+    pub fn kind(&self) -> EntryKind {
+        match self {
+            BankEvent::AccountOpened { .. } => EntryKind::OpeningBalance,
+            BankEvent::FundsTransferred { kind, .. } => *kind,
+            BankEvent::InterestAccrued { .. } => EntryKind::InterestAccrual,
+            BankEvent::BanksMerged { .. } => EntryKind::Merge,
+        }
+    }
+
+    /// How the event changed the balances of accounts: positive amounts were credited and
+    /// negative ones debited
+    pub fn movements(&self) -> Vec<(AccountId, Money)> {
+        match self {
+            BankEvent::AccountOpened {
+                account, balances, ..
+            } => balances
+                .iter()
+                .map(|balance| (*account, *balance))
+                .collect(),
+            BankEvent::FundsTransferred {
+                sender,
+                receiver,
+                amount,
+                converted_amount,
+                ..
+            } => {
+                let debited = Money::from_minor(-amount.minor(), amount.currency());
+                let credited = converted_amount.unwrap_or(*amount);
+                let sender = sender.map(|sender| (sender, debited));
+                let receiver = receiver.map(|receiver| (receiver, credited));
+                sender.into_iter().chain(receiver).collect()
+            }
+            BankEvent::InterestAccrued { account, interest } => vec![(*account, *interest)],
+            BankEvent::BanksMerged { credited, .. } => credited.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RecordedEvent {
+    sequence: u64,
+    timestamp: Timestamp,
+    event: BankEvent,
+}
+
+impl RecordedEvent {
+    /// Position of the event among the events of its bank, from zero
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
+
+    pub fn event(&self) -> &BankEvent {
+        &self.event
+    }
+}
+
+/// Append-only history of every event of a bank
+#[derive(Default)]
+pub struct EventLog {
+    events: Vec<RecordedEvent>,
+}
+
+impl EventLog {
+    pub fn events(&self) -> &[RecordedEvent] {
+        &self.events
+    }
+
+    /// Applies every event to `projection`, oldest first
+    pub fn replay<P: Projection + ?Sized>(&self, projection: &mut P) {
+        for event in &self.events {
+            projection.apply(event);
+        }
+    }
+
+    /// Applies the events recorded at or before `as_of` to `projection`, which then shows the
+    /// bank as it was at that time
+    pub fn replay_as_of<P: Projection + ?Sized>(&self, projection: &mut P, as_of: Timestamp) {
+        for event in self.events.iter().filter(|event| event.timestamp <= as_of) {
+            projection.apply(event);
+        }
+    }
+
+    /// Log holding `events`, which must be numbered in order from zero
+    pub(crate) fn from_events(events: Vec<RecordedEvent>) -> Option<Self> {
+        let in_order = events
+            .iter()
+            .enumerate()
+            .all(|(index, event)| event.sequence == index as u64);
+        in_order.then_some(EventLog { events })
+    }
+
+    /// The events that `ledger`, recorded by a bank before it recorded events, stands for.
+    ///
+    /// The opening balances of an account become one `AccountOpened` with the name, currency and
+    /// credit line `accounts` holds for it, and the entries of a merge one `BanksMerged` without
+    /// the name of the merged bank. A merge ends where the timestamp changes or an account is
+    /// credited again in the same currency. Accounts opened without a balance have no event,
+    /// which replays them alike.
+    #[cfg(feature = "serde")]
+    pub(crate) fn from_ledger(
+        ledger: &[LedgerEntry],
+        accounts: &BTreeMap<AccountId, (String, Currency, Money)>,
+    ) -> Self {
+        let mut events = EventLog::default();
+        let mut pending: Option<(&LedgerEntry, BankEvent)> = None;
+        for entry in ledger {
+            let event = match (entry.kind(), entry.receiver()) {
+                (EntryKind::OpeningBalance, Some(account)) => {
+                    if let Some((
+                        _,
+                        BankEvent::AccountOpened {
+                            account: opened,
+                            balances,
+                            ..
+                        },
+                    )) = &mut pending
+                        && *opened == account
+                    {
+                        balances.push(entry.amount());
+                        continue;
+                    }
+                    // Only a bank changed behind its back lacks the account
+                    let Some((username, currency, credit_line)) = accounts.get(&account).cloned()
+                    else {
+                        continue;
+                    };
+                    BankEvent::AccountOpened {
+                        account,
+                        username,
+                        currency,
+                        credit_line,
+                        balances: vec![entry.amount()],
+                    }
+                }
+                (EntryKind::InterestAccrual, Some(account)) => BankEvent::InterestAccrued {
+                    account,
+                    interest: entry.amount(),
+                },
+                (EntryKind::Merge, Some(account)) => {
+                    if let Some((first, BankEvent::BanksMerged { credited, .. })) = &mut pending
+                        && first.timestamp() == entry.timestamp()
+                        && !credited.iter().any(|(credited, amount)| {
+                            *credited == account && amount.currency() == entry.amount().currency()
+                        })
+                    {
+                        credited.push((account, entry.amount()));
+                        continue;
+                    }
+                    BankEvent::BanksMerged {
+                        bank: String::new(),
+                        credited: vec![(account, entry.amount())],
+                    }
+                }
+                (kind, receiver) => BankEvent::FundsTransferred {
+                    kind,
+                    sender: entry.sender(),
+                    receiver,
+                    amount: entry.amount(),
+                    converted_amount: entry.converted_amount(),
+                },
+            };
+            if let Some((first, event)) = pending.replace((entry, event)) {
+                events.record(first.timestamp(), event);
+            }
+        }
+        if let Some((first, event)) = pending {
+            events.record(first.timestamp(), event);
+        }
+        events
+    }
+
+    pub(crate) fn record(&mut self, timestamp: Timestamp, event: BankEvent) {
+        self.events.push(RecordedEvent {
+            sequence: self.events.len() as u64,
+            timestamp,
+            event,
+        });
+    }
+}
+
+/// A view of a bank built from its events, such as the balances of its accounts.
+///
+/// Projections are built by [`EventLog::replay`] or [`EventLog::replay_as_of`], which apply the
+/// events one at a time in the order they were recorded.
+pub trait Projection {
+    fn apply(&mut self, event: &RecordedEvent);
+}
+
+/// Balance of every account in every currency it holds
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Balances {
+    balances: BTreeMap<(AccountId, Currency), i64>,
+}
+
+impl Balances {
+    pub fn balance(&self, account: AccountId, currency: Currency) -> Money {
+        let balance = self.balances.get(&(account, currency)).copied();
+        Money::from_minor(balance.unwrap_or(0), currency)
+    }
+
+    /// Every balance, by account then currency
+    pub fn iter(&self) -> impl Iterator<Item = (AccountId, Money)> + '_ {
+        self.balances.iter().map(|((account, currency), balance)| {
+            (*account, Money::from_minor(*balance, *currency))
+        })
+    }
+
+    /// Balance sheet of every currency held. The equity is the money the events put into the
+    /// accounts, so the balance sheets always balance.
+    ///
+    /// Fails with [`MoneyError::Overflow`] if a total does not fit in a [`Money`].
+    pub fn balance_sheets(&self) -> Result<BTreeMap<Currency, BalanceSheet>, MoneyError> {
+        let mut totals: BTreeMap<Currency, BalanceTotals> = BTreeMap::new();
+        for (_, balance) in self.iter() {
+            totals
+                .entry(balance.currency())
+                .or_insert_with(|| BalanceTotals::new(balance.currency()))
+                .add(balance);
+        }
+        totals
+            .into_iter()
+            .map(|(currency, totals)| {
+                let equity = Money::from_total(totals.net(), currency)?;
+                Ok((currency, totals.balance_sheet(equity)?))
+            })
+            .collect()
+    }
+}
+
+impl Projection for Balances {
+    fn apply(&mut self, event: &RecordedEvent) {
+        for (account, amount) in event.event.movements() {
+            let balance = self
+                .balances
+                .entry((account, amount.currency()))
+                .or_default();
+            *balance = balance.wrapping_add(amount.minor());
+        }
+    }
+}
+
+/// A change to the balance of an account
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatementLine {
+    /// Sequence of the event that made the change
+    pub sequence: u64,
+    pub timestamp: Timestamp,
+    pub kind: EntryKind,
+    /// Positive when credited and negative when debited
+    pub amount: Money,
+    /// Balance in the currency of `amount` after the change
+    pub balance: Money,
+}
+
+/// Every change to the balances of one account, oldest first
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Statement {
+    account: AccountId,
+    lines: Vec<StatementLine>,
+    balances: BTreeMap<Currency, i64>,
+}
+
+impl Statement {
+    pub fn new(account: AccountId) -> Self {
+        Statement {
+            account,
+            lines: vec![],
+            balances: BTreeMap::new(),
+        }
+    }
+
+    pub fn account(&self) -> AccountId {
+        self.account
+    }
+
+    pub fn lines(&self) -> &[StatementLine] {
+        &self.lines
+    }
+
+    /// Balance after the last line in `currency`
+    pub fn closing_balance(&self, currency: Currency) -> Money {
+        Money::from_minor(self.balances.get(&currency).copied().unwrap_or(0), currency)
+    }
+}
+
+impl Projection for Statement {
+    fn apply(&mut self, event: &RecordedEvent) {
+        for (account, amount) in event.event.movements() {
+            if account != self.account {
+                continue;
+            }
+            let balance = self.balances.entry(amount.currency()).or_default();
+            *balance = balance.wrapping_add(amount.minor());
+            self.lines.push(StatementLine {
+                sequence: event.sequence,
+                timestamp: event.timestamp,
+                kind: event.event.kind(),
+                amount,
+                balance: Money::from_minor(*balance, amount.currency()),
+            });
+        }
+    }
+}
+
+/// Money credited to and debited from the accounts during one day
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DailyTotal {
+    pub credited: Money,
+    /// Never negative
+    pub debited: Money,
+}
+
+/// Totals of every day and currency with events, by UTC day.
+///
+/// Totals are added up in 128 bits, and reading one that does not fit in a [`Money`] fails with
+/// [`MoneyError::Overflow`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DailyTotals {
+    /// Credited and debited in minor units
+    totals: BTreeMap<(NaiveDate, Currency), (i128, i128)>,
+}
+
+impl DailyTotals {
+    pub fn get(
+        &self,
+        day: NaiveDate,
+        currency: Currency,
+    ) -> Option<Result<DailyTotal, MoneyError>> {
+        let totals = self.totals.get(&(day, currency))?;
+        Some(daily_total(*totals, currency))
+    }
+
+    /// Every total, by day then currency
+    pub fn iter(&self) -> impl Iterator<Item = (NaiveDate, Result<DailyTotal, MoneyError>)> + '_ {
+        self.totals
+            .iter()
+            .map(|((day, currency), totals)| (*day, daily_total(*totals, *currency)))
+    }
+}
+
+fn daily_total(
+    (credited, debited): (i128, i128),
+    currency: Currency,
+) -> Result<DailyTotal, MoneyError> {
+    Ok(DailyTotal {
+        credited: Money::from_total(credited, currency)?,
+        debited: Money::from_total(debited, currency)?,
+    })
+}
+
+impl Projection for DailyTotals {
+    fn apply(&mut self, event: &RecordedEvent) {
+        let day = event.timestamp.date_naive();
+        for (_, amount) in event.event.movements() {
+            let (credited, debited) = self.totals.entry((day, amount.currency())).or_default();
+            match amount.is_negative() {
+                false => *credited += i128::from(amount.minor()),
+                true => *debited -= i128::from(amount.minor()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{eur, usd};
+
+    /// Noon of `day` of March 2024
+    fn march(day: u32) -> Timestamp {
+        NaiveDate::from_ymd_opt(2024, 3, day)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+            .and_utc()
+    }
+
+    fn opened(account: u64, balances: Vec<Money>) -> BankEvent {
+        BankEvent::AccountOpened {
+            account: AccountId::new(account),
+            username: format!("name{account}"),
+            currency: balances[0].currency(),
+            credit_line: Money::zero(balances[0].currency()),
+            balances,
+        }
+    }
+
+    fn transferred(sender: u64, receiver: u64, amount: Money) -> BankEvent {
+        BankEvent::FundsTransferred {
+            kind: EntryKind::Transfer,
+            sender: Some(AccountId::new(sender)),
+            receiver: Some(AccountId::new(receiver)),
+            amount,
+            converted_amount: None,
+        }
+    }
+
+    fn log() -> EventLog {
+        let mut log = EventLog::default();
+        log.record(march(1), opened(0, vec![eur(100), usd(20)]));
+        log.record(march(1), opened(1, vec![eur(0)]));
+        log.record(march(2), transferred(0, 1, eur(150)));
+        log.record(
+            march(2),
+            BankEvent::FundsTransferred {
+                kind: EntryKind::Transfer,
+                sender: Some(AccountId::new(1)),
+                receiver: Some(AccountId::new(0)),
+                amount: eur(50),
+                converted_amount: Some(usd(55)),
+            },
+        );
+        log.record(
+            march(3),
+            BankEvent::InterestAccrued {
+                account: AccountId::new(1),
+                interest: eur(1),
+            },
+        );
+        log.record(
+            march(4),
+            BankEvent::BanksMerged {
+                bank: "Other".to_string(),
+                credited: vec![(AccountId::new(0), eur(70)), (AccountId::new(2), eur(5))],
+            },
+        );
+        log
+    }
+
+    #[test]
+    fn balances_replay_every_event() {
+        let mut balances = Balances::default();
+        log().replay(&mut balances);
+
+        let (id0, id1) = (AccountId::new(0), AccountId::new(1));
+        assert_eq!(balances.balance(id0, Currency::EUR), eur(20));
+        assert_eq!(balances.balance(id0, Currency::USD), usd(75));
+        assert_eq!(balances.balance(id1, Currency::EUR), eur(101));
+        assert_eq!(balances.balance(id1, Currency::GBP).minor(), 0);
+        assert_eq!(balances.iter().count(), 4);
+
+        let sheets = balances.balance_sheets().unwrap();
+        assert_eq!(sheets[&Currency::EUR].assets, eur(126));
+        assert_eq!(sheets[&Currency::EUR].liabilities, eur(0));
+        assert!(sheets.values().all(BalanceSheet::is_balanced));
+    }
+
+    #[test]
+    fn replay_as_of_leaves_later_events_out() {
+        let mut balances = Balances::default();
+        log().replay_as_of(&mut balances, march(2));
+
+        let sheets = balances.balance_sheets().unwrap();
+        assert_eq!(sheets[&Currency::EUR].assets, eur(100));
+        assert_eq!(sheets[&Currency::EUR].liabilities, eur(50));
+        assert_eq!(sheets[&Currency::EUR].equity, eur(50));
+        assert_eq!(sheets[&Currency::USD].assets, usd(75));
+    }
+
+    #[test]
+    fn totals_beyond_money_are_reported_as_overflow() {
+        let half = eur(i64::MAX / 2 + 1);
+        let mut log = EventLog::default();
+        log.record(
+            march(1),
+            BankEvent::BanksMerged {
+                bank: "Other".to_string(),
+                credited: vec![(AccountId::new(0), half), (AccountId::new(1), half)],
+            },
+        );
+        let mut balances = Balances::default();
+        let mut totals = DailyTotals::default();
+        log.replay(&mut balances);
+        log.replay(&mut totals);
+
+        assert_eq!(balances.balance_sheets(), Err(MoneyError::Overflow));
+        let day = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        assert_eq!(
+            totals.get(day, Currency::EUR),
+            Some(Err(MoneyError::Overflow))
+        );
+    }
+
+    #[test]
+    fn statements_follow_one_account() {
+        let mut statement = Statement::new(AccountId::new(1));
+        log().replay(&mut statement);
+
+        let lines: Vec<_> = statement
+            .lines()
+            .iter()
+            .map(|line| (line.sequence, line.kind, line.amount, line.balance))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (1, EntryKind::OpeningBalance, eur(0), eur(0)),
+                (2, EntryKind::Transfer, eur(150), eur(150)),
+                (3, EntryKind::Transfer, eur(-50), eur(100)),
+                (4, EntryKind::InterestAccrual, eur(1), eur(101)),
+            ]
+        );
+        assert_eq!(statement.closing_balance(Currency::EUR), eur(101));
+    }
+
+    #[test]
+    fn daily_totals_split_credits_and_debits() {
+        let mut totals = DailyTotals::default();
+        log().replay(&mut totals);
+
+        let day = |day| NaiveDate::from_ymd_opt(2024, 3, day).unwrap();
+        assert_eq!(
+            totals.get(day(2), Currency::EUR),
+            Some(Ok(DailyTotal {
+                credited: eur(150),
+                debited: eur(200),
+            }))
+        );
+        assert_eq!(
+            totals.get(day(2), Currency::USD).unwrap().unwrap().credited,
+            usd(55)
+        );
+        assert_eq!(totals.get(day(5), Currency::EUR), None);
+        assert_eq!(totals.iter().count(), 6);
+    }
+
+    #[test]
+    fn logs_are_numbered_in_order() {
+        let events = log().events;
+        assert!(EventLog::from_events(events.clone()).is_some());
+        assert!(EventLog::from_events(events[1..].to_vec()).is_none());
+    }
+}
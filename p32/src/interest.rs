@@ -0,0 +1,237 @@
+use crate::AccountId;
+use crate::currency::Currency;
+use crate::money::{BasisPoints, Money, Rounding};
+use chrono::{Datelike, NaiveDate};
+use std::collections::BTreeMap;
+
+/// Accrued interest is kept in billionths of the minor unit until it is posted
+const ACCRUAL_SCALE: i128 = 1_000_000_000;
+
+/// How the days of an accrual period and of a year are counted
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DayCount {
+    /// Actual days over a 360-day year
+    Act360,
+    /// Actual days over a 365-day year
+    #[default]
+    Act365,
+    /// 30-day months over a 360-day year, following the US bond basis
+    Thirty360,
+}
+
+impl DayCount {
+    /// Days counted from `from`, inclusive, to `to`, exclusive
+    pub fn days(&self, from: NaiveDate, to: NaiveDate) -> i64 {
+        match self {
+            DayCount::Act360 | DayCount::Act365 => (to - from).num_days(),
+            DayCount::Thirty360 => {
+                let from_day = from.day().min(30) as i64;
+                let to_day = match to.day() == 31 && from_day == 30 {
+                    true => 30,
+                    false => to.day() as i64,
+                };
+                360 * (to.year() - from.year()) as i64
+                    + 30 * (to.month() as i64 - from.month() as i64)
+                    + (to_day - from_day)
+            }
+        }
+    }
+
+    pub fn days_per_year(&self) -> i64 {
+        match self {
+            DayCount::Act360 | DayCount::Thirty360 => 360,
+            DayCount::Act365 => 365,
+        }
+    }
+}
+
+/// How often accrued interest is posted to the balances, which then earn interest themselves
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Compounding {
+    Daily,
+    #[default]
+    Monthly,
+    Annually,
+}
+
+impl Compounding {
+    /// First day of the compounding period following the one `date` is in
+    pub fn next_period_start(&self, date: NaiveDate) -> NaiveDate {
+        let next = match self {
+            Compounding::Daily => date.succ_opt(),
+            Compounding::Monthly => match date.month() {
+                12 => NaiveDate::from_ymd_opt(date.year() + 1, 1, 1),
+                month => NaiveDate::from_ymd_opt(date.year(), month + 1, 1),
+            },
+            Compounding::Annually => NaiveDate::from_ymd_opt(date.year() + 1, 1, 1),
+        };
+        next.expect("date out of range")
+    }
+}
+
+/// Conventions of the interest accrued by `Bank::accrue_interest_between`.
+///
+/// The interest rates of the bank are annual rates. Interest accrues with sub-minor-unit
+/// precision and is rounded with `rounding` when posted; the rounding difference stays accrued.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InterestConventions {
+    pub day_count: DayCount,
+    pub compounding: Compounding,
+    pub rounding: Rounding,
+}
+
+/// Interest accrued but not posted yet, per account and currency
+#[derive(Default)]
+pub(crate) struct InterestAccruals {
+    pub(crate) conventions: InterestConventions,
+    accrued: BTreeMap<(AccountId, Currency), i128>,
+    /// End of the last accrued period, exclusive
+    pub(crate) accrued_through: Option<NaiveDate>,
+}
+
+impl InterestAccruals {
+    /// Accrues the interest `balance` earns at the annual `rate` from `from` to `to`. Interest
+    /// that does not fit in the accrual is not accrued.
+    pub(crate) fn accrue(
+        &mut self,
+        account: AccountId,
+        balance: Money,
+        rate: BasisPoints,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) {
+        let day_count = self.conventions.day_count;
+        let interest = (balance.minor() as i128)
+            .checked_mul(rate.value() as i128)
+            .and_then(|interest| interest.checked_mul(day_count.days(from, to) as i128))
+            .and_then(|interest| interest.checked_mul(ACCRUAL_SCALE))
+            .map(|interest| {
+                Rounding::HalfEven.divide(interest, 10_000 * day_count.days_per_year() as i128)
+            });
+        let Some(interest) = interest else {
+            return;
+        };
+        let accrued = self
+            .accrued
+            .entry((account, balance.currency()))
+            .or_default();
+        if let Some(total) = accrued.checked_add(interest) {
+            *accrued = total;
+        }
+    }
+
+    /// Accrued interest, rounded to the minor unit
+    pub(crate) fn accrued(&self, account: AccountId, currency: Currency) -> Money {
+        let accrued = self.accrued.get(&(account, currency)).copied().unwrap_or(0);
+        let rounded = self.conventions.rounding.divide(accrued, ACCRUAL_SCALE);
+        Money::from_minor(i64::try_from(rounded).unwrap_or(0), currency)
+    }
+
+    /// Removes `posted` from the accrued interest, leaving the rounding difference
+    pub(crate) fn posted(&mut self, account: AccountId, posted: Money) {
+        let key = (account, posted.currency());
+        if let Some(accrued) = self.accrued.get_mut(&key) {
+            *accrued -= posted.minor() as i128 * ACCRUAL_SCALE;
+        }
+    }
+
+    /// Accrued interest of every account and currency, in billionths of the minor unit
+    pub(crate) fn raw_accruals(&self) -> impl Iterator<Item = (AccountId, Currency, i128)> + '_ {
+        self.accrued
+            .iter()
+            .map(|((account, currency), accrued)| (*account, *currency, *accrued))
+    }
+
+    pub(crate) fn restore_accrual(
+        &mut self,
+        account: AccountId,
+        currency: Currency,
+        accrued: i128,
+    ) {
+        self.accrued.insert((account, currency), accrued);
+    }
+
+    /// Drops the rounding differences left in the accruals of `account`
+    pub(crate) fn discard(&mut self, account: AccountId) {
+        self.accrued.retain(|(id, _), _| *id != account);
+    }
+
+    pub(crate) fn accounts(&self) -> Vec<(AccountId, Currency)> {
+        self.accrued.keys().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::date;
+
+    #[test]
+    fn day_counts() {
+        let (from, to) = (date(2024, 1, 31), date(2024, 3, 1));
+
+        assert_eq!(DayCount::Act360.days(from, to), 30);
+        assert_eq!(DayCount::Act365.days(from, to), 30);
+        assert_eq!(DayCount::Thirty360.days(from, to), 31);
+        assert_eq!(
+            DayCount::Thirty360.days(date(2024, 1, 30), date(2024, 1, 31)),
+            0
+        );
+        assert_eq!(
+            DayCount::Thirty360.days(date(2024, 1, 15), date(2024, 7, 15)),
+            180
+        );
+        assert_eq!(
+            DayCount::Thirty360.days(date(2023, 12, 1), date(2024, 12, 1)),
+            360
+        );
+    }
+
+    #[test]
+    fn compounding_periods() {
+        let day = date(2024, 12, 15);
+
+        assert_eq!(
+            Compounding::Daily.next_period_start(day),
+            date(2024, 12, 16)
+        );
+        assert_eq!(
+            Compounding::Monthly.next_period_start(day),
+            date(2025, 1, 1)
+        );
+        assert_eq!(
+            Compounding::Monthly.next_period_start(date(2024, 2, 1)),
+            date(2024, 3, 1)
+        );
+        assert_eq!(
+            Compounding::Annually.next_period_start(day),
+            date(2025, 1, 1)
+        );
+    }
+
+    #[test]
+    fn accrued_interest_keeps_sub_minor_precision() {
+        let mut accruals = InterestAccruals::default();
+        let account = AccountId::new(0);
+        let balance = Money::from_minor(100, Currency::EUR);
+
+        // 1% of 1.00 EUR over 73 days is 0.2 cents
+        for day in 1..=5 {
+            accruals.accrue(
+                account,
+                balance,
+                BasisPoints::new(100),
+                date(2024, 1, day),
+                date(2024, 1, day) + chrono::Days::new(73),
+            );
+        }
+
+        assert_eq!(
+            accruals.accrued(account, Currency::EUR),
+            Money::from_minor(1, Currency::EUR)
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use crate::AccountId;
+use crate::clock::Timestamp;
+use crate::currency::Currency;
+use crate::money::{Money, MoneyError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EntryId(u64);
+
+impl EntryId {
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EntryKind {
+    OpeningBalance,
+    Transfer,
+    InterestAccrual,
+    Merge,
+    OverdraftFee,
+    /// The remaining balance of a closed account moved to another one
+    Settlement,
+}
+
+/// A single, immutable balance change.
+///
+/// Money always moves from `sender` to `receiver`. A missing side is the bank itself, e.g. the
+/// bank is the sender of the interest paid to a user. The receiver gets `converted_amount`
+/// instead of `amount` when the transfer was converted into another currency.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LedgerEntry {
+    id: EntryId,
+    timestamp: Timestamp,
+    kind: EntryKind,
+    sender: Option<AccountId>,
+    receiver: Option<AccountId>,
+    amount: Money,
+    converted_amount: Option<Money>,
+    sender_balance: Option<Money>,
+    receiver_balance: Option<Money>,
+}
+
+impl LedgerEntry {
+    pub fn id(&self) -> EntryId {
+        self.id
+    }
+
+    pub fn timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
+
+    pub fn kind(&self) -> EntryKind {
+        self.kind
+    }
+
+    pub fn sender(&self) -> Option<AccountId> {
+        self.sender
+    }
+
+    pub fn receiver(&self) -> Option<AccountId> {
+        self.receiver
+    }
+
+    pub fn amount(&self) -> Money {
+        self.amount
+    }
+
+    pub fn converted_amount(&self) -> Option<Money> {
+        self.converted_amount
+    }
+
+    /// The amount credited to the receiver
+    pub fn received_amount(&self) -> Money {
+        self.converted_amount.unwrap_or(self.amount)
+    }
+
+    /// Balance of the sender, in the currency it was debited in, once this entry was applied
+    pub fn sender_balance(&self) -> Option<Money> {
+        self.sender_balance
+    }
+
+    /// Balance of the receiver, in the currency it was credited in, once this entry was applied
+    pub fn receiver_balance(&self) -> Option<Money> {
+        self.receiver_balance
+    }
+
+    fn involves(&self, account: AccountId) -> bool {
+        self.sender == Some(account) || self.receiver == Some(account)
+    }
+}
+
+/// Append-only history of every balance change in a bank
+#[derive(Default)]
+pub struct Ledger {
+    entries: Vec<LedgerEntry>,
+}
+
+impl Ledger {
+    pub fn entries(&self) -> &[LedgerEntry] {
+        &self.entries
+    }
+
+    pub fn entries_for(&self, account: AccountId) -> impl Iterator<Item = &LedgerEntry> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.involves(account))
+    }
+
+    /// Entries involving `account` whose timestamp is in `[from, to]`
+    pub fn entries_for_between(
+        &self,
+        account: AccountId,
+        from: Timestamp,
+        to: Timestamp,
+    ) -> impl Iterator<Item = &LedgerEntry> {
+        self.entries_for(account)
+            .filter(move |entry| from <= entry.timestamp && entry.timestamp <= to)
+    }
+
+    /// Rebuilds the balance of `account` in `currency` from the amounts in the ledger alone.
+    ///
+    /// Fails with [`MoneyError::Overflow`] if the balance does not fit in a [`Money`].
+    pub fn replay_balance(
+        &self,
+        account: AccountId,
+        currency: Currency,
+    ) -> Result<Money, MoneyError> {
+        let balance = self
+            .entries_for(account)
+            .map(|entry| {
+                let mut delta = 0;
+                let received = entry.received_amount();
+                if entry.receiver == Some(account) && received.currency() == currency {
+                    delta += i128::from(received.minor());
+                }
+                if entry.sender == Some(account) && entry.amount.currency() == currency {
+                    delta -= i128::from(entry.amount.minor());
+                }
+                delta
+            })
+            .sum();
+        Money::from_total(balance, currency)
+    }
+
+    /// Ledger holding `entries`, which must be numbered in order from zero
+    pub(crate) fn from_entries(entries: Vec<LedgerEntry>) -> Option<Self> {
+        let in_order = entries
+            .iter()
+            .enumerate()
+            .all(|(index, entry)| entry.id == EntryId(index as u64));
+        in_order.then_some(Ledger { entries })
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        timestamp: Timestamp,
+        kind: EntryKind,
+        sender: Option<(AccountId, Money)>,
+        receiver: Option<(AccountId, Money)>,
+        amount: Money,
+        converted_amount: Option<Money>,
+    ) -> EntryId {
+        let id = EntryId(self.entries.len() as u64);
+        let (sender, sender_balance) = sender.unzip();
+        let (receiver, receiver_balance) = receiver.unzip();
+        self.entries.push(LedgerEntry {
+            id,
+            timestamp,
+            kind,
+            sender,
+            receiver,
+            amount,
+            converted_amount,
+            sender_balance,
+            receiver_balance,
+        });
+        id
+    }
+}
@@ -1,344 +1,3144 @@
-use crate::TransferFundsError::{
-    ReceiverNotExistsError, SenderNotEnoughBalance, SenderNotExistsError,
+pub mod accounting;
+#[cfg(feature = "bulk")]
+pub mod bulk;
+pub mod clock;
+pub mod concurrent;
+pub mod currency;
+pub mod error;
+pub mod events;
+pub mod interest;
+pub mod ledger;
+pub mod merge;
+pub mod money;
+pub mod rates;
+pub mod repository;
+#[cfg(feature = "async")]
+pub mod service;
+pub mod snapshot;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(test)]
+pub(crate) mod test_support;
+mod transfer;
+mod users;
+#[cfg(feature = "wal")]
+pub mod wal;
+
+pub use crate::error::{BatchTransferError, TransferFundsError};
+pub use crate::transfer::Transfer;
+pub use crate::users::AccountId;
+
+use crate::accounting::{Account, Books};
+use crate::clock::{Clock, SystemClock, Timestamp};
+use crate::currency::{CrossCurrencyTransfers, Currency, ExchangeRateProvider};
+use crate::error::{AccountError, AccountNotFound, CreditLineError, InterestError, MergeError};
+use crate::events::{Balances, BankEvent, EventLog};
+use crate::interest::{InterestAccruals, InterestConventions};
+use crate::ledger::{EntryKind, Ledger, LedgerEntry};
+use crate::merge::{
+    AccountReconciliation, ConflictResolution, CreditLines, MergeAction, MergePolicy, MergeReport,
+    MergedAccount,
 };
+use crate::money::{BasisPoints, Money, MoneyError};
+use crate::rates::{FlatRate, RateBand, RatePolicy};
+use crate::transfer::{CheckedTransfer, TransferRules};
+use crate::users::UserStore;
+use chrono::NaiveDate;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+/// What an account is allowed to do
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AccountState {
+    #[default]
+    Active,
+    /// The balance is below the credit line, which was lowered under it or exceeded by debit
+    /// interest. The account only receives funds until its balance is back within the credit
+    /// line.
+    OverLimit,
+    /// The account cannot send funds
+    Frozen,
+    /// The account neither sends nor receives funds and only remains for its history
+    Closed,
+}
 
 pub struct User {
     name: String,
-    credit_line: u64,
-    balance: i64,
+    credit_line: Money,
+    requested_credit_line: Option<Money>,
+    currency: Currency,
+    balances: BTreeMap<Currency, i64>,
+    state: AccountState,
+    rate_policy: Option<Arc<dyn RatePolicy>>,
 }
 
 impl User {
-    pub(crate) fn new(name: String, credit_line: u64, balance: i64) -> Self {
+    /// The currency of `balance` becomes the home currency of the user, which is the only one
+    /// the credit line can be drawn in
+    pub fn new(name: String, credit_line: Money, balance: Money) -> Self {
+        debug_assert_eq!(credit_line.currency(), balance.currency());
         User {
             name,
             credit_line,
-            balance,
+            requested_credit_line: None,
+            currency: balance.currency(),
+            balances: BTreeMap::from([(balance.currency(), balance.minor())]),
+            state: AccountState::Active,
+            rate_policy: None,
+        }
+    }
+
+    /// Adds a balance in another currency
+    pub fn with_balance(mut self, balance: Money) -> Self {
+        self.balances.insert(balance.currency(), balance.minor());
+        self
+    }
+
+    /// Accrues interest following `policy` rather than the rates of the bank
+    pub fn with_rate_policy(mut self, policy: Arc<dyn RatePolicy>) -> Self {
+        self.rate_policy = Some(policy);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn credit_line(&self) -> Money {
+        self.credit_line
+    }
+
+    /// Credit line requested and not approved yet
+    pub fn requested_credit_line(&self) -> Option<Money> {
+        self.requested_credit_line
+    }
+
+    pub fn state(&self) -> AccountState {
+        self.state
+    }
+
+    pub fn balance(&self, currency: Currency) -> Money {
+        Money::from_minor(self.balances.get(&currency).copied().unwrap_or(0), currency)
+    }
+
+    fn set_balance(&mut self, balance: Money) {
+        self.balances.insert(balance.currency(), balance.minor());
+        self.update_limit_state();
+    }
+
+    fn set_credit_line(&mut self, credit_line: Money) {
+        self.credit_line = credit_line;
+        self.update_limit_state();
+    }
+
+    /// Moves an active account over its limit when the balance fell below the credit line, and
+    /// back once it is within it again
+    fn update_limit_state(&mut self) {
+        self.state = self.state_with(self.balance(self.currency));
+    }
+
+    /// The state the account would be in with `balance` instead of its balance in that currency
+    fn state_with(&self, balance: Money) -> AccountState {
+        if balance.currency() != self.currency {
+            return self.state;
+        }
+        match (self.state, self.is_below_credit_line(balance)) {
+            (AccountState::Frozen | AccountState::Closed, _) => self.state,
+            (_, true) => AccountState::OverLimit,
+            (_, false) => AccountState::Active,
+        }
+    }
+
+    /// Whether the balance in the home currency is below the credit line
+    fn is_over_limit(&self) -> bool {
+        self.is_below_credit_line(self.balance(self.currency))
+    }
+
+    fn is_below_credit_line(&self, balance: Money) -> bool {
+        balance
+            .checked_add(self.credit_line)
+            .is_ok_and(|available| available.is_negative())
+    }
+
+    fn holds(&self, currency: Currency) -> bool {
+        self.balances.contains_key(&currency)
+    }
+
+    /// A copy of the user holding the sum of its balances and the ones of `other`
+    fn merged_with(&self, other: &User, credit_line: Money) -> Result<User, MoneyError> {
+        let mut merged = User {
+            name: self.name.clone(),
+            credit_line,
+            requested_credit_line: self.requested_credit_line,
+            currency: self.currency,
+            balances: self.balances.clone(),
+            state: self.state,
+            rate_policy: self.rate_policy.clone(),
+        };
+        for (currency, balance) in &other.balances {
+            let balance = Money::from_minor(*balance, *currency);
+            merged.set_balance(merged.balance(*currency).checked_add(balance)?);
+        }
+        Ok(merged)
+    }
+
+    fn merged_credit_line(
+        &self,
+        other: &User,
+        credit_lines: CreditLines,
+    ) -> Result<Money, MoneyError> {
+        let (own, other) = (self.credit_line, other.credit_line);
+        if own.currency() != other.currency() {
+            return Ok(own);
+        }
+        match credit_lines {
+            CreditLines::Keep => Ok(own),
+            CreditLines::Max => Ok(Money::from_minor(
+                own.minor().max(other.minor()),
+                own.currency(),
+            )),
+            CreditLines::Min => Ok(Money::from_minor(
+                own.minor().min(other.minor()),
+                own.currency(),
+            )),
+            CreditLines::Sum => own.checked_add(other),
         }
     }
 }
 
 pub struct Bank {
-    users: Vec<User>,
+    users: UserStore,
     pub name: String,
-    credit_interest: u64,
-    debit_interest: u64,
+    /// Currency of the balance sheet returned by `calc_balance`
+    pub currency: Currency,
+    credit_interest: BasisPoints,
+    debit_interest: BasisPoints,
+    transfer_rules: TransferRules,
+    interest: InterestAccruals,
+    /// Fee charged per day to over-limit accounts, per currency
+    overdraft_fees: BTreeMap<Currency, Money>,
+    ledger: Ledger,
+    books: Books,
+    events: EventLog,
+    clock: Arc<dyn Clock>,
 }
 
 impl Bank {
-    pub fn merge_bank(&mut self, mut other: Bank) {
-        let mut merged_users: Vec<User> = vec![];
-        // TODO: is there a function call chain to zip by a given property?
-        // Instead of:
-        // self.users.iter().zip(merged_users.iter_mut()).for_each(|(user, merged_user)| {})
-        // Try:
-        // https://docs.rs/itertools/latest/itertools/trait.Itertools.html#method.chunk_by
-        //
-        // self.users.chunk_by(|user| user.name);
-        // Not working because the lambda needs to be a predicate
-        //
-        // Try
-        // v.into_iter().map(|n| (n, f(n))).collect();, from https://www.reddit.com/r/rust/comments/18m24wb/how_to_convert_vec_to_hashmap/
-        // let users_by_name = self.users.into_iter().map(|n| (n.name, n)).collect();
-        // Compilation error = Cannot move
-
-        for user in &mut self.users {
-            let maybe_overlapping_user = other.users.iter_mut().find(|x| x.name == user.name);
-            let mut balance = user.balance;
-            if let Some(overlapping_user) = maybe_overlapping_user {
-                balance += overlapping_user.balance;
-                overlapping_user.balance = 0;
-            }
-            other.users.retain(|x| x.name != user.name);
-            merged_users.push(User::new(user.name.clone(), user.credit_line, balance));
-        }
-
-        for non_overlapping_user in &other.users {
-            merged_users.push(User::new(
-                non_overlapping_user.name.clone(),
-                non_overlapping_user.credit_line,
-                non_overlapping_user.balance,
-            ));
-        }
-
-        self.users = merged_users;
+    /// Moves every account of `other` into this bank as planned by [`Bank::plan_merge`].
+    ///
+    /// Nothing changes if the merge fails.
+    pub fn merge_bank(
+        &mut self,
+        other: Bank,
+        policy: &MergePolicy,
+    ) -> Result<MergeReport, MergeError> {
+        let report = self.plan_merge(&other, policy)?;
+        let conflicts: Vec<AccountId> = report.conflicts().map(|account| account.source).collect();
+        if !conflicts.is_empty() {
+            return Err(MergeError::Conflicts {
+                accounts: conflicts,
+            });
+        }
+        let mut merged_amounts: Vec<(AccountId, Money)> = vec![];
+
+        for ((source, mut other_user), account) in other.users.into_iter().zip(&report.accounts) {
+            debug_assert_eq!(source, account.source);
+            let amounts = other_user.balances.iter().map(|(currency, balance)| {
+                (account.target, Money::from_minor(*balance, *currency))
+            });
+            merged_amounts.extend(amounts);
+            match &account.action {
+                MergeAction::Combined { credit_line } => {
+                    let user = &mut self.users[account.target];
+                    *user = user
+                        .merged_with(&other_user, *credit_line)
+                        .expect("combined balances are checked when planning the merge");
+                }
+                MergeAction::Renamed { username } => {
+                    other_user.name = username.clone();
+                    let id = self.users.insert(other_user);
+                    debug_assert_eq!(id, account.target);
+                    self.record_opened(id, vec![]);
+                }
+                MergeAction::Opened => {
+                    let id = self.users.insert(other_user);
+                    debug_assert_eq!(id, account.target);
+                    self.record_opened(id, vec![]);
+                }
+                MergeAction::Conflict => unreachable!("conflicts are rejected above"),
+            }
+        }
+
+        for (id, amount) in merged_amounts
+            .iter()
+            .filter(|(_, amount)| !amount.is_zero())
+        {
+            self.record(
+                EntryKind::Merge,
+                Account::Equity,
+                Account::CustomerDeposit(*id),
+                *amount,
+                None,
+            );
+        }
+        self.record_event(BankEvent::BanksMerged {
+            bank: other.name,
+            credited: merged_amounts,
+        });
+        Ok(report)
+    }
+
+    /// Reports what merging `other` into this bank with `policy` would do, without merging
+    pub fn plan_merge(
+        &self,
+        other: &Bank,
+        policy: &MergePolicy,
+    ) -> Result<MergeReport, MergeError> {
+        let matches = self.match_accounts(other, &policy.reconciliation)?;
+        let mut next_id = self.users.next_id().value();
+        let mut open_account = || {
+            next_id += 1;
+            AccountId::new(next_id - 1)
+        };
+        // Several accounts of `other` may be combined into the same account
+        let mut combined: BTreeMap<AccountId, User> = BTreeMap::new();
+        let mut accounts = vec![];
+
+        for (source, other_user) in other.users.iter() {
+            let (target, action) = match (matches.get(&source), policy.conflicts) {
+                (None, _) => (open_account(), MergeAction::Opened),
+                (Some(target), ConflictResolution::Combine) => {
+                    let overflow = |source_error| MergeError::Overflow {
+                        account: source,
+                        source: source_error,
+                    };
+                    let user = combined.get(target).unwrap_or(&self.users[*target]);
+                    let credit_line = user
+                        .merged_credit_line(other_user, policy.credit_lines)
+                        .map_err(overflow)?;
+                    let merged = user
+                        .merged_with(other_user, credit_line)
+                        .map_err(overflow)?;
+                    combined.insert(*target, merged);
+                    (*target, MergeAction::Combined { credit_line })
+                }
+                (Some(_), ConflictResolution::Rename) => {
+                    let username = format!("{} ({})", other_user.name, other.name);
+                    (open_account(), MergeAction::Renamed { username })
+                }
+                (Some(target), ConflictResolution::Reject) => (*target, MergeAction::Conflict),
+            };
+            accounts.push(MergedAccount {
+                source,
+                target,
+                action,
+            });
+        }
+
+        let other_interest = (other.credit_interest, other.debit_interest);
+        let discarded_interest = (other_interest != (self.credit_interest, self.debit_interest))
+            .then_some(other_interest);
+        Ok(MergeReport {
+            accounts,
+            discarded_interest,
+        })
+    }
+
+    /// The account of this bank each matched account of `other` is combined with
+    fn match_accounts(
+        &self,
+        other: &Bank,
+        reconciliation: &AccountReconciliation,
+    ) -> Result<BTreeMap<AccountId, AccountId>, MergeError> {
+        match reconciliation {
+            AccountReconciliation::ByName => {
+                let mut matches = BTreeMap::new();
+                for (other_id, other_user) in other.users.iter() {
+                    let ids: Vec<AccountId> = self
+                        .users
+                        .ids_named(&other_user.name)
+                        .filter(|id| self.users[*id].state != AccountState::Closed)
+                        .collect();
+                    let other_ids = other.users.ids_named(&other_user.name).count();
+                    match ids[..] {
+                        [] => {}
+                        [id] if other_ids == 1 => {
+                            matches.insert(other_id, id);
+                        }
+                        _ => {
+                            return Err(MergeError::AmbiguousName {
+                                username: other_user.name.clone(),
+                            });
+                        }
+                    }
+                }
+                Ok(matches)
+            }
+            AccountReconciliation::Explicit(matches) => {
+                for (other_id, id) in matches {
+                    if !other.users.contains(*other_id) {
+                        return Err(MergeError::UnknownSourceAccount { account: *other_id });
+                    }
+                    match self.users.get(*id) {
+                        None => return Err(MergeError::UnknownTargetAccount { account: *id }),
+                        Some(user) if user.state == AccountState::Closed => {
+                            return Err(MergeError::ClosedTargetAccount { account: *id });
+                        }
+                        Some(_) => {}
+                    }
+                }
+                Ok(matches.clone())
+            }
+            AccountReconciliation::Separate => Ok(BTreeMap::new()),
+        }
     }
 }
 
 impl Bank {
+    /// Adds one period of interest to every balance, at the rates that apply today
     pub fn accrue_interest(&mut self) {
-        for user in self.users.iter_mut() {
-            let applicable_interest = match user.balance >= 0 {
-                true => self.debit_interest,
-                false => self.credit_interest,
+        let today = self.clock.now().date_naive();
+        for id in self.users.ids() {
+            let balances: Vec<Money> = self.users[id]
+                .balances
+                .iter()
+                .map(|(currency, balance)| Money::from_minor(*balance, *currency))
+                .collect();
+            for balance in balances {
+                // Balances too large to hold their interest are left untouched
+                let interest = self
+                    .rate_bands(id, balance, today)
+                    .iter()
+                    .try_fold(Money::zero(balance.currency()), |interest, band| {
+                        interest.checked_add(band.rate.of(band.amount)?)
+                    });
+                let Ok(interest) = interest else {
+                    continue;
+                };
+                self.post_interest(id, balance, interest);
+            }
+        }
+    }
+
+    /// Accrues interest day by day from `from`, inclusive, to `to`, exclusive, at the annual
+    /// interest rates of every account and following the [`InterestConventions`] of the bank.
+    ///
+    /// The interest accrued is posted at the end of every compounding period; the interest of a
+    /// period that does not end within the range stays accrued until a later call.
+    pub fn accrue_interest_between(
+        &mut self,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<(), InterestError> {
+        if to < from {
+            return Err(InterestError::InvalidRange { from, to });
+        }
+        if let Some(through) = self.interest.accrued_through
+            && from < through
+        {
+            return Err(InterestError::AlreadyAccrued { through });
+        }
+
+        let mut start = from;
+        while start < to {
+            let next_period_start = self
+                .interest
+                .conventions
+                .compounding
+                .next_period_start(start);
+            let end = next_period_start.min(to);
+            for id in self.users.ids() {
+                let balances: Vec<Money> = self.users[id]
+                    .balances
+                    .iter()
+                    .map(|(currency, balance)| Money::from_minor(*balance, *currency))
+                    .collect();
+                for balance in balances {
+                    self.accrue_balance_interest(id, balance, start, end);
+                }
+            }
+            self.charge_overdraft_fees((end - start).num_days());
+            if end == next_period_start {
+                self.post_accrued_interest();
+            }
+            start = end;
+        }
+        self.interest.accrued_through = Some(to);
+        Ok(())
+    }
+
+    /// Interest accrued by `account` in `currency` and not posted yet, rounded to the minor unit
+    pub fn accrued_interest(&self, account: AccountId, currency: Currency) -> Money {
+        self.interest.accrued(account, currency)
+    }
+
+    /// Posts the interest accrued so far, rounded to the minor unit
+    pub fn post_accrued_interest(&mut self) {
+        for (id, currency) in self.interest.accounts() {
+            self.post_accrued_interest_of(id, currency);
+        }
+    }
+
+    fn post_accrued_interest_of(&mut self, id: AccountId, currency: Currency) {
+        let Some(user) = self.users.get(id) else {
+            return;
+        };
+        let balance = user.balance(currency);
+        let interest = self.interest.accrued(id, currency);
+        if self.post_interest(id, balance, interest) {
+            self.interest.posted(id, interest);
+        }
+    }
+
+    pub fn set_interest_conventions(&mut self, conventions: InterestConventions) {
+        self.interest.conventions = conventions;
+    }
+
+    /// Charges the overdraft fee of `days` days to every over-limit account
+    fn charge_overdraft_fees(&mut self, days: i64) {
+        for id in self.users.ids() {
+            let user = &self.users[id];
+            if user.state != AccountState::OverLimit {
+                continue;
+            }
+            let Some(fee) = self.overdraft_fees.get(&user.currency) else {
+                continue;
+            };
+            let Some(fee) = fee.minor().checked_mul(days) else {
+                continue;
+            };
+            let fee = Money::from_minor(fee, user.currency);
+            let Ok(new_balance) = user.balance(user.currency).checked_sub(fee) else {
+                continue;
+            };
+            if fee.is_zero() {
+                continue;
+            }
+            self.users[id].set_balance(new_balance);
+            self.record(
+                EntryKind::OverdraftFee,
+                Account::CustomerDeposit(id),
+                Account::FeeIncome,
+                fee,
+                None,
+            );
+            self.record_event(BankEvent::FundsTransferred {
+                kind: EntryKind::OverdraftFee,
+                sender: Some(id),
+                receiver: None,
+                amount: fee,
+                converted_amount: None,
+            });
+        }
+    }
+
+    /// Applies `policy` to the interest of `account` from now on
+    pub fn set_rate_policy(
+        &mut self,
+        account: AccountId,
+        policy: Arc<dyn RatePolicy>,
+    ) -> Result<(), AccountNotFound> {
+        if !self.users.contains(account) {
+            return Err(AccountNotFound(account));
+        }
+        self.users[account].rate_policy = Some(policy);
+        Ok(())
+    }
+
+    /// Accrues the interest of `balance` from `from` to `to`, splitting the range where the
+    /// rates of the account change
+    fn accrue_balance_interest(
+        &mut self,
+        id: AccountId,
+        balance: Money,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) {
+        let mut start = from;
+        while start < to {
+            let change = match &self.users[id].rate_policy {
+                Some(policy) => policy.next_change(start),
+                None => None,
             };
-            user.balance += user.balance * applicable_interest as i64 / 10_000;
+            let end = change.map_or(to, |change| change.min(to));
+            for band in self.rate_bands(id, balance, start) {
+                self.interest.accrue(id, band.amount, band.rate, start, end);
+            }
+            start = end;
+        }
+    }
+
+    fn rate_bands(&self, id: AccountId, balance: Money, date: NaiveDate) -> Vec<RateBand> {
+        match &self.users[id].rate_policy {
+            Some(policy) => policy.bands(balance, date),
+            None => FlatRate {
+                credit: self.credit_interest,
+                debit: self.debit_interest,
+            }
+            .bands(balance, date),
+        }
+    }
+
+    /// Adds `interest` to `balance` of `id`, unless it is zero or the balance would overflow.
+    /// Returns whether the interest was posted.
+    fn post_interest(&mut self, id: AccountId, balance: Money, interest: Money) -> bool {
+        let Ok(new_balance) = balance.checked_add(interest) else {
+            return false;
+        };
+        if interest.is_zero() {
+            return false;
         }
+        let counterpart = match balance.is_negative() {
+            false => Account::InterestIncome,
+            true => Account::InterestExpense,
+        };
+        self.users[id].set_balance(new_balance);
+        self.record(
+            EntryKind::InterestAccrual,
+            counterpart,
+            Account::CustomerDeposit(id),
+            interest,
+            None,
+        );
+        self.record_event(BankEvent::InterestAccrued {
+            account: id,
+            interest,
+        });
+        true
     }
 }
 
 impl Bank {
+    /// Moves `amount` from `sender` to `receiver`.
+    ///
+    /// The sender is debited in the currency of `amount`. A receiver that holds no balance in
+    /// that currency is credited in its home currency if the bank converts cross-currency
+    /// transfers, and the transfer is rejected otherwise.
     pub fn transfer_funds(
         &mut self,
-        sender: &str,
-        receiver: &str,
-        amount: i64,
+        sender: AccountId,
+        receiver: AccountId,
+        amount: Money,
     ) -> Result<(), TransferFundsError> {
-        let Some(receiver_position) = self.index_of_user_by_username(receiver) else {
-            return Err(ReceiverNotExistsError);
+        let transfer = Transfer {
+            sender,
+            receiver,
+            amount,
         };
+        let checked = self.check_transfer(&transfer, &BTreeMap::new())?;
+        self.apply_transfer(checked);
+        Ok(())
+    }
+
+    /// Applies every transfer in order, or none of them.
+    ///
+    /// Each transfer is checked like in [`Bank::transfer_funds`], against the balances left by
+    /// the transfers before it and the states those balances put the accounts in.
+    pub fn transfer_batch(&mut self, transfers: &[Transfer]) -> Result<(), BatchTransferError> {
+        let mut running_balances = BTreeMap::new();
+        let mut checked_transfers = Vec::with_capacity(transfers.len());
+        for (index, transfer) in transfers.iter().enumerate() {
+            let checked = self
+                .check_transfer(transfer, &running_balances)
+                .map_err(|reason| BatchTransferError { index, reason })?;
+            running_balances.insert(
+                (transfer.sender, transfer.amount.currency()),
+                checked.new_sender_balance,
+            );
+            running_balances.insert(
+                (transfer.receiver, checked.credited.currency()),
+                checked.new_receiver_balance,
+            );
+            checked_transfers.push(checked);
+        }
+        for checked in checked_transfers {
+            self.apply_transfer(checked);
+        }
+        Ok(())
+    }
 
-        let Some(sender_position) = self.index_of_user_by_username(sender) else {
-            return Err(SenderNotExistsError);
+    /// Checks `transfer` against the balances in `running_balances`, falling back to the current
+    /// balances of the users
+    fn check_transfer(
+        &self,
+        transfer: &Transfer,
+        running_balances: &BTreeMap<(AccountId, Currency), Money>,
+    ) -> Result<CheckedTransfer, TransferFundsError> {
+        transfer::check_accounts(transfer, |account| self.users.contains(account))?;
+        let sender = &self.users[transfer.sender];
+        let sender_state = match running_balances.get(&(transfer.sender, sender.currency)) {
+            Some(balance) => sender.state_with(*balance),
+            None => sender.state,
         };
+        self.transfer_rules.check(
+            transfer,
+            sender,
+            sender_state,
+            &self.users[transfer.receiver],
+            |account, currency| {
+                running_balances
+                    .get(&(account, currency))
+                    .copied()
+                    .unwrap_or_else(|| self.users[account].balance(currency))
+            },
+        )
+    }
 
-        if (self.users[sender_position].balance as u64 + self.users[sender_position].credit_line)
-            < (amount as u64)
-        {
-            return Err(SenderNotEnoughBalance);
-        }
+    fn apply_transfer(&mut self, checked: CheckedTransfer) {
+        let Transfer {
+            sender, receiver, ..
+        } = checked.transfer;
+        self.users[sender].set_balance(checked.new_sender_balance);
+        self.users[receiver].set_balance(checked.new_receiver_balance);
+        self.record(
+            EntryKind::Transfer,
+            Account::CustomerDeposit(sender),
+            Account::CustomerDeposit(receiver),
+            checked.transfer.amount,
+            checked.converted_amount(),
+        );
+        self.record_event(BankEvent::FundsTransferred {
+            kind: EntryKind::Transfer,
+            sender: Some(sender),
+            receiver: Some(receiver),
+            amount: checked.transfer.amount,
+            converted_amount: checked.converted_amount(),
+        });
+    }
+}
 
-        self.users[sender_position].balance -= amount;
-        self.users[receiver_position].balance += amount;
+impl Bank {
+    /// Opens an account for `user`, with its balances as opening balances. An account opened
+    /// with a balance below its credit line is over limit from the start.
+    pub fn open_account(&mut self, user: User) -> Result<AccountId, AccountError> {
+        if user.name.trim().is_empty() {
+            return Err(AccountError::EmptyName);
+        }
+        if user.credit_line.is_negative() {
+            return Err(AccountError::NegativeCreditLine {
+                credit_line: user.credit_line,
+            });
+        }
+        if user.credit_line.currency() != user.currency {
+            return Err(AccountError::CreditLineCurrencyMismatch {
+                credit_line: user.credit_line,
+                currency: user.currency,
+            });
+        }
+        Ok(self.insert_account(user))
+    }
 
+    /// Stops `account` from sending funds until it is unfrozen
+    pub fn freeze_account(&mut self, account: AccountId) -> Result<(), AccountError> {
+        self.open_user(account)?;
+        self.users[account].state = AccountState::Frozen;
         Ok(())
     }
 
-    fn index_of_user_by_username(&self, username: &str) -> Option<usize> {
-        self.users.iter().position(|u| u.name == username)
+    /// Lets a frozen account send funds again, unless it is over its credit line
+    pub fn unfreeze_account(&mut self, account: AccountId) -> Result<AccountState, AccountError> {
+        let user = self.open_user(account)?;
+        if user.state == AccountState::Frozen {
+            let state = match user.is_over_limit() {
+                true => AccountState::OverLimit,
+                false => AccountState::Active,
+            };
+            self.users[account].state = state;
+        }
+        Ok(self.users[account].state)
     }
-}
 
-pub struct BalanceSheet {
-    pub liabilities: u64,
-    pub assets: u64,
-}
+    /// Closes `account` after posting the interest it accrued, and moves its remaining balances
+    /// to `settlement_account`. Returns the balances moved.
+    ///
+    /// If the bank accrues interest with [`Bank::accrue_interest_between`], the account first
+    /// accrues its interest from the end of the last accrued period to today, at simple
+    /// interest. Banks that only use [`Bank::accrue_interest`] post whole periods, and have no
+    /// partial period to accrue.
+    ///
+    /// A frozen account cannot be closed, since that would move out the funds the freeze holds.
+    /// The account and its history stay in the bank, but it can no longer send or receive funds
+    /// and its pending credit line request is dropped.
+    pub fn close_account(
+        &mut self,
+        account: AccountId,
+        settlement_account: AccountId,
+    ) -> Result<Vec<Money>, AccountError> {
+        let user = self.open_user(account)?;
+        if user.state == AccountState::Frozen {
+            return Err(AccountError::AccountFrozen { account });
+        }
+        self.open_user(settlement_account)?;
+        if account == settlement_account {
+            return Err(AccountError::SameAccount { account });
+        }
 
-impl Bank {
-    pub fn calc_balance(&self) -> BalanceSheet {
-        let mut liabilities: i64 = 0;
-        let mut assets: i64 = 0;
+        let accrued: Vec<_> = self
+            .interest
+            .raw_accruals()
+            .filter(|(id, _, _)| *id == account)
+            .collect();
+        self.accrue_until_today(account);
+        let settlements = self.settlements(account, settlement_account);
+        let Ok(settlements) = settlements else {
+            self.interest.discard(account);
+            for (id, currency, accrued) in accrued {
+                self.interest.restore_accrual(id, currency, accrued);
+            }
+            return settlements;
+        };
 
-        for user in &self.users {
-            if user.balance >= 0 {
-                assets += user.balance;
-            } else {
-                liabilities += -user.balance;
+        for currency in self.users[account]
+            .balances
+            .keys()
+            .copied()
+            .collect::<Vec<_>>()
+        {
+            self.post_accrued_interest_of(account, currency);
+        }
+        self.interest.discard(account);
+        let mut settled = vec![];
+        for currency in settlements.iter().map(Money::currency) {
+            let balance = self.users[account].balance(currency);
+            if balance.is_zero() {
+                continue;
             }
+            let new_receiver_balance = self.users[settlement_account]
+                .balance(currency)
+                .checked_add(balance)
+                .expect("settlements are checked before posting the interest they include");
+            self.users[account].set_balance(Money::zero(currency));
+            self.users[settlement_account].set_balance(new_receiver_balance);
+            self.record(
+                EntryKind::Settlement,
+                Account::CustomerDeposit(account),
+                Account::CustomerDeposit(settlement_account),
+                balance,
+                None,
+            );
+            self.record_event(BankEvent::FundsTransferred {
+                kind: EntryKind::Settlement,
+                sender: Some(account),
+                receiver: Some(settlement_account),
+                amount: balance,
+                converted_amount: None,
+            });
+            settled.push(balance);
         }
+        let user = &mut self.users[account];
+        user.state = AccountState::Closed;
+        user.requested_credit_line = None;
+        Ok(settled)
+    }
 
-        let liabilities: u64 = liabilities as u64;
-        let assets: u64 = assets as u64;
+    /// Accrues the interest of `account` alone from the end of the last accrued period to today
+    fn accrue_until_today(&mut self, account: AccountId) {
+        let today = self.clock.now().date_naive();
+        let Some(from) = self.interest.accrued_through.filter(|from| *from < today) else {
+            return;
+        };
+        let balances: Vec<Money> = self.users[account]
+            .balances
+            .iter()
+            .map(|(currency, balance)| Money::from_minor(*balance, *currency))
+            .collect();
+        for balance in balances {
+            self.accrue_balance_interest(account, balance, from, today);
+        }
+    }
 
-        BalanceSheet {
-            liabilities,
-            assets,
+    /// The balances that closing `account` moves to `settlement_account`, with the interest
+    /// accrued by `account`
+    fn settlements(
+        &self,
+        account: AccountId,
+        settlement_account: AccountId,
+    ) -> Result<Vec<Money>, AccountError> {
+        let settlement_user = &self.users[settlement_account];
+        let mut settlements = vec![];
+        for (currency, balance) in &self.users[account].balances {
+            let balance = Money::from_minor(*balance, *currency);
+            let interest = self.interest.accrued(account, *currency);
+            let balance = balance.checked_add(interest).unwrap_or(balance);
+            if balance.is_negative() {
+                return Err(AccountError::OutstandingDebt { account, balance });
+            }
+            settlement_user
+                .balance(*currency)
+                .checked_add(balance)
+                .map_err(|source| AccountError::Overflow {
+                    account: settlement_account,
+                    source,
+                })?;
+            settlements.push(balance);
         }
+        Ok(settlements)
     }
-}
 
-impl Bank {
-    pub fn new(users: Vec<User>, name: String, credit_interest: u64, debit_interest: u64) -> Self {
-        Bank {
-            users,
-            name,
-            credit_interest,
-            debit_interest,
+    /// The user holding `account`, unless it is closed
+    fn open_user(&self, account: AccountId) -> Result<&User, AccountError> {
+        let user = self.users.get(account).ok_or(AccountNotFound(account))?;
+        if user.state == AccountState::Closed {
+            return Err(AccountError::AccountClosed { account });
         }
+        Ok(user)
     }
 }
 
-pub enum TransferFundsError {
-    SenderNotExistsError,
-    ReceiverNotExistsError,
-    SenderNotEnoughBalance,
+/// How much of its credit line an account draws
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CreditUtilisation {
+    pub limit: Money,
+    /// Negative part of the balance, which exceeds `limit` when the account is over limit
+    pub used: Money,
+    pub available: Money,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl Bank {
+    /// Records a request for a credit line of `amount`, replacing any pending one
+    pub fn request_credit_line(
+        &mut self,
+        account: AccountId,
+        amount: Money,
+    ) -> Result<(), CreditLineError> {
+        self.check_credit_line(account, amount)?;
+        self.users[account].requested_credit_line = Some(amount);
+        Ok(())
+    }
 
-    #[derive(Debug)]
-    pub struct Balance {
-        value: i64,
+    /// Grants the pending credit line request of `account`
+    pub fn approve_credit_line(&mut self, account: AccountId) -> Result<Money, CreditLineError> {
+        let user = self.users.get(account).ok_or(AccountNotFound(account))?;
+        if user.state == AccountState::Closed {
+            return Err(CreditLineError::AccountClosed { account });
+        }
+        let Some(amount) = user.requested_credit_line else {
+            return Err(CreditLineError::NoPendingRequest { account });
+        };
+        let user = &mut self.users[account];
+        user.requested_credit_line = None;
+        user.set_credit_line(amount);
+        Ok(amount)
     }
 
-    impl Balance {
-        pub fn new(value: i64) -> Self {
-            Balance { value }
+    pub fn raise_credit_line(
+        &mut self,
+        account: AccountId,
+        amount: Money,
+    ) -> Result<(), CreditLineError> {
+        let current = self.check_credit_line(account, amount)?;
+        if amount.minor() < current.minor() {
+            return Err(CreditLineError::NotARaise {
+                current,
+                requested: amount,
+            });
         }
+        self.users[account].set_credit_line(amount);
+        Ok(())
     }
 
-    impl PartialEq<Self> for Balance {
-        fn eq(&self, other: &Self) -> bool {
-            self.value == other.value
+    /// Lowers the credit line of `account`, which goes over limit if its balance is below the
+    /// new credit line
+    pub fn lower_credit_line(
+        &mut self,
+        account: AccountId,
+        amount: Money,
+    ) -> Result<AccountState, CreditLineError> {
+        let current = self.check_credit_line(account, amount)?;
+        if amount.minor() > current.minor() {
+            return Err(CreditLineError::NotALowering {
+                current,
+                requested: amount,
+            });
         }
+        self.users[account].set_credit_line(amount);
+        Ok(self.users[account].state)
     }
 
-    #[test]
-    fn user_constructor_fields() {
-        let user = User::new("Name Surname".to_string(), 4u64, -1i64);
+    /// Lowers the credit line of `account` to zero
+    pub fn revoke_credit_line(
+        &mut self,
+        account: AccountId,
+    ) -> Result<AccountState, CreditLineError> {
+        let user = self.users.get(account).ok_or(AccountNotFound(account))?;
+        self.lower_credit_line(account, Money::zero(user.currency))
+    }
 
-        assert_eq!(user.name, "Name Surname".to_string());
-        assert_eq!(user.credit_line, 4u64);
-        assert_eq!(user.balance, -1i64);
+    pub fn credit_utilisation(
+        &self,
+        account: AccountId,
+    ) -> Result<CreditUtilisation, AccountNotFound> {
+        let user = self.users.get(account).ok_or(AccountNotFound(account))?;
+        let balance = user.balance(user.currency).minor();
+        let used = balance.min(0).saturating_neg();
+        let available = user.credit_line.minor().saturating_sub(used).max(0);
+        Ok(CreditUtilisation {
+            limit: user.credit_line,
+            used: Money::from_minor(used, user.currency),
+            available: Money::from_minor(available, user.currency),
+        })
     }
 
-    #[test]
-    fn bank_constructor_fields() {
-        let user = User::new("Name Surname".to_string(), 4u64, -1i64);
+    /// Charges `fee` per day to over-limit accounts of its currency, whenever interest is
+    /// accrued with [`Bank::accrue_interest_between`]
+    pub fn set_overdraft_fee(&mut self, fee: Money) {
+        self.overdraft_fees.insert(fee.currency(), fee);
+    }
+
+    /// Checks that `account` can have a credit line of `amount` and returns its current one
+    fn check_credit_line(
+        &self,
+        account: AccountId,
+        amount: Money,
+    ) -> Result<Money, CreditLineError> {
+        let user = self.users.get(account).ok_or(AccountNotFound(account))?;
+        if user.state == AccountState::Closed {
+            return Err(CreditLineError::AccountClosed { account });
+        }
+        if amount.currency() != user.currency {
+            return Err(CreditLineError::CurrencyMismatch {
+                account,
+                currency: amount.currency(),
+            });
+        }
+        if amount.is_negative() {
+            return Err(CreditLineError::NegativeAmount { amount });
+        }
+        Ok(user.credit_line)
+    }
+}
 
-        let bank = Bank::new(vec![user], "Bank Name".to_string(), 4u64, 1u64);
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BalanceSheet {
+    pub currency: Currency,
+    pub liabilities: Money,
+    pub assets: Money,
+    /// Equity according to the books, which the customer balances must add up to
+    pub equity: Money,
+}
 
-        assert_eq!(bank.users.len(), 1);
-        assert_eq!(bank.name, "Bank Name".to_string());
-        assert_eq!(bank.credit_interest, 4u64);
-        assert_eq!(bank.debit_interest, 1u64);
+impl BalanceSheet {
+    pub fn is_balanced(&self) -> bool {
+        self.assets.checked_sub(self.liabilities) == Ok(self.equity)
     }
+}
 
-    #[test]
-    fn calculate_balance_sheet_with_negative_balance() {
-        let user1 = User::new("Name Surname".to_string(), 0u64, -2i64);
-        let user2 = User::new("Name Surname".to_string(), 0u64, 1i64);
-        let bank = Bank::new(vec![user1, user2], "Bank Name".to_string(), 4u64, 1u64);
+/// Customer balances in one currency, added up in 128 bits so that no number of balances
+/// overflows the totals
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BalanceTotals {
+    currency: Currency,
+    assets: i128,
+    liabilities: i128,
+}
 
-        let balance_sheet: BalanceSheet = bank.calc_balance();
+impl BalanceTotals {
+    pub(crate) fn new(currency: Currency) -> Self {
+        BalanceTotals {
+            currency,
+            assets: 0,
+            liabilities: 0,
+        }
+    }
 
-        assert_eq!(balance_sheet.liabilities, 2u64);
-        assert_eq!(balance_sheet.assets, 1u64);
+    /// Adds a customer balance, an asset when positive and a liability when negative
+    pub(crate) fn add(&mut self, balance: Money) {
+        match balance.is_negative() {
+            false => self.assets += i128::from(balance.minor()),
+            true => self.liabilities -= i128::from(balance.minor()),
+        }
     }
-    #[test]
-    fn calculate_balance_sheet_with_positive_balance() {
-        let user1 = User::new("Name Surname".to_string(), 0u64, 2i64);
-        let user2 = User::new("Name Surname".to_string(), 0u64, 1i64);
-        let bank = Bank::new(vec![user1, user2], "Bank Name".to_string(), 4u64, 1u64);
 
-        let balance_sheet: BalanceSheet = bank.calc_balance();
+    pub(crate) fn remove(&mut self, balance: Money) {
+        match balance.is_negative() {
+            false => self.assets -= i128::from(balance.minor()),
+            true => self.liabilities += i128::from(balance.minor()),
+        }
+    }
 
-        assert_eq!(balance_sheet.liabilities, 0u64);
-        assert_eq!(balance_sheet.assets, 3u64);
+    /// Sum of the balances added
+    pub(crate) fn net(&self) -> i128 {
+        self.assets - self.liabilities
     }
-    #[test]
-    fn transfer_funds_happy_path() {
-        let user1 = User::new("name1".to_string(), 0u64, 2i64);
-        let user2 = User::new("name2".to_string(), 0u64, 1i64);
-        let mut bank = Bank::new(vec![user1, user2], "Bank Name".to_string(), 4u64, 1u64);
 
-        let result = bank.transfer_funds("name1", "name2", 2);
+    /// Fails with [`MoneyError::Overflow`] if a total does not fit in a [`Money`]
+    pub(crate) fn balance_sheet(&self, equity: Money) -> Result<BalanceSheet, MoneyError> {
+        Ok(BalanceSheet {
+            currency: self.currency,
+            liabilities: Money::from_total(self.liabilities, self.currency)?,
+            assets: Money::from_total(self.assets, self.currency)?,
+            equity,
+        })
+    }
+}
 
-        assert!(result.is_ok());
-        assert_eq!(bank.calc_balance().assets, 3u64);
-        let bank_helper = BankHelper { bank: &bank };
-        assert_eq!(bank_helper.balance_for("name1"), Balance::new(0i64));
-        assert_eq!(bank_helper.balance_for("name2"), Balance::new(3i64));
+impl Bank {
+    /// Balance sheet of the accounts held in the currency of the bank.
+    ///
+    /// Fails with [`MoneyError::Overflow`] if the assets or liabilities add up to more than a
+    /// [`Money`] holds.
+    pub fn calc_balance(&self) -> Result<BalanceSheet, MoneyError> {
+        self.calc_balance_in(self.currency)
     }
-    #[test]
-    fn transfer_funds_when_sender_does_not_exist() {
-        let user2 = User::new("name2".to_string(), 0u64, 1i64);
-        let mut bank = Bank::new(vec![user2], "Bank Name".to_string(), 4u64, 1u64);
 
-        let result: Result<(), TransferFundsError> = bank.transfer_funds("nonexisting", "name2", 2);
+    /// Balance sheet of every currency held, failing like [`Bank::calc_balance`] if any of
+    /// them overflows
+    pub fn calc_balance_per_currency(
+        &self,
+    ) -> Result<BTreeMap<Currency, BalanceSheet>, MoneyError> {
+        self.currencies_held()
+            .into_iter()
+            .map(|currency| Ok((currency, self.calc_balance_in(currency)?)))
+            .collect()
+    }
 
-        assert!(result.is_err());
+    fn calc_balance_in(&self, currency: Currency) -> Result<BalanceSheet, MoneyError> {
+        self.balance_totals(currency)
+            .balance_sheet(self.books.equity(currency)?)
+    }
 
-        assert_eq!(bank.calc_balance().assets, 1u64);
-        let bank_helper = BankHelper { bank: &bank };
-        assert_eq!(bank_helper.balance_for("name2"), Balance::new(1i64));
+    /// Currencies with customer balances or postings in the books
+    pub(crate) fn currencies_held(&self) -> BTreeSet<Currency> {
+        let mut currencies: BTreeSet<Currency> = self.books.currencies();
+        for (_, user) in self.users.iter() {
+            currencies.extend(user.balances.keys());
+        }
+        currencies
     }
 
-    #[test]
-    fn transfer_funds_when_receiver_does_not_exist() {
-        let user1 = User::new("name1".to_string(), 0u64, 1i64);
-        let mut bank = Bank::new(vec![user1], "Bank Name".to_string(), 4u64, 1u64);
+    pub(crate) fn balance_totals(&self, currency: Currency) -> BalanceTotals {
+        let mut totals = BalanceTotals::new(currency);
+        for (_, user) in self.users.iter() {
+            totals.add(user.balance(currency));
+        }
+        totals
+    }
+}
 
-        let result: Result<(), TransferFundsError> = bank.transfer_funds("name1", "nonexisting", 2);
+impl Bank {
+    pub fn new(
+        users: Vec<User>,
+        name: String,
+        credit_interest: BasisPoints,
+        debit_interest: BasisPoints,
+    ) -> Self {
+        Self::new_with_clock(
+            users,
+            name,
+            credit_interest,
+            debit_interest,
+            Arc::new(SystemClock),
+        )
+    }
 
-        assert!(result.is_err());
+    pub fn new_with_clock(
+        users: Vec<User>,
+        name: String,
+        credit_interest: BasisPoints,
+        debit_interest: BasisPoints,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let mut bank = Bank {
+            users: UserStore::default(),
+            name,
+            currency: Currency::EUR,
+            credit_interest,
+            debit_interest,
+            transfer_rules: TransferRules::default(),
+            interest: InterestAccruals::default(),
+            overdraft_fees: BTreeMap::new(),
+            ledger: Ledger::default(),
+            books: Books::default(),
+            events: EventLog::default(),
+            clock,
+        };
+        for user in users {
+            bank.insert_account(user);
+        }
+        bank
+    }
 
-        assert_eq!(bank.calc_balance().assets, 1u64);
-        let bank_helper = BankHelper { bank: &bank };
-        assert_eq!(bank_helper.balance_for("name1"), Balance::new(1i64));
+    /// Adds `user` and records its opening balances
+    fn insert_account(&mut self, user: User) -> AccountId {
+        let opening_balances: Vec<Money> = user
+            .balances
+            .iter()
+            .map(|(currency, balance)| Money::from_minor(*balance, *currency))
+            .collect();
+        let id = self.users.insert(user);
+        self.users[id].update_limit_state();
+        self.record_opened(id, opening_balances.clone());
+        for balance in opening_balances {
+            self.record(
+                EntryKind::OpeningBalance,
+                Account::Equity,
+                Account::CustomerDeposit(id),
+                balance,
+                None,
+            );
+        }
+        id
     }
 
-    #[test]
-    fn transfer_funds_when_not_enough_balance_without_credit_line() {
-        let user1 = User::new("name1".to_string(), 0u64, 2i64);
-        let user2 = User::new("name2".to_string(), 0u64, 1i64);
-        let mut bank = Bank::new(vec![user1, user2], "Bank Name".to_string(), 4u64, 1u64);
+    pub fn set_exchange_rates(&mut self, exchange_rates: Arc<dyn ExchangeRateProvider>) {
+        self.transfer_rules.exchange_rates = exchange_rates;
+    }
 
-        let result = bank.transfer_funds("name1", "name2", 3);
+    pub fn set_cross_currency_transfers(
+        &mut self,
+        cross_currency_transfers: CrossCurrencyTransfers,
+    ) {
+        self.transfer_rules.cross_currency_transfers = cross_currency_transfers;
+    }
 
-        assert!(result.is_err());
-        assert_eq!(bank.calc_balance().assets, 3u64);
-        let bank_helper = BankHelper { bank: &bank };
-        assert_eq!(bank_helper.balance_for("name1"), Balance::new(2i64));
-        assert_eq!(bank_helper.balance_for("name2"), Balance::new(1i64));
+    /// Gives this bank the exchange rate provider and the account rate policies of `other`,
+    /// which snapshots leave out
+    pub(crate) fn keep_unsaved_settings(&mut self, other: &Bank) {
+        self.transfer_rules.exchange_rates = other.transfer_rules.exchange_rates.clone();
+        for (id, user) in other.users.iter() {
+            if self.users.contains(id) {
+                self.users[id].rate_policy = user.rate_policy.clone();
+            }
+        }
     }
+}
 
-    #[test]
-    fn transfer_funds_when_not_enough_balance_but_credit_line_is_enough() {
-        let user1 = User::new("name1".to_string(), 1u64, 2i64);
-        let user2 = User::new("name2".to_string(), 0u64, 1i64);
-        let mut bank = Bank::new(vec![user1, user2], "Bank Name".to_string(), 4u64, 1u64);
+impl Bank {
+    /// Records `amount` moving from `from` to `to` in both the ledger and the books, converted
+    /// into `converted_amount` on the way if present.
+    /// The user balances must already include the movement.
+    fn record(
+        &mut self,
+        kind: EntryKind,
+        from: Account,
+        to: Account,
+        amount: Money,
+        converted_amount: Option<Money>,
+    ) {
+        let received = converted_amount.unwrap_or(amount);
+        let sender = self.party(&from, amount.currency());
+        let receiver = self.party(&to, received.currency());
+        let ledger_entry = self.ledger.record(
+            self.clock.now(),
+            kind,
+            sender,
+            receiver,
+            amount,
+            converted_amount,
+        );
+        self.books
+            .post_movement(ledger_entry, from, to, amount, converted_amount);
+    }
 
-        let result = bank.transfer_funds("name1", "name2", 3);
+    fn record_event(&mut self, event: BankEvent) {
+        self.events.record(self.clock.now(), event);
+    }
 
-        assert!(result.is_ok());
-        assert_eq!(bank.calc_balance().assets, 4u64);
-        let bank_helper = BankHelper { bank: &bank };
-        assert_eq!(bank_helper.balance_for("name1"), Balance::new(-1i64));
-        assert_eq!(bank_helper.balance_for("name2"), Balance::new(4i64));
+    fn record_opened(&mut self, account: AccountId, balances: Vec<Money>) {
+        let user = &self.users[account];
+        self.record_event(BankEvent::AccountOpened {
+            account,
+            username: user.name.clone(),
+            currency: user.currency,
+            credit_line: user.credit_line,
+            balances,
+        });
     }
 
-    #[test]
-    fn transfer_funds_when_balance_even_plus_credit_line_is_not_enough() {
-        let user1 = User::new("name1".to_string(), 1u64, 2i64);
-        let user2 = User::new("name2".to_string(), 0u64, 1i64);
-        let mut bank = Bank::new(vec![user1, user2], "Bank Name".to_string(), 4u64, 1u64);
+    fn party(&self, account: &Account, currency: Currency) -> Option<(AccountId, Money)> {
+        let Account::CustomerDeposit(id) = account else {
+            return None;
+        };
+        self.users
+            .get(*id)
+            .map(|user| (*id, user.balance(currency)))
+    }
+}
 
-        let result = bank.transfer_funds("name1", "name2", 4);
+impl Bank {
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
 
-        assert!(result.is_err());
-        assert_eq!(bank.calc_balance().assets, 3u64);
-        let bank_helper = BankHelper { bank: &bank };
-        assert_eq!(bank_helper.balance_for("name1"), Balance::new(2i64));
-        assert_eq!(bank_helper.balance_for("name2"), Balance::new(1i64));
+    pub fn books(&self) -> &Books {
+        &self.books
     }
 
-    #[test]
-    fn accrue_interest() {
-        let user1 = User::new("name1".to_string(), 0u64, -100i64);
-        let user2 = User::new("name2".to_string(), 0u64, 100i64);
-        let mut bank = Bank::new(vec![user1, user2], "Bank Name".to_string(), 400u64, 100u64);
+    /// Every event of the bank, from which its balances are rebuilt on restore
+    pub fn events(&self) -> &EventLog {
+        &self.events
+    }
 
-        bank.accrue_interest();
+    /// Balance sheet of every currency held at `as_of`, rebuilt from the events recorded until
+    /// then. Fails like [`Bank::calc_balance_per_currency`].
+    pub fn balance_sheets_as_of(
+        &self,
+        as_of: Timestamp,
+    ) -> Result<BTreeMap<Currency, BalanceSheet>, MoneyError> {
+        let mut balances = Balances::default();
+        self.events.replay_as_of(&mut balances, as_of);
+        balances.balance_sheets()
+    }
 
-        let bank_helper = BankHelper { bank: &bank };
-        assert_eq!(bank_helper.balance_for("name1"), Balance::new(-104i64));
-        assert_eq!(bank_helper.balance_for("name2"), Balance::new(101i64));
+    /// Ledger entries involving `account` recorded between `from` and `to`, both inclusive
+    pub fn entries_for_account_between(
+        &self,
+        account: AccountId,
+        from: Timestamp,
+        to: Timestamp,
+    ) -> Vec<&LedgerEntry> {
+        self.ledger.entries_for_between(account, from, to).collect()
     }
-    #[test]
-    fn merge_bank() {
-        let user1_1 = User::new("name1".to_string(), 0u64, 4i64);
-        let mut bank1 = Bank::new(vec![user1_1], "Bank1".to_string(), 4u64, 1u64);
-        let user1_2 = User::new("name1".to_string(), 0u64, 4i64);
-        let user2 = User::new("name2".to_string(), 0u64, 2i64);
-        let user3 = User::new("name3".to_string(), 0u64, 3i64);
-        let bank2 = Bank::new(vec![user1_2, user2, user3], "Bank2".to_string(), 4u64, 1u64);
+}
 
-        bank1.merge_bank(bank2);
+impl Bank {
+    pub fn user(&self, account: AccountId) -> Option<&User> {
+        self.users.get(account)
+    }
 
-        let bank_helper = BankHelper { bank: &bank1 };
-        assert_eq!(bank_helper.balance_for("name1"), Balance::new(2 * 4i64));
-        assert_eq!(bank_helper.balance_for("name2"), Balance::new(2i64));
-        assert_eq!(bank_helper.balance_for("name3"), Balance::new(3i64));
+    pub fn account_ids(&self) -> Vec<AccountId> {
+        self.users.ids()
     }
 
-    struct BankHelper<'a> {
-        bank: &'a Bank,
+    /// Accounts held by `username`, oldest first
+    pub fn accounts_named(&self, username: &str) -> Vec<AccountId> {
+        self.users.ids_named(username).collect()
     }
 
-    impl BankHelper<'_> {
+    /// Changes the username of an account, which keeps its id, balances and history
+    pub fn rename_account(
+        &mut self,
+        account: AccountId,
+        username: String,
+    ) -> Result<(), AccountNotFound> {
+        self.users
+            .rename(account, username)
+            .ok_or(AccountNotFound(account))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TransferFundsError::{
+        AccountClosed, AccountFrozen, AccountOverLimit, CurrencyMismatch, ExchangeRateUnavailable,
+        NonPositiveAmount, Overflow, SameAccount, SenderNotEnoughBalance,
+    };
+    use crate::currency::{ExchangeRate, FixedExchangeRates};
+    use crate::error::ErrorCode;
+    use crate::rates::{SteppedRate, TieredRate};
+    use crate::test_support::{ManualClock, date, eur, gbp, timestamp, usd};
+    use chrono::NaiveTime;
+    use proptest::prelude::*;
+    use std::error::Error;
+
+    #[derive(Debug)]
+    pub struct Balance {
+        value: i64,
+    }
+
+    impl Balance {
+        pub fn new(value: i64) -> Self {
+            Balance { value }
+        }
+    }
+
+    impl PartialEq<Self> for Balance {
+        fn eq(&self, other: &Self) -> bool {
+            self.value == other.value
+        }
+    }
+
+    #[test]
+    fn user_constructor_fields() {
+        let user = User::new("Name Surname".to_string(), eur(4), eur(-1));
+
+        assert_eq!(user.name, "Name Surname".to_string());
+        assert_eq!(user.credit_line, eur(4));
+        assert_eq!(user.balance(Currency::EUR), eur(-1));
+    }
+
+    /// Tests of a bank kept as `$bank`, which `$open` makes of a [`Bank`]: run against a bare
+    /// [`Bank`] and against a [`StoredBank`](crate::repository::StoredBank) in every repository.
+    /// Tests that set accounts up by reaching into the bank only run against a bare [`Bank`].
+    macro_rules! bank_tests {
+        ($bank:ty, $open:expr) => {
+            type TestBank = $bank;
+
+            fn open(bank: Bank) -> TestBank {
+                $open(bank)
+            }
+
+            #[test]
+            fn bank_constructor_fields() {
+                let user = User::new("Name Surname".to_string(), eur(4), eur(-1));
+
+                let bank = open(Bank::new(
+                    vec![user],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                assert_eq!(bank.users.iter().count(), 1);
+                assert_eq!(bank.name, "Bank Name".to_string());
+                assert_eq!(bank.credit_interest, BasisPoints::new(4));
+                assert_eq!(bank.debit_interest, BasisPoints::new(1));
+            }
+
+            #[test]
+            fn calculate_balance_sheet_with_negative_balance() {
+                let user1 = User::new("Name Surname".to_string(), eur(0), eur(-2));
+                let user2 = User::new("Name Surname".to_string(), eur(0), eur(1));
+                let bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                let balance_sheet: BalanceSheet = bank.calc_balance().unwrap();
+
+                assert_eq!(balance_sheet.liabilities, eur(2));
+                assert_eq!(balance_sheet.assets, eur(1));
+            }
+
+            #[test]
+            fn calculate_balance_sheet_with_positive_balance() {
+                let user1 = User::new("Name Surname".to_string(), eur(0), eur(2));
+                let user2 = User::new("Name Surname".to_string(), eur(0), eur(1));
+                let bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                let balance_sheet: BalanceSheet = bank.calc_balance().unwrap();
+
+                assert_eq!(balance_sheet.liabilities, eur(0));
+                assert_eq!(balance_sheet.assets, eur(3));
+            }
+
+            #[test]
+            fn books_add_up_opening_balances_beyond_money() {
+                let bank = open(interest_bank(&[i64::MAX, i64::MAX], 0));
+                let books = bank.books();
+
+                assert_eq!(books.trial_balance(Currency::EUR), Ok(eur(0)));
+                assert_eq!(
+                    books.balance(&Account::Equity, Currency::EUR),
+                    Err(MoneyError::Overflow)
+                );
+                assert_eq!(bank.calc_balance(), Err(MoneyError::Overflow));
+
+                let bank = open(interest_bank(&[i64::MIN], 0));
+                let books = bank.books();
+                let account = Account::CustomerDeposit(bank.id("name0"));
+                assert_eq!(books.balance(&account, Currency::EUR), Ok(eur(i64::MIN)));
+                assert_eq!(
+                    books.balance(&Account::Equity, Currency::EUR),
+                    Err(MoneyError::Overflow)
+                );
+                assert!(books.journal().iter().all(|entry| entry.is_balanced()));
+            }
+
+            #[test]
+            fn transfer_funds_happy_path() {
+                let user1 = User::new("name1".to_string(), eur(0), eur(2));
+                let user2 = User::new("name2".to_string(), eur(0), eur(1));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                let result = bank.transfer_funds(bank.id("name1"), bank.id("name2"), eur(2));
+
+                assert!(result.is_ok());
+                assert_eq!(bank.calc_balance().unwrap().assets, eur(3));
+                let bank_helper = BankHelper { bank: &bank };
+                assert_eq!(bank_helper.balance_for("name1"), Balance::new(0i64));
+                assert_eq!(bank_helper.balance_for("name2"), Balance::new(3i64));
+            }
+
+            #[test]
+            fn transfer_funds_when_sender_does_not_exist() {
+                let user2 = User::new("name2".to_string(), eur(0), eur(1));
+                let mut bank = open(Bank::new(
+                    vec![user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                let result: Result<(), TransferFundsError> =
+                    bank.transfer_funds(AccountId::new(99), bank.id("name2"), eur(2));
+
+                assert!(result.is_err());
+
+                assert_eq!(bank.calc_balance().unwrap().assets, eur(1));
+                let bank_helper = BankHelper { bank: &bank };
+                assert_eq!(bank_helper.balance_for("name2"), Balance::new(1i64));
+            }
+
+            #[test]
+            fn transfer_funds_when_receiver_does_not_exist() {
+                let user1 = User::new("name1".to_string(), eur(0), eur(1));
+                let mut bank = open(Bank::new(
+                    vec![user1],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                let result: Result<(), TransferFundsError> =
+                    bank.transfer_funds(bank.id("name1"), AccountId::new(99), eur(2));
+
+                assert!(result.is_err());
+
+                assert_eq!(bank.calc_balance().unwrap().assets, eur(1));
+                let bank_helper = BankHelper { bank: &bank };
+                assert_eq!(bank_helper.balance_for("name1"), Balance::new(1i64));
+            }
+
+            #[test]
+            fn transfer_funds_when_not_enough_balance_without_credit_line() {
+                let user1 = User::new("name1".to_string(), eur(0), eur(2));
+                let user2 = User::new("name2".to_string(), eur(0), eur(1));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                let result = bank.transfer_funds(bank.id("name1"), bank.id("name2"), eur(3));
+
+                assert!(result.is_err());
+                assert_eq!(bank.calc_balance().unwrap().assets, eur(3));
+                let bank_helper = BankHelper { bank: &bank };
+                assert_eq!(bank_helper.balance_for("name1"), Balance::new(2i64));
+                assert_eq!(bank_helper.balance_for("name2"), Balance::new(1i64));
+            }
+
+            #[test]
+            fn transfer_funds_when_not_enough_balance_but_credit_line_is_enough() {
+                let user1 = User::new("name1".to_string(), eur(1), eur(2));
+                let user2 = User::new("name2".to_string(), eur(0), eur(1));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                let result = bank.transfer_funds(bank.id("name1"), bank.id("name2"), eur(3));
+
+                assert!(result.is_ok());
+                assert_eq!(bank.calc_balance().unwrap().assets, eur(4));
+                let bank_helper = BankHelper { bank: &bank };
+                assert_eq!(bank_helper.balance_for("name1"), Balance::new(-1i64));
+                assert_eq!(bank_helper.balance_for("name2"), Balance::new(4i64));
+            }
+
+            #[test]
+            fn transfer_funds_when_balance_even_plus_credit_line_is_not_enough() {
+                let user1 = User::new("name1".to_string(), eur(1), eur(2));
+                let user2 = User::new("name2".to_string(), eur(0), eur(1));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                let result = bank.transfer_funds(bank.id("name1"), bank.id("name2"), eur(4));
+
+                assert!(result.is_err());
+                assert_eq!(bank.calc_balance().unwrap().assets, eur(3));
+                let bank_helper = BankHelper { bank: &bank };
+                assert_eq!(bank_helper.balance_for("name1"), Balance::new(2i64));
+                assert_eq!(bank_helper.balance_for("name2"), Balance::new(1i64));
+            }
+
+            #[test]
+            fn transfer_funds_when_already_overdrawn() {
+                let user1 = User::new("name1".to_string(), eur(10), eur(-5));
+                let user2 = User::new("name2".to_string(), eur(0), eur(1));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                let result = bank.transfer_funds(bank.id("name1"), bank.id("name2"), eur(6));
+
+                assert!(matches!(result, Err(SenderNotEnoughBalance { .. })));
+                assert!(
+                    bank.transfer_funds(bank.id("name1"), bank.id("name2"), eur(5))
+                        .is_ok()
+                );
+                let bank_helper = BankHelper { bank: &bank };
+                assert_eq!(bank_helper.balance_for("name1"), Balance::new(-10i64));
+                assert_eq!(bank_helper.balance_for("name2"), Balance::new(6i64));
+            }
+
+            #[test]
+            fn transfer_funds_with_non_positive_amount() {
+                let user1 = User::new("name1".to_string(), eur(0), eur(2));
+                let user2 = User::new("name2".to_string(), eur(0), eur(1));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                let zero = bank.transfer_funds(bank.id("name1"), bank.id("name2"), eur(0));
+                let negative = bank.transfer_funds(bank.id("name1"), bank.id("name2"), eur(-1));
+
+                assert!(matches!(zero, Err(NonPositiveAmount { .. })));
+                assert!(matches!(negative, Err(NonPositiveAmount { .. })));
+                let bank_helper = BankHelper { bank: &bank };
+                assert_eq!(bank_helper.balance_for("name1"), Balance::new(2i64));
+                assert_eq!(bank_helper.balance_for("name2"), Balance::new(1i64));
+            }
+
+            #[test]
+            fn transfer_funds_to_same_account() {
+                let user1 = User::new("name1".to_string(), eur(0), eur(2));
+                let mut bank = open(Bank::new(
+                    vec![user1],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                let result = bank.transfer_funds(bank.id("name1"), bank.id("name1"), eur(1));
+
+                assert!(matches!(result, Err(SameAccount { .. })));
+                assert_eq!(bank.ledger().entries().len(), 1);
+            }
+
+            #[test]
+            fn transfer_funds_when_receiver_balance_overflows() {
+                let user1 = User::new("name1".to_string(), eur(0), eur(2));
+                let user2 = User::new("name2".to_string(), eur(0), eur(i64::MAX - 1));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                let result = bank.transfer_funds(bank.id("name1"), bank.id("name2"), eur(2));
+
+                assert!(matches!(result, Err(Overflow { .. })));
+                let bank_helper = BankHelper { bank: &bank };
+                assert_eq!(bank_helper.balance_for("name1"), Balance::new(2i64));
+                assert_eq!(bank_helper.balance_for("name2"), Balance::new(i64::MAX - 1));
+            }
+
+            #[test]
+            fn transfer_funds_when_credit_line_overflows() {
+                let user1 = User::new("name1".to_string(), eur(i64::MAX), eur(1));
+                let user2 = User::new("name2".to_string(), eur(0), eur(0));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                let result = bank.transfer_funds(bank.id("name1"), bank.id("name2"), eur(1));
+
+                assert!(matches!(result, Err(Overflow { .. })));
+            }
+
+            #[test]
+            fn transfer_funds_error_reports_context() {
+                let user1 = User::new("name1".to_string(), eur(1), eur(2));
+                let user2 = User::new("name2".to_string(), eur(0), eur(1));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                let error = bank
+                    .transfer_funds(bank.id("name1"), bank.id("name2"), eur(4))
+                    .unwrap_err();
+
+                assert_eq!(
+                    error,
+                    SenderNotEnoughBalance {
+                        account: bank.id("name1"),
+                        requested: eur(4),
+                        available: eur(3),
+                    }
+                );
+                assert_eq!(error.code(), ErrorCode::InsufficientFunds);
+                assert_eq!(error.code().as_str(), "INSUFFICIENT_FUNDS");
+                assert_eq!(
+                    ErrorCode::parse("INSUFFICIENT_FUNDS"),
+                    Some(ErrorCode::InsufficientFunds)
+                );
+                assert!(
+                    ErrorCode::ALL
+                        .iter()
+                        .all(|code| ErrorCode::parse(code.as_str()) == Some(*code))
+                );
+                assert_eq!(ErrorCode::parse("insufficient_funds"), None);
+                assert_eq!(
+                    error.to_string(),
+                    "sender account #0 requested 0.04 EUR but only 0.03 EUR is available"
+                );
+            }
+
+            #[test]
+            fn transfer_funds_error_chains_its_source() {
+                let user1 = User::new("name1".to_string(), eur(10), eur(-5));
+                let user2 = User::new("name2".to_string(), eur(0), eur(i64::MAX));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+                let transfer = |bank: &mut TestBank| -> Result<(), Box<dyn Error>> {
+                    bank.transfer_funds(bank.id("name1"), bank.id("name2"), eur(1))?;
+                    Ok(())
+                };
+
+                let error = transfer(&mut bank).unwrap_err();
+
+                assert_eq!(
+                    error.to_string(),
+                    "the balance of account #1 would overflow"
+                );
+                assert_eq!(error.source().unwrap().to_string(), "amount out of range");
+            }
+
+            #[test]
+            fn transfer_batch_uses_running_balances() {
+                let user1 = User::new("name1".to_string(), eur(0), eur(10));
+                let user2 = User::new("name2".to_string(), eur(0), eur(0));
+                let user3 = User::new("name3".to_string(), eur(0), eur(0));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2, user3],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+                let (id1, id2, id3) = (bank.id("name1"), bank.id("name2"), bank.id("name3"));
+
+                let result = bank.transfer_batch(&[
+                    Transfer {
+                        sender: id1,
+                        receiver: id2,
+                        amount: eur(10),
+                    },
+                    Transfer {
+                        sender: id2,
+                        receiver: id3,
+                        amount: eur(4),
+                    },
+                ]);
+
+                assert_eq!(result, Ok(()));
+                let bank_helper = BankHelper { bank: &bank };
+                assert_eq!(bank_helper.balance_for("name1"), Balance::new(0i64));
+                assert_eq!(bank_helper.balance_for("name2"), Balance::new(6i64));
+                assert_eq!(bank_helper.balance_for("name3"), Balance::new(4i64));
+                let transfer = bank.ledger().entries().last().unwrap();
+                assert_eq!(transfer.sender_balance(), Some(eur(6)));
+            }
+
+            #[test]
+            fn transfer_batch_is_all_or_nothing() {
+                let user1 = User::new("name1".to_string(), eur(5), eur(10));
+                let user2 = User::new("name2".to_string(), eur(0), eur(0));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+                let (id1, id2) = (bank.id("name1"), bank.id("name2"));
+                let payment = Transfer {
+                    sender: id1,
+                    receiver: id2,
+                    amount: eur(6),
+                };
+
+                let error = bank
+                    .transfer_batch(&[payment, payment, payment])
+                    .unwrap_err();
+
+                assert_eq!(
+                    error,
+                    BatchTransferError {
+                        index: 2,
+                        reason: SenderNotEnoughBalance {
+                            account: id1,
+                            requested: eur(6),
+                            available: eur(3),
+                        },
+                    }
+                );
+                assert_eq!(
+                    error.to_string(),
+                    "transfer 2 of the batch failed: \
+                     sender account #0 requested 0.06 EUR but only 0.03 EUR is available"
+                );
+                let bank_helper = BankHelper { bank: &bank };
+                assert_eq!(bank_helper.balance_for("name1"), Balance::new(10i64));
+                assert_eq!(bank_helper.balance_for("name2"), Balance::new(0i64));
+                assert_eq!(bank.ledger().entries().len(), 2);
+            }
+
+            #[test]
+            fn accrue_interest() {
+                let user1 = User::new("name1".to_string(), eur(0), eur(-100));
+                let user2 = User::new("name2".to_string(), eur(0), eur(100));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(400),
+                    BasisPoints::new(100),
+                ));
+
+                bank.accrue_interest();
+
+                let bank_helper = BankHelper { bank: &bank };
+                assert_eq!(bank_helper.balance_for("name1"), Balance::new(-104i64));
+                assert_eq!(bank_helper.balance_for("name2"), Balance::new(101i64));
+            }
+
+            #[test]
+            fn accrue_interest_between_posts_at_period_ends() {
+                let mut bank = open(interest_bank(&[1_000_000, -1_000_000], 365));
+                let (id0, id1) = (bank.id("name0"), bank.id("name1"));
+
+                // 3.65% over the 31 days of January on a 365-day year is 0.31%
+                bank.accrue_interest_between(date(2024, 1, 1), date(2024, 2, 1))
+                    .unwrap();
+
+                assert_eq!(bank.users[id0].balance(Currency::EUR), eur(1_003_100));
+                assert_eq!(bank.users[id1].balance(Currency::EUR), eur(-1_003_100));
+                assert_eq!(bank.accrued_interest(id0, Currency::EUR), eur(0));
+                assert_eq!(
+                    bank.books()
+                        .balance(&Account::InterestIncome, Currency::EUR)
+                        .unwrap(),
+                    eur(-3100)
+                );
+                assert_eq!(
+                    bank.books()
+                        .balance(&Account::InterestExpense, Currency::EUR)
+                        .unwrap(),
+                    eur(3100)
+                );
+                assert!(bank.books().trial_balance(Currency::EUR).unwrap().is_zero());
+            }
+
+            #[test]
+            fn accrue_interest_between_keeps_unfinished_periods_accrued() {
+                let mut bank = open(interest_bank(&[1_000_000], 365));
+                let id = bank.id("name0");
+
+                bank.accrue_interest_between(date(2024, 1, 1), date(2024, 1, 11))
+                    .unwrap();
+
+                assert_eq!(bank.users[id].balance(Currency::EUR), eur(1_000_000));
+                assert_eq!(bank.accrued_interest(id, Currency::EUR), eur(1000));
+
+                bank.post_accrued_interest();
+
+                assert_eq!(bank.users[id].balance(Currency::EUR), eur(1_001_000));
+                assert_eq!(bank.accrued_interest(id, Currency::EUR), eur(0));
+            }
+
+            #[test]
+            fn accrue_interest_between_compounds() {
+                let mut bank = open(interest_bank(&[1_000_000], 3650));
+                let id = bank.id("name0");
+                bank.set_interest_conventions(InterestConventions {
+                    compounding: interest::Compounding::Daily,
+                    ..InterestConventions::default()
+                });
+
+                bank.accrue_interest_between(date(2024, 1, 1), date(2024, 1, 3))
+                    .unwrap();
+
+                assert_eq!(bank.users[id].balance(Currency::EUR), eur(1_002_001));
+            }
+
+            #[test]
+            fn accrue_interest_between_rejects_accrued_ranges() {
+                let mut bank = open(interest_bank(&[1_000_000], 365));
+
+                bank.accrue_interest_between(date(2024, 1, 1), date(2024, 1, 11))
+                    .unwrap();
+
+                assert_eq!(
+                    bank.accrue_interest_between(date(2024, 1, 10), date(2024, 1, 20)),
+                    Err(InterestError::AlreadyAccrued {
+                        through: date(2024, 1, 11)
+                    })
+                );
+                assert_eq!(
+                    bank.accrue_interest_between(date(2024, 1, 20), date(2024, 1, 12)),
+                    Err(InterestError::InvalidRange {
+                        from: date(2024, 1, 20),
+                        to: date(2024, 1, 12)
+                    })
+                );
+                assert_eq!(
+                    bank.accrue_interest_between(date(2024, 1, 11), date(2024, 1, 20)),
+                    Ok(())
+                );
+            }
+
+            #[test]
+            fn accrue_interest_follows_account_rate_policies() {
+                let mut bank = open(interest_bank(&[1_500_000, 100], 100));
+                let (id0, id1) = (bank.id("name0"), bank.id("name1"));
+                let tiered = TieredRate::new(
+                    BasisPoints::new(100),
+                    vec![
+                        (0, BasisPoints::new(50)),
+                        (1_000_000, BasisPoints::new(100)),
+                    ],
+                );
+
+                bank.set_rate_policy(id0, Arc::new(tiered)).unwrap();
+                bank.accrue_interest();
+
+                assert_eq!(bank.users[id0].balance(Currency::EUR), eur(1_510_000));
+                assert_eq!(bank.users[id1].balance(Currency::EUR), eur(101));
+                assert_eq!(
+                    bank.set_rate_policy(
+                        AccountId::new(9),
+                        Arc::new(FlatRate {
+                            credit: BasisPoints::new(0),
+                            debit: BasisPoints::new(0),
+                        })
+                    ),
+                    Err(AccountNotFound(AccountId::new(9)))
+                );
+            }
+
+            #[test]
+            fn accrue_interest_between_splits_at_rate_changes() {
+                let flat = |rate| {
+                    Arc::new(FlatRate {
+                        credit: BasisPoints::new(rate),
+                        debit: BasisPoints::new(rate),
+                    })
+                };
+                let stepped = SteppedRate::new(flat(365)).changing_on(date(2024, 1, 11), flat(730));
+                let user = User::new("name0".to_string(), eur(0), eur(1_000_000))
+                    .with_rate_policy(Arc::new(stepped));
+                let mut bank = open(Bank::new(
+                    vec![user],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(0),
+                    BasisPoints::new(0),
+                ));
+                let id = bank.id("name0");
+
+                // 10 days at 3.65% and 21 days at 7.3%
+                bank.accrue_interest_between(date(2024, 1, 1), date(2024, 2, 1))
+                    .unwrap();
+
+                assert_eq!(bank.users[id].balance(Currency::EUR), eur(1_005_200));
+            }
+
+            #[test]
+            fn credit_line_lifecycle() {
+                let mut bank = open(interest_bank(&[-300], 0));
+                let id = bank.id("name0");
+
+                bank.request_credit_line(id, eur(1000)).unwrap();
+                assert_eq!(bank.users[id].requested_credit_line(), Some(eur(1000)));
+                assert_eq!(bank.approve_credit_line(id), Ok(eur(1000)));
+                assert_eq!(
+                    bank.approve_credit_line(id),
+                    Err(CreditLineError::NoPendingRequest { account: id })
+                );
+                assert_eq!(
+                    bank.credit_utilisation(id),
+                    Ok(CreditUtilisation {
+                        limit: eur(1000),
+                        used: eur(300),
+                        available: eur(700),
+                    })
+                );
+
+                bank.raise_credit_line(id, eur(2000)).unwrap();
+                assert_eq!(
+                    bank.raise_credit_line(id, eur(1500)),
+                    Err(CreditLineError::NotARaise {
+                        current: eur(2000),
+                        requested: eur(1500),
+                    })
+                );
+                assert_eq!(
+                    bank.lower_credit_line(id, eur(500)),
+                    Ok(AccountState::Active)
+                );
+                assert_eq!(
+                    bank.request_credit_line(id, usd(10)),
+                    Err(CreditLineError::CurrencyMismatch {
+                        account: id,
+                        currency: Currency::USD,
+                    })
+                );
+                assert_eq!(
+                    bank.request_credit_line(id, eur(-10)),
+                    Err(CreditLineError::NegativeAmount { amount: eur(-10) })
+                );
+                assert_eq!(bank.revoke_credit_line(id), Ok(AccountState::OverLimit));
+                assert_eq!(bank.users[id].credit_line(), eur(0));
+                assert_eq!(
+                    bank.revoke_credit_line(AccountId::new(9)),
+                    Err(CreditLineError::AccountNotFound(AccountId::new(9)))
+                );
+            }
+
+            #[test]
+            fn frozen_accounts_cannot_send_funds() {
+                let mut bank = open(interest_bank(&[500, 500], 0));
+                let (id0, id1) = (bank.id("name0"), bank.id("name1"));
+                bank.freeze_account(id0).unwrap();
+
+                assert_eq!(
+                    bank.transfer_funds(id0, id1, eur(1)),
+                    Err(AccountFrozen { account: id0 })
+                );
+                assert_eq!(bank.transfer_funds(id1, id0, eur(1)), Ok(()));
+                assert_eq!(bank.raise_credit_line(id0, eur(100)), Ok(()));
+                assert_eq!(bank.users[id0].state(), AccountState::Frozen);
+
+                assert_eq!(bank.unfreeze_account(id0), Ok(AccountState::Active));
+                assert_eq!(bank.transfer_funds(id0, id1, eur(1)), Ok(()));
+            }
+
+            #[test]
+            fn open_account_validates_and_records_opening_balances() {
+                let mut bank = open(interest_bank(&[500], 0));
+
+                let id = bank
+                    .open_account(User::new("name1".to_string(), eur(100), eur(20)))
+                    .unwrap();
+
+                assert_eq!(id, AccountId::new(1));
+                assert_eq!(bank.ledger().entries_for(id).count(), 1);
+                assert_eq!(bank.calc_balance().unwrap().assets, eur(520));
+                assert_eq!(
+                    bank.open_account(User::new(" ".to_string(), eur(0), eur(0))),
+                    Err(AccountError::EmptyName)
+                );
+                assert_eq!(
+                    bank.open_account(User::new("name2".to_string(), eur(-1), eur(0))),
+                    Err(AccountError::NegativeCreditLine {
+                        credit_line: eur(-1)
+                    })
+                );
+                assert_eq!(bank.account_ids().len(), 2);
+
+                let id = bank
+                    .open_account(User::new("name3".to_string(), eur(10), eur(-20)))
+                    .unwrap();
+                assert_eq!(bank.users[id].state(), AccountState::OverLimit);
+            }
+
+            #[test]
+            fn close_account_settles_the_remaining_balance() {
+                let mut bank = interest_bank(&[1_000_000, 10, -5], 365);
+                let closing_day = date(2024, 1, 16).and_time(NaiveTime::MIN).and_utc();
+                bank.clock = Arc::new(ManualClock::at(closing_day.timestamp()));
+                let mut bank = open(bank);
+                let (id0, id1, id2) = (bank.id("name0"), bank.id("name1"), bank.id("name2"));
+                bank.accrue_interest_between(date(2024, 1, 1), date(2024, 1, 11))
+                    .unwrap();
+
+                assert_eq!(
+                    bank.close_account(id2, id1),
+                    Err(AccountError::OutstandingDebt {
+                        account: id2,
+                        balance: eur(-5),
+                    })
+                );
+                assert_eq!(
+                    bank.close_account(id0, id0),
+                    Err(AccountError::SameAccount { account: id0 })
+                );
+                bank.freeze_account(id0).unwrap();
+                assert_eq!(
+                    bank.close_account(id0, id1),
+                    Err(AccountError::AccountFrozen { account: id0 })
+                );
+                bank.unfreeze_account(id0).unwrap();
+                bank.request_credit_line(id0, eur(100)).unwrap();
+                assert_eq!(bank.accrued_interest(id0, Currency::EUR), eur(1000));
+                // Five more days of interest accrue until the account is closed
+                assert_eq!(bank.close_account(id0, id1), Ok(vec![eur(1_001_500)]));
+
+                assert_eq!(bank.users[id0].state(), AccountState::Closed);
+                assert_eq!(bank.users[id0].balance(Currency::EUR), eur(0));
+                assert_eq!(bank.users[id1].balance(Currency::EUR), eur(1_001_510));
+                assert_eq!(bank.accrued_interest(id0, Currency::EUR), eur(0));
+                assert_eq!(bank.users[id0].requested_credit_line(), None);
+                assert_eq!(
+                    bank.approve_credit_line(id0),
+                    Err(CreditLineError::AccountClosed { account: id0 })
+                );
+                assert_eq!(
+                    bank.ledger().entries_for(id0).last().unwrap().kind(),
+                    EntryKind::Settlement
+                );
+                assert_eq!(
+                    bank.transfer_funds(id1, id0, eur(1)),
+                    Err(AccountClosed { account: id0 })
+                );
+                assert_eq!(
+                    bank.close_account(id0, id1),
+                    Err(AccountError::AccountClosed { account: id0 })
+                );
+                assert!(bank.calc_balance().unwrap().is_balanced());
+
+                let other = interest_bank(&[1], 0);
+                let mapping = BTreeMap::from([(AccountId::new(0), id0)]);
+                assert_eq!(
+                    bank.merge_bank(
+                        other,
+                        &reconciled_by(AccountReconciliation::Explicit(mapping))
+                    ),
+                    Err(MergeError::ClosedTargetAccount { account: id0 })
+                );
+            }
+
+            #[test]
+            fn merge_bank() {
+                let user1_1 = User::new("name1".to_string(), eur(0), eur(4));
+                let mut bank1 = open(Bank::new(
+                    vec![user1_1],
+                    "Bank1".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+                let user1_2 = User::new("name1".to_string(), eur(0), eur(4));
+                let user2 = User::new("name2".to_string(), eur(0), eur(2)).with_balance(usd(0));
+                let user3 = User::new("name3".to_string(), eur(0), eur(3));
+                let bank2 = Bank::new(
+                    vec![user1_2, user2, user3],
+                    "Bank2".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                );
+
+                bank1.merge_bank(bank2, &MergePolicy::default()).unwrap();
+
+                let bank_helper = BankHelper { bank: &bank1 };
+                assert_eq!(bank_helper.balance_for("name1"), Balance::new(2 * 4i64));
+                assert_eq!(bank_helper.balance_for("name2"), Balance::new(2i64));
+                assert_eq!(bank_helper.balance_for("name3"), Balance::new(3i64));
+                let restored = Bank::restore(bank1.snapshot()).unwrap();
+                assert!(restored.user_named("name2").holds(Currency::USD));
+            }
+
+            #[test]
+            fn merge_bank_rejects_ambiguous_names() {
+                let mut bank1 = open(Bank::new(
+                    vec![User::new("John Smith".to_string(), eur(0), eur(4))],
+                    "Bank1".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+                let bank2 = Bank::new(
+                    vec![
+                        User::new("John Smith".to_string(), eur(0), eur(2)),
+                        User::new("John Smith".to_string(), eur(0), eur(3)),
+                    ],
+                    "Bank2".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                );
+
+                let result = bank1.merge_bank(bank2, &MergePolicy::default());
+
+                assert_eq!(
+                    result,
+                    Err(MergeError::AmbiguousName {
+                        username: "John Smith".to_string()
+                    })
+                );
+                assert_eq!(bank1.account_ids().len(), 1);
+                assert_eq!(bank1.ledger().entries().len(), 1);
+            }
+
+            #[test]
+            fn merge_bank_with_explicit_mapping() {
+                let mut bank1 = open(Bank::new(
+                    vec![
+                        User::new("John Smith".to_string(), eur(0), eur(4)),
+                        User::new("John Smith".to_string(), eur(0), eur(5)),
+                    ],
+                    "Bank1".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+                let bank2 = Bank::new(
+                    vec![
+                        User::new("J. Smith".to_string(), eur(0), eur(2)),
+                        User::new("John Smith".to_string(), eur(0), eur(3)),
+                    ],
+                    "Bank2".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                );
+                let mapping = BTreeMap::from([(AccountId::new(0), AccountId::new(1))]);
+
+                let report = bank1
+                    .merge_bank(
+                        bank2,
+                        &reconciled_by(AccountReconciliation::Explicit(mapping)),
+                    )
+                    .unwrap();
+
+                assert_eq!(
+                    report.ids(),
+                    BTreeMap::from([
+                        (AccountId::new(0), AccountId::new(1)),
+                        (AccountId::new(1), AccountId::new(2)),
+                    ])
+                );
+                let balances: Vec<Money> = bank1
+                    .account_ids()
+                    .into_iter()
+                    .map(|id| bank1.user(id).unwrap().balance(Currency::EUR))
+                    .collect();
+                assert_eq!(balances, [eur(4), eur(7), eur(3)]);
+                assert_eq!(bank1.user(AccountId::new(1)).unwrap().name(), "John Smith");
+                assert!(bank1.calc_balance().unwrap().is_balanced());
+            }
+
+            #[test]
+            fn merge_bank_with_unknown_account_in_mapping() {
+                let mut bank1 = open(Bank::new(
+                    vec![User::new("name1".to_string(), eur(0), eur(4))],
+                    "Bank1".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+                let bank2 = Bank::new(
+                    vec![User::new("name1".to_string(), eur(0), eur(2))],
+                    "Bank2".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                );
+                let mapping = BTreeMap::from([(AccountId::new(0), AccountId::new(7))]);
+
+                let result = bank1.merge_bank(
+                    bank2,
+                    &reconciled_by(AccountReconciliation::Explicit(mapping)),
+                );
+
+                assert_eq!(
+                    result,
+                    Err(MergeError::UnknownTargetAccount {
+                        account: AccountId::new(7)
+                    })
+                );
+                assert_eq!(bank1.user_named("name1").balance(Currency::EUR), eur(4));
+            }
+
+            #[test]
+            fn merge_bank_keeping_accounts_separate() {
+                let mut bank1 = open(Bank::new(
+                    vec![User::new("name1".to_string(), eur(0), eur(4))],
+                    "Bank1".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+                let bank2 = Bank::new(
+                    vec![User::new("name1".to_string(), eur(0), eur(2))],
+                    "Bank2".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                );
+
+                bank1
+                    .merge_bank(bank2, &reconciled_by(AccountReconciliation::Separate))
+                    .unwrap();
+
+                assert_eq!(
+                    bank1.accounts_named("name1"),
+                    [AccountId::new(0), AccountId::new(1)]
+                );
+            }
+
+            #[test]
+            fn merge_bank_combines_credit_lines() {
+                let policies = [
+                    (CreditLines::Keep, eur(5)),
+                    (CreditLines::Max, eur(8)),
+                    (CreditLines::Min, eur(5)),
+                    (CreditLines::Sum, eur(13)),
+                ];
+                for (credit_lines, expected) in policies {
+                    let mut bank1 = open(Bank::new(
+                        vec![User::new("name1".to_string(), eur(5), eur(4))],
+                        "Bank1".to_string(),
+                        BasisPoints::new(4),
+                        BasisPoints::new(1),
+                    ));
+                    let bank2 = Bank::new(
+                        vec![User::new("name1".to_string(), eur(8), eur(2))],
+                        "Bank2".to_string(),
+                        BasisPoints::new(4),
+                        BasisPoints::new(1),
+                    );
+                    let policy = MergePolicy {
+                        credit_lines,
+                        ..MergePolicy::default()
+                    };
+
+                    bank1.merge_bank(bank2, &policy).unwrap();
+
+                    let user = bank1.user_named("name1");
+                    assert_eq!(user.credit_line(), expected);
+                    assert_eq!(user.balance(Currency::EUR), eur(6));
+                }
+            }
+
+            #[test]
+            fn merge_bank_renames_conflicting_accounts() {
+                let mut bank1 = open(Bank::new(
+                    vec![User::new("name1".to_string(), eur(0), eur(4))],
+                    "Bank1".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+                let bank2 = Bank::new(
+                    vec![
+                        User::new("name1".to_string(), eur(0), eur(2)),
+                        User::new("name2".to_string(), eur(0), eur(3)),
+                    ],
+                    "Bank2".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                );
+                let policy = MergePolicy {
+                    conflicts: ConflictResolution::Rename,
+                    ..MergePolicy::default()
+                };
+
+                let report = bank1.merge_bank(bank2, &policy).unwrap();
+
+                assert_eq!(
+                    report.accounts,
+                    [
+                        MergedAccount {
+                            source: AccountId::new(0),
+                            target: AccountId::new(1),
+                            action: MergeAction::Renamed {
+                                username: "name1 (Bank2)".to_string()
+                            },
+                        },
+                        MergedAccount {
+                            source: AccountId::new(1),
+                            target: AccountId::new(2),
+                            action: MergeAction::Opened,
+                        },
+                    ]
+                );
+                assert_eq!(bank1.user_named("name1").balance(Currency::EUR), eur(4));
+                assert_eq!(
+                    bank1.user_named("name1 (Bank2)").balance(Currency::EUR),
+                    eur(2)
+                );
+                assert!(bank1.calc_balance().unwrap().is_balanced());
+            }
+
+            #[test]
+            fn merge_bank_rejects_conflicts() {
+                let mut bank1 = open(Bank::new(
+                    vec![User::new("name1".to_string(), eur(0), eur(4))],
+                    "Bank1".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+                let bank2 = Bank::new(
+                    vec![
+                        User::new("name2".to_string(), eur(0), eur(3)),
+                        User::new("name1".to_string(), eur(0), eur(2)),
+                    ],
+                    "Bank2".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                );
+                let policy = MergePolicy {
+                    conflicts: ConflictResolution::Reject,
+                    ..MergePolicy::default()
+                };
+
+                let result = bank1.merge_bank(bank2, &policy);
+
+                assert_eq!(
+                    result,
+                    Err(MergeError::Conflicts {
+                        accounts: vec![AccountId::new(1)]
+                    })
+                );
+                assert_eq!(bank1.account_ids(), [AccountId::new(0)]);
+                assert_eq!(bank1.ledger().entries().len(), 1);
+            }
+
+            #[test]
+            fn plan_merge_reports_without_merging() {
+                let mut bank1 = open(Bank::new(
+                    vec![User::new("name1".to_string(), eur(5), eur(4))],
+                    "Bank1".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+                let bank2 = Bank::new(
+                    vec![
+                        User::new("name1".to_string(), eur(8), eur(2)),
+                        User::new("name2".to_string(), eur(0), eur(3)),
+                    ],
+                    "Bank2".to_string(),
+                    BasisPoints::new(6),
+                    BasisPoints::new(1),
+                );
+                let policy = MergePolicy {
+                    credit_lines: CreditLines::Max,
+                    ..MergePolicy::default()
+                };
+
+                let plan = bank1.plan_merge(&bank2, &policy).unwrap();
+
+                assert_eq!(
+                    plan.accounts[0].action,
+                    MergeAction::Combined {
+                        credit_line: eur(8)
+                    }
+                );
+                assert_eq!(
+                    plan.discarded_interest,
+                    Some((BasisPoints::new(6), BasisPoints::new(1)))
+                );
+                assert_eq!(plan.conflicts().count(), 0);
+                assert_eq!(bank1.user_named("name1").credit_line(), eur(5));
+                assert_eq!(bank1.merge_bank(bank2, &policy), Ok(plan));
+            }
+
+            #[test]
+            fn merge_bank_when_combined_balance_overflows() {
+                let mut bank1 = open(Bank::new(
+                    vec![User::new("name1".to_string(), eur(0), eur(i64::MAX))],
+                    "Bank1".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+                let bank2 = Bank::new(
+                    vec![User::new("name1".to_string(), eur(0), eur(1))],
+                    "Bank2".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                );
+
+                let result = bank1.merge_bank(bank2, &MergePolicy::default());
+
+                assert!(matches!(result, Err(MergeError::Overflow { .. })));
+                assert_eq!(
+                    bank1.user_named("name1").balance(Currency::EUR),
+                    eur(i64::MAX)
+                );
+            }
+
+            #[test]
+            fn rename_account_keeps_its_id_and_history() {
+                let user1 = User::new("name1".to_string(), eur(0), eur(4));
+                let user2 = User::new("name2".to_string(), eur(0), eur(0));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+                let id = bank.id("name1");
+
+                assert_eq!(bank.rename_account(id, "renamed".to_string()), Ok(()));
+                assert!(bank.transfer_funds(id, bank.id("name2"), eur(1)).is_ok());
+
+                assert!(bank.accounts_named("name1").is_empty());
+                assert_eq!(bank.id("renamed"), id);
+                assert_eq!(bank.ledger().entries_for(id).count(), 2);
+                assert_eq!(bank.ledger().replay_balance(id, Currency::EUR), Ok(eur(3)));
+                assert_eq!(
+                    bank.rename_account(AccountId::new(99), "name".to_string()),
+                    Err(AccountNotFound(AccountId::new(99)))
+                );
+            }
+
+            #[test]
+            fn ledger_records_every_balance_change() {
+                let user1 = User::new("name1".to_string(), eur(0), eur(100));
+                let user2 = User::new("name2".to_string(), eur(100), eur(50));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(400),
+                    BasisPoints::new(100),
+                ));
+                let other = Bank::new(
+                    vec![User::new("name3".to_string(), eur(0), eur(3))],
+                    "Bank2".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                );
+
+                assert!(
+                    bank.transfer_funds(bank.id("name2"), bank.id("name1"), eur(100))
+                        .is_ok()
+                );
+                bank.accrue_interest();
+                bank.merge_bank(other, &MergePolicy::default()).unwrap();
+
+                let kinds: Vec<EntryKind> = bank.ledger().entries().iter().map(|e| e.kind()).collect();
+                assert_eq!(
+                    kinds,
+                    [
+                        EntryKind::OpeningBalance,
+                        EntryKind::OpeningBalance,
+                        EntryKind::Transfer,
+                        EntryKind::InterestAccrual,
+                        EntryKind::InterestAccrual,
+                        EntryKind::Merge,
+                    ]
+                );
+                let transfer = &bank.ledger().entries()[2];
+                assert_eq!(transfer.sender(), Some(bank.id("name2")));
+                assert_eq!(transfer.receiver(), Some(bank.id("name1")));
+                assert_eq!(transfer.amount(), eur(100));
+                assert_eq!(transfer.sender_balance(), Some(eur(-50)));
+                assert_eq!(transfer.receiver_balance(), Some(eur(200)));
+                let bank_helper = BankHelper { bank: &bank };
+                for name in ["name1", "name2", "name3"] {
+                    assert_eq!(
+                        Balance::new(
+                            bank.ledger()
+                                .replay_balance(bank.id(name), Currency::EUR)
+                                .unwrap()
+                                .minor()
+                        ),
+                        bank_helper.balance_for(name)
+                    );
+                }
+            }
+
+            #[test]
+            fn ledger_ignores_rejected_transfers() {
+                let user1 = User::new("name1".to_string(), eur(0), eur(2));
+                let user2 = User::new("name2".to_string(), eur(0), eur(1));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                assert!(
+                    bank.transfer_funds(bank.id("name1"), bank.id("name2"), eur(3))
+                        .is_err()
+                );
+
+                assert_eq!(bank.ledger().entries().len(), 2);
+            }
+
+            #[test]
+            fn ledger_entries_for_user_between_timestamps() {
+                let clock = Arc::new(ManualClock::at(0));
+                let user1 = User::new("name1".to_string(), eur(0), eur(10));
+                let user2 = User::new("name2".to_string(), eur(0), eur(10));
+                let user3 = User::new("name3".to_string(), eur(0), eur(10));
+                let mut bank = open(Bank::new_with_clock(
+                    vec![user1, user2, user3],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                    clock.clone(),
+                ));
+                clock.set(10);
+                assert!(
+                    bank.transfer_funds(bank.id("name1"), bank.id("name2"), eur(1))
+                        .is_ok()
+                );
+                clock.set(20);
+                assert!(
+                    bank.transfer_funds(bank.id("name2"), bank.id("name3"), eur(2))
+                        .is_ok()
+                );
+                clock.set(30);
+                assert!(
+                    bank.transfer_funds(bank.id("name1"), bank.id("name2"), eur(3))
+                        .is_ok()
+                );
+
+                let entries =
+                    bank.entries_for_account_between(bank.id("name2"), timestamp(10), timestamp(20));
+
+                let amounts: Vec<i64> = entries.iter().map(|e| e.amount().minor()).collect();
+                assert_eq!(amounts, [1, 2]);
+                assert!(
+                    bank.entries_for_account_between(bank.id("name3"), timestamp(21), timestamp(30))
+                        .is_empty()
+                );
+            }
+
+            #[test]
+            fn balance_sheets_as_of_replay_earlier_events() {
+                let clock = Arc::new(ManualClock::at(0));
+                let user1 = User::new("name1".to_string(), eur(50), eur(10));
+                let user2 = User::new("name2".to_string(), eur(0), eur(10));
+                let mut bank = open(Bank::new_with_clock(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                    clock.clone(),
+                ));
+                clock.set(10);
+                bank.transfer_funds(bank.id("name1"), bank.id("name2"), eur(30))
+                    .unwrap();
+                clock.set(20);
+                let other = Bank::new(
+                    vec![User::new("name3".to_string(), eur(0), eur(5))],
+                    "Other".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                );
+                bank.merge_bank(other, &MergePolicy::default()).unwrap();
+
+                let sheet = |seconds| bank.balance_sheets_as_of(timestamp(seconds)).unwrap();
+                assert!(sheet(-1).is_empty());
+                let sheets = sheet(5);
+                assert_eq!(sheets[&Currency::EUR].assets, eur(20));
+                assert_eq!(sheets[&Currency::EUR].liabilities, eur(0));
+                let sheets = sheet(10);
+                assert_eq!(sheets[&Currency::EUR].assets, eur(40));
+                assert_eq!(sheets[&Currency::EUR].liabilities, eur(20));
+                assert_eq!(sheets[&Currency::EUR].equity, eur(20));
+                assert_eq!(sheet(20), bank.calc_balance_per_currency().unwrap());
+
+                let kinds: Vec<EntryKind> = bank
+                    .events()
+                    .events()
+                    .iter()
+                    .map(|event| event.event().kind())
+                    .collect();
+                assert_eq!(
+                    kinds,
+                    [
+                        EntryKind::OpeningBalance,
+                        EntryKind::OpeningBalance,
+                        EntryKind::Transfer,
+                        EntryKind::OpeningBalance,
+                        EntryKind::Merge,
+                    ]
+                );
+                assert!(matches!(
+                    bank.events().events()[4].event(),
+                    BankEvent::BanksMerged { bank, credited } if bank == "Other" && credited.len() == 1
+                ));
+            }
+
+            #[test]
+            fn interest_is_posted_against_interest_accounts() {
+                let user1 = User::new("name1".to_string(), eur(0), eur(-100));
+                let user2 = User::new("name2".to_string(), eur(0), eur(100));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(400),
+                    BasisPoints::new(100),
+                ));
+
+                bank.accrue_interest();
+
+                let books = bank.books();
+                assert_eq!(
+                    books
+                        .balance(&Account::InterestExpense, Currency::EUR)
+                        .unwrap(),
+                    eur(4)
+                );
+                assert_eq!(
+                    books
+                        .balance(&Account::InterestIncome, Currency::EUR)
+                        .unwrap(),
+                    eur(-1)
+                );
+                assert_eq!(
+                    books.balance(&Account::Equity, Currency::EUR).unwrap(),
+                    eur(0)
+                );
+                assert_eq!(
+                    books
+                        .balance(&Account::CustomerDeposit(bank.id("name1")), Currency::EUR)
+                        .unwrap(),
+                    eur(-104)
+                );
+                let balance_sheet = bank.calc_balance().unwrap();
+                assert_eq!(balance_sheet.equity, eur(-3));
+                assert!(balance_sheet.is_balanced());
+            }
+
+            #[test]
+            fn books_stay_balanced() {
+                let user1 = User::new("name1".to_string(), eur(50), eur(100));
+                let user2 = User::new("name2".to_string(), eur(0), eur(-30));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(400),
+                    BasisPoints::new(100),
+                ));
+                let other = Bank::new(
+                    vec![
+                        User::new("name2".to_string(), eur(0), eur(7)),
+                        User::new("name3".to_string(), eur(0), eur(3)),
+                    ],
+                    "Bank2".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                );
+
+                assert!(
+                    bank.transfer_funds(bank.id("name1"), bank.id("name2"), eur(120))
+                        .is_ok()
+                );
+                bank.accrue_interest();
+                bank.merge_bank(other, &MergePolicy::default()).unwrap();
+
+                let books = bank.books();
+                assert!(books.journal().iter().all(|entry| entry.is_balanced()));
+                assert_eq!(books.journal().len(), bank.ledger().entries().len());
+                assert_eq!(books.trial_balance(Currency::EUR).unwrap(), eur(0));
+                assert!(bank.calc_balance().unwrap().is_balanced());
+                for (id, user) in bank.users.iter() {
+                    let account = Account::CustomerDeposit(id);
+                    assert_eq!(
+                        books.balance(&account, Currency::EUR).unwrap(),
+                        user.balance(Currency::EUR)
+                    );
+                }
+            }
+
+            #[test]
+            fn transfer_funds_in_a_foreign_currency() {
+                let user1 = User::new("name1".to_string(), eur(0), eur(0)).with_balance(usd(5));
+                let user2 = User::new("name2".to_string(), eur(0), eur(0)).with_balance(usd(1));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                assert!(
+                    bank.transfer_funds(bank.id("name1"), bank.id("name2"), usd(3))
+                        .is_ok()
+                );
+
+                assert_eq!(bank.user_named("name1").balance(Currency::USD), usd(2));
+                assert_eq!(bank.user_named("name2").balance(Currency::USD), usd(4));
+                assert_eq!(bank.calc_balance().unwrap().assets, eur(0));
+            }
+
+            #[test]
+            fn transfer_funds_credit_line_only_applies_to_home_currency() {
+                let user1 = User::new("name1".to_string(), eur(10), eur(0)).with_balance(usd(0));
+                let user2 = User::new("name2".to_string(), eur(0), eur(0)).with_balance(usd(0));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                let result = bank.transfer_funds(bank.id("name1"), bank.id("name2"), usd(1));
+
+                assert!(matches!(result, Err(SenderNotEnoughBalance { .. })));
+                assert!(
+                    bank.transfer_funds(bank.id("name1"), bank.id("name2"), eur(10))
+                        .is_ok()
+                );
+            }
+
+            #[test]
+            fn transfer_funds_rejects_cross_currency_by_default() {
+                let user1 = User::new("name1".to_string(), usd(0), usd(5));
+                let user2 = User::new("name2".to_string(), eur(0), eur(1));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                let result = bank.transfer_funds(bank.id("name1"), bank.id("name2"), usd(3));
+
+                assert!(matches!(result, Err(CurrencyMismatch { .. })));
+                assert_eq!(bank.user_named("name1").balance(Currency::USD), usd(5));
+                assert!(!bank.user_named("name2").holds(Currency::USD));
+            }
+
+            #[test]
+            fn transfer_funds_without_exchange_rate() {
+                let user1 = User::new("name1".to_string(), usd(0), usd(5));
+                let user2 = User::new("name2".to_string(), eur(0), eur(1));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+                bank.set_cross_currency_transfers(CrossCurrencyTransfers::Convert);
+
+                let result = bank.transfer_funds(bank.id("name1"), bank.id("name2"), usd(3));
+
+                assert!(matches!(result, Err(ExchangeRateUnavailable { .. })));
+                assert_eq!(bank.user_named("name1").balance(Currency::USD), usd(5));
+            }
+
+            #[test]
+            fn transfer_funds_converts_into_receiver_currency() {
+                let user1 = User::new("name1".to_string(), usd(0), usd(500));
+                let user2 = User::new("name2".to_string(), eur(0), eur(100));
+                let mut bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+                let rates = FixedExchangeRates::default().with_rate(
+                    Currency::USD,
+                    Currency::EUR,
+                    ExchangeRate::new(90, 100).unwrap(),
+                );
+                bank.set_exchange_rates(Arc::new(rates));
+                bank.set_cross_currency_transfers(CrossCurrencyTransfers::Convert);
+
+                assert!(
+                    bank.transfer_funds(bank.id("name1"), bank.id("name2"), usd(300))
+                        .is_ok()
+                );
+
+                assert_eq!(bank.user_named("name1").balance(Currency::USD), usd(200));
+                assert_eq!(bank.user_named("name2").balance(Currency::EUR), eur(370));
+                let transfer = bank.ledger().entries().last().unwrap();
+                assert_eq!(transfer.amount(), usd(300));
+                assert_eq!(transfer.received_amount(), eur(270));
+                assert_eq!(
+                    bank.ledger()
+                        .replay_balance(bank.id("name2"), Currency::EUR),
+                    Ok(eur(370))
+                );
+                let books = bank.books();
+                assert!(books.journal().iter().all(|entry| entry.is_balanced()));
+                assert_eq!(
+                    books
+                        .balance(&Account::CurrencyExchange, Currency::USD)
+                        .unwrap(),
+                    usd(300)
+                );
+                assert_eq!(
+                    books
+                        .balance(&Account::CurrencyExchange, Currency::EUR)
+                        .unwrap(),
+                    eur(-270)
+                );
+            }
+
+            #[test]
+            fn calculate_balance_sheet_per_currency() {
+                let user1 = User::new("name1".to_string(), eur(0), eur(-2)).with_balance(gbp(7));
+                let user2 = User::new("name2".to_string(), usd(0), usd(3));
+                let bank = open(Bank::new(
+                    vec![user1, user2],
+                    "Bank Name".to_string(),
+                    BasisPoints::new(4),
+                    BasisPoints::new(1),
+                ));
+
+                let balance_sheets = bank.calc_balance_per_currency().unwrap();
+
+                assert_eq!(
+                    balance_sheets.keys().copied().collect::<Vec<_>>(),
+                    [Currency::EUR, Currency::USD, Currency::GBP]
+                );
+                assert_eq!(balance_sheets[&Currency::EUR].liabilities, eur(2));
+                assert_eq!(balance_sheets[&Currency::USD].assets, usd(3));
+                assert_eq!(balance_sheets[&Currency::GBP].assets, gbp(7));
+                assert!(balance_sheets.values().all(|sheet| sheet.is_balanced()));
+                assert_eq!(bank.calc_balance().unwrap().currency, Currency::EUR);
+            }
+        };
+    }
+
+    mod bare {
+        use super::*;
+
+        bank_tests!(Bank, |bank| bank);
+    }
+
+    mod memory {
+        use super::*;
+        use crate::repository::MemoryRepository;
+        use crate::test_support::Stored;
+
+        fn stored(bank: Bank) -> Stored<MemoryRepository> {
+            Stored::create(MemoryRepository::default(), bank)
+        }
+
+        bank_tests!(Stored<MemoryRepository>, stored);
+    }
+
+    #[cfg(feature = "sqlite")]
+    mod sqlite {
+        use super::*;
+        use crate::sqlite::SqliteRepository;
+        use crate::test_support::Stored;
+
+        fn stored(bank: Bank) -> Stored<SqliteRepository> {
+            Stored::create(SqliteRepository::open_in_memory().unwrap(), bank)
+        }
+
+        bank_tests!(Stored<SqliteRepository>, stored);
+    }
+
+    #[test]
+    fn balance_sheets_beyond_money_are_reported_as_overflow() {
+        let mut bank = interest_bank(&[0, 0], 0);
+        let (id0, id1) = (bank.id("name0"), bank.id("name1"));
+        bank.users[id0].set_balance(eur(i64::MAX / 2 + 1));
+        bank.users[id1].set_balance(eur(i64::MAX / 2 + 1));
+
+        assert_eq!(bank.calc_balance(), Err(MoneyError::Overflow));
+        assert_eq!(bank.calc_balance_per_currency(), Err(MoneyError::Overflow));
+
+        bank.users[id1].set_balance(eur(-i64::MAX));
+        assert_eq!(bank.calc_balance().unwrap().liabilities, eur(i64::MAX));
+    }
+    #[test]
+    fn transfer_batch_uses_running_account_states() {
+        let mut bank = interest_bank(&[-150, 100], 0);
+        let (id0, id1) = (bank.id("name0"), bank.id("name1"));
+        bank.users[id0].set_credit_line(eur(100));
+        assert_eq!(bank.users[id0].state(), AccountState::OverLimit);
+        let repayment = Transfer {
+            sender: id1,
+            receiver: id0,
+            amount: eur(100),
+        };
+        let payment = Transfer {
+            sender: id0,
+            receiver: id1,
+            amount: eur(10),
+        };
+
+        assert_eq!(
+            bank.transfer_batch(&[payment, repayment])
+                .unwrap_err()
+                .reason,
+            AccountOverLimit { account: id0 }
+        );
+        assert_eq!(bank.transfer_batch(&[repayment, payment]), Ok(()));
+        assert_eq!(bank.users[id0].balance(Currency::EUR), eur(-60));
+        assert_eq!(bank.users[id0].state(), AccountState::Active);
+    }
+
+    fn amounts() -> impl Strategy<Value = i64> {
+        prop_oneof![-10i64..1_000, Just(i64::MAX), Just(i64::MIN), any::<i64>()]
+    }
+
+    proptest! {
+        #[test]
+        fn transfer_funds_conserves_money(
+            accounts in prop::collection::vec(
+                (0i64..=i64::MAX, -(i64::MAX / 8)..=i64::MAX / 8),
+                2..5,
+            ),
+            transfers in prop::collection::vec((0usize..5, 0usize..5, amounts()), 1..40),
+        ) {
+            let users = accounts
+                .iter()
+                .enumerate()
+                .map(|(i, (credit_line, balance))| {
+                    User::new(format!("name{i}"), eur(*credit_line), eur(*balance))
+                })
+                .collect();
+            let mut bank = Bank::new(
+                users,
+                "Bank Name".to_string(),
+                BasisPoints::new(0),
+                BasisPoints::new(0),
+            );
+            let balances = |bank: &Bank| -> Vec<i64> {
+                bank.users
+                    .iter()
+                    .map(|(_, user)| user.balance(Currency::EUR).minor())
+                    .collect()
+            };
+            let total = |balances: &[i64]| -> i128 {
+                balances.iter().map(|balance| *balance as i128).sum()
+            };
+            let initial_total = total(&balances(&bank));
+
+            for (sender, receiver, amount) in transfers {
+                let before = balances(&bank);
+                let result = bank.transfer_funds(
+                    AccountId::new(sender as u64),
+                    AccountId::new(receiver as u64),
+                    eur(amount),
+                );
+                let after = balances(&bank);
+
+                prop_assert_eq!(total(&after), initial_total);
+                match result {
+                    Ok(()) => {
+                        prop_assert!(amount > 0);
+                        let sender = &bank.users[AccountId::new(sender as u64)];
+                        prop_assert!(
+                            sender.balance(Currency::EUR).minor() as i128
+                                >= -(sender.credit_line.minor() as i128)
+                        );
+                    }
+                    Err(_) => prop_assert_eq!(before, after),
+                }
+            }
+
+            prop_assert_eq!(bank.books().trial_balance(Currency::EUR).unwrap(), eur(0));
+            for (id, user) in bank.users.iter() {
+                let account = Account::CustomerDeposit(id);
+                prop_assert_eq!(
+                    bank.books().balance(&account, Currency::EUR).unwrap(),
+                    user.balance(Currency::EUR)
+                );
+            }
+        }
+    }
+
+    fn interest_bank(balances: &[i64], rate: u32) -> Bank {
+        let users = balances
+            .iter()
+            .enumerate()
+            .map(|(i, balance)| User::new(format!("name{i}"), eur(0), eur(*balance)))
+            .collect();
+        Bank::new(
+            users,
+            "Bank Name".to_string(),
+            BasisPoints::new(rate),
+            BasisPoints::new(rate),
+        )
+    }
+
+    #[test]
+    fn close_account_accrues_nothing_more_without_accrued_periods() {
+        let mut bank = interest_bank(&[1000, 0], 365);
+        let (id0, id1) = (bank.id("name0"), bank.id("name1"));
+        bank.accrue_interest();
+        let balance = bank.users[id0].balance(Currency::EUR);
+
+        assert_eq!(balance, eur(1036));
+        assert_eq!(bank.close_account(id0, id1), Ok(vec![balance]));
+    }
+
+    #[test]
+    fn over_limit_accounts_only_receive_funds() {
+        let mut bank = interest_bank(&[-300, 500], 0);
+        let (id0, id1) = (bank.id("name0"), bank.id("name1"));
+        bank.users[id0].set_credit_line(eur(1000));
+        bank.lower_credit_line(id0, eur(100)).unwrap();
+
+        assert_eq!(bank.users[id0].state(), AccountState::OverLimit);
+        assert_eq!(
+            bank.transfer_funds(id0, id1, eur(1)),
+            Err(AccountOverLimit { account: id0 })
+        );
+
+        bank.transfer_funds(id1, id0, eur(150)).unwrap();
+        assert_eq!(bank.users[id0].state(), AccountState::OverLimit);
+        bank.transfer_funds(id1, id0, eur(60)).unwrap();
+        assert_eq!(bank.users[id0].state(), AccountState::Active);
+        assert_eq!(bank.transfer_funds(id0, id1, eur(1)), Ok(()));
+    }
+
+    #[test]
+    fn debit_interest_beyond_the_credit_line_puts_accounts_over_limit() {
+        let mut bank = interest_bank(&[-100], 1000);
+        let id = bank.id("name0");
+        bank.users[id].set_credit_line(eur(100));
+        bank.set_overdraft_fee(eur(5));
+
+        bank.accrue_interest();
+        assert_eq!(bank.users[id].balance(Currency::EUR), eur(-110));
+        assert_eq!(bank.users[id].state(), AccountState::OverLimit);
+
+        bank.accrue_interest_between(date(2024, 1, 1), date(2024, 1, 11))
+            .unwrap();
+        assert_eq!(bank.users[id].balance(Currency::EUR), eur(-110 - 10 * 5));
+    }
+
+    #[test]
+    fn overdraft_fees_are_charged_on_over_limit_days() {
+        let mut bank = interest_bank(&[-300, -50], 0);
+        let (id0, id1) = (bank.id("name0"), bank.id("name1"));
+        bank.users[id1].set_credit_line(eur(100));
+        bank.revoke_credit_line(id0).unwrap();
+        bank.set_overdraft_fee(eur(5));
+
+        bank.accrue_interest_between(date(2024, 1, 1), date(2024, 2, 11))
+            .unwrap();
+
+        assert_eq!(bank.users[id0].balance(Currency::EUR), eur(-300 - 41 * 5));
+        assert_eq!(bank.users[id1].balance(Currency::EUR), eur(-50));
+        assert_eq!(
+            bank.books()
+                .balance(&Account::FeeIncome, Currency::EUR)
+                .unwrap(),
+            eur(41 * 5)
+        );
+        assert!(bank.calc_balance().unwrap().is_balanced());
+    }
+
+    fn reconciled_by(reconciliation: AccountReconciliation) -> MergePolicy {
+        MergePolicy {
+            reconciliation,
+            ..MergePolicy::default()
+        }
+    }
+
+    struct BankHelper<'a> {
+        bank: &'a Bank,
+    }
+
+    impl BankHelper<'_> {
         fn balance_for(&self, username: &str) -> Balance {
             (*self.bank).balance_of_user(username)
         }
@@ -346,7 +3146,19 @@ mod tests {
 
     impl Bank {
         pub(crate) fn balance_of_user(&self, user_name: &str) -> Balance {
-            Balance::new(self.users[self.index_of_user_by_username(user_name).unwrap()].balance)
+            Balance::new(self.user_named(user_name).balance(Currency::EUR).minor())
+        }
+
+        fn user_named(&self, user_name: &str) -> &User {
+            &self.users[self.id(user_name)]
+        }
+
+        /// The only account held by `user_name`
+        fn id(&self, user_name: &str) -> AccountId {
+            let [id] = self.accounts_named(user_name)[..] else {
+                panic!("expected a single account held by {user_name}");
+            };
+            id
         }
     }
 }
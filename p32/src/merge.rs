@@ -0,0 +1,105 @@
+use crate::AccountId;
+use crate::money::{BasisPoints, Money};
+use std::collections::BTreeMap;
+
+/// How the accounts of a merged bank are matched with the accounts of the bank it is merged into.
+///
+/// Matched accounts conflict with each other and are resolved according to the
+/// [`ConflictResolution`] of the merge; the others are opened as new accounts.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AccountReconciliation {
+    /// Match accounts held by the same username. Fails if a matched username is held by several
+    /// accounts of either bank.
+    #[default]
+    ByName,
+    /// Match every account of the merged bank in the keys with the account in the values
+    Explicit(BTreeMap<AccountId, AccountId>),
+    /// Open every account of the merged bank as a new account
+    Separate,
+}
+
+/// Credit line of an account combined with a matched account of the merged bank.
+///
+/// Credit lines in different home currencies cannot be compared, so the one of the account
+/// merged into is kept for those.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CreditLines {
+    /// Keep the credit line of the account merged into
+    #[default]
+    Keep,
+    Max,
+    Min,
+    Sum,
+}
+
+/// What to do with an account of the merged bank that matches an existing account
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConflictResolution {
+    /// Combine both accounts into the existing one, summing their balances
+    #[default]
+    Combine,
+    /// Open the account as a new one, its username suffixed with the name of its bank
+    Rename,
+    /// Fail the merge
+    Reject,
+}
+
+/// How `Bank::merge_bank` combines two banks
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MergePolicy {
+    pub reconciliation: AccountReconciliation,
+    pub credit_lines: CreditLines,
+    pub conflicts: ConflictResolution,
+}
+
+/// What happens to an account of the merged bank
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MergeAction {
+    /// Combined into an existing account, which ends up with `credit_line`
+    Combined { credit_line: Money },
+    /// Opened as a new account under another username
+    Renamed { username: String },
+    /// Opened as a new account
+    Opened,
+    /// Matches an existing account, which makes the merge fail
+    Conflict,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergedAccount {
+    /// Id of the account in the merged bank
+    pub source: AccountId,
+    /// Id of the account once merged, or of the existing account it conflicts with
+    pub target: AccountId,
+    pub action: MergeAction,
+}
+
+/// Outcome of merging two banks, available before committing to it through `Bank::plan_merge`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergeReport {
+    /// Every account of the merged bank, by id in that bank
+    pub accounts: Vec<MergedAccount>,
+    /// Credit and debit interest of the merged bank when they differ from the ones of the bank
+    /// merged into, which are the only ones kept
+    pub discarded_interest: Option<(BasisPoints, BasisPoints)>,
+}
+
+impl MergeReport {
+    /// The id every account of the merged bank gets in the bank merged into
+    pub fn ids(&self) -> BTreeMap<AccountId, AccountId> {
+        self.accounts
+            .iter()
+            .map(|account| (account.source, account.target))
+            .collect()
+    }
+
+    pub fn conflicts(&self) -> impl Iterator<Item = &MergedAccount> {
+        self.accounts
+            .iter()
+            .filter(|account| account.action == MergeAction::Conflict)
+    }
+}
@@ -0,0 +1,335 @@
+use crate::currency::Currency;
+use std::error::Error;
+use std::fmt;
+
+/// An amount of money, counted in the minor unit of its currency (e.g. cents).
+///
+/// Arithmetic is checked: it fails instead of overflowing or mixing currencies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Money {
+    minor: i64,
+    currency: Currency,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoneyError {
+    CurrencyMismatch(Currency, Currency),
+    Overflow,
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::CurrencyMismatch(left, right) => {
+                write!(f, "cannot combine amounts in {left} and {right}")
+            }
+            MoneyError::Overflow => f.write_str("amount out of range"),
+        }
+    }
+}
+
+impl Error for MoneyError {}
+
+/// Why text is not an amount of money
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseMoneyError {
+    /// Not a decimal number such as `-12.30`
+    Malformed,
+    /// More decimal digits than the minor unit of the currency has
+    TooPrecise,
+    Overflow,
+}
+
+impl fmt::Display for ParseMoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseMoneyError::Malformed => f.write_str("not a decimal amount"),
+            ParseMoneyError::TooPrecise => f.write_str("too many decimal digits"),
+            ParseMoneyError::Overflow => f.write_str("amount out of range"),
+        }
+    }
+}
+
+impl Error for ParseMoneyError {}
+
+impl Money {
+    pub fn from_minor(minor: i64, currency: Currency) -> Self {
+        Money { minor, currency }
+    }
+
+    /// An amount summed up in 128 bits, unless it does not fit in minor units
+    pub(crate) fn from_total(total: i128, currency: Currency) -> Result<Self, MoneyError> {
+        i64::try_from(total)
+            .map(|minor| Money::from_minor(minor, currency))
+            .map_err(|_| MoneyError::Overflow)
+    }
+
+    /// Returns `None` if the amount cannot be represented in minor units
+    pub fn from_major(major: i64, currency: Currency) -> Option<Self> {
+        major
+            .checked_mul(currency.minor_units_per_major())
+            .map(|minor| Money::from_minor(minor, currency))
+    }
+
+    /// Parses an amount in major units, such as `-12.3`, with at most as many decimal digits as
+    /// the minor unit of `currency`
+    pub fn from_decimal(text: &str, currency: Currency) -> Result<Self, ParseMoneyError> {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let (major, fraction) = match digits.split_once('.') {
+            Some((_, "")) => return Err(ParseMoneyError::Malformed),
+            Some(parts) => parts,
+            None => (digits, ""),
+        };
+        let all_digits = major
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|b| b.is_ascii_digit());
+        if digits.is_empty() || !all_digits {
+            return Err(ParseMoneyError::Malformed);
+        }
+        let scale = currency.minor_units() as usize;
+        if fraction.len() > scale {
+            return Err(ParseMoneyError::TooPrecise);
+        }
+        // Only digits are left, so parsing fails on overflow alone. The magnitude is unsigned
+        // and summed up in 128 bits so that the most negative amount parses too.
+        let major: u64 = match major {
+            "" => 0,
+            major => major.parse().map_err(|_| ParseMoneyError::Overflow)?,
+        };
+        let fraction: u64 = match scale {
+            0 => 0,
+            _ => format!("{fraction:0<scale$}").parse().unwrap_or_default(),
+        };
+        let magnitude =
+            i128::from(major) * i128::from(currency.minor_units_per_major()) + i128::from(fraction);
+        let minor = if negative { -magnitude } else { magnitude };
+        Money::from_total(minor, currency).map_err(|_| ParseMoneyError::Overflow)
+    }
+
+    /// The amount in major units without its currency, such as `-12.30`
+    pub fn to_decimal(&self) -> String {
+        let sign = if self.minor < 0 { "-" } else { "" };
+        let per_major = self.currency.minor_units_per_major().unsigned_abs();
+        let major = self.minor.unsigned_abs() / per_major;
+        let minor = self.minor.unsigned_abs() % per_major;
+        match self.currency.minor_units() as usize {
+            0 => format!("{sign}{major}"),
+            scale => format!("{sign}{major}.{minor:0scale$}"),
+        }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Money::from_minor(0, currency)
+    }
+
+    pub fn minor(&self) -> i64 {
+        self.minor
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.minor < 0
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.minor > 0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.minor == 0
+    }
+
+    pub fn checked_add(self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(other)?;
+        self.minor
+            .checked_add(other.minor)
+            .map(|minor| Money::from_minor(minor, self.currency))
+            .ok_or(MoneyError::Overflow)
+    }
+
+    pub fn checked_sub(self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(other)?;
+        self.minor
+            .checked_sub(other.minor)
+            .map(|minor| Money::from_minor(minor, self.currency))
+            .ok_or(MoneyError::Overflow)
+    }
+
+    pub fn checked_neg(self) -> Result<Money, MoneyError> {
+        self.minor
+            .checked_neg()
+            .map(|minor| Money::from_minor(minor, self.currency))
+            .ok_or(MoneyError::Overflow)
+    }
+
+    fn same_currency(&self, other: Money) -> Result<(), MoneyError> {
+        match self.currency == other.currency {
+            true => Ok(()),
+            false => Err(MoneyError::CurrencyMismatch(self.currency, other.currency)),
+        }
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.to_decimal(), self.currency)
+    }
+}
+
+/// An interest rate in hundredths of a percent
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BasisPoints(u32);
+
+impl BasisPoints {
+    pub fn new(basis_points: u32) -> Self {
+        BasisPoints(basis_points)
+    }
+
+    pub fn value(&self) -> u32 {
+        self.0
+    }
+
+    /// `amount` times this rate, rounded half to even to the minor unit
+    pub fn of(&self, amount: Money) -> Result<Money, MoneyError> {
+        let interest = div_round_half_even(amount.minor as i128 * self.0 as i128, 10_000);
+        i64::try_from(interest)
+            .map(|minor| Money::from_minor(minor, amount.currency))
+            .map_err(|_| MoneyError::Overflow)
+    }
+}
+
+/// How a fraction of the minor unit is rounded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Rounding {
+    /// Half to even (banker's rounding)
+    #[default]
+    HalfEven,
+    /// Half away from zero
+    HalfUp,
+    /// Drop the fraction
+    TowardZero,
+}
+
+impl Rounding {
+    /// `numerator / denominator` rounded with this rule, for a positive denominator
+    pub fn divide(&self, numerator: i128, denominator: i128) -> i128 {
+        match self {
+            Rounding::HalfEven => div_round_half_even(numerator, denominator),
+            Rounding::HalfUp => {
+                let quotient = numerator / denominator;
+                let remainder = numerator % denominator;
+                match 2 * remainder.abs() >= denominator {
+                    true => quotient + numerator.signum(),
+                    false => quotient,
+                }
+            }
+            Rounding::TowardZero => numerator / denominator,
+        }
+    }
+}
+
+/// `numerator / denominator` rounded half to even (banker's rounding), for a positive denominator
+pub(crate) fn div_round_half_even(numerator: i128, denominator: i128) -> i128 {
+    let quotient = numerator.div_euclid(denominator);
+    let remainder = numerator.rem_euclid(denominator);
+    match (2 * remainder).cmp(&denominator) {
+        std::cmp::Ordering::Less => quotient,
+        std::cmp::Ordering::Greater => quotient + 1,
+        std::cmp::Ordering::Equal => quotient + quotient.rem_euclid(2),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::eur;
+
+    #[test]
+    fn rates_round_half_to_even() {
+        let one_percent = BasisPoints::new(100);
+
+        assert_eq!(one_percent.of(eur(50)), Ok(eur(0)));
+        assert_eq!(one_percent.of(eur(150)), Ok(eur(2)));
+        assert_eq!(one_percent.of(eur(250)), Ok(eur(2)));
+        assert_eq!(one_percent.of(eur(-250)), Ok(eur(-2)));
+        assert_eq!(one_percent.of(eur(-80)), Ok(eur(-1)));
+        assert_eq!(one_percent.of(eur(149)), Ok(eur(1)));
+    }
+
+    #[test]
+    fn rounding_rules() {
+        let cases = [(5, 10), (15, 10), (-5, 10), (-15, 10), (14, 10), (-16, 10)];
+        let round = |rounding: Rounding| -> Vec<i128> {
+            cases
+                .iter()
+                .map(|(numerator, denominator)| rounding.divide(*numerator, *denominator))
+                .collect()
+        };
+
+        assert_eq!(round(Rounding::HalfEven), [0, 2, 0, -2, 1, -2]);
+        assert_eq!(round(Rounding::HalfUp), [1, 2, -1, -2, 1, -2]);
+        assert_eq!(round(Rounding::TowardZero), [0, 1, 0, -1, 1, -1]);
+    }
+
+    #[test]
+    fn checked_arithmetic() {
+        let usd = Money::from_minor(1, Currency::USD);
+
+        assert_eq!(eur(1).checked_add(eur(2)), Ok(eur(3)));
+        assert_eq!(eur(1).checked_sub(eur(2)), Ok(eur(-1)));
+        assert_eq!(
+            eur(1).checked_add(usd),
+            Err(MoneyError::CurrencyMismatch(Currency::EUR, Currency::USD))
+        );
+        assert_eq!(eur(i64::MAX).checked_add(eur(1)), Err(MoneyError::Overflow));
+        assert_eq!(eur(i64::MIN).checked_neg(), Err(MoneyError::Overflow));
+        assert_eq!(Money::from_major(i64::MAX, Currency::EUR), None);
+    }
+
+    #[test]
+    fn display_uses_the_minor_unit_scale() {
+        assert_eq!(
+            Money::from_major(12, Currency::GBP).unwrap().to_string(),
+            "12.00 GBP"
+        );
+        assert_eq!(eur(-104).to_string(), "-1.04 EUR");
+        assert_eq!(eur(5).to_string(), "0.05 EUR");
+    }
+
+    #[test]
+    fn decimal_amounts() {
+        let parse = |text: &str| Money::from_decimal(text, Currency::EUR);
+
+        assert_eq!(parse("12.3"), Ok(eur(1230)));
+        assert_eq!(parse("-0.05"), Ok(eur(-5)));
+        assert_eq!(parse("+7"), Ok(eur(700)));
+        assert_eq!(parse(".5"), Ok(eur(50)));
+        assert_eq!(parse("1.234"), Err(ParseMoneyError::TooPrecise));
+        for malformed in ["", "-", "1.", "1,5", "1e3", " 1", "--1"] {
+            assert_eq!(parse(malformed), Err(ParseMoneyError::Malformed));
+        }
+        assert_eq!(
+            parse("99999999999999999999"),
+            Err(ParseMoneyError::Overflow)
+        );
+        assert_eq!(eur(-1230).to_decimal(), "-12.30");
+        assert_eq!(parse(&eur(-1230).to_decimal()), Ok(eur(-1230)));
+        for extreme in [i64::MIN, i64::MAX] {
+            assert_eq!(parse(&eur(extreme).to_decimal()), Ok(eur(extreme)));
+        }
+        assert_eq!(
+            parse("92233720368547758.08"),
+            Err(ParseMoneyError::Overflow)
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use crate::money::{BasisPoints, Money};
+use chrono::NaiveDate;
+use std::collections::BTreeMap;
+use std::ops::Bound::{Excluded, Unbounded};
+use std::sync::Arc;
+
+/// A part of a balance and the annual rate it earns, or pays if the balance is negative
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateBand {
+    pub amount: Money,
+    pub rate: BasisPoints,
+}
+
+/// The interest rates an account earns or pays.
+///
+/// A policy can be shared by every account of a product.
+pub trait RatePolicy: Send + Sync {
+    /// Splits `balance` into the bands that earn a rate on `date`. The bands add up to at most
+    /// `balance`; what they leave out earns no interest.
+    fn bands(&self, balance: Money, date: NaiveDate) -> Vec<RateBand>;
+
+    /// First day after `date` on which the rates change, if they ever do
+    fn next_change(&self, _date: NaiveDate) -> Option<NaiveDate> {
+        None
+    }
+}
+
+/// One rate for positive balances and one for negative balances, the policy of accounts that
+/// have none of their own
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlatRate {
+    /// Rate of negative balances
+    pub credit: BasisPoints,
+    /// Rate of positive balances
+    pub debit: BasisPoints,
+}
+
+impl RatePolicy for FlatRate {
+    fn bands(&self, balance: Money, _date: NaiveDate) -> Vec<RateBand> {
+        let rate = match balance.is_negative() {
+            false => self.debit,
+            true => self.credit,
+        };
+        vec![RateBand {
+            amount: balance,
+            rate,
+        }]
+    }
+}
+
+/// Positive balances earn a rate per balance band, such as 0.5% up to 10k and 1% above;
+/// negative balances pay a single rate
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TieredRate {
+    credit: BasisPoints,
+    tiers: Vec<(i64, BasisPoints)>,
+}
+
+impl TieredRate {
+    /// Every tier is the amount, in minor units, above which its rate applies. A balance below
+    /// the lowest tier earns nothing.
+    pub fn new(credit: BasisPoints, mut tiers: Vec<(i64, BasisPoints)>) -> Self {
+        tiers.sort_by_key(|(from, _)| *from);
+        TieredRate { credit, tiers }
+    }
+}
+
+impl RatePolicy for TieredRate {
+    fn bands(&self, balance: Money, _date: NaiveDate) -> Vec<RateBand> {
+        if balance.is_negative() {
+            return vec![RateBand {
+                amount: balance,
+                rate: self.credit,
+            }];
+        }
+        let upper_bounds = self.tiers.iter().skip(1).map(|(from, _)| Some(*from));
+        self.tiers
+            .iter()
+            .zip(upper_bounds.chain([None]))
+            .filter(|((from, _), _)| balance.minor() > *from)
+            .map(|((from, rate), to)| {
+                let to = to.map_or(balance.minor(), |to| to.min(balance.minor()));
+                RateBand {
+                    amount: Money::from_minor(to - from, balance.currency()),
+                    rate: *rate,
+                }
+            })
+            .collect()
+    }
+}
+
+/// A policy replaced by other ones on given dates
+#[derive(Clone)]
+pub struct SteppedRate {
+    initial: Arc<dyn RatePolicy>,
+    steps: BTreeMap<NaiveDate, Arc<dyn RatePolicy>>,
+}
+
+impl SteppedRate {
+    pub fn new(initial: Arc<dyn RatePolicy>) -> Self {
+        SteppedRate {
+            initial,
+            steps: BTreeMap::new(),
+        }
+    }
+
+    /// Applies `policy` from `date` on
+    pub fn changing_on(mut self, date: NaiveDate, policy: Arc<dyn RatePolicy>) -> Self {
+        self.steps.insert(date, policy);
+        self
+    }
+
+    fn policy_on(&self, date: NaiveDate) -> &dyn RatePolicy {
+        match self.steps.range(..=date).next_back() {
+            Some((_, policy)) => policy.as_ref(),
+            None => self.initial.as_ref(),
+        }
+    }
+}
+
+impl RatePolicy for SteppedRate {
+    fn bands(&self, balance: Money, date: NaiveDate) -> Vec<RateBand> {
+        self.policy_on(date).bands(balance, date)
+    }
+
+    fn next_change(&self, date: NaiveDate) -> Option<NaiveDate> {
+        let step = self
+            .steps
+            .range((Excluded(date), Unbounded))
+            .next()
+            .map(|(step, _)| *step);
+        match (step, self.policy_on(date).next_change(date)) {
+            (Some(step), Some(change)) => Some(step.min(change)),
+            (step, change) => step.or(change),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{date, eur};
+
+    fn band(amount: i64, rate: u32) -> RateBand {
+        RateBand {
+            amount: eur(amount),
+            rate: BasisPoints::new(rate),
+        }
+    }
+
+    #[test]
+    fn flat_rate_by_sign() {
+        let flat = FlatRate {
+            credit: BasisPoints::new(400),
+            debit: BasisPoints::new(100),
+        };
+        let day = date(2024, 1, 1);
+
+        assert_eq!(flat.bands(eur(50), day), vec![band(50, 100)]);
+        assert_eq!(flat.bands(eur(-50), day), vec![band(-50, 400)]);
+        assert_eq!(flat.next_change(day), None);
+    }
+
+    #[test]
+    fn tiered_rate_bands() {
+        let tiered = TieredRate::new(
+            BasisPoints::new(900),
+            vec![
+                (1_000_000, BasisPoints::new(100)),
+                (0, BasisPoints::new(50)),
+            ],
+        );
+        let day = date(2024, 1, 1);
+
+        assert_eq!(tiered.bands(eur(400_000), day), vec![band(400_000, 50)]);
+        assert_eq!(
+            tiered.bands(eur(1_500_000), day),
+            vec![band(1_000_000, 50), band(500_000, 100)]
+        );
+        assert_eq!(tiered.bands(eur(0), day), vec![]);
+        assert_eq!(tiered.bands(eur(-10), day), vec![band(-10, 900)]);
+    }
+
+    #[test]
+    fn stepped_rate_changes_on_dates() {
+        let flat = |rate| {
+            Arc::new(FlatRate {
+                credit: BasisPoints::new(rate),
+                debit: BasisPoints::new(rate),
+            })
+        };
+        let stepped = SteppedRate::new(flat(100))
+            .changing_on(date(2024, 3, 1), flat(300))
+            .changing_on(date(2024, 2, 1), flat(200));
+
+        assert_eq!(
+            stepped.bands(eur(10), date(2024, 1, 31)),
+            vec![band(10, 100)]
+        );
+        assert_eq!(
+            stepped.bands(eur(10), date(2024, 2, 1)),
+            vec![band(10, 200)]
+        );
+        assert_eq!(
+            stepped.bands(eur(10), date(2024, 6, 1)),
+            vec![band(10, 300)]
+        );
+        assert_eq!(
+            stepped.next_change(date(2024, 1, 15)),
+            Some(date(2024, 2, 1))
+        );
+        assert_eq!(
+            stepped.next_change(date(2024, 2, 1)),
+            Some(date(2024, 3, 1))
+        );
+        assert_eq!(stepped.next_change(date(2024, 3, 1)), None);
+    }
+}
@@ -0,0 +1,656 @@
+use crate::clock::{Clock, SystemClock};
+use crate::currency::{CrossCurrencyTransfers, ExchangeRateProvider};
+use crate::error::{
+    AccountError, AccountNotFound, CreditLineError, InterestError, MergeError, RepositoryError,
+    StorageError,
+};
+use crate::interest::InterestConventions;
+use crate::merge::{MergePolicy, MergeReport};
+use crate::money::Money;
+use crate::rates::RatePolicy;
+use crate::snapshot::BankSnapshot;
+use crate::{
+    AccountId, AccountState, Bank, BatchTransferError, Transfer, TransferFundsError, User,
+};
+use chrono::NaiveDate;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+/// Storage of a [`StoredBank`].
+///
+/// A repository holds a bank as a [`BankSnapshot`] that is saved piece by piece. Besides the
+/// settings of the bank, every save brings the accounts that changed, which replace the stored
+/// ones, and the ledger and journal entries added since the previous save.
+pub trait BankRepository {
+    /// The stored bank, if the repository holds one
+    fn load(&self) -> Result<Option<BankSnapshot>, RepositoryError>;
+
+    /// Stores `changes` all at once: if saving fails, the repository is left as it was
+    fn save(&mut self, changes: &BankSnapshot) -> Result<(), RepositoryError>;
+}
+
+/// Repository keeping the bank in memory, as a list of accounts sorted by id
+#[derive(Default)]
+pub struct MemoryRepository {
+    bank: Option<BankSnapshot>,
+}
+
+impl BankRepository for MemoryRepository {
+    fn load(&self) -> Result<Option<BankSnapshot>, RepositoryError> {
+        Ok(self.bank.clone())
+    }
+
+    fn save(&mut self, changes: &BankSnapshot) -> Result<(), RepositoryError> {
+        match &mut self.bank {
+            Some(bank) => bank.merge_changes(changes),
+            None => self.bank = Some(changes.clone()),
+        }
+        Ok(())
+    }
+}
+
+/// The accounts a mutation may have changed besides the ones its ledger entries involve
+pub(crate) enum Changed {
+    Accounts(Vec<AccountId>),
+    All,
+}
+
+/// How much of a bank was saved: its accounts up to `next_account_id` and its entries and events
+/// up to the given lengths
+#[derive(Clone, Copy)]
+pub(crate) struct Saved {
+    next_account_id: AccountId,
+    ledger: usize,
+    journal: usize,
+    events: usize,
+}
+
+impl Saved {
+    /// All of `bank`
+    pub(crate) fn of(bank: &Bank) -> Self {
+        Saved {
+            next_account_id: bank.users.next_id(),
+            ledger: bank.ledger.entries().len(),
+            journal: bank.books.journal().len(),
+            events: bank.events.events().len(),
+        }
+    }
+
+    /// What a mutation of `bank` changed since it was saved: the `changed` accounts, the
+    /// accounts it opened and the ones involved in the entries it recorded
+    pub(crate) fn changes(&self, bank: &Bank, changed: Changed) -> BankSnapshot {
+        let mut accounts: BTreeSet<AccountId> = match changed {
+            Changed::Accounts(accounts) => accounts.into_iter().collect(),
+            Changed::All => bank.users.ids().into_iter().collect(),
+        };
+        let opened = self.next_account_id.value()..bank.users.next_id().value();
+        accounts.extend(opened.map(AccountId::new));
+        for entry in &bank.ledger.entries()[self.ledger..] {
+            accounts.extend(entry.sender());
+            accounts.extend(entry.receiver());
+        }
+        bank.partial_snapshot(accounts, self.ledger, self.journal, self.events)
+    }
+}
+
+/// A bank saved to a [`BankRepository`] after every mutation.
+///
+/// A mutation is applied to the bank in memory, then the accounts and entries it changed are
+/// saved at once, e.g. in a single database transaction. If saving fails, the bank is reloaded
+/// from the repository, so that it never shows changes the repository does not hold.
+pub struct StoredBank<R> {
+    bank: Bank,
+    repository: R,
+    /// What the repository holds of the bank
+    saved: Saved,
+}
+
+impl<R: BankRepository> StoredBank<R> {
+    /// Saves `bank` in `repository`, which must not hold a bank yet
+    pub fn create(mut repository: R, bank: Bank) -> Result<Self, RepositoryError> {
+        if repository.load()?.is_some() {
+            return Err(RepositoryError::AlreadyExists);
+        }
+        repository.save(&bank.snapshot())?;
+        Ok(StoredBank::loaded(bank, repository))
+    }
+
+    /// Loads the bank held by `repository`, with the system clock
+    pub fn open(repository: R) -> Result<Self, RepositoryError> {
+        StoredBank::open_with_clock(repository, Arc::new(SystemClock))
+    }
+
+    pub fn open_with_clock(repository: R, clock: Arc<dyn Clock>) -> Result<Self, RepositoryError> {
+        let bank = load(&repository, clock)?;
+        Ok(StoredBank::loaded(bank, repository))
+    }
+
+    pub fn bank(&self) -> &Bank {
+        &self.bank
+    }
+
+    pub fn repository(&self) -> &R {
+        &self.repository
+    }
+
+    pub fn transfer_funds(
+        &mut self,
+        sender: AccountId,
+        receiver: AccountId,
+        amount: Money,
+    ) -> Result<(), StorageError<TransferFundsError>> {
+        self.execute(Changed::Accounts(vec![sender, receiver]), |bank| {
+            bank.transfer_funds(sender, receiver, amount)
+        })
+    }
+
+    pub fn transfer_batch(
+        &mut self,
+        transfers: &[Transfer],
+    ) -> Result<(), StorageError<BatchTransferError>> {
+        self.execute(Changed::Accounts(vec![]), |bank| {
+            bank.transfer_batch(transfers)
+        })
+    }
+
+    pub fn accrue_interest(&mut self) -> Result<(), StorageError> {
+        self.execute(Changed::Accounts(vec![]), |bank| {
+            bank.accrue_interest();
+            Ok(())
+        })
+    }
+
+    pub fn accrue_interest_between(
+        &mut self,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<(), StorageError<InterestError>> {
+        self.execute(Changed::Accounts(vec![]), |bank| {
+            bank.accrue_interest_between(from, to)
+        })
+    }
+
+    pub fn post_accrued_interest(&mut self) -> Result<(), StorageError> {
+        self.execute(Changed::Accounts(vec![]), |bank| {
+            bank.post_accrued_interest();
+            Ok(())
+        })
+    }
+
+    pub fn set_interest_conventions(
+        &mut self,
+        conventions: InterestConventions,
+    ) -> Result<(), StorageError> {
+        self.execute(Changed::Accounts(vec![]), |bank| {
+            bank.set_interest_conventions(conventions);
+            Ok(())
+        })
+    }
+
+    pub fn set_overdraft_fee(&mut self, fee: Money) -> Result<(), StorageError> {
+        self.execute(Changed::Accounts(vec![]), |bank| {
+            bank.set_overdraft_fee(fee);
+            Ok(())
+        })
+    }
+
+    pub fn set_cross_currency_transfers(
+        &mut self,
+        cross_currency_transfers: CrossCurrencyTransfers,
+    ) -> Result<(), StorageError> {
+        self.execute(Changed::Accounts(vec![]), |bank| {
+            bank.set_cross_currency_transfers(cross_currency_transfers);
+            Ok(())
+        })
+    }
+
+    /// Sets the exchange rate provider of the bank in memory. Providers are not data the
+    /// repository can hold, so set it again after opening the bank.
+    pub fn set_exchange_rates(&mut self, exchange_rates: Arc<dyn ExchangeRateProvider>) {
+        self.bank.set_exchange_rates(exchange_rates);
+    }
+
+    /// Sets the rate policy of `account` in memory, like [`StoredBank::set_exchange_rates`].
+    /// The interest it accrues is saved.
+    pub fn set_rate_policy(
+        &mut self,
+        account: AccountId,
+        policy: Arc<dyn RatePolicy>,
+    ) -> Result<(), AccountNotFound> {
+        self.bank.set_rate_policy(account, policy)
+    }
+
+    pub fn merge_bank(
+        &mut self,
+        other: Bank,
+        policy: &MergePolicy,
+    ) -> Result<MergeReport, StorageError<MergeError>> {
+        self.execute(Changed::All, |bank| bank.merge_bank(other, policy))
+    }
+
+    pub fn open_account(&mut self, user: User) -> Result<AccountId, StorageError<AccountError>> {
+        self.execute(Changed::Accounts(vec![]), |bank| bank.open_account(user))
+    }
+
+    pub fn rename_account(
+        &mut self,
+        account: AccountId,
+        username: String,
+    ) -> Result<(), StorageError<AccountNotFound>> {
+        self.execute(Changed::Accounts(vec![account]), |bank| {
+            bank.rename_account(account, username)
+        })
+    }
+
+    pub fn freeze_account(&mut self, account: AccountId) -> Result<(), StorageError<AccountError>> {
+        self.execute(Changed::Accounts(vec![account]), |bank| {
+            bank.freeze_account(account)
+        })
+    }
+
+    pub fn unfreeze_account(
+        &mut self,
+        account: AccountId,
+    ) -> Result<AccountState, StorageError<AccountError>> {
+        self.execute(Changed::Accounts(vec![account]), |bank| {
+            bank.unfreeze_account(account)
+        })
+    }
+
+    pub fn close_account(
+        &mut self,
+        account: AccountId,
+        settlement_account: AccountId,
+    ) -> Result<Vec<Money>, StorageError<AccountError>> {
+        self.execute(Changed::Accounts(vec![account]), |bank| {
+            bank.close_account(account, settlement_account)
+        })
+    }
+
+    pub fn request_credit_line(
+        &mut self,
+        account: AccountId,
+        amount: Money,
+    ) -> Result<(), StorageError<CreditLineError>> {
+        self.execute(Changed::Accounts(vec![account]), |bank| {
+            bank.request_credit_line(account, amount)
+        })
+    }
+
+    pub fn approve_credit_line(
+        &mut self,
+        account: AccountId,
+    ) -> Result<Money, StorageError<CreditLineError>> {
+        self.execute(Changed::Accounts(vec![account]), |bank| {
+            bank.approve_credit_line(account)
+        })
+    }
+
+    pub fn raise_credit_line(
+        &mut self,
+        account: AccountId,
+        amount: Money,
+    ) -> Result<(), StorageError<CreditLineError>> {
+        self.execute(Changed::Accounts(vec![account]), |bank| {
+            bank.raise_credit_line(account, amount)
+        })
+    }
+
+    pub fn lower_credit_line(
+        &mut self,
+        account: AccountId,
+        amount: Money,
+    ) -> Result<AccountState, StorageError<CreditLineError>> {
+        self.execute(Changed::Accounts(vec![account]), |bank| {
+            bank.lower_credit_line(account, amount)
+        })
+    }
+
+    pub fn revoke_credit_line(
+        &mut self,
+        account: AccountId,
+    ) -> Result<AccountState, StorageError<CreditLineError>> {
+        self.execute(Changed::Accounts(vec![account]), |bank| {
+            bank.revoke_credit_line(account)
+        })
+    }
+
+    fn loaded(bank: Bank, repository: R) -> Self {
+        StoredBank {
+            saved: Saved::of(&bank),
+            bank,
+            repository,
+        }
+    }
+
+    /// Applies a mutation with `apply`, then saves what it changed
+    fn execute<T, E>(
+        &mut self,
+        changed: Changed,
+        apply: impl FnOnce(&mut Bank) -> Result<T, E>,
+    ) -> Result<T, StorageError<E>> {
+        let value = apply(&mut self.bank).map_err(StorageError::Rejected)?;
+        let changes = self.saved.changes(&self.bank, changed);
+
+        if let Err(error) = self.repository.save(&changes) {
+            // Should reloading fail too, the unsaved entries are saved with the next mutation
+            if let Ok(mut bank) = load(&self.repository, self.bank.clock.clone()) {
+                bank.keep_unsaved_settings(&self.bank);
+                self.saved = Saved::of(&bank);
+                self.bank = bank;
+            }
+            return Err(error.into());
+        }
+        self.saved = Saved::of(&self.bank);
+        Ok(value)
+    }
+}
+
+fn load(repository: &impl BankRepository, clock: Arc<dyn Clock>) -> Result<Bank, RepositoryError> {
+    let snapshot = repository.load()?.ok_or(RepositoryError::NoBank)?;
+    Ok(Bank::restore_with_clock(snapshot, clock)?)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::currency::{Currency, ExchangeRate, FixedExchangeRates};
+    use crate::test_support::{self, eur, usd};
+
+    /// Tests of a [`StoredBank`] kept in the repository built by `$repository`, run for every
+    /// repository
+    macro_rules! repository_tests {
+        ($repository:expr) => {
+            mod suite {
+                use super::*;
+                use crate::currency::{
+                    CrossCurrencyTransfers, Currency, ExchangeRate, FixedExchangeRates,
+                };
+                use crate::error::{AccountError, RepositoryError, StorageError};
+                use crate::merge::{CreditLines, MergePolicy};
+                use crate::money::BasisPoints;
+                use crate::repository::{BankRepository, StoredBank};
+                use crate::snapshot::BankSnapshot;
+                use crate::test_support::{self, date, eur, usd};
+                use crate::{AccountId, AccountState, Bank, Transfer, TransferFundsError, User};
+                use std::sync::Arc;
+
+                fn bank() -> Bank {
+                    test_support::bank([("name1", 0, 100), ("name2", 50, 0)], 365)
+                }
+
+                fn stored_bank() -> StoredBank<impl BankRepository> {
+                    StoredBank::create($repository, bank()).unwrap()
+                }
+
+                /// The bank held by the repository of `stored`
+                fn saved<R: BankRepository>(stored: &StoredBank<R>) -> BankSnapshot {
+                    let snapshot = stored.repository().load().unwrap().unwrap();
+                    Bank::restore(snapshot).unwrap().snapshot()
+                }
+
+                #[test]
+                fn create_saves_the_bank() {
+                    let stored = stored_bank();
+
+                    assert_eq!(saved(&stored), stored.bank().snapshot());
+                }
+
+                #[test]
+                fn create_and_open_need_a_matching_repository() {
+                    assert!(matches!(
+                        StoredBank::open($repository),
+                        Err(RepositoryError::NoBank)
+                    ));
+                    let mut repository = $repository;
+                    repository.save(&bank().snapshot()).unwrap();
+                    assert!(matches!(
+                        StoredBank::create(repository, bank()),
+                        Err(RepositoryError::AlreadyExists)
+                    ));
+                }
+
+                #[test]
+                fn transfers_are_saved() {
+                    let (id1, id2) = (AccountId::new(0), AccountId::new(1));
+                    let mut stored = stored_bank();
+
+                    stored.transfer_funds(id1, id2, eur(60)).unwrap();
+                    assert!(matches!(
+                        stored.transfer_funds(id1, id2, eur(60)),
+                        Err(StorageError::Rejected(
+                            TransferFundsError::SenderNotEnoughBalance { .. }
+                        ))
+                    ));
+                    stored
+                        .transfer_batch(&[
+                            Transfer {
+                                sender: id2,
+                                receiver: id1,
+                                amount: eur(30),
+                            },
+                            Transfer {
+                                sender: id1,
+                                receiver: id2,
+                                amount: eur(5),
+                            },
+                        ])
+                        .unwrap();
+
+                    assert_eq!(saved(&stored), stored.bank().snapshot());
+                    let saved_bank = Bank::restore(saved(&stored)).unwrap();
+                    assert_eq!(
+                        saved_bank.user(id2).unwrap().balance(Currency::EUR),
+                        eur(35)
+                    );
+                }
+
+                #[test]
+                fn currency_conversion_is_saved() {
+                    let mut stored = stored_bank();
+                    let rates = FixedExchangeRates::default().with_rate(
+                        Currency::EUR,
+                        Currency::USD,
+                        ExchangeRate::new(110, 100).unwrap(),
+                    );
+                    stored.set_exchange_rates(Arc::new(rates));
+                    stored
+                        .set_cross_currency_transfers(CrossCurrencyTransfers::Convert)
+                        .unwrap();
+                    let id3 = stored
+                        .open_account(User::new("name3".to_string(), usd(0), usd(0)))
+                        .unwrap();
+
+                    stored
+                        .transfer_funds(AccountId::new(0), id3, eur(50))
+                        .unwrap();
+
+                    assert_eq!(saved(&stored), stored.bank().snapshot());
+                    let saved_bank = Bank::restore(saved(&stored)).unwrap();
+                    assert_eq!(
+                        saved_bank.user(id3).unwrap().balance(Currency::USD),
+                        usd(55)
+                    );
+                    assert_eq!(
+                        saved(&stored).cross_currency_transfers,
+                        CrossCurrencyTransfers::Convert
+                    );
+                }
+
+                #[test]
+                fn interest_and_fees_are_saved() {
+                    let (id1, id2) = (AccountId::new(0), AccountId::new(1));
+                    let mut stored = stored_bank();
+                    stored.transfer_funds(id2, id1, eur(40)).unwrap();
+
+                    stored.set_overdraft_fee(eur(3)).unwrap();
+                    stored
+                        .accrue_interest_between(date(2024, 1, 1), date(2024, 1, 11))
+                        .unwrap();
+                    assert_eq!(saved(&stored), stored.bank().snapshot());
+                    stored.post_accrued_interest().unwrap();
+                    stored.accrue_interest().unwrap();
+
+                    assert_eq!(saved(&stored), stored.bank().snapshot());
+                }
+
+                #[test]
+                fn account_lifecycle_is_saved() {
+                    let (id1, id2) = (AccountId::new(0), AccountId::new(1));
+                    let mut stored = stored_bank();
+
+                    let id3 = stored
+                        .open_account(User::new("name3".to_string(), eur(0), eur(7)))
+                        .unwrap();
+                    stored.rename_account(id3, "renamed".to_string()).unwrap();
+                    stored.freeze_account(id2).unwrap();
+                    assert_eq!(saved(&stored), stored.bank().snapshot());
+                    stored.unfreeze_account(id2).unwrap();
+                    stored.close_account(id3, id1).unwrap();
+                    assert!(matches!(
+                        stored.close_account(id3, id1),
+                        Err(StorageError::Rejected(AccountError::AccountClosed { .. }))
+                    ));
+
+                    assert_eq!(saved(&stored), stored.bank().snapshot());
+                    let saved_bank = Bank::restore(saved(&stored)).unwrap();
+                    assert_eq!(saved_bank.user(id3).unwrap().state(), AccountState::Closed);
+                    assert_eq!(
+                        saved_bank.user(id1).unwrap().balance(Currency::EUR),
+                        eur(107)
+                    );
+                }
+
+                #[test]
+                fn credit_lines_are_saved() {
+                    let (id1, id2) = (AccountId::new(0), AccountId::new(1));
+                    let mut stored = stored_bank();
+
+                    stored.request_credit_line(id1, eur(80)).unwrap();
+                    assert_eq!(saved(&stored), stored.bank().snapshot());
+                    stored.approve_credit_line(id1).unwrap();
+                    stored.raise_credit_line(id2, eur(90)).unwrap();
+                    stored.transfer_funds(id2, id1, eur(70)).unwrap();
+                    assert_eq!(
+                        stored.lower_credit_line(id2, eur(60)).unwrap(),
+                        AccountState::OverLimit
+                    );
+                    stored.revoke_credit_line(id1).unwrap();
+
+                    assert_eq!(saved(&stored), stored.bank().snapshot());
+                }
+
+                #[test]
+                fn merges_are_saved() {
+                    let mut stored = stored_bank();
+                    let other = Bank::new(
+                        vec![
+                            User::new("name2".to_string(), eur(10), eur(5)),
+                            User::new("name4".to_string(), eur(0), eur(20)),
+                        ],
+                        "Other".to_string(),
+                        BasisPoints::new(400),
+                        BasisPoints::new(365),
+                    );
+
+                    let policy = MergePolicy {
+                        credit_lines: CreditLines::Sum,
+                        ..MergePolicy::default()
+                    };
+                    stored.merge_bank(other, &policy).unwrap();
+
+                    assert_eq!(saved(&stored), stored.bank().snapshot());
+                    assert_eq!(stored.bank().snapshot().accounts.len(), 3);
+                }
+
+                #[test]
+                fn open_loads_the_saved_bank() {
+                    let (id1, id2) = (AccountId::new(0), AccountId::new(1));
+                    let mut stored = stored_bank();
+                    stored.transfer_funds(id1, id2, eur(25)).unwrap();
+                    let expected = stored.bank().snapshot();
+
+                    let mut repository = $repository;
+                    repository
+                        .save(&stored.repository().load().unwrap().unwrap())
+                        .unwrap();
+                    let mut reopened = StoredBank::open(repository).unwrap();
+
+                    assert_eq!(reopened.bank().snapshot(), expected);
+                    reopened.transfer_funds(id1, id2, eur(5)).unwrap();
+                    assert_eq!(saved(&reopened), reopened.bank().snapshot());
+                }
+            }
+        };
+    }
+    #[cfg(feature = "sqlite")]
+    pub(crate) use repository_tests;
+
+    repository_tests!(MemoryRepository::default());
+
+    /// Memory repository whose saves fail once `failing` is set
+    #[derive(Default)]
+    struct FailingRepository {
+        repository: MemoryRepository,
+        failing: bool,
+    }
+
+    impl BankRepository for FailingRepository {
+        fn load(&self) -> Result<Option<BankSnapshot>, RepositoryError> {
+            self.repository.load()
+        }
+
+        fn save(&mut self, changes: &BankSnapshot) -> Result<(), RepositoryError> {
+            if self.failing {
+                return Err(RepositoryError::NoBank);
+            }
+            self.repository.save(changes)
+        }
+    }
+
+    #[test]
+    fn failed_saves_reload_the_bank() {
+        let (id1, id2) = (AccountId::new(0), AccountId::new(1));
+        let bank = test_support::bank([("name1", 0, 100), ("name2", 0, 0)], 365);
+        let mut stored = StoredBank::create(FailingRepository::default(), bank).unwrap();
+        let rates = FixedExchangeRates::default().with_rate(
+            Currency::EUR,
+            Currency::USD,
+            ExchangeRate::new(110, 100).unwrap(),
+        );
+        stored.set_exchange_rates(Arc::new(rates));
+        stored
+            .set_cross_currency_transfers(CrossCurrencyTransfers::Convert)
+            .unwrap();
+        let id3 = stored
+            .open_account(User::new("name3".to_string(), usd(0), usd(0)))
+            .unwrap();
+        stored.transfer_funds(id1, id2, eur(10)).unwrap();
+        let saved = stored.bank().snapshot();
+
+        stored.repository.failing = true;
+        assert!(matches!(
+            stored.transfer_funds(id1, id2, eur(20)),
+            Err(StorageError::Repository(RepositoryError::NoBank))
+        ));
+        assert!(stored.freeze_account(id1).is_err());
+        assert_eq!(stored.bank().snapshot(), saved);
+
+        stored.repository.failing = false;
+        stored.transfer_funds(id1, id2, eur(30)).unwrap();
+        assert_eq!(
+            stored.repository().load().unwrap().unwrap().ledger,
+            stored.bank().snapshot().ledger
+        );
+        assert_eq!(
+            stored.bank().user(id2).unwrap().balance(Currency::EUR),
+            eur(40)
+        );
+        // The exchange rates set before the failure are kept
+        stored.transfer_funds(id1, id3, eur(10)).unwrap();
+        assert_eq!(
+            stored.bank().user(id3).unwrap().balance(Currency::USD),
+            usd(11)
+        );
+    }
+}
@@ -0,0 +1,437 @@
+use crate::currency::Currency;
+use crate::error::{InterestError, MergeError};
+use crate::ledger::LedgerEntry;
+use crate::merge::{MergePolicy, MergeReport};
+use crate::money::{Money, MoneyError};
+use crate::{AccountId, BalanceSheet, Bank, TransferFundsError};
+use chrono::NaiveDate;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt;
+use std::num::NonZeroUsize;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+
+/// Why a request to a bank running on its own task was not served
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServiceError<E = Infallible> {
+    /// The bank handled the request and refused it
+    Rejected(E),
+    /// Too many requests are queued; only returned by the `try_` methods
+    Busy,
+    /// The bank was shut down
+    ShutDown,
+}
+
+impl<E: fmt::Display> fmt::Display for ServiceError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceError::Rejected(error) => error.fmt(f),
+            ServiceError::Busy => f.write_str("too many requests are queued"),
+            ServiceError::ShutDown => f.write_str("the bank was shut down"),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for ServiceError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ServiceError::Rejected(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+enum Request {
+    TransferFunds {
+        sender: AccountId,
+        receiver: AccountId,
+        amount: Money,
+        reply: oneshot::Sender<Result<LedgerEntry, TransferFundsError>>,
+    },
+    AccrueInterest {
+        reply: oneshot::Sender<()>,
+    },
+    AccrueInterestBetween {
+        from: NaiveDate,
+        to: NaiveDate,
+        reply: oneshot::Sender<Result<(), InterestError>>,
+    },
+    PostAccruedInterest {
+        reply: oneshot::Sender<Vec<LedgerEntry>>,
+    },
+    CalcBalance {
+        reply: oneshot::Sender<Result<BalanceSheet, MoneyError>>,
+    },
+    CalcBalancePerCurrency {
+        reply: oneshot::Sender<Result<BTreeMap<Currency, BalanceSheet>, MoneyError>>,
+    },
+    LedgerEntries {
+        from: usize,
+        reply: oneshot::Sender<Vec<LedgerEntry>>,
+    },
+    MergeBank {
+        other: Box<Bank>,
+        policy: MergePolicy,
+        reply: oneshot::Sender<Result<MergeReport, MergeError>>,
+    },
+    Shutdown {
+        reply: oneshot::Sender<()>,
+    },
+}
+
+/// Handle to a bank running on its own tokio task, which serves requests one at a time.
+///
+/// At most `capacity` requests wait in the queue of the bank: sending more waits until there is
+/// room, or fails with [`ServiceError::Busy`] with the `try_` methods.
+#[derive(Clone)]
+pub struct BankHandle {
+    requests: mpsc::Sender<Request>,
+    ledger_len: watch::Receiver<usize>,
+}
+
+impl BankHandle {
+    /// Runs `bank` on a new task of the current runtime. The task returns the bank once it was
+    /// shut down or every handle was dropped.
+    pub fn spawn(bank: Bank, capacity: NonZeroUsize) -> (BankHandle, JoinHandle<Bank>) {
+        let (requests, queue) = mpsc::channel(capacity.get());
+        let (ledger_len, watched) = watch::channel(bank.ledger().entries().len());
+        let task = tokio::spawn(serve(bank, queue, ledger_len));
+        let handle = BankHandle {
+            requests,
+            ledger_len: watched,
+        };
+        (handle, task)
+    }
+
+    pub async fn transfer_funds(
+        &self,
+        sender: AccountId,
+        receiver: AccountId,
+        amount: Money,
+    ) -> Result<(), ServiceError<TransferFundsError>> {
+        self.transfer_funds_recorded(sender, receiver, amount)
+            .await
+            .map(drop)
+    }
+
+    /// Like [`BankHandle::transfer_funds`], but returns the ledger entry of the transfer
+    pub async fn transfer_funds_recorded(
+        &self,
+        sender: AccountId,
+        receiver: AccountId,
+        amount: Money,
+    ) -> Result<LedgerEntry, ServiceError<TransferFundsError>> {
+        self.request(|reply| Request::TransferFunds {
+            sender,
+            receiver,
+            amount,
+            reply,
+        })
+        .await?
+        .map_err(ServiceError::Rejected)
+    }
+
+    /// Like [`BankHandle::transfer_funds`], but fails instead of waiting for room in the queue
+    pub async fn try_transfer_funds(
+        &self,
+        sender: AccountId,
+        receiver: AccountId,
+        amount: Money,
+    ) -> Result<(), ServiceError<TransferFundsError>> {
+        let (reply, response) = oneshot::channel();
+        let request = Request::TransferFunds {
+            sender,
+            receiver,
+            amount,
+            reply,
+        };
+        self.requests
+            .try_send(request)
+            .map_err(|error| match error {
+                TrySendError::Full(_) => ServiceError::Busy,
+                TrySendError::Closed(_) => ServiceError::ShutDown,
+            })?;
+        response
+            .await
+            .map_err(|_| ServiceError::ShutDown)?
+            .map(drop)
+            .map_err(ServiceError::Rejected)
+    }
+
+    pub async fn accrue_interest(&self) -> Result<(), ServiceError> {
+        self.request(|reply| Request::AccrueInterest { reply })
+            .await
+    }
+
+    pub async fn accrue_interest_between(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<(), ServiceError<InterestError>> {
+        self.request(|reply| Request::AccrueInterestBetween { from, to, reply })
+            .await?
+            .map_err(ServiceError::Rejected)
+    }
+
+    /// Posts the interest accrued so far and returns the ledger entries it recorded
+    pub async fn post_accrued_interest(&self) -> Result<Vec<LedgerEntry>, ServiceError> {
+        self.request(|reply| Request::PostAccruedInterest { reply })
+            .await
+    }
+
+    pub async fn calc_balance(&self) -> Result<BalanceSheet, ServiceError<MoneyError>> {
+        self.request(|reply| Request::CalcBalance { reply })
+            .await?
+            .map_err(ServiceError::Rejected)
+    }
+
+    pub async fn calc_balance_per_currency(
+        &self,
+    ) -> Result<BTreeMap<Currency, BalanceSheet>, ServiceError<MoneyError>> {
+        self.request(|reply| Request::CalcBalancePerCurrency { reply })
+            .await?
+            .map_err(ServiceError::Rejected)
+    }
+
+    /// Entries of the ledger from position `from` on, oldest first
+    pub async fn ledger_entries(&self, from: usize) -> Result<Vec<LedgerEntry>, ServiceError> {
+        self.request(|reply| Request::LedgerEntries { from, reply })
+            .await
+    }
+
+    /// Watches the number of entries in the ledger, which changes whenever the bank records
+    /// new ones. The watch is closed once the bank stopped.
+    pub fn ledger_len(&self) -> watch::Receiver<usize> {
+        self.ledger_len.clone()
+    }
+
+    pub async fn merge_bank(
+        &self,
+        other: Bank,
+        policy: MergePolicy,
+    ) -> Result<MergeReport, ServiceError<MergeError>> {
+        self.request(|reply| Request::MergeBank {
+            other: Box::new(other),
+            policy,
+            reply,
+        })
+        .await?
+        .map_err(ServiceError::Rejected)
+    }
+
+    /// Stops taking requests and waits until the ones already queued are served
+    pub async fn shutdown(&self) -> Result<(), ServiceError> {
+        self.request(|reply| Request::Shutdown { reply }).await
+    }
+
+    async fn request<T, E>(
+        &self,
+        request: impl FnOnce(oneshot::Sender<T>) -> Request,
+    ) -> Result<T, ServiceError<E>> {
+        let (reply, response) = oneshot::channel();
+        self.requests
+            .send(request(reply))
+            .await
+            .map_err(|_| ServiceError::ShutDown)?;
+        response.await.map_err(|_| ServiceError::ShutDown)
+    }
+}
+
+async fn serve(
+    mut bank: Bank,
+    mut queue: mpsc::Receiver<Request>,
+    ledger_len: watch::Sender<usize>,
+) -> Bank {
+    let mut shutdowns = vec![];
+    while let Some(request) = queue.recv().await {
+        let recorded = bank.ledger().entries().len();
+        // A requester that went away does not need its reply
+        match request {
+            Request::TransferFunds {
+                sender,
+                receiver,
+                amount,
+                reply,
+            } => {
+                let transferred = bank.transfer_funds(sender, receiver, amount).map(|()| {
+                    let entries = bank.ledger().entries();
+                    entries.last().expect("the transfer is recorded").clone()
+                });
+                let _ = reply.send(transferred);
+            }
+            Request::AccrueInterest { reply } => {
+                bank.accrue_interest();
+                let _ = reply.send(());
+            }
+            Request::AccrueInterestBetween { from, to, reply } => {
+                let _ = reply.send(bank.accrue_interest_between(from, to));
+            }
+            Request::PostAccruedInterest { reply } => {
+                bank.post_accrued_interest();
+                let _ = reply.send(bank.ledger().entries()[recorded..].to_vec());
+            }
+            Request::CalcBalance { reply } => {
+                let _ = reply.send(bank.calc_balance());
+            }
+            Request::CalcBalancePerCurrency { reply } => {
+                let _ = reply.send(bank.calc_balance_per_currency());
+            }
+            Request::LedgerEntries { from, reply } => {
+                let entries = bank.ledger().entries();
+                let _ = reply.send(entries.get(from..).unwrap_or_default().to_vec());
+            }
+            Request::MergeBank {
+                other,
+                policy,
+                reply,
+            } => {
+                let _ = reply.send(bank.merge_bank(*other, &policy));
+            }
+            Request::Shutdown { reply } => {
+                // New requests are refused, the queued ones are still served
+                queue.close();
+                shutdowns.push(reply);
+            }
+        }
+        let len = bank.ledger().entries().len();
+        ledger_len.send_if_modified(|watched| std::mem::replace(watched, len) != len);
+    }
+    for reply in shutdowns {
+        let _ = reply.send(());
+    }
+    bank
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currency::Currency;
+    use crate::test_support::{self, eur};
+
+    fn bank(name: &str, users: &[(&str, i64)]) -> Bank {
+        let users = users
+            .iter()
+            .map(|(username, balance)| (username, 0, *balance));
+        let mut bank = test_support::bank(users, 100);
+        bank.name = name.to_string();
+        bank
+    }
+
+    #[tokio::test]
+    async fn serves_requests_in_order() {
+        let (handle, task) = BankHandle::spawn(
+            bank("Bank", &[("name1", 100), ("name2", 0)]),
+            NonZeroUsize::new(4).unwrap(),
+        );
+        let (id1, id2) = (AccountId::new(0), AccountId::new(1));
+
+        assert_eq!(handle.transfer_funds(id1, id2, eur(60)).await, Ok(()));
+        assert!(matches!(
+            handle.transfer_funds(id1, id2, eur(60)).await,
+            Err(ServiceError::Rejected(
+                TransferFundsError::SenderNotEnoughBalance { .. }
+            ))
+        ));
+        assert_eq!(handle.accrue_interest().await, Ok(()));
+        let other = bank("Other", &[("name3", 5)]);
+        let report = handle
+            .merge_bank(other, MergePolicy::default())
+            .await
+            .unwrap();
+        assert_eq!(report.ids()[&AccountId::new(0)], AccountId::new(2));
+        assert_eq!(handle.calc_balance().await.unwrap().assets, eur(106));
+        assert_eq!(handle.shutdown().await, Ok(()));
+
+        let bank = task.await.unwrap();
+        assert_eq!(bank.user(id1).unwrap().balance(Currency::EUR), eur(40));
+    }
+
+    #[tokio::test]
+    async fn watches_the_ledger() {
+        let users = [("name1", 1_000_000), ("name2", 0)];
+        let (handle, _task) =
+            BankHandle::spawn(bank("Bank", &users), NonZeroUsize::new(4).unwrap());
+        let (id1, id2) = (AccountId::new(0), AccountId::new(1));
+        let mut ledger_len = handle.ledger_len();
+        // Both opening balances are recorded
+        assert_eq!(*ledger_len.borrow_and_update(), 2);
+
+        let entry = handle.transfer_funds_recorded(id1, id2, eur(600_000)).await;
+        assert_eq!(entry.unwrap().amount(), eur(600_000));
+        assert!(ledger_len.has_changed().unwrap());
+        assert_eq!(*ledger_len.borrow_and_update(), 3);
+        // Requests that record nothing leave the watch alone
+        handle.calc_balance_per_currency().await.unwrap();
+        assert!(!ledger_len.has_changed().unwrap());
+
+        let from = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let to = NaiveDate::from_ymd_opt(2024, 1, 11).unwrap();
+        assert_eq!(handle.accrue_interest_between(from, to).await, Ok(()));
+        assert_eq!(
+            handle.accrue_interest_between(from, to).await,
+            Err(ServiceError::Rejected(InterestError::AlreadyAccrued {
+                through: to
+            }))
+        );
+        let posted = handle.post_accrued_interest().await.unwrap();
+        assert_eq!(posted.len(), 2);
+        let entries = handle.ledger_entries(2).await.unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1..], posted);
+        assert_eq!(handle.ledger_entries(7).await, Ok(vec![]));
+        assert_eq!(*ledger_len.borrow_and_update(), 5);
+    }
+
+    #[tokio::test]
+    async fn applies_backpressure() {
+        let (handle, _task) = BankHandle::spawn(
+            bank("Bank", &[("name1", 100), ("name2", 0)]),
+            NonZeroUsize::new(1).unwrap(),
+        );
+        let (id1, id2) = (AccountId::new(0), AccountId::new(1));
+
+        // The bank only runs once this task yields, so the first request fills the queue
+        let queued = handle.try_transfer_funds(id1, id2, eur(1));
+        let refused = handle.try_transfer_funds(id1, id2, eur(1));
+        let (queued, refused) = tokio::join!(queued, refused);
+
+        assert_eq!(queued, Ok(()));
+        assert_eq!(refused, Err(ServiceError::Busy));
+    }
+
+    #[tokio::test]
+    async fn shuts_down_gracefully() {
+        let (handle, task) = BankHandle::spawn(
+            bank("Bank", &[("name1", 100), ("name2", 0)]),
+            NonZeroUsize::new(4).unwrap(),
+        );
+        let (id1, id2) = (AccountId::new(0), AccountId::new(1));
+
+        let (transfer, shutdown) =
+            tokio::join!(handle.transfer_funds(id1, id2, eur(10)), handle.shutdown());
+
+        assert_eq!(transfer, Ok(()));
+        assert_eq!(shutdown, Ok(()));
+        assert_eq!(
+            handle.transfer_funds(id1, id2, eur(10)).await,
+            Err(ServiceError::ShutDown)
+        );
+        let bank = task.await.unwrap();
+        assert_eq!(bank.user(id2).unwrap().balance(Currency::EUR), eur(10));
+    }
+
+    #[tokio::test]
+    async fn stops_once_every_handle_is_dropped() {
+        let (handle, task) = BankHandle::spawn(
+            bank("Bank", &[("name1", 100)]),
+            NonZeroUsize::new(4).unwrap(),
+        );
+
+        drop(handle);
+
+        assert_eq!(task.await.unwrap().name, "Bank");
+    }
+}
@@ -0,0 +1,797 @@
+use crate::accounting::{Books, JournalEntry};
+use crate::clock::{Clock, SystemClock};
+use crate::currency::{CrossCurrencyTransfers, Currency};
+use crate::error::RestoreError;
+#[cfg(feature = "serde")]
+use crate::error::SnapshotError;
+use crate::events::{Balances, EventLog, RecordedEvent};
+use crate::interest::{InterestAccruals, InterestConventions};
+use crate::ledger::{Ledger, LedgerEntry};
+use crate::money::{BasisPoints, Money};
+use crate::transfer::TransferRules;
+use crate::users::UserStore;
+use crate::{AccountId, AccountState, Bank, User};
+use chrono::NaiveDate;
+use std::collections::BTreeMap;
+#[cfg(feature = "serde")]
+use std::fs::File;
+#[cfg(feature = "serde")]
+use std::io::{self, Write};
+#[cfg(feature = "serde")]
+use std::path::Path;
+use std::sync::Arc;
+
+/// Version of the [`BankSnapshot`] layout, raised on every incompatible change
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Leading bytes of a binary snapshot, which JSON snapshots cannot start with
+#[cfg(feature = "serde")]
+const BINARY_MAGIC: &[u8; 4] = b"P32S";
+
+/// The state of a [`Bank`] as plain data.
+///
+/// Only data is kept: a restored bank gets the clock it is restored with, no exchange rate
+/// provider and no account rate policies.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BankSnapshot {
+    /// Always the first field, so that it can be read before the rest of the layout is known
+    pub schema_version: u32,
+    pub name: String,
+    pub currency: Currency,
+    pub credit_interest: BasisPoints,
+    pub debit_interest: BasisPoints,
+    pub cross_currency_transfers: CrossCurrencyTransfers,
+    pub interest_conventions: InterestConventions,
+    pub interest_accrued_through: Option<NaiveDate>,
+    pub accrued_interest: Vec<AccruedInterest>,
+    pub overdraft_fees: Vec<Money>,
+    pub next_account_id: AccountId,
+    pub accounts: Vec<AccountSnapshot>,
+    pub ledger: Vec<LedgerEntry>,
+    pub journal: Vec<JournalEntry>,
+    pub events: Vec<RecordedEvent>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AccountSnapshot {
+    pub id: AccountId,
+    pub name: String,
+    pub currency: Currency,
+    pub credit_line: Money,
+    pub requested_credit_line: Option<Money>,
+    /// What the events of the account add up to. Kept for readers of the snapshot: restoring
+    /// replays the balances from the events instead.
+    pub balances: Vec<Money>,
+    pub state: AccountState,
+}
+
+/// Interest accrued by an account and not posted yet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AccruedInterest {
+    pub account: AccountId,
+    pub currency: Currency,
+    /// In billionths of the minor unit
+    pub accrued: i128,
+}
+
+/// How a snapshot is encoded
+#[cfg(feature = "serde")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    /// Human-readable, for inspection and debugging
+    Json,
+    /// Compact, prefixed with a magic number
+    Binary,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct SchemaVersion {
+    schema_version: u32,
+}
+
+/// Layout of the snapshots of version 1, taken before banks recorded events
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize))]
+struct BankSnapshotV1 {
+    #[serde(rename = "schema_version")]
+    _schema_version: u32,
+    name: String,
+    currency: Currency,
+    credit_interest: BasisPoints,
+    debit_interest: BasisPoints,
+    cross_currency_transfers: CrossCurrencyTransfers,
+    interest_conventions: InterestConventions,
+    interest_accrued_through: Option<NaiveDate>,
+    accrued_interest: Vec<AccruedInterest>,
+    overdraft_fees: Vec<Money>,
+    next_account_id: AccountId,
+    accounts: Vec<AccountSnapshot>,
+    ledger: Vec<LedgerEntry>,
+    journal: Vec<JournalEntry>,
+}
+
+/// Rebuilds the events from the ledger, as migrating a SQLite database of version 1 does
+#[cfg(feature = "serde")]
+impl From<BankSnapshotV1> for BankSnapshot {
+    fn from(snapshot: BankSnapshotV1) -> Self {
+        let accounts = snapshot
+            .accounts
+            .iter()
+            .map(|account| {
+                let details = (account.name.clone(), account.currency, account.credit_line);
+                (account.id, details)
+            })
+            .collect();
+        let events = EventLog::from_ledger(&snapshot.ledger, &accounts);
+        BankSnapshot {
+            schema_version: SCHEMA_VERSION,
+            name: snapshot.name,
+            currency: snapshot.currency,
+            credit_interest: snapshot.credit_interest,
+            debit_interest: snapshot.debit_interest,
+            cross_currency_transfers: snapshot.cross_currency_transfers,
+            interest_conventions: snapshot.interest_conventions,
+            interest_accrued_through: snapshot.interest_accrued_through,
+            accrued_interest: snapshot.accrued_interest,
+            overdraft_fees: snapshot.overdraft_fees,
+            next_account_id: snapshot.next_account_id,
+            accounts: snapshot.accounts,
+            ledger: snapshot.ledger,
+            journal: snapshot.journal,
+            events: events.events().to_vec(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl BankSnapshot {
+    pub fn encode(&self, format: SnapshotFormat) -> Result<Vec<u8>, SnapshotError> {
+        match format {
+            SnapshotFormat::Json => Ok(serde_json::to_vec_pretty(self)?),
+            SnapshotFormat::Binary => {
+                let mut bytes = BINARY_MAGIC.to_vec();
+                bytes.extend(postcard::to_stdvec(self)?);
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// Decodes a snapshot of either format, after checking its schema version. Snapshots of
+    /// version 1 are migrated to the current version.
+    pub fn decode(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        if let Some(payload) = bytes.strip_prefix(BINARY_MAGIC) {
+            let (schema_version, _) = postcard::take_from_bytes::<u32>(payload)?;
+            if schema_version == 1 {
+                return Ok(postcard::from_bytes::<BankSnapshotV1>(payload)?.into());
+            }
+            check_schema_version(schema_version)?;
+            return Ok(postcard::from_bytes(payload)?);
+        }
+        if bytes.trim_ascii_start().starts_with(b"{") {
+            let SchemaVersion { schema_version } = serde_json::from_slice(bytes)?;
+            if schema_version == 1 {
+                return Ok(serde_json::from_slice::<BankSnapshotV1>(bytes)?.into());
+            }
+            check_schema_version(schema_version)?;
+            return Ok(serde_json::from_slice(bytes)?);
+        }
+        Err(SnapshotError::UnknownFormat)
+    }
+}
+
+impl BankSnapshot {
+    /// Applies `changes`, a partial snapshot saved after this one: its accounts replace the ones
+    /// with the same id, its entries and events are appended and its settings replace these
+    pub(crate) fn merge_changes(&mut self, changes: &BankSnapshot) {
+        let mut accounts = std::mem::take(&mut self.accounts);
+        for account in &changes.accounts {
+            match accounts.binary_search_by_key(&account.id, |stored| stored.id) {
+                Ok(index) => accounts[index] = account.clone(),
+                Err(index) => accounts.insert(index, account.clone()),
+            }
+        }
+        let mut ledger = std::mem::take(&mut self.ledger);
+        ledger.extend_from_slice(&changes.ledger);
+        let mut journal = std::mem::take(&mut self.journal);
+        journal.extend_from_slice(&changes.journal);
+        let mut events = std::mem::take(&mut self.events);
+        events.extend_from_slice(&changes.events);
+        *self = BankSnapshot {
+            accounts,
+            ledger,
+            journal,
+            events,
+            ..changes.clone()
+        };
+    }
+}
+
+fn check_schema_version(schema_version: u32) -> Result<(), RestoreError> {
+    if schema_version != SCHEMA_VERSION {
+        return Err(RestoreError::UnsupportedVersion {
+            found: schema_version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    Ok(())
+}
+
+impl Bank {
+    pub fn snapshot(&self) -> BankSnapshot {
+        let accounts = self
+            .users
+            .iter()
+            .map(|(id, user)| account_snapshot(id, user))
+            .collect();
+        self.snapshot_with(accounts, 0, 0, 0)
+    }
+
+    /// Snapshot holding only `accounts`, and the ledger entries, journal entries and events from
+    /// `ledger_from`, `journal_from` and `events_from` on. Accounts the bank does not have are
+    /// left out.
+    pub(crate) fn partial_snapshot(
+        &self,
+        accounts: impl IntoIterator<Item = AccountId>,
+        ledger_from: usize,
+        journal_from: usize,
+        events_from: usize,
+    ) -> BankSnapshot {
+        let accounts = accounts
+            .into_iter()
+            .filter_map(|id| Some(account_snapshot(id, self.users.get(id)?)))
+            .collect();
+        self.snapshot_with(accounts, ledger_from, journal_from, events_from)
+    }
+
+    fn snapshot_with(
+        &self,
+        accounts: Vec<AccountSnapshot>,
+        ledger_from: usize,
+        journal_from: usize,
+        events_from: usize,
+    ) -> BankSnapshot {
+        BankSnapshot {
+            schema_version: SCHEMA_VERSION,
+            name: self.name.clone(),
+            currency: self.currency,
+            credit_interest: self.credit_interest,
+            debit_interest: self.debit_interest,
+            cross_currency_transfers: self.transfer_rules.cross_currency_transfers,
+            interest_conventions: self.interest.conventions,
+            interest_accrued_through: self.interest.accrued_through,
+            accrued_interest: self
+                .interest
+                .raw_accruals()
+                .map(|(account, currency, accrued)| AccruedInterest {
+                    account,
+                    currency,
+                    accrued,
+                })
+                .collect(),
+            overdraft_fees: self.overdraft_fees.values().copied().collect(),
+            next_account_id: self.users.next_id(),
+            accounts,
+            ledger: self.ledger.entries()[ledger_from..].to_vec(),
+            journal: self.books.journal()[journal_from..].to_vec(),
+            events: self.events.events()[events_from..].to_vec(),
+        }
+    }
+
+    /// Rebuilds the bank `snapshot` was taken of, with the system clock
+    pub fn restore(snapshot: BankSnapshot) -> Result<Bank, RestoreError> {
+        Bank::restore_with_clock(snapshot, Arc::new(SystemClock))
+    }
+
+    /// Rebuilds the bank `snapshot` was taken of, with the balances of its accounts replayed from
+    /// its events, after checking that its accounts are valid, that their ledger entries add up
+    /// to the same balances, that their states follow from their balances and credit lines, and
+    /// that the journal posts every ledger entry
+    pub fn restore_with_clock(
+        snapshot: BankSnapshot,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Bank, RestoreError> {
+        check_schema_version(snapshot.schema_version)?;
+
+        let events =
+            EventLog::from_events(snapshot.events).ok_or(RestoreError::EventsOutOfOrder)?;
+        let mut replayed = Balances::default();
+        events.replay(&mut replayed);
+        let mut balances: BTreeMap<AccountId, BTreeMap<Currency, i64>> = BTreeMap::new();
+        for (account, balance) in replayed.iter() {
+            balances
+                .entry(account)
+                .or_default()
+                .insert(balance.currency(), balance.minor());
+        }
+
+        let mut users = UserStore::default();
+        for account in snapshot.accounts {
+            let id = account.id;
+            let user = restore_user(account, balances.remove(&id).unwrap_or_default())?;
+            if !users.restore(id, user) {
+                return Err(RestoreError::DuplicateAccount { account: id });
+            }
+        }
+        if let Some(&account) = balances.keys().next() {
+            return Err(RestoreError::UnknownEventAccount { account });
+        }
+        if !users.set_next_id(snapshot.next_account_id) {
+            return Err(RestoreError::InvalidNextAccountId {
+                next_account_id: snapshot.next_account_id,
+            });
+        }
+
+        let ledger = Ledger::from_entries(snapshot.ledger).ok_or(RestoreError::LedgerOutOfOrder)?;
+        if let Some(entry) = snapshot.journal.iter().find(|entry| !entry.is_balanced()) {
+            return Err(RestoreError::UnbalancedJournalEntry {
+                ledger_entry: entry.ledger_entry(),
+            });
+        }
+        for (index, entry) in ledger.entries().iter().enumerate() {
+            if !snapshot
+                .journal
+                .get(index)
+                .is_some_and(|journal_entry| journal_entry.matches(entry))
+            {
+                return Err(RestoreError::JournalMismatch {
+                    ledger_entry: entry.id(),
+                });
+            }
+        }
+        if let Some(extra) = snapshot.journal.get(ledger.entries().len()) {
+            return Err(RestoreError::JournalMismatch {
+                ledger_entry: extra.ledger_entry(),
+            });
+        }
+        for (id, user) in users.iter() {
+            for (currency, balance) in &user.balances {
+                let balance = Money::from_minor(*balance, *currency);
+                let replayed = ledger
+                    .replay_balance(id, *currency)
+                    .map_err(|_| RestoreError::BalanceOverflow { account: id })?;
+                if balance != replayed {
+                    return Err(RestoreError::BalanceMismatch {
+                        account: id,
+                        balance,
+                        replayed,
+                    });
+                }
+            }
+        }
+        for (id, user) in users.iter() {
+            let expected = user.state_with(user.balance(user.currency));
+            if user.state != expected {
+                return Err(RestoreError::StateMismatch {
+                    account: id,
+                    state: user.state,
+                    expected,
+                });
+            }
+        }
+
+        let mut interest = InterestAccruals::default();
+        interest.conventions = snapshot.interest_conventions;
+        interest.accrued_through = snapshot.interest_accrued_through;
+        for accrual in snapshot.accrued_interest {
+            if !users.contains(accrual.account) {
+                return Err(RestoreError::UnknownAccrualAccount {
+                    account: accrual.account,
+                });
+            }
+            interest.restore_accrual(accrual.account, accrual.currency, accrual.accrued);
+        }
+
+        Ok(Bank {
+            users,
+            name: snapshot.name,
+            currency: snapshot.currency,
+            credit_interest: snapshot.credit_interest,
+            debit_interest: snapshot.debit_interest,
+            transfer_rules: TransferRules {
+                cross_currency_transfers: snapshot.cross_currency_transfers,
+                ..TransferRules::default()
+            },
+            interest,
+            overdraft_fees: snapshot
+                .overdraft_fees
+                .into_iter()
+                .map(|fee| (fee.currency(), fee))
+                .collect(),
+            ledger,
+            books: Books::from_journal(snapshot.journal),
+            events,
+            clock,
+        })
+    }
+
+    /// Puts the bank back in the state `snapshot` was taken in. Unlike [`Bank::restore`], it
+    /// keeps its clock, its exchange rate provider and the rate policies of its accounts.
+    pub fn revert_to(&mut self, snapshot: BankSnapshot) -> Result<(), RestoreError> {
+        let mut bank = Bank::restore_with_clock(snapshot, self.clock.clone())?;
+        bank.keep_unsaved_settings(self);
+        *self = bank;
+        Ok(())
+    }
+
+    /// Writes a snapshot of the bank to `path`, replacing the file only once it is complete and
+    /// synced to disk
+    #[cfg(feature = "serde")]
+    pub fn save_snapshot(
+        &self,
+        path: impl AsRef<Path>,
+        format: SnapshotFormat,
+    ) -> Result<(), SnapshotError> {
+        write_durably(path.as_ref(), &self.snapshot().encode(format)?)?;
+        Ok(())
+    }
+
+    /// Restores the bank of a snapshot file of either format
+    #[cfg(feature = "serde")]
+    pub fn load_snapshot(path: impl AsRef<Path>) -> Result<Bank, SnapshotError> {
+        let snapshot = BankSnapshot::decode(&std::fs::read(path)?)?;
+        Ok(Bank::restore(snapshot)?)
+    }
+}
+
+/// Replaces `path` by a file holding `contents`, which is written and synced under a `.partial`
+/// name first. After a crash, `path` holds either its old or its new contents.
+#[cfg(feature = "serde")]
+pub fn write_durably(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let mut file = File::create(&partial)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    std::fs::rename(&partial, path)?;
+    match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => sync_directory(directory),
+        _ => sync_directory(Path::new(".")),
+    }
+}
+
+/// Makes the creation and renaming of files in `directory` durable
+#[cfg(feature = "serde")]
+pub(crate) fn sync_directory(directory: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(directory)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = directory;
+    Ok(())
+}
+
+fn account_snapshot(id: AccountId, user: &User) -> AccountSnapshot {
+    AccountSnapshot {
+        id,
+        name: user.name.clone(),
+        currency: user.currency,
+        credit_line: user.credit_line,
+        requested_credit_line: user.requested_credit_line,
+        balances: user
+            .balances
+            .iter()
+            .map(|(currency, balance)| Money::from_minor(*balance, *currency))
+            .collect(),
+        state: user.state,
+    }
+}
+
+/// The user of `account`, holding the `balances` its events add up to
+fn restore_user(
+    account: AccountSnapshot,
+    mut balances: BTreeMap<Currency, i64>,
+) -> Result<User, RestoreError> {
+    let id = account.id;
+    if account.name.trim().is_empty() {
+        return Err(RestoreError::EmptyName { account: id });
+    }
+    let credit_lines = [Some(account.credit_line), account.requested_credit_line];
+    for credit_line in credit_lines.into_iter().flatten() {
+        if credit_line.is_negative() {
+            return Err(RestoreError::NegativeCreditLine { account: id });
+        }
+        if credit_line.currency() != account.currency {
+            return Err(RestoreError::CreditLineCurrencyMismatch { account: id });
+        }
+    }
+    balances.entry(account.currency).or_insert(0);
+    Ok(User {
+        name: account.name,
+        credit_line: account.credit_line,
+        requested_credit_line: account.requested_credit_line,
+        currency: account.currency,
+        balances,
+        state: account.state,
+        rate_policy: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::Account;
+    use crate::currency::{ExchangeRate, FixedExchangeRates};
+    #[cfg(feature = "serde")]
+    use crate::events::BankEvent;
+    use crate::ledger::EntryKind;
+    use crate::test_support::{self, eur, usd};
+
+    fn bank() -> Bank {
+        let mut bank = test_support::bank([("name1", 100, 50), ("name2", 0, 20)], 100);
+        bank.transfer_funds(AccountId::new(0), AccountId::new(1), eur(70))
+            .unwrap();
+        bank.request_credit_line(AccountId::new(1), eur(30))
+            .unwrap();
+        bank.freeze_account(AccountId::new(1)).unwrap();
+        bank
+    }
+
+    #[test]
+    fn restore_rebuilds_the_bank() {
+        let bank = bank();
+        let snapshot = bank.snapshot();
+
+        let mut restored = Bank::restore(snapshot.clone()).unwrap();
+
+        assert_eq!(restored.snapshot(), snapshot);
+        assert_eq!(restored.calc_balance(), bank.calc_balance());
+        assert_eq!(
+            restored.books().trial_balance(Currency::EUR).unwrap(),
+            eur(0)
+        );
+        assert_eq!(
+            restored.user(AccountId::new(1)).map(User::state),
+            Some(AccountState::Frozen)
+        );
+        let id = restored
+            .open_account(User::new("name3".to_string(), eur(0), eur(0)))
+            .unwrap();
+        assert_eq!(id, AccountId::new(2));
+    }
+
+    #[test]
+    fn revert_to_keeps_what_snapshots_leave_out() {
+        let mut bank = bank();
+        let rates = FixedExchangeRates::default().with_rate(
+            Currency::EUR,
+            Currency::USD,
+            ExchangeRate::new(110, 100).unwrap(),
+        );
+        bank.set_exchange_rates(Arc::new(rates));
+        bank.set_cross_currency_transfers(CrossCurrencyTransfers::Convert);
+        let clock = bank.clock.clone();
+        let id3 = bank
+            .open_account(User::new("name3".to_string(), usd(0), usd(0)))
+            .unwrap();
+        let before = bank.snapshot();
+        bank.transfer_funds(AccountId::new(0), id3, eur(10))
+            .unwrap();
+
+        bank.revert_to(before.clone()).unwrap();
+
+        assert_eq!(bank.snapshot(), before);
+        assert!(Arc::ptr_eq(&bank.clock, &clock));
+        bank.transfer_funds(AccountId::new(0), id3, eur(20))
+            .unwrap();
+        assert_eq!(bank.user(id3).unwrap().balance(Currency::USD), usd(22));
+    }
+
+    #[test]
+    fn restore_validates_the_snapshot() {
+        let snapshot = bank().snapshot();
+        let restore = |change: fn(&mut BankSnapshot)| {
+            let mut snapshot = snapshot.clone();
+            change(&mut snapshot);
+            Bank::restore(snapshot).err()
+        };
+
+        assert_eq!(
+            restore(|snapshot| snapshot.schema_version = 3),
+            Some(RestoreError::UnsupportedVersion {
+                found: 3,
+                supported: SCHEMA_VERSION
+            })
+        );
+        assert_eq!(
+            restore(|snapshot| snapshot.accounts[1].id = AccountId::new(0)),
+            Some(RestoreError::DuplicateAccount {
+                account: AccountId::new(0)
+            })
+        );
+        assert_eq!(
+            restore(|snapshot| snapshot.accounts[0].credit_line = eur(-1)),
+            Some(RestoreError::NegativeCreditLine {
+                account: AccountId::new(0)
+            })
+        );
+        assert_eq!(
+            restore(|snapshot| {
+                snapshot.accounts[1].requested_credit_line =
+                    Some(Money::from_minor(1, Currency::USD))
+            }),
+            Some(RestoreError::CreditLineCurrencyMismatch {
+                account: AccountId::new(1)
+            })
+        );
+        assert_eq!(
+            restore(|snapshot| snapshot.next_account_id = AccountId::new(1)),
+            Some(RestoreError::InvalidNextAccountId {
+                next_account_id: AccountId::new(1)
+            })
+        );
+        assert_eq!(
+            restore(|snapshot| {
+                snapshot.accounts.pop();
+            }),
+            Some(RestoreError::UnknownEventAccount {
+                account: AccountId::new(1)
+            })
+        );
+        assert_eq!(
+            restore(|snapshot| {
+                let mut ledger = Ledger::default();
+                let mut books = Books::default();
+                for _ in 0..2 {
+                    let balance = eur(i64::MAX);
+                    let receiver = Some((AccountId::new(0), balance));
+                    let timestamp = test_support::timestamp(0);
+                    let kind = EntryKind::OpeningBalance;
+                    let id = ledger.record(timestamp, kind, None, receiver, balance, None);
+                    let deposit = Account::CustomerDeposit(AccountId::new(0));
+                    books.post_movement(id, Account::Equity, deposit, balance, None);
+                }
+                snapshot.ledger = ledger.entries().to_vec();
+                snapshot.journal = books.journal().to_vec();
+            }),
+            Some(RestoreError::BalanceOverflow {
+                account: AccountId::new(0)
+            })
+        );
+        assert_eq!(
+            restore(|snapshot| snapshot.accounts[0].state = AccountState::OverLimit),
+            Some(RestoreError::StateMismatch {
+                account: AccountId::new(0),
+                state: AccountState::OverLimit,
+                expected: AccountState::Active,
+            })
+        );
+        assert_eq!(
+            restore(|snapshot| {
+                snapshot.accrued_interest.push(AccruedInterest {
+                    account: AccountId::new(9),
+                    currency: Currency::EUR,
+                    accrued: 1,
+                })
+            }),
+            Some(RestoreError::UnknownAccrualAccount {
+                account: AccountId::new(9)
+            })
+        );
+        assert_eq!(
+            restore(|snapshot| snapshot.journal.swap(0, 1)),
+            Some(RestoreError::JournalMismatch {
+                ledger_entry: snapshot.ledger[0].id()
+            })
+        );
+        assert_eq!(
+            restore(|snapshot| {
+                snapshot.journal.pop();
+            }),
+            Some(RestoreError::JournalMismatch {
+                ledger_entry: snapshot.ledger[2].id()
+            })
+        );
+        assert_eq!(
+            restore(|snapshot| snapshot.ledger.swap(0, 1)),
+            Some(RestoreError::LedgerOutOfOrder)
+        );
+        assert_eq!(
+            restore(|snapshot| snapshot.events.swap(0, 1)),
+            Some(RestoreError::EventsOutOfOrder)
+        );
+        assert_eq!(
+            restore(|snapshot| {
+                snapshot.events.pop();
+            }),
+            Some(RestoreError::BalanceMismatch {
+                account: AccountId::new(0),
+                balance: eur(50),
+                replayed: eur(-20),
+            })
+        );
+    }
+
+    #[test]
+    fn restore_replays_the_balances_from_the_events() {
+        let snapshot = bank().snapshot();
+        let mut tampered = snapshot.clone();
+        tampered.accounts[0].balances = vec![eur(1000), usd(5)];
+
+        let restored = Bank::restore(tampered).unwrap();
+        assert_eq!(restored.snapshot(), snapshot);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn snapshot_formats_round_trip() {
+        let snapshot = bank().snapshot();
+
+        for format in [SnapshotFormat::Json, SnapshotFormat::Binary] {
+            let bytes = snapshot.encode(format).unwrap();
+            assert_eq!(BankSnapshot::decode(&bytes).unwrap(), snapshot);
+        }
+        let json = snapshot.encode(SnapshotFormat::Json).unwrap();
+        let binary = snapshot.encode(SnapshotFormat::Binary).unwrap();
+        assert!(binary.len() < json.len());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn decode_migrates_version_1_snapshots() {
+        let snapshot = bank().snapshot();
+        let mut json = serde_json::to_value(&snapshot).unwrap();
+        json["schema_version"] = 1.into();
+        json.as_object_mut().unwrap().remove("events");
+        let v1: BankSnapshotV1 = serde_json::from_value(json.clone()).unwrap();
+        let binary = [&BINARY_MAGIC[..], &postcard::to_stdvec(&v1).unwrap()].concat();
+
+        for bytes in [serde_json::to_vec(&json).unwrap(), binary] {
+            let decoded = BankSnapshot::decode(&bytes).unwrap();
+
+            assert_eq!(decoded.schema_version, SCHEMA_VERSION);
+            let events = |snapshot: &BankSnapshot| -> Vec<BankEvent> {
+                let events = snapshot.events.iter();
+                events.map(|event| event.event().clone()).collect()
+            };
+            assert_eq!(events(&decoded), events(&snapshot));
+            assert_eq!(
+                BankSnapshot {
+                    events: snapshot.events.clone(),
+                    ..decoded.clone()
+                },
+                snapshot
+            );
+            assert!(Bank::restore(decoded).is_ok());
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn decode_checks_the_schema_version_first() {
+        let json = br#"{"schema_version": 7, "layout": "unknown"}"#;
+        let mut binary = BINARY_MAGIC.to_vec();
+        binary.extend(postcard::to_stdvec(&7u32).unwrap());
+
+        for bytes in [&json[..], &binary] {
+            assert!(matches!(
+                BankSnapshot::decode(bytes),
+                Err(SnapshotError::Invalid(RestoreError::UnsupportedVersion {
+                    found: 7,
+                    ..
+                }))
+            ));
+        }
+        assert!(matches!(
+            BankSnapshot::decode(b"not a snapshot"),
+            Err(SnapshotError::UnknownFormat)
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn save_and_load_snapshot_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("bank.snapshot");
+        let bank = bank();
+
+        bank.save_snapshot(&path, SnapshotFormat::Binary).unwrap();
+        let loaded = Bank::load_snapshot(&path).unwrap();
+
+        assert_eq!(loaded.snapshot(), bank.snapshot());
+        assert!(!directory.path().join("bank.snapshot.partial").exists());
+    }
+}
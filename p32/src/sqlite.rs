@@ -0,0 +1,652 @@
+use crate::AccountId;
+use crate::currency::{CrossCurrencyTransfers, Currency};
+use crate::error::RepositoryError;
+use crate::events::{EventLog, RecordedEvent};
+use crate::interest::InterestConventions;
+use crate::ledger::LedgerEntry;
+use crate::money::{BasisPoints, Money};
+use crate::repository::BankRepository;
+use crate::snapshot::{AccountSnapshot, AccruedInterest, BankSnapshot, SCHEMA_VERSION};
+use chrono::NaiveDate;
+use rusqlite::{Connection, OptionalExtension, Transaction, params};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Schema changes, applied in order to bring a database up to date. The version of a database
+/// is the number of migrations it went through.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE bank (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        name TEXT NOT NULL,
+        currency TEXT NOT NULL,
+        next_account_id INTEGER NOT NULL,
+        -- Interest rates, conventions and fees as JSON
+        settings TEXT NOT NULL
+    );
+    CREATE TABLE accounts (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        currency TEXT NOT NULL,
+        -- In minor units of the currency of the account
+        credit_line INTEGER NOT NULL,
+        requested_credit_line INTEGER,
+        state TEXT NOT NULL
+    );
+    CREATE TABLE balances (
+        account_id INTEGER NOT NULL REFERENCES accounts (id),
+        currency TEXT NOT NULL,
+        -- In minor units
+        balance INTEGER NOT NULL,
+        PRIMARY KEY (account_id, currency)
+    );
+    CREATE TABLE ledger_entries (
+        id INTEGER PRIMARY KEY,
+        timestamp TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        sender INTEGER,
+        receiver INTEGER,
+        amount INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        received_amount INTEGER NOT NULL,
+        received_currency TEXT NOT NULL,
+        -- The whole entry as JSON
+        entry TEXT NOT NULL
+    );
+    CREATE INDEX ledger_entries_sender ON ledger_entries (sender);
+    CREATE INDEX ledger_entries_receiver ON ledger_entries (receiver);
+    CREATE TABLE journal_entries (
+        position INTEGER PRIMARY KEY,
+        ledger_entry INTEGER NOT NULL REFERENCES ledger_entries (id),
+        -- The whole entry as JSON
+        entry TEXT NOT NULL
+    );
+    CREATE TABLE accrued_interest (
+        account_id INTEGER NOT NULL REFERENCES accounts (id),
+        currency TEXT NOT NULL,
+        -- In billionths of the minor unit, as text since it may not fit in 64 bits
+        accrued TEXT NOT NULL,
+        PRIMARY KEY (account_id, currency)
+    );
+    CREATE VIEW account_balances AS
+        SELECT accounts.id AS account_id, accounts.name, accounts.state, balances.currency,
+            balances.balance
+        FROM accounts JOIN balances ON balances.account_id = accounts.id;
+",
+    "
+    CREATE TABLE events (
+        sequence INTEGER PRIMARY KEY,
+        timestamp TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        -- The whole event as JSON
+        event TEXT NOT NULL
+    );
+",
+];
+
+/// Version whose migration added the events, which older databases get rebuilt from their
+/// ledger
+const EVENTS_VERSION: u32 = 2;
+
+/// The settings of the bank, stored as JSON in the `bank` table
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Settings {
+    credit_interest: BasisPoints,
+    debit_interest: BasisPoints,
+    cross_currency_transfers: CrossCurrencyTransfers,
+    interest_conventions: InterestConventions,
+    interest_accrued_through: Option<NaiveDate>,
+    overdraft_fees: Vec<Money>,
+}
+
+/// Repository keeping the bank in an SQLite database, whose tables can be queried directly,
+/// e.g. the balances of every account through the `account_balances` view.
+///
+/// Amounts are stored in minor units, and enums such as currencies and account states by name.
+/// Every save runs in a single transaction.
+pub struct SqliteRepository {
+    connection: Connection,
+}
+
+impl SqliteRepository {
+    /// Opens the database file at `path`, creating it if needed, and migrates its schema
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, RepositoryError> {
+        let connection = Connection::open(path)?;
+        // Lets readers query the database while the bank writes to it
+        connection.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        SqliteRepository::with_connection(connection)
+    }
+
+    /// Opens a new database held in memory
+    pub fn open_in_memory() -> Result<Self, RepositoryError> {
+        SqliteRepository::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(mut connection: Connection) -> Result<Self, RepositoryError> {
+        connection.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut connection)?;
+        Ok(SqliteRepository { connection })
+    }
+
+    /// The database, to query it. Changing it behind the bank's back makes it fail to load.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+}
+
+/// Applies the migrations the database has not gone through yet
+fn migrate(connection: &mut Connection) -> Result<(), RepositoryError> {
+    let transaction = connection.transaction()?;
+    let version: u32 = transaction.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    let latest = MIGRATIONS.len() as u32;
+    if version > latest {
+        return Err(RepositoryError::UnsupportedSchema {
+            found: version,
+            supported: latest,
+        });
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        transaction.execute_batch(migration)?;
+    }
+    if version < EVENTS_VERSION {
+        backfill_events(&transaction)?;
+    }
+    transaction.pragma_update(None, "user_version", latest)?;
+    transaction.commit()?;
+    Ok(())
+}
+
+impl BankRepository for SqliteRepository {
+    fn load(&self) -> Result<Option<BankSnapshot>, RepositoryError> {
+        // Reads every table as of the same save
+        let transaction = self.connection.unchecked_transaction()?;
+        let bank = transaction
+            .query_row(
+                "SELECT name, currency, next_account_id, settings FROM bank",
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, u64>(2)?,
+                        row.get::<_, String>(3)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let Some((name, currency, next_account_id, settings)) = bank else {
+            return Ok(None);
+        };
+        let settings: Settings = serde_json::from_str(&settings)?;
+
+        let mut balances: BTreeMap<u64, Vec<Money>> = BTreeMap::new();
+        let mut statement = transaction
+            .prepare("SELECT account_id, currency, balance FROM balances ORDER BY account_id")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let currency = from_name(row.get(1)?)?;
+            balances
+                .entry(row.get(0)?)
+                .or_default()
+                .push(Money::from_minor(row.get(2)?, currency));
+        }
+
+        let mut accounts = vec![];
+        let mut statement = transaction.prepare(
+            "SELECT id, name, currency, credit_line, requested_credit_line, state
+            FROM accounts ORDER BY id",
+        )?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let id: u64 = row.get(0)?;
+            let currency: Currency = from_name(row.get(2)?)?;
+            let requested_credit_line: Option<i64> = row.get(4)?;
+            accounts.push(AccountSnapshot {
+                id: AccountId::new(id),
+                name: row.get(1)?,
+                currency,
+                credit_line: Money::from_minor(row.get(3)?, currency),
+                requested_credit_line: requested_credit_line
+                    .map(|credit_line| Money::from_minor(credit_line, currency)),
+                balances: balances.remove(&id).unwrap_or_default(),
+                state: from_name(row.get(5)?)?,
+            });
+        }
+
+        let mut accrued_interest = vec![];
+        let mut statement = transaction.prepare(
+            "SELECT account_id, currency, accrued FROM accrued_interest ORDER BY account_id",
+        )?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            accrued_interest.push(AccruedInterest {
+                account: AccountId::new(row.get(0)?),
+                currency: from_name(row.get(1)?)?,
+                accrued: serde_json::from_str(&row.get::<_, String>(2)?)?,
+            });
+        }
+
+        Ok(Some(BankSnapshot {
+            schema_version: SCHEMA_VERSION,
+            name,
+            currency: from_name(currency)?,
+            credit_interest: settings.credit_interest,
+            debit_interest: settings.debit_interest,
+            cross_currency_transfers: settings.cross_currency_transfers,
+            interest_conventions: settings.interest_conventions,
+            interest_accrued_through: settings.interest_accrued_through,
+            accrued_interest,
+            overdraft_fees: settings.overdraft_fees,
+            next_account_id: AccountId::new(next_account_id),
+            accounts,
+            ledger: load_json(&transaction, "SELECT entry FROM ledger_entries ORDER BY id")?,
+            journal: load_json(
+                &transaction,
+                "SELECT entry FROM journal_entries ORDER BY position",
+            )?,
+            events: load_json(&transaction, "SELECT event FROM events ORDER BY sequence")?,
+        }))
+    }
+
+    fn save(&mut self, changes: &BankSnapshot) -> Result<(), RepositoryError> {
+        // Rolled back when dropped before committing
+        let transaction = self.connection.transaction()?;
+        let settings = Settings {
+            credit_interest: changes.credit_interest,
+            debit_interest: changes.debit_interest,
+            cross_currency_transfers: changes.cross_currency_transfers,
+            interest_conventions: changes.interest_conventions,
+            interest_accrued_through: changes.interest_accrued_through,
+            overdraft_fees: changes.overdraft_fees.clone(),
+        };
+        transaction.execute(
+            "INSERT INTO bank (id, name, currency, next_account_id, settings)
+            VALUES (0, ?1, ?2, ?3, ?4)
+            ON CONFLICT (id) DO UPDATE SET name = excluded.name, currency = excluded.currency,
+                next_account_id = excluded.next_account_id, settings = excluded.settings",
+            params![
+                changes.name,
+                to_name(&changes.currency)?,
+                changes.next_account_id.value(),
+                serde_json::to_string(&settings)?,
+            ],
+        )?;
+
+        for account in &changes.accounts {
+            transaction
+                .prepare_cached(
+                    "INSERT INTO accounts
+                        (id, name, currency, credit_line, requested_credit_line, state)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                    ON CONFLICT (id) DO UPDATE SET name = excluded.name,
+                        currency = excluded.currency, credit_line = excluded.credit_line,
+                        requested_credit_line = excluded.requested_credit_line,
+                        state = excluded.state",
+                )?
+                .execute(params![
+                    account.id.value(),
+                    account.name,
+                    to_name(&account.currency)?,
+                    account.credit_line.minor(),
+                    account
+                        .requested_credit_line
+                        .map(|credit_line| credit_line.minor()),
+                    to_name(&account.state)?,
+                ])?;
+            transaction
+                .prepare_cached("DELETE FROM balances WHERE account_id = ?1")?
+                .execute([account.id.value()])?;
+            for balance in &account.balances {
+                transaction
+                    .prepare_cached(
+                        "INSERT INTO balances (account_id, currency, balance) VALUES (?1, ?2, ?3)",
+                    )?
+                    .execute(params![
+                        account.id.value(),
+                        to_name(&balance.currency())?,
+                        balance.minor(),
+                    ])?;
+            }
+        }
+
+        for entry in &changes.ledger {
+            let received = entry.received_amount();
+            transaction
+                .prepare_cached(
+                    "INSERT INTO ledger_entries (id, timestamp, kind, sender, receiver, amount,
+                        currency, received_amount, received_currency, entry)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                )?
+                .execute(params![
+                    entry.id().value(),
+                    entry.timestamp().to_rfc3339(),
+                    to_name(&entry.kind())?,
+                    entry.sender().map(|id| id.value()),
+                    entry.receiver().map(|id| id.value()),
+                    entry.amount().minor(),
+                    to_name(&entry.amount().currency())?,
+                    received.minor(),
+                    to_name(&received.currency())?,
+                    serde_json::to_string(entry)?,
+                ])?;
+        }
+        let position = transaction.query_row(
+            "SELECT COALESCE(MAX(position) + 1, 0) FROM journal_entries",
+            [],
+            |row| row.get::<_, u64>(0),
+        )?;
+        for (position, entry) in (position..).zip(&changes.journal) {
+            transaction
+                .prepare_cached(
+                    "INSERT INTO journal_entries (position, ledger_entry, entry)
+                    VALUES (?1, ?2, ?3)",
+                )?
+                .execute(params![
+                    position,
+                    entry.ledger_entry().value(),
+                    serde_json::to_string(entry)?,
+                ])?;
+        }
+        insert_events(&transaction, &changes.events)?;
+
+        transaction.execute("DELETE FROM accrued_interest", [])?;
+        for accrual in &changes.accrued_interest {
+            transaction
+                .prepare_cached(
+                    "INSERT INTO accrued_interest (account_id, currency, accrued)
+                    VALUES (?1, ?2, ?3)",
+                )?
+                .execute(params![
+                    accrual.account.value(),
+                    to_name(&accrual.currency)?,
+                    accrual.accrued.to_string(),
+                ])?;
+        }
+
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+/// Records the events that the ledger entries of a bank saved before events were recorded
+/// stand for, as [`EventLog::from_ledger`] tells them
+fn backfill_events(transaction: &Transaction) -> Result<(), RepositoryError> {
+    let ledger: Vec<LedgerEntry> =
+        load_json(transaction, "SELECT entry FROM ledger_entries ORDER BY id")?;
+    let mut accounts = BTreeMap::new();
+    let mut statement =
+        transaction.prepare("SELECT id, name, currency, credit_line FROM accounts")?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let currency: Currency = from_name(row.get(2)?)?;
+        let credit_line = Money::from_minor(row.get(3)?, currency);
+        accounts.insert(
+            AccountId::new(row.get(0)?),
+            (row.get::<_, String>(1)?, currency, credit_line),
+        );
+    }
+    let events = EventLog::from_ledger(&ledger, &accounts);
+    insert_events(transaction, events.events())
+}
+
+fn insert_events(
+    transaction: &Transaction,
+    events: &[RecordedEvent],
+) -> Result<(), RepositoryError> {
+    for event in events {
+        transaction
+            .prepare_cached(
+                "INSERT INTO events (sequence, timestamp, kind, event) VALUES (?1, ?2, ?3, ?4)",
+            )?
+            .execute(params![
+                event.sequence(),
+                event.timestamp().to_rfc3339(),
+                to_name(&event.event().kind())?,
+                serde_json::to_string(event)?,
+            ])?;
+    }
+    Ok(())
+}
+
+/// Name of a unit enum variant, such as a currency, as it is serialized
+fn to_name(value: &impl Serialize) -> Result<String, RepositoryError> {
+    match serde_json::to_value(value)? {
+        serde_json::Value::String(name) => Ok(name),
+        other => Ok(other.to_string()),
+    }
+}
+
+fn from_name<T: DeserializeOwned>(name: String) -> Result<T, RepositoryError> {
+    Ok(serde_json::from_value(serde_json::Value::String(name))?)
+}
+
+/// Decodes the JSON in the only column of the rows of `query`
+fn load_json<T: DeserializeOwned>(
+    connection: &Connection,
+    query: &str,
+) -> Result<Vec<T>, RepositoryError> {
+    let mut statement = connection.prepare(query)?;
+    let mut rows = statement.query([])?;
+    let mut values = vec![];
+    while let Some(row) = rows.next()? {
+        values.push(serde_json::from_str(&row.get::<_, String>(0)?)?);
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::StorageError;
+    use crate::events::BankEvent;
+    use crate::ledger::EntryKind;
+    use crate::repository::StoredBank;
+    use crate::test_support::{self, ManualClock, eur};
+    use crate::{AccountState, Bank, User};
+    use std::sync::Arc;
+
+    crate::repository::tests::repository_tests!(SqliteRepository::open_in_memory().unwrap());
+
+    fn bank() -> Bank {
+        test_support::bank([("name1", 0, 100), ("name2", 0, 0)], 365)
+    }
+
+    #[test]
+    fn balances_can_be_queried_with_sql() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("bank.sqlite");
+        let (id1, id2) = (AccountId::new(0), AccountId::new(1));
+        let mut stored =
+            StoredBank::create(SqliteRepository::open(&path).unwrap(), bank()).unwrap();
+        stored.transfer_funds(id1, id2, eur(30)).unwrap();
+        stored.freeze_account(id2).unwrap();
+
+        // Another connection, as a reporting tool would open
+        let reports = Connection::open(&path).unwrap();
+        let mut statement = reports
+            .prepare(
+                "SELECT name, state, currency, balance FROM account_balances ORDER BY account_id",
+            )
+            .unwrap();
+        let balances: Vec<(String, String, String, i64)> = statement
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(
+            balances,
+            vec![
+                ("name1".into(), "Active".into(), "EUR".into(), 70),
+                ("name2".into(), "Frozen".into(), "EUR".into(), 30),
+            ]
+        );
+        let transferred: i64 = reports
+            .query_row(
+                "SELECT SUM(amount) FROM ledger_entries WHERE kind = 'Transfer'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(transferred, 30);
+    }
+
+    #[test]
+    fn reopening_the_file_loads_the_bank() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("bank.sqlite");
+        let (id1, id2) = (AccountId::new(0), AccountId::new(1));
+        let mut stored =
+            StoredBank::create(SqliteRepository::open(&path).unwrap(), bank()).unwrap();
+        stored.transfer_funds(id1, id2, eur(30)).unwrap();
+        stored.request_credit_line(id2, eur(40)).unwrap();
+        let expected = stored.bank().snapshot();
+        drop(stored);
+
+        let reopened = StoredBank::open(SqliteRepository::open(&path).unwrap()).unwrap();
+
+        assert_eq!(reopened.bank().snapshot(), expected);
+        assert_eq!(
+            reopened.bank().user(id2).unwrap().requested_credit_line(),
+            Some(eur(40))
+        );
+    }
+
+    #[test]
+    fn open_migrates_the_schema() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("bank.sqlite");
+        let version = |connection: &Connection| -> u32 {
+            connection
+                .pragma_query_value(None, "user_version", |row| row.get(0))
+                .unwrap()
+        };
+
+        let repository = SqliteRepository::open(&path).unwrap();
+        assert_eq!(version(repository.connection()), MIGRATIONS.len() as u32);
+        drop(repository);
+        // Migrating again changes nothing
+        SqliteRepository::open(&path).unwrap();
+
+        Connection::open(&path)
+            .unwrap()
+            .pragma_update(None, "user_version", 99)
+            .unwrap();
+        assert!(matches!(
+            SqliteRepository::open(&path),
+            Err(RepositoryError::UnsupportedSchema {
+                found: 99,
+                supported: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn migrating_rebuilds_the_events_from_the_ledger() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("bank.sqlite");
+        let (id1, id2) = (AccountId::new(0), AccountId::new(1));
+        // Every entry is recorded at the same time, so that only what they credit tells the
+        // two merges apart
+        let bank = Bank::new_with_clock(
+            vec![
+                User::new("name1".to_string(), eur(0), eur(100)),
+                User::new("name2".to_string(), eur(0), eur(0)),
+            ],
+            "Bank".to_string(),
+            BasisPoints::new(400),
+            BasisPoints::new(365),
+            Arc::new(ManualClock::at(0)),
+        );
+        let mut stored = StoredBank::create(SqliteRepository::open(&path).unwrap(), bank).unwrap();
+        stored.transfer_funds(id1, id2, eur(30)).unwrap();
+        let other = Bank::new(
+            vec![
+                User::new("name1".to_string(), eur(0), eur(2)),
+                User::new("name3".to_string(), eur(0), eur(5)),
+            ],
+            "Other".to_string(),
+            BasisPoints::new(400),
+            BasisPoints::new(365),
+        );
+        stored.merge_bank(other, &Default::default()).unwrap();
+        let again = Bank::new(
+            vec![User::new("name1".to_string(), eur(0), eur(1))],
+            "Again".to_string(),
+            BasisPoints::new(400),
+            BasisPoints::new(365),
+        );
+        stored.merge_bank(again, &Default::default()).unwrap();
+        stored.close_account(id2, id1).unwrap();
+        let balances = stored.bank().calc_balance_per_currency().unwrap();
+        drop(stored);
+        // The database as it was before events were stored
+        let connection = Connection::open(&path).unwrap();
+        connection
+            .execute_batch("DROP TABLE events; PRAGMA user_version = 1;")
+            .unwrap();
+        drop(connection);
+
+        let reopened = StoredBank::open(SqliteRepository::open(&path).unwrap()).unwrap();
+
+        let events = reopened.bank().events().events();
+        let kinds: Vec<EntryKind> = events.iter().map(|event| event.event().kind()).collect();
+        assert_eq!(
+            kinds,
+            [
+                EntryKind::OpeningBalance,
+                EntryKind::OpeningBalance,
+                EntryKind::Transfer,
+                EntryKind::Merge,
+                EntryKind::Merge,
+                EntryKind::Settlement,
+            ]
+        );
+        assert!(matches!(
+            events[3].event(),
+            BankEvent::BanksMerged { bank, credited } if bank.is_empty() && credited.len() == 2
+        ));
+        assert!(matches!(
+            events[4].event(),
+            BankEvent::BanksMerged { credited, .. } if credited.len() == 1
+        ));
+        assert_eq!(
+            reopened.bank().balance_sheets_as_of(events[5].timestamp()),
+            Ok(balances)
+        );
+    }
+
+    #[test]
+    fn failed_transactions_leave_the_database_unchanged() {
+        let (id1, id2) = (AccountId::new(0), AccountId::new(1));
+        let mut stored =
+            StoredBank::create(SqliteRepository::open_in_memory().unwrap(), bank()).unwrap();
+        stored.transfer_funds(id1, id2, eur(10)).unwrap();
+        let saved = stored.bank().snapshot();
+        // Makes the journal insert fail after the balances and ledger entry were written
+        stored
+            .repository()
+            .connection()
+            .execute_batch(
+                "CREATE TRIGGER reject_journal BEFORE INSERT ON journal_entries
+                BEGIN SELECT RAISE(ABORT, 'rejected'); END",
+            )
+            .unwrap();
+
+        assert!(matches!(
+            stored.transfer_funds(id1, id2, eur(20)),
+            Err(StorageError::Repository(RepositoryError::Sqlite(_)))
+        ));
+        assert_eq!(stored.bank().snapshot(), saved);
+        assert_eq!(
+            stored.bank().user(id1).unwrap().state(),
+            AccountState::Active
+        );
+        let snapshot = stored.repository().load().unwrap().unwrap();
+        assert_eq!(Bank::restore(snapshot).unwrap().snapshot(), saved);
+    }
+}
@@ -0,0 +1,266 @@
+//! Fixtures shared by the tests of every module
+
+use crate::clock::{Clock, Timestamp};
+use crate::currency::{CrossCurrencyTransfers, Currency, ExchangeRateProvider};
+use crate::error::{
+    AccountError, AccountNotFound, CreditLineError, InterestError, MergeError, StorageError,
+};
+use crate::interest::InterestConventions;
+use crate::merge::{MergePolicy, MergeReport};
+use crate::money::{BasisPoints, Money};
+use crate::rates::RatePolicy;
+use crate::repository::{BankRepository, StoredBank};
+use crate::{
+    AccountId, AccountState, Bank, BatchTransferError, Transfer, TransferFundsError, User,
+};
+use chrono::NaiveDate;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+pub(crate) fn eur(amount: i64) -> Money {
+    Money::from_minor(amount, Currency::EUR)
+}
+
+pub(crate) fn usd(amount: i64) -> Money {
+    Money::from_minor(amount, Currency::USD)
+}
+
+pub(crate) fn gbp(amount: i64) -> Money {
+    Money::from_minor(amount, Currency::GBP)
+}
+
+pub(crate) fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+pub(crate) fn timestamp(seconds: i64) -> Timestamp {
+    Timestamp::from_timestamp(seconds, 0).unwrap()
+}
+
+/// Clock showing the time it was last set to
+pub(crate) struct ManualClock {
+    now: Mutex<Timestamp>,
+}
+
+impl ManualClock {
+    /// At `seconds` after the epoch
+    pub(crate) fn at(seconds: i64) -> Self {
+        ManualClock {
+            now: Mutex::new(timestamp(seconds)),
+        }
+    }
+
+    pub(crate) fn set(&self, seconds: i64) {
+        *self.now.lock().unwrap() = timestamp(seconds);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// A bank named "Bank" paying 4% credit interest and charging `debit_interest` basis points,
+/// with an account for every `(name, credit line, balance)` in euro cents
+pub(crate) fn bank<S: ToString>(
+    users: impl IntoIterator<Item = (S, i64, i64)>,
+    debit_interest: u32,
+) -> Bank {
+    let users = users
+        .into_iter()
+        .map(|(name, credit_line, balance)| {
+            User::new(name.to_string(), eur(credit_line), eur(balance))
+        })
+        .collect();
+    Bank::new(
+        users,
+        "Bank".to_string(),
+        BasisPoints::new(400),
+        BasisPoints::new(debit_interest),
+    )
+}
+
+/// A [`StoredBank`] with the methods of [`Bank`], for running the tests of [`Bank`] against a
+/// repository. Every mutation checks that the repository holds the bank it left behind.
+pub(crate) struct Stored<R>(StoredBank<R>);
+
+impl<R: BankRepository> Stored<R> {
+    pub(crate) fn create(repository: R, bank: Bank) -> Self {
+        Stored(StoredBank::create(repository, bank).unwrap())
+    }
+
+    pub(crate) fn transfer_funds(
+        &mut self,
+        sender: AccountId,
+        receiver: AccountId,
+        amount: Money,
+    ) -> Result<(), TransferFundsError> {
+        let result = self.0.transfer_funds(sender, receiver, amount);
+        self.saved(result)
+    }
+
+    pub(crate) fn transfer_batch(
+        &mut self,
+        transfers: &[Transfer],
+    ) -> Result<(), BatchTransferError> {
+        let result = self.0.transfer_batch(transfers);
+        self.saved(result)
+    }
+
+    pub(crate) fn accrue_interest(&mut self) {
+        let result = self.0.accrue_interest();
+        self.saved(result).unwrap()
+    }
+
+    pub(crate) fn accrue_interest_between(
+        &mut self,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<(), InterestError> {
+        let result = self.0.accrue_interest_between(from, to);
+        self.saved(result)
+    }
+
+    pub(crate) fn post_accrued_interest(&mut self) {
+        let result = self.0.post_accrued_interest();
+        self.saved(result).unwrap()
+    }
+
+    pub(crate) fn set_interest_conventions(&mut self, conventions: InterestConventions) {
+        let result = self.0.set_interest_conventions(conventions);
+        self.saved(result).unwrap()
+    }
+
+    pub(crate) fn set_cross_currency_transfers(
+        &mut self,
+        cross_currency_transfers: CrossCurrencyTransfers,
+    ) {
+        let result = self
+            .0
+            .set_cross_currency_transfers(cross_currency_transfers);
+        self.saved(result).unwrap()
+    }
+
+    pub(crate) fn set_exchange_rates(&mut self, exchange_rates: Arc<dyn ExchangeRateProvider>) {
+        self.0.set_exchange_rates(exchange_rates);
+    }
+
+    pub(crate) fn set_rate_policy(
+        &mut self,
+        account: AccountId,
+        policy: Arc<dyn RatePolicy>,
+    ) -> Result<(), AccountNotFound> {
+        self.0.set_rate_policy(account, policy)
+    }
+
+    pub(crate) fn merge_bank(
+        &mut self,
+        other: Bank,
+        policy: &MergePolicy,
+    ) -> Result<MergeReport, MergeError> {
+        let result = self.0.merge_bank(other, policy);
+        self.saved(result)
+    }
+
+    pub(crate) fn open_account(&mut self, user: User) -> Result<AccountId, AccountError> {
+        let result = self.0.open_account(user);
+        self.saved(result)
+    }
+
+    pub(crate) fn rename_account(
+        &mut self,
+        account: AccountId,
+        username: String,
+    ) -> Result<(), AccountNotFound> {
+        let result = self.0.rename_account(account, username);
+        self.saved(result)
+    }
+
+    pub(crate) fn freeze_account(&mut self, account: AccountId) -> Result<(), AccountError> {
+        let result = self.0.freeze_account(account);
+        self.saved(result)
+    }
+
+    pub(crate) fn unfreeze_account(
+        &mut self,
+        account: AccountId,
+    ) -> Result<AccountState, AccountError> {
+        let result = self.0.unfreeze_account(account);
+        self.saved(result)
+    }
+
+    pub(crate) fn close_account(
+        &mut self,
+        account: AccountId,
+        settlement_account: AccountId,
+    ) -> Result<Vec<Money>, AccountError> {
+        let result = self.0.close_account(account, settlement_account);
+        self.saved(result)
+    }
+
+    pub(crate) fn request_credit_line(
+        &mut self,
+        account: AccountId,
+        amount: Money,
+    ) -> Result<(), CreditLineError> {
+        let result = self.0.request_credit_line(account, amount);
+        self.saved(result)
+    }
+
+    pub(crate) fn approve_credit_line(
+        &mut self,
+        account: AccountId,
+    ) -> Result<Money, CreditLineError> {
+        let result = self.0.approve_credit_line(account);
+        self.saved(result)
+    }
+
+    pub(crate) fn raise_credit_line(
+        &mut self,
+        account: AccountId,
+        amount: Money,
+    ) -> Result<(), CreditLineError> {
+        let result = self.0.raise_credit_line(account, amount);
+        self.saved(result)
+    }
+
+    pub(crate) fn lower_credit_line(
+        &mut self,
+        account: AccountId,
+        amount: Money,
+    ) -> Result<AccountState, CreditLineError> {
+        let result = self.0.lower_credit_line(account, amount);
+        self.saved(result)
+    }
+
+    pub(crate) fn revoke_credit_line(
+        &mut self,
+        account: AccountId,
+    ) -> Result<AccountState, CreditLineError> {
+        let result = self.0.revoke_credit_line(account);
+        self.saved(result)
+    }
+
+    /// Checks that the repository holds the bank, then gives back what the bank returned
+    fn saved<T, E>(&self, result: Result<T, StorageError<E>>) -> Result<T, E> {
+        let snapshot = self.0.repository().load().unwrap().unwrap();
+        assert_eq!(
+            Bank::restore(snapshot).unwrap().snapshot(),
+            self.0.bank().snapshot(),
+            "the repository does not hold the bank"
+        );
+        result.map_err(|error| match error {
+            StorageError::Rejected(error) => error,
+            StorageError::Repository(error) => panic!("saving the bank failed: {error}"),
+        })
+    }
+}
+
+impl<R: BankRepository> Deref for Stored<R> {
+    type Target = Bank;
+
+    fn deref(&self) -> &Bank {
+        self.0.bank()
+    }
+}
@@ -0,0 +1,191 @@
+use crate::TransferFundsError::{
+    AccountClosed, AccountFrozen, AccountOverLimit, CurrencyMismatch, ExchangeRateUnavailable,
+    NonPositiveAmount, Overflow, ReceiverNotExistsError, SameAccount, SenderNotEnoughBalance,
+    SenderNotExistsError,
+};
+use crate::currency::{CrossCurrencyTransfers, Currency, ExchangeRateProvider, FixedExchangeRates};
+use crate::money::Money;
+use crate::{AccountId, AccountState, TransferFundsError, User};
+use std::sync::Arc;
+
+/// A movement of `amount` from `sender` to `receiver`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Transfer {
+    pub sender: AccountId,
+    pub receiver: AccountId,
+    pub amount: Money,
+}
+
+/// A transfer that passed every check, with the balances it leaves behind
+pub(crate) struct CheckedTransfer {
+    pub(crate) transfer: Transfer,
+    /// The amount credited to the receiver, converted into its currency if needed
+    pub(crate) credited: Money,
+    pub(crate) new_sender_balance: Money,
+    pub(crate) new_receiver_balance: Money,
+}
+
+impl CheckedTransfer {
+    /// The amount credited to the receiver if it differs from the amount debited to the sender
+    pub(crate) fn converted_amount(&self) -> Option<Money> {
+        (self.credited != self.transfer.amount).then_some(self.credited)
+    }
+}
+
+/// Checks `transfer` up to the point where the accounts themselves are needed
+pub(crate) fn check_accounts(
+    transfer: &Transfer,
+    exists: impl Fn(AccountId) -> bool,
+) -> Result<(), TransferFundsError> {
+    let Transfer {
+        sender,
+        receiver,
+        amount,
+    } = *transfer;
+
+    if !amount.is_positive() {
+        return Err(NonPositiveAmount { amount });
+    }
+
+    if !exists(receiver) {
+        return Err(ReceiverNotExistsError { account: receiver });
+    }
+
+    if !exists(sender) {
+        return Err(SenderNotExistsError { account: sender });
+    }
+
+    if sender == receiver {
+        return Err(SameAccount { account: sender });
+    }
+
+    Ok(())
+}
+
+/// How a bank moves funds between currencies
+#[derive(Clone)]
+pub(crate) struct TransferRules {
+    pub(crate) cross_currency_transfers: CrossCurrencyTransfers,
+    pub(crate) exchange_rates: Arc<dyn ExchangeRateProvider>,
+}
+
+impl Default for TransferRules {
+    fn default() -> Self {
+        TransferRules {
+            cross_currency_transfers: CrossCurrencyTransfers::default(),
+            exchange_rates: Arc::new(FixedExchangeRates::default()),
+        }
+    }
+}
+
+impl TransferRules {
+    /// Checks that `sender`, in `sender_state`, can move the funds of `transfer`, which already
+    /// passed [`check_accounts`], to `receiver`. `balance` is the balance of an account in a
+    /// currency.
+    pub(crate) fn check(
+        &self,
+        transfer: &Transfer,
+        sender: &User,
+        sender_state: AccountState,
+        receiver: &User,
+        balance: impl Fn(AccountId, Currency) -> Money,
+    ) -> Result<CheckedTransfer, TransferFundsError> {
+        match sender_state {
+            AccountState::Active => {}
+            AccountState::OverLimit => {
+                return Err(AccountOverLimit {
+                    account: transfer.sender,
+                });
+            }
+            AccountState::Frozen => {
+                return Err(AccountFrozen {
+                    account: transfer.sender,
+                });
+            }
+            AccountState::Closed => {
+                return Err(AccountClosed {
+                    account: transfer.sender,
+                });
+            }
+        }
+        if receiver.state == AccountState::Closed {
+            return Err(AccountClosed {
+                account: transfer.receiver,
+            });
+        }
+        let amount = transfer.amount;
+        let credit_line = match sender.currency == amount.currency() {
+            true => sender.credit_line,
+            false => Money::zero(amount.currency()),
+        };
+        let sender_balance = balance(transfer.sender, amount.currency());
+        let available = sender_balance
+            .checked_add(credit_line)
+            .map_err(|source| Overflow {
+                account: transfer.sender,
+                source,
+            })?;
+        if available.minor() < amount.minor() {
+            return Err(SenderNotEnoughBalance {
+                account: transfer.sender,
+                requested: amount,
+                available,
+            });
+        }
+
+        let credited = self.amount_credited_to(transfer.receiver, receiver, amount)?;
+        let new_sender_balance = sender_balance
+            .checked_sub(amount)
+            .map_err(|source| Overflow {
+                account: transfer.sender,
+                source,
+            })?;
+        let new_receiver_balance = balance(transfer.receiver, credited.currency())
+            .checked_add(credited)
+            .map_err(|source| Overflow {
+                account: transfer.receiver,
+                source,
+            })?;
+
+        Ok(CheckedTransfer {
+            transfer: *transfer,
+            credited,
+            new_sender_balance,
+            new_receiver_balance,
+        })
+    }
+
+    fn amount_credited_to(
+        &self,
+        receiver_id: AccountId,
+        receiver: &User,
+        amount: Money,
+    ) -> Result<Money, TransferFundsError> {
+        if receiver.holds(amount.currency()) {
+            return Ok(amount);
+        }
+        match self.cross_currency_transfers {
+            CrossCurrencyTransfers::Reject => Err(CurrencyMismatch {
+                account: receiver_id,
+                currency: amount.currency(),
+            }),
+            CrossCurrencyTransfers::Convert => {
+                let Some(rate) = self
+                    .exchange_rates
+                    .rate(amount.currency(), receiver.currency)
+                else {
+                    return Err(ExchangeRateUnavailable {
+                        from: amount.currency(),
+                        to: receiver.currency,
+                    });
+                };
+                rate.convert(amount, receiver.currency)
+                    .map_err(|source| Overflow {
+                        account: receiver_id,
+                        source,
+                    })
+            }
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::User;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::ops::{Index, IndexMut};
+
+/// Identifies an account within its bank. Ids are handed out in increasing order, never reused
+/// and kept when the account holder is renamed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AccountId(u64);
+
+impl AccountId {
+    pub fn new(id: u64) -> Self {
+        AccountId(id)
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for AccountId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Users of a bank keyed by account id, with an index from username to the accounts holding it.
+/// Several accounts may share a username.
+#[derive(Default)]
+pub(crate) struct UserStore {
+    users: BTreeMap<AccountId, User>,
+    ids_by_username: HashMap<String, BTreeSet<AccountId>>,
+    next_id: u64,
+}
+
+impl UserStore {
+    pub(crate) fn insert(&mut self, user: User) -> AccountId {
+        let id = AccountId(self.next_id);
+        self.next_id += 1;
+        self.index(&user.name, id);
+        self.users.insert(id, user);
+        id
+    }
+
+    /// The id the next inserted user gets
+    pub(crate) fn next_id(&self) -> AccountId {
+        AccountId(self.next_id)
+    }
+
+    /// Inserts `user` under an id it already had, e.g. in a restored snapshot. Returns `false`
+    /// if the id is taken.
+    pub(crate) fn restore(&mut self, id: AccountId, user: User) -> bool {
+        if self.users.contains_key(&id) {
+            return false;
+        }
+        self.index(&user.name, id);
+        self.users.insert(id, user);
+        self.next_id = self.next_id.max(id.0 + 1);
+        true
+    }
+
+    /// Makes `next_id` the id of the next inserted user, unless an existing user has it or a
+    /// higher one. Returns whether it does.
+    pub(crate) fn set_next_id(&mut self, next_id: AccountId) -> bool {
+        if next_id.0 < self.next_id {
+            return false;
+        }
+        self.next_id = next_id.0;
+        true
+    }
+
+    pub(crate) fn ids_named(&self, username: &str) -> impl Iterator<Item = AccountId> + '_ {
+        self.ids_by_username
+            .get(username)
+            .into_iter()
+            .flatten()
+            .copied()
+    }
+
+    pub(crate) fn get(&self, id: AccountId) -> Option<&User> {
+        self.users.get(&id)
+    }
+
+    pub(crate) fn contains(&self, id: AccountId) -> bool {
+        self.users.contains_key(&id)
+    }
+
+    pub(crate) fn rename(&mut self, id: AccountId, username: String) -> Option<()> {
+        let user = self.users.get_mut(&id)?;
+        let old_username = std::mem::replace(&mut user.name, username.clone());
+        self.unindex(&old_username, id);
+        self.index(&username, id);
+        Some(())
+    }
+
+    pub(crate) fn ids(&self) -> Vec<AccountId> {
+        self.users.keys().copied().collect()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (AccountId, &User)> {
+        self.users.iter().map(|(id, user)| (*id, user))
+    }
+
+    fn index(&mut self, username: &str, id: AccountId) {
+        self.ids_by_username
+            .entry(username.to_string())
+            .or_default()
+            .insert(id);
+    }
+
+    fn unindex(&mut self, username: &str, id: AccountId) {
+        if let Some(ids) = self.ids_by_username.get_mut(username) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.ids_by_username.remove(username);
+            }
+        }
+    }
+}
+
+impl IntoIterator for UserStore {
+    type Item = (AccountId, User);
+    type IntoIter = std::collections::btree_map::IntoIter<AccountId, User>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.users.into_iter()
+    }
+}
+
+impl Index<AccountId> for UserStore {
+    type Output = User;
+
+    fn index(&self, id: AccountId) -> &User {
+        &self.users[&id]
+    }
+}
+
+impl IndexMut<AccountId> for UserStore {
+    fn index_mut(&mut self, id: AccountId) -> &mut User {
+        self.users
+            .get_mut(&id)
+            .expect("no user with this account id")
+    }
+}
@@ -0,0 +1,635 @@
+use crate::clock::{Clock, SystemClock};
+use crate::currency::{CrossCurrencyTransfers, ExchangeRateProvider};
+use crate::error::{
+    AccountError, AccountNotFound, CreditLineError, DurableError, InterestError, MergeError,
+    WalError,
+};
+use crate::interest::InterestConventions;
+use crate::merge::{MergePolicy, MergeReport};
+use crate::money::Money;
+use crate::rates::RatePolicy;
+use crate::repository::{Changed, Saved};
+use crate::snapshot::{BankSnapshot, SnapshotFormat, sync_directory, write_durably};
+use crate::{
+    AccountId, AccountState, Bank, BatchTransferError, Transfer, TransferFundsError, User,
+};
+use chrono::NaiveDate;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const LOG_FILE: &str = "wal.log";
+const CHECKPOINT_PREFIX: &str = "checkpoint-";
+const CHECKPOINT_SUFFIX: &str = ".snapshot";
+/// Length and CRC-32 of the payload, both little-endian
+const FRAME_HEADER_LEN: usize = 8;
+
+/// What a mutation changed, as the partial snapshot a [`StoredBank`] would save.
+///
+/// Logging outcomes rather than the mutations themselves means recovery needs neither the clock
+/// nor the exchange rates and rate policies the mutations were applied with.
+///
+/// [`StoredBank`]: crate::repository::StoredBank
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Record {
+    sequence: u64,
+    changes: BankSnapshot,
+}
+
+/// A bank whose every mutation is written to a checksummed log, and synced to disk, before the
+/// mutation returns.
+///
+/// Its directory holds the log and the latest checkpoint, a snapshot of the bank after a given
+/// log record. Opening the directory applies the changes logged after the checkpoint, up to the
+/// first record that was torn by a crash, then compacts the log into a new checkpoint.
+pub struct DurableBank {
+    bank: Bank,
+    directory: PathBuf,
+    log: File,
+    log_len: u64,
+    next_sequence: u64,
+    /// What the directory holds of the bank
+    saved: Saved,
+}
+
+impl DurableBank {
+    /// Stores `bank` in `directory`, which must not hold a bank yet
+    pub fn create(directory: impl AsRef<Path>, bank: Bank) -> Result<Self, WalError> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+        if latest_checkpoint(&directory)?.is_some() {
+            return Err(WalError::Io(std::io::ErrorKind::AlreadyExists.into()));
+        }
+        write_checkpoint(&directory, 0, &bank)?;
+        let log = reset_log(&directory)?;
+        Ok(DurableBank {
+            saved: Saved::of(&bank),
+            bank,
+            directory,
+            log,
+            log_len: 0,
+            next_sequence: 1,
+        })
+    }
+
+    /// Recovers the bank stored in `directory`, with the system clock
+    pub fn open(directory: impl AsRef<Path>) -> Result<Self, WalError> {
+        DurableBank::open_with_clock(directory, Arc::new(SystemClock))
+    }
+
+    pub fn open_with_clock(
+        directory: impl AsRef<Path>,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self, WalError> {
+        let directory = directory.as_ref().to_path_buf();
+        let (bank, sequence) = recover(&directory, clock)?;
+        let mut durable_bank = DurableBank {
+            saved: Saved::of(&bank),
+            bank,
+            log: OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(directory.join(LOG_FILE))?,
+            directory,
+            log_len: 0,
+            next_sequence: sequence + 1,
+        };
+        durable_bank.checkpoint()?;
+        Ok(durable_bank)
+    }
+
+    pub fn bank(&self) -> &Bank {
+        &self.bank
+    }
+
+    /// Writes a checkpoint of the bank and empties the log
+    pub fn checkpoint(&mut self) -> Result<(), WalError> {
+        let sequence = self.next_sequence - 1;
+        write_checkpoint(&self.directory, sequence, &self.bank)?;
+        self.log = reset_log(&self.directory)?;
+        self.log_len = 0;
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if checkpoint_sequence(&path).is_some_and(|older| older < sequence) {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn transfer_funds(
+        &mut self,
+        sender: AccountId,
+        receiver: AccountId,
+        amount: Money,
+    ) -> Result<(), DurableError<TransferFundsError>> {
+        self.execute(Changed::Accounts(vec![sender, receiver]), |bank| {
+            bank.transfer_funds(sender, receiver, amount)
+        })
+    }
+
+    pub fn transfer_batch(
+        &mut self,
+        transfers: &[Transfer],
+    ) -> Result<(), DurableError<BatchTransferError>> {
+        self.execute(Changed::Accounts(vec![]), |bank| {
+            bank.transfer_batch(transfers)
+        })
+    }
+
+    pub fn accrue_interest(&mut self) -> Result<(), DurableError> {
+        self.execute(Changed::Accounts(vec![]), |bank| {
+            bank.accrue_interest();
+            Ok(())
+        })
+    }
+
+    pub fn accrue_interest_between(
+        &mut self,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<(), DurableError<InterestError>> {
+        self.execute(Changed::Accounts(vec![]), |bank| {
+            bank.accrue_interest_between(from, to)
+        })
+    }
+
+    pub fn post_accrued_interest(&mut self) -> Result<(), DurableError> {
+        self.execute(Changed::Accounts(vec![]), |bank| {
+            bank.post_accrued_interest();
+            Ok(())
+        })
+    }
+
+    pub fn set_interest_conventions(
+        &mut self,
+        conventions: InterestConventions,
+    ) -> Result<(), DurableError> {
+        self.execute(Changed::Accounts(vec![]), |bank| {
+            bank.set_interest_conventions(conventions);
+            Ok(())
+        })
+    }
+
+    pub fn set_overdraft_fee(&mut self, fee: Money) -> Result<(), DurableError> {
+        self.execute(Changed::Accounts(vec![]), |bank| {
+            bank.set_overdraft_fee(fee);
+            Ok(())
+        })
+    }
+
+    pub fn set_cross_currency_transfers(
+        &mut self,
+        cross_currency_transfers: CrossCurrencyTransfers,
+    ) -> Result<(), DurableError> {
+        self.execute(Changed::Accounts(vec![]), |bank| {
+            bank.set_cross_currency_transfers(cross_currency_transfers);
+            Ok(())
+        })
+    }
+
+    /// Sets the exchange rate provider of the bank without logging it, since a provider is not
+    /// data. Set it again after opening the bank.
+    pub fn set_exchange_rates(&mut self, exchange_rates: Arc<dyn ExchangeRateProvider>) {
+        self.bank.set_exchange_rates(exchange_rates);
+    }
+
+    /// Sets the rate policy of `account` without logging it, like
+    /// [`DurableBank::set_exchange_rates`]
+    pub fn set_rate_policy(
+        &mut self,
+        account: AccountId,
+        policy: Arc<dyn RatePolicy>,
+    ) -> Result<(), AccountNotFound> {
+        self.bank.set_rate_policy(account, policy)
+    }
+
+    pub fn merge_bank(
+        &mut self,
+        other: Bank,
+        policy: &MergePolicy,
+    ) -> Result<MergeReport, DurableError<MergeError>> {
+        self.execute(Changed::All, |bank| bank.merge_bank(other, policy))
+    }
+
+    pub fn open_account(&mut self, user: User) -> Result<AccountId, DurableError<AccountError>> {
+        self.execute(Changed::Accounts(vec![]), |bank| bank.open_account(user))
+    }
+
+    pub fn rename_account(
+        &mut self,
+        account: AccountId,
+        username: String,
+    ) -> Result<(), DurableError<AccountNotFound>> {
+        self.execute(Changed::Accounts(vec![account]), |bank| {
+            bank.rename_account(account, username)
+        })
+    }
+
+    pub fn freeze_account(&mut self, account: AccountId) -> Result<(), DurableError<AccountError>> {
+        self.execute(Changed::Accounts(vec![account]), |bank| {
+            bank.freeze_account(account)
+        })
+    }
+
+    pub fn unfreeze_account(
+        &mut self,
+        account: AccountId,
+    ) -> Result<AccountState, DurableError<AccountError>> {
+        self.execute(Changed::Accounts(vec![account]), |bank| {
+            bank.unfreeze_account(account)
+        })
+    }
+
+    pub fn close_account(
+        &mut self,
+        account: AccountId,
+        settlement_account: AccountId,
+    ) -> Result<Vec<Money>, DurableError<AccountError>> {
+        self.execute(Changed::Accounts(vec![account]), |bank| {
+            bank.close_account(account, settlement_account)
+        })
+    }
+
+    pub fn request_credit_line(
+        &mut self,
+        account: AccountId,
+        amount: Money,
+    ) -> Result<(), DurableError<CreditLineError>> {
+        self.execute(Changed::Accounts(vec![account]), |bank| {
+            bank.request_credit_line(account, amount)
+        })
+    }
+
+    pub fn approve_credit_line(
+        &mut self,
+        account: AccountId,
+    ) -> Result<Money, DurableError<CreditLineError>> {
+        self.execute(Changed::Accounts(vec![account]), |bank| {
+            bank.approve_credit_line(account)
+        })
+    }
+
+    pub fn raise_credit_line(
+        &mut self,
+        account: AccountId,
+        amount: Money,
+    ) -> Result<(), DurableError<CreditLineError>> {
+        self.execute(Changed::Accounts(vec![account]), |bank| {
+            bank.raise_credit_line(account, amount)
+        })
+    }
+
+    pub fn lower_credit_line(
+        &mut self,
+        account: AccountId,
+        amount: Money,
+    ) -> Result<AccountState, DurableError<CreditLineError>> {
+        self.execute(Changed::Accounts(vec![account]), |bank| {
+            bank.lower_credit_line(account, amount)
+        })
+    }
+
+    pub fn revoke_credit_line(
+        &mut self,
+        account: AccountId,
+    ) -> Result<AccountState, DurableError<CreditLineError>> {
+        self.execute(Changed::Accounts(vec![account]), |bank| {
+            bank.revoke_credit_line(account)
+        })
+    }
+
+    /// Applies a mutation with `apply`, then logs what it changed. If logging fails, the bank
+    /// is recovered from the directory, so that it never shows changes the log does not hold.
+    fn execute<T, E>(
+        &mut self,
+        changed: Changed,
+        apply: impl FnOnce(&mut Bank) -> Result<T, E>,
+    ) -> Result<T, DurableError<E>> {
+        let value = apply(&mut self.bank).map_err(DurableError::Rejected)?;
+        let record = Record {
+            sequence: self.next_sequence,
+            changes: self.saved.changes(&self.bank, changed),
+        };
+
+        if let Err(error) = self.append(&record) {
+            // Should recovering fail too, the unlogged changes are logged with the next mutation
+            if let Ok((mut bank, sequence)) = recover(&self.directory, self.bank.clock.clone()) {
+                bank.keep_unsaved_settings(&self.bank);
+                self.saved = Saved::of(&bank);
+                self.next_sequence = sequence + 1;
+                self.bank = bank;
+            }
+            return Err(error.into());
+        }
+        self.next_sequence += 1;
+        self.saved = Saved::of(&self.bank);
+        Ok(value)
+    }
+
+    fn append(&mut self, record: &Record) -> Result<(), WalError> {
+        let payload = postcard::to_stdvec(record)?;
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+        frame.extend((payload.len() as u32).to_le_bytes());
+        frame.extend(crc32fast::hash(&payload).to_le_bytes());
+        frame.extend(payload);
+
+        let written = self
+            .log
+            .write_all(&frame)
+            .and_then(|()| self.log.sync_data());
+        if let Err(error) = written {
+            // Leave no partial record for the next ones to follow
+            let _ = self.log.set_len(self.log_len);
+            return Err(error.into());
+        }
+        self.log_len += frame.len() as u64;
+        Ok(())
+    }
+}
+
+/// The records of `log` up to the first torn or corrupted one
+fn read_records(log: &[u8]) -> Vec<Record> {
+    let mut records = vec![];
+    let mut rest = log;
+    while let Some((header, after_header)) = rest.split_first_chunk::<FRAME_HEADER_LEN>() {
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+        let Some((payload, after_payload)) = after_header.split_at_checked(len) else {
+            break;
+        };
+        if crc32fast::hash(payload) != crc {
+            break;
+        }
+        let Ok(record) = postcard::from_bytes(payload) else {
+            break;
+        };
+        records.push(record);
+        rest = after_payload;
+    }
+    records
+}
+
+/// The bank held by `directory`: its latest checkpoint with the changes logged after it, and
+/// the sequence number of the last record applied
+fn recover(directory: &Path, clock: Arc<dyn Clock>) -> Result<(Bank, u64), WalError> {
+    let Some((checkpoint, path)) = latest_checkpoint(directory)? else {
+        return Err(WalError::NoCheckpoint);
+    };
+    let mut snapshot = BankSnapshot::decode(&fs::read(path)?)?;
+    let log = match fs::read(directory.join(LOG_FILE)) {
+        Ok(log) => log,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(error) => return Err(error.into()),
+    };
+    let mut sequence = checkpoint;
+    for record in read_records(&log) {
+        if record.sequence <= sequence {
+            continue;
+        }
+        sequence = record.sequence;
+        snapshot.merge_changes(&record.changes);
+    }
+    let bank = Bank::restore_with_clock(snapshot, clock)
+        .map_err(|error| WalError::Snapshot(error.into()))?;
+    Ok((bank, sequence))
+}
+
+fn checkpoint_path(directory: &Path, sequence: u64) -> PathBuf {
+    directory.join(format!(
+        "{CHECKPOINT_PREFIX}{sequence:020}{CHECKPOINT_SUFFIX}"
+    ))
+}
+
+fn checkpoint_sequence(path: &Path) -> Option<u64> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix(CHECKPOINT_PREFIX)?
+        .strip_suffix(CHECKPOINT_SUFFIX)?
+        .parse()
+        .ok()
+}
+
+fn latest_checkpoint(directory: &Path) -> Result<Option<(u64, PathBuf)>, WalError> {
+    let mut checkpoints = BTreeMap::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if let Some(sequence) = checkpoint_sequence(&path) {
+            checkpoints.insert(sequence, path);
+        }
+    }
+    Ok(checkpoints.pop_last())
+}
+
+/// Writes a snapshot of `bank` as the checkpoint after log record `sequence`
+fn write_checkpoint(directory: &Path, sequence: u64, bank: &Bank) -> Result<(), WalError> {
+    let path = checkpoint_path(directory, sequence);
+    write_durably(&path, &bank.snapshot().encode(SnapshotFormat::Binary)?)?;
+    Ok(())
+}
+
+/// Replaces the log with an empty one, opened for appending
+fn reset_log(directory: &Path) -> Result<File, WalError> {
+    let path = directory.join(LOG_FILE);
+    let log = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&path)?;
+    log.sync_all()?;
+    sync_directory(directory)?;
+    Ok(OpenOptions::new().append(true).open(&path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currency::{Currency, ExchangeRate, FixedExchangeRates};
+    use crate::money::BasisPoints;
+    use crate::rates::FlatRate;
+    use crate::test_support::{self, date, eur, usd};
+
+    fn bank() -> Bank {
+        test_support::bank([("name1", 0, 100), ("name2", 0, 0)], 365)
+    }
+
+    fn log_len(directory: &Path) -> u64 {
+        fs::metadata(directory.join(LOG_FILE)).unwrap().len()
+    }
+
+    fn checkpoints(directory: &Path) -> usize {
+        fs::read_dir(directory)
+            .unwrap()
+            .filter(|entry| checkpoint_sequence(&entry.as_ref().unwrap().path()).is_some())
+            .count()
+    }
+
+    #[test]
+    fn open_applies_the_log_and_compacts_it() {
+        let directory = tempfile::tempdir().unwrap();
+        let (id1, id2) = (AccountId::new(0), AccountId::new(1));
+        let mut durable = DurableBank::create(directory.path(), bank()).unwrap();
+
+        durable.transfer_funds(id1, id2, eur(60)).unwrap();
+        assert!(matches!(
+            durable.transfer_funds(id1, id2, eur(60)),
+            Err(DurableError::Rejected(
+                TransferFundsError::SenderNotEnoughBalance { .. }
+            ))
+        ));
+        let id3 = durable
+            .open_account(User::new("name3".to_string(), eur(50), eur(7)))
+            .unwrap();
+        durable.freeze_account(id2).unwrap();
+        durable
+            .accrue_interest_between(date(2024, 1, 1), date(2024, 1, 11))
+            .unwrap();
+        durable.request_credit_line(id3, eur(80)).unwrap();
+        durable
+            .set_cross_currency_transfers(CrossCurrencyTransfers::Convert)
+            .unwrap();
+        let expected = durable.bank().snapshot();
+        drop(durable);
+        assert!(log_len(directory.path()) > 0);
+
+        let recovered = DurableBank::open(directory.path()).unwrap();
+
+        assert_eq!(recovered.bank().snapshot(), expected);
+        assert_eq!(log_len(directory.path()), 0);
+        assert_eq!(checkpoints(directory.path()), 1);
+    }
+
+    #[test]
+    fn recovery_needs_no_exchange_rates_or_rate_policies() {
+        let directory = tempfile::tempdir().unwrap();
+        let id1 = AccountId::new(0);
+        let mut durable = DurableBank::create(directory.path(), bank()).unwrap();
+        let rates = FixedExchangeRates::default().with_rate(
+            Currency::EUR,
+            Currency::USD,
+            ExchangeRate::new(110, 100).unwrap(),
+        );
+        durable.set_exchange_rates(Arc::new(rates));
+        durable
+            .set_cross_currency_transfers(CrossCurrencyTransfers::Convert)
+            .unwrap();
+        let policy = FlatRate {
+            credit: BasisPoints::new(0),
+            debit: BasisPoints::new(36_500),
+        };
+        durable.set_rate_policy(id1, Arc::new(policy)).unwrap();
+        let id3 = durable
+            .open_account(User::new("name3".to_string(), usd(0), usd(0)))
+            .unwrap();
+
+        durable.transfer_funds(id1, id3, eur(50)).unwrap();
+        durable
+            .accrue_interest_between(date(2024, 1, 1), date(2024, 1, 11))
+            .unwrap();
+        let expected = durable.bank().snapshot();
+        drop(durable);
+
+        let recovered = DurableBank::open(directory.path()).unwrap();
+        assert_eq!(recovered.bank().snapshot(), expected);
+        assert_eq!(
+            recovered.bank().user(id3).unwrap().balance(Currency::USD),
+            usd(55)
+        );
+    }
+
+    #[test]
+    fn recovery_stops_at_a_torn_record() {
+        let directory = tempfile::tempdir().unwrap();
+        let (id1, id2) = (AccountId::new(0), AccountId::new(1));
+        let mut durable = DurableBank::create(directory.path(), bank()).unwrap();
+        durable.transfer_funds(id1, id2, eur(10)).unwrap();
+        let log_after_first = log_len(directory.path());
+        durable.transfer_funds(id1, id2, eur(20)).unwrap();
+        drop(durable);
+
+        // A crash in the middle of the second append
+        let log = OpenOptions::new()
+            .write(true)
+            .open(directory.path().join(LOG_FILE))
+            .unwrap();
+        log.set_len(log_len(directory.path()) - 3).unwrap();
+        assert!(log_len(directory.path()) > log_after_first);
+
+        let mut recovered = DurableBank::open(directory.path()).unwrap();
+        assert_eq!(
+            recovered.bank().user(id2).unwrap().balance(Currency::EUR),
+            eur(10)
+        );
+
+        // Later mutations are logged after the recovered ones
+        recovered.transfer_funds(id1, id2, eur(5)).unwrap();
+        drop(recovered);
+        let recovered = DurableBank::open(directory.path()).unwrap();
+        assert_eq!(
+            recovered.bank().user(id2).unwrap().balance(Currency::EUR),
+            eur(15)
+        );
+    }
+
+    #[test]
+    fn recovery_stops_at_a_corrupted_record() {
+        let directory = tempfile::tempdir().unwrap();
+        let (id1, id2) = (AccountId::new(0), AccountId::new(1));
+        let mut durable = DurableBank::create(directory.path(), bank()).unwrap();
+        durable.transfer_funds(id1, id2, eur(10)).unwrap();
+        durable.transfer_funds(id1, id2, eur(20)).unwrap();
+        drop(durable);
+
+        let path = directory.path().join(LOG_FILE);
+        let mut log = fs::read(&path).unwrap();
+        *log.last_mut().unwrap() ^= 0xff;
+        fs::write(&path, log).unwrap();
+
+        let recovered = DurableBank::open(directory.path()).unwrap();
+        assert_eq!(
+            recovered.bank().user(id2).unwrap().balance(Currency::EUR),
+            eur(10)
+        );
+    }
+
+    #[test]
+    fn checkpoint_replaces_older_ones() {
+        let directory = tempfile::tempdir().unwrap();
+        let (id1, id2) = (AccountId::new(0), AccountId::new(1));
+        let mut durable = DurableBank::create(directory.path(), bank()).unwrap();
+        durable.transfer_funds(id1, id2, eur(10)).unwrap();
+
+        durable.checkpoint().unwrap();
+
+        assert_eq!(log_len(directory.path()), 0);
+        assert_eq!(checkpoints(directory.path()), 1);
+        assert!(checkpoint_path(directory.path(), 1).exists());
+        durable.accrue_interest().unwrap();
+        let expected = durable.bank().snapshot();
+        drop(durable);
+        assert_eq!(
+            DurableBank::open(directory.path())
+                .unwrap()
+                .bank()
+                .snapshot(),
+            expected
+        );
+    }
+
+    #[test]
+    fn create_and_open_need_a_matching_directory() {
+        let directory = tempfile::tempdir().unwrap();
+
+        assert!(matches!(
+            DurableBank::open(directory.path()),
+            Err(WalError::NoCheckpoint)
+        ));
+        DurableBank::create(directory.path(), bank()).unwrap();
+        assert!(matches!(
+            DurableBank::create(directory.path(), bank()),
+            Err(WalError::Io(_))
+        ));
+    }
+}